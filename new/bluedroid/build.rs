@@ -1,4 +1,13 @@
 fn main() -> anyhow::Result<()> {
-    embuild::build::CfgArgs::output_propagated("ESP_IDF")?;
-    embuild::build::LinkArgs::output_propagated("ESP_IDF")
+    if std::env::var("CARGO_CFG_TARGET_OS").as_deref() == Ok("espidf") {
+        embuild::build::CfgArgs::output_propagated("ESP_IDF")?;
+        embuild::build::LinkArgs::output_propagated("ESP_IDF")
+    } else {
+        // The simulated stack mirrors the ESP-IDF 4.x API, with the BLE 5.0 features and robust caching enabled.
+        println!("cargo:rustc-cfg=esp_idf_version_major=\"4\"");
+        println!("cargo:rustc-cfg=esp_idf_bt_ble_50_features_supported");
+        println!("cargo:rustc-cfg=esp_idf_bt_gatts_send_service_change_manual");
+        println!("cargo:rustc-cfg=esp_idf_bt_gatts_robust_caching_enabled");
+        Ok(())
+    }
 }
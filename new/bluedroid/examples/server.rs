@@ -1,7 +1,7 @@
 use std::sync::{Arc, RwLock};
 
 use bluedroid::{
-    gatt_server::{Characteristic, Profile, Service, GLOBAL_GATT_SERVER},
+    gatt_server::{Characteristic, GattServer, GattServerConfig, Profile, Service},
     utilities::{AttributePermissions, CharacteristicProperties},
     uuid128,
 };
@@ -64,11 +64,12 @@ fn main() {
     .properties(CharacteristicProperties::new().read().write())
     .on_read(move |_param| {
         info!("Read from writable characteristic.");
-        return char_value_read.read().unwrap().clone();
+        Ok(char_value_read.read().unwrap().clone())
     })
     .on_write(move |value, _param| {
         info!("Wrote to writable characteristic: {:?}", value);
         *char_value_write.write().unwrap() = value;
+        Ok(())
     })
     .show_name()
     .build();
@@ -89,14 +90,19 @@ fn main() {
         .service(&service)
         .build();
 
-    GLOBAL_GATT_SERVER
+    let server = GattServer::new(
+        GattServerConfig::new()
+            .device_name("ESP32-GATT-Server")
+            .appearance(bluedroid::utilities::Appearance::WristWornPulseOximeter),
+    );
+
+    server
         .lock()
         .unwrap()
         .profile(profile)
-        .device_name("ESP32-GATT-Server")
-        .appearance(bluedroid::utilities::Appearance::WristWornPulseOximeter)
         .advertise_service(&service)
-        .start();
+        .start()
+        .unwrap();
 
     std::thread::spawn(move || {
         let mut counter = 0;
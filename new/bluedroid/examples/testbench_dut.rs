@@ -1,7 +1,7 @@
 use std::sync::{Arc, RwLock};
 
 use bluedroid::{
-    gatt_server::{Characteristic, Profile, Service, GLOBAL_GATT_SERVER},
+    gatt_server::{Characteristic, GattServer, GattServerConfig, Profile, Service},
     utilities::{AttributePermissions, BleUuid, CharacteristicProperties},
 };
 
@@ -45,11 +45,12 @@ fn main() {
     )
     .on_read(move |_param| {
         info!("Read from writable characteristic.");
-        return char_value_read.read().unwrap().clone();
+        Ok(char_value_read.read().unwrap().clone())
     })
     .on_write(move |value, _param| {
         info!("Wrote to writable characteristic: {:?}", value);
         *char_value_write.write().unwrap() = value;
+        Ok(())
     })
     .show_name()
     .build();
@@ -102,14 +103,19 @@ fn main() {
         .service(&another_service)
         .build();
 
-    GLOBAL_GATT_SERVER
+    let server = GattServer::new(
+        GattServerConfig::new()
+            .device_name("BLUEDROID-DUT")
+            .appearance(bluedroid::utilities::Appearance::GenericUnknown),
+    );
+
+    server
         .lock()
         .unwrap()
         .profile(profile)
-        .device_name("BLUEDROID-DUT")
-        .appearance(bluedroid::utilities::Appearance::GenericUnknown)
         .advertise_service(&advertised_service)
-        .start();
+        .start()
+        .unwrap();
 
     std::thread::spawn(move || {
         let mut counter = 0;
@@ -0,0 +1,814 @@
+//! Backend that forwards every call to the ESP-IDF Bluedroid stack.
+
+use std::{
+    ffi::CString,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+use embedded_svc::storage::RawStorage;
+use esp_idf_svc::nvs::{EspDefaultNvs, EspDefaultNvsPartition};
+use esp_idf_sys::*;
+use log::{info, warn};
+
+use super::Backend;
+use crate::leaky_box_raw;
+
+/// The UUID of the Peripheral Preferred Connection Parameters characteristic.
+#[cfg(feature = "gap-preferred-parameters")]
+const PREFERRED_CONNECTION_PARAMETERS_UUID: u16 = 0x2A04;
+
+// `GAP_BleAttrDBUpdate` and the layout of its value are internal to Bluedroid,
+// and have only been checked against ESP-IDF 4.x and 5.x.
+#[cfg(all(
+    feature = "gap-preferred-parameters",
+    not(any(esp_idf_version_major = "4", esp_idf_version_major = "5"))
+))]
+compile_error!("the `gap-preferred-parameters` feature requires ESP-IDF 4.x or 5.x");
+
+/// The value of a characteristic of the stack's GAP service (`tGAP_BLE_ATTR_VALUE`),
+/// for the Peripheral Preferred Connection Parameters.
+#[cfg(feature = "gap-preferred-parameters")]
+#[repr(C)]
+struct GapPreferredParameters {
+    int_min: u16,
+    int_max: u16,
+    latency: u16,
+    sp_tout: u16,
+}
+
+#[cfg(feature = "gap-preferred-parameters")]
+extern "C" {
+    /// Updates a characteristic of the GAP service created by Bluedroid.
+    ///
+    /// It is not part of the ESP-IDF API, which only exposes the name and the appearance.
+    /// It writes the value without switching to the stack's task, so it is only called from its events.
+    fn GAP_BleAttrDBUpdate(attr_uuid: u16, p_value: *mut GapPreferredParameters);
+}
+
+/// Copies a buffer passed to the stack to a location that is never freed.
+///
+/// # Safety
+///
+/// The pointer must be valid for reads of `length` bytes, unless it is null.
+unsafe fn leak_bytes(pointer: *mut u8, length: usize) -> *mut u8 {
+    if pointer.is_null() {
+        return pointer;
+    }
+
+    let bytes = std::slice::from_raw_parts(pointer, length).to_vec();
+    Box::leak(bytes.into_boxed_slice()).as_mut_ptr()
+}
+
+/// The name of the NVS namespace used by this crate.
+const NAMESPACE: &str = "ble";
+
+/// The NVS namespace used by this crate, opened on first use.
+static STORAGE: Mutex<Option<EspDefaultNvs>> = Mutex::new(None);
+
+/// Returns the devices bonded with the stack, with their keys.
+#[allow(clippy::cast_sign_loss)]
+fn bond_devices() -> Result<Vec<esp_ble_bond_dev_t>, EspError> {
+    let mut count = unsafe { esp_ble_get_bond_device_num() };
+    if count <= 0 {
+        return Ok(Vec::new());
+    }
+
+    let mut devices: Vec<esp_ble_bond_dev_t> =
+        (0..count).map(|_| unsafe { std::mem::zeroed() }).collect();
+    unsafe {
+        esp!(esp_ble_get_bond_device_list(
+            &mut count,
+            devices.as_mut_ptr()
+        ))?
+    };
+    devices.truncate(count as usize);
+
+    Ok(devices)
+}
+
+/// Runs an operation on the NVS namespace, opening it if needed.
+fn with_storage<T>(
+    operation: impl FnOnce(&mut EspDefaultNvs) -> Result<T, EspError>,
+) -> Result<T, EspError> {
+    let mut storage = STORAGE
+        .lock()
+        .map_err(|_| EspError::from_infallible::<ESP_ERR_INVALID_STATE>())?;
+
+    if storage.is_none() {
+        *storage = Some(EspDefaultNvs::new(
+            EspDefaultNvsPartition::take()?,
+            NAMESPACE,
+            true,
+        )?);
+    }
+
+    operation(storage.as_mut().unwrap())
+}
+
+/// Whether the memory of the Bluetooth Classic controller has been released.
+static CLASSIC_MEMORY_RELEASED: AtomicBool = AtomicBool::new(false);
+
+/// The hardware backend.
+pub(crate) struct EspBackend;
+
+impl Backend for EspBackend {
+    #[allow(clippy::too_many_lines)]
+    fn initialise(
+        &self,
+        gatts_callback: esp_gatts_cb_t,
+        gap_callback: esp_gap_ble_cb_t,
+    ) -> Result<(), EspError> {
+        info!("Initialising BLE stack.");
+
+        // NVS initialisation.
+        unsafe {
+            let result = nvs_flash_init();
+            if result == ESP_ERR_NVS_NO_FREE_PAGES || result == ESP_ERR_NVS_NEW_VERSION_FOUND {
+                warn!("NVS initialisation failed. Erasing NVS.");
+                esp!(nvs_flash_erase())?;
+                esp!(nvs_flash_init())?;
+            }
+        }
+
+        #[cfg(esp32)]
+        let default_controller_configuration = esp_bt_controller_config_t {
+            controller_task_stack_size: ESP_TASK_BT_CONTROLLER_STACK as _,
+            controller_task_prio: ESP_TASK_BT_CONTROLLER_PRIO as _,
+            hci_uart_no: BT_HCI_UART_NO_DEFAULT as _,
+            hci_uart_baudrate: BT_HCI_UART_BAUDRATE_DEFAULT,
+            scan_duplicate_mode: SCAN_DUPLICATE_MODE as _,
+            scan_duplicate_type: SCAN_DUPLICATE_TYPE_VALUE as _,
+            normal_adv_size: NORMAL_SCAN_DUPLICATE_CACHE_SIZE as _,
+            mesh_adv_size: MESH_DUPLICATE_SCAN_CACHE_SIZE as _,
+            send_adv_reserved_size: SCAN_SEND_ADV_RESERVED_SIZE as _,
+            controller_debug_flag: CONTROLLER_ADV_LOST_DEBUG_BIT,
+            mode: esp_bt_mode_t_ESP_BT_MODE_BLE as _,
+            ble_max_conn: CONFIG_BTDM_CTRL_BLE_MAX_CONN_EFF as _,
+            bt_max_acl_conn: CONFIG_BTDM_CTRL_BR_EDR_MAX_ACL_CONN_EFF as _,
+            bt_sco_datapath: CONFIG_BTDM_CTRL_BR_EDR_SCO_DATA_PATH_EFF as _,
+            auto_latency: BTDM_CTRL_AUTO_LATENCY_EFF != 0,
+            bt_legacy_auth_vs_evt: BTDM_CTRL_LEGACY_AUTH_VENDOR_EVT_EFF != 0,
+            bt_max_sync_conn: CONFIG_BTDM_CTRL_BR_EDR_MAX_SYNC_CONN_EFF as _,
+            ble_sca: CONFIG_BTDM_BLE_SLEEP_CLOCK_ACCURACY_INDEX_EFF as _,
+            pcm_role: CONFIG_BTDM_CTRL_PCM_ROLE_EFF as _,
+            pcm_polar: CONFIG_BTDM_CTRL_PCM_POLAR_EFF as _,
+            hli: BTDM_CTRL_HLI != 0,
+            magic: ESP_BT_CONTROLLER_CONFIG_MAGIC_VAL,
+            #[cfg(any(esp_idf_version = "5.0", esp_idf_version = "5.1"))]
+            dup_list_refresh_period: SCAN_DUPL_CACHE_REFRESH_PERIOD as u16,
+        };
+
+        #[cfg(esp32c3)]
+        let default_controller_configuration = esp_bt_controller_config_t {
+            magic: ESP_BT_CTRL_CONFIG_MAGIC_VAL,
+            version: ESP_BT_CTRL_CONFIG_VERSION,
+            controller_task_stack_size: ESP_TASK_BT_CONTROLLER_STACK as u16,
+            controller_task_prio: ESP_TASK_BT_CONTROLLER_PRIO as u8,
+            controller_task_run_cpu: CONFIG_BT_CTRL_PINNED_TO_CORE as u8,
+            bluetooth_mode: CONFIG_BT_CTRL_MODE_EFF as u8,
+            ble_max_act: CONFIG_BT_CTRL_BLE_MAX_ACT_EFF as u8,
+            sleep_mode: CONFIG_BT_CTRL_SLEEP_MODE_EFF as u8,
+            sleep_clock: CONFIG_BT_CTRL_SLEEP_CLOCK_EFF as u8,
+            ble_st_acl_tx_buf_nb: CONFIG_BT_CTRL_BLE_STATIC_ACL_TX_BUF_NB as u8,
+            ble_hw_cca_check: CONFIG_BT_CTRL_HW_CCA_EFF as u8,
+            ble_adv_dup_filt_max: CONFIG_BT_CTRL_ADV_DUP_FILT_MAX as u16,
+            coex_param_en: false,
+            ce_len_type: CONFIG_BT_CTRL_CE_LENGTH_TYPE_EFF as u8,
+            coex_use_hooks: false,
+            hci_tl_type: CONFIG_BT_CTRL_HCI_TL_EFF as u8,
+            hci_tl_funcs: std::ptr::null_mut(),
+            txant_dft: CONFIG_BT_CTRL_TX_ANTENNA_INDEX_EFF as u8,
+            rxant_dft: CONFIG_BT_CTRL_RX_ANTENNA_INDEX_EFF as u8,
+            txpwr_dft: CONFIG_BT_CTRL_DFT_TX_POWER_LEVEL_EFF as u8,
+            #[cfg(any(esp_idf_version = "5.1"))]
+            cfg_mask: CFG_MASK,
+            #[cfg(any(
+                esp_idf_version_full = "4.4.3",
+                esp_idf_version_full = "4.4.4",
+                esp_idf_version = "5.0"
+            ))]
+            cfg_mask: CFG_NASK,
+            scan_duplicate_mode: SCAN_DUPLICATE_MODE as u8,
+            scan_duplicate_type: SCAN_DUPLICATE_TYPE_VALUE as u8,
+            normal_adv_size: NORMAL_SCAN_DUPLICATE_CACHE_SIZE as u16,
+            mesh_adv_size: MESH_DUPLICATE_SCAN_CACHE_SIZE as u16,
+            coex_phy_coded_tx_rx_time_limit: CONFIG_BT_CTRL_COEX_PHY_CODED_TX_RX_TLIM_EFF as u8,
+            #[cfg(any(
+                esp_idf_version_full = "4.4.3",
+                esp_idf_version_full = "4.4.4",
+                esp_idf_version = "5.0"
+            ))]
+            hw_target_code: BLE_HW_TARGET_CODE_ESP32C3_CHIP_ECO0,
+            #[cfg(any(esp_idf_version = "5.1"))]
+            hw_target_code: BLE_HW_TARGET_CODE_CHIP_ECO0,
+            slave_ce_len_min: SLAVE_CE_LEN_MIN_DEFAULT as u8,
+            hw_recorrect_en: AGC_RECORRECT_EN as u8,
+            cca_thresh: CONFIG_BT_CTRL_HW_CCA_VAL as u8,
+            #[cfg(any(
+                esp_idf_version_full = "4.4.4",
+                esp_idf_version = "5.0",
+                esp_idf_version = "5.1"
+            ))]
+            scan_backoff_upperlimitmax: BT_CTRL_SCAN_BACKOFF_UPPERLIMITMAX as u16,
+            #[cfg(any(esp_idf_version = "5.0", esp_idf_version = "5.1"))]
+            dup_list_refresh_period: DUPL_SCAN_CACHE_REFRESH_PERIOD as u16,
+            #[cfg(any(esp_idf_version = "5.1"))]
+            ble_50_feat_supp: BT_CTRL_50_FEATURE_SUPPORT != 0,
+        };
+
+        #[cfg(esp32s3)]
+        let default_controller_configuration = esp_bt_controller_config_t {
+            magic: ESP_BT_CTRL_CONFIG_MAGIC_VAL,
+            version: ESP_BT_CTRL_CONFIG_VERSION,
+            controller_task_stack_size: ESP_TASK_BT_CONTROLLER_STACK as u16,
+            controller_task_prio: ESP_TASK_BT_CONTROLLER_PRIO as u8,
+            controller_task_run_cpu: CONFIG_BT_CTRL_PINNED_TO_CORE as u8,
+            bluetooth_mode: CONFIG_BT_CTRL_MODE_EFF as u8,
+            ble_max_act: CONFIG_BT_CTRL_BLE_MAX_ACT_EFF as u8,
+            sleep_mode: CONFIG_BT_CTRL_SLEEP_MODE_EFF as u8,
+            sleep_clock: CONFIG_BT_CTRL_SLEEP_CLOCK_EFF as u8,
+            ble_st_acl_tx_buf_nb: CONFIG_BT_CTRL_BLE_STATIC_ACL_TX_BUF_NB as u8,
+            ble_hw_cca_check: CONFIG_BT_CTRL_HW_CCA_EFF as u8,
+            ble_adv_dup_filt_max: CONFIG_BT_CTRL_ADV_DUP_FILT_MAX as u16,
+            coex_param_en: false,
+            ce_len_type: CONFIG_BT_CTRL_CE_LENGTH_TYPE_EFF as u8,
+            coex_use_hooks: false,
+            hci_tl_type: CONFIG_BT_CTRL_HCI_TL_EFF as u8,
+            hci_tl_funcs: std::ptr::null_mut(),
+            txant_dft: CONFIG_BT_CTRL_TX_ANTENNA_INDEX_EFF as u8,
+            rxant_dft: CONFIG_BT_CTRL_RX_ANTENNA_INDEX_EFF as u8,
+            txpwr_dft: CONFIG_BT_CTRL_DFT_TX_POWER_LEVEL_EFF as u8,
+            cfg_mask: CFG_MASK,
+            scan_duplicate_mode: SCAN_DUPLICATE_MODE as u8,
+            scan_duplicate_type: SCAN_DUPLICATE_TYPE_VALUE as u8,
+            normal_adv_size: NORMAL_SCAN_DUPLICATE_CACHE_SIZE as u16,
+            mesh_adv_size: MESH_DUPLICATE_SCAN_CACHE_SIZE as u16,
+            coex_phy_coded_tx_rx_time_limit: CONFIG_BT_CTRL_COEX_PHY_CODED_TX_RX_TLIM_EFF as u8,
+
+            #[cfg(any(esp_idf_version = "4.4", esp_idf_version = "5.0"))]
+            hw_target_code: BLE_HW_TARGET_CODE_ESP32S3_CHIP_ECO0,
+            #[cfg(esp_idf_version = "5.1")]
+            hw_target_code: BLE_HW_TARGET_CODE_CHIP_ECO0,
+            slave_ce_len_min: SLAVE_CE_LEN_MIN_DEFAULT as u8,
+            hw_recorrect_en: AGC_RECORRECT_EN as u8,
+            cca_thresh: CONFIG_BT_CTRL_HW_CCA_VAL as u8,
+            #[cfg(any(
+                esp_idf_version_full = "4.4.4",
+                esp_idf_version = "5.0",
+                esp_idf_version = "5.1"
+            ))]
+            scan_backoff_upperlimitmax: BT_CTRL_SCAN_BACKOFF_UPPERLIMITMAX as u16,
+            #[cfg(any(esp_idf_version = "5.0", esp_idf_version = "5.1"))]
+            dup_list_refresh_period: DUPL_SCAN_CACHE_REFRESH_PERIOD as u16,
+            #[cfg(any(esp_idf_version = "5.1"))]
+            ble_50_feat_supp: EXT_CSD_SEC_FEATURE_SUPPORT != 0,
+        };
+
+        // BLE controller initialisation.
+        unsafe {
+            // The memory can only be released once.
+            if !CLASSIC_MEMORY_RELEASED.swap(true, Ordering::SeqCst) {
+                esp!(esp_bt_controller_mem_release(
+                    esp_bt_mode_t_ESP_BT_MODE_CLASSIC_BT
+                ))?;
+            }
+
+            esp!(esp_bt_controller_init(leaky_box_raw!(
+                default_controller_configuration
+            )))?;
+            esp!(esp_bt_controller_enable(esp_bt_mode_t_ESP_BT_MODE_BLE))?;
+            esp!(esp_bluedroid_init())?;
+            esp!(esp_bluedroid_enable())?;
+            esp!(esp_ble_gatts_register_callback(gatts_callback))?;
+            esp!(esp_ble_gap_register_callback(gap_callback))?;
+        }
+
+        Ok(())
+    }
+
+    fn deinitialise(&self) -> Result<(), EspError> {
+        info!("Deinitialising BLE stack.");
+
+        unsafe {
+            esp!(esp_bluedroid_disable())?;
+            esp!(esp_bluedroid_deinit())?;
+            esp!(esp_bt_controller_disable())?;
+            esp!(esp_bt_controller_deinit())?;
+        }
+
+        Ok(())
+    }
+
+    fn app_register(&self, app_id: u16) -> Result<(), EspError> {
+        unsafe { esp!(esp_ble_gatts_app_register(app_id)) }
+    }
+
+    fn create_service(
+        &self,
+        gatts_if: esp_gatt_if_t,
+        service_id: esp_gatt_srvc_id_t,
+        num_handles: u16,
+    ) -> Result<(), EspError> {
+        unsafe {
+            esp!(esp_ble_gatts_create_service(
+                gatts_if,
+                leaky_box_raw!(service_id),
+                num_handles,
+            ))
+        }
+    }
+
+    fn create_attr_tab(
+        &self,
+        gatts_if: esp_gatt_if_t,
+        table: &[esp_gatts_attr_db_t],
+        service_instance: u8,
+    ) -> Result<(), EspError> {
+        // Bluedroid copies the table, but reads the UUIDs and the values it points to later, from its own task.
+        let table: Vec<esp_gatts_attr_db_t> = table
+            .iter()
+            .map(|entry| {
+                let mut entry = *entry;
+                let description = &mut entry.att_desc;
+                unsafe {
+                    description.uuid_p =
+                        leak_bytes(description.uuid_p, usize::from(description.uuid_length));
+                    description.value =
+                        leak_bytes(description.value, usize::from(description.length));
+                }
+                entry
+            })
+            .collect();
+
+        let count = table
+            .len()
+            .try_into()
+            .map_err(|_| EspError::from_infallible::<ESP_ERR_INVALID_ARG>())?;
+
+        unsafe {
+            esp!(esp_ble_gatts_create_attr_tab(
+                table.as_ptr(),
+                gatts_if,
+                count,
+                service_instance,
+            ))
+        }
+    }
+
+    fn start_service(&self, service_handle: u16) -> Result<(), EspError> {
+        unsafe { esp!(esp_ble_gatts_start_service(service_handle)) }
+    }
+
+    fn stop_service(&self, service_handle: u16) -> Result<(), EspError> {
+        unsafe { esp!(esp_ble_gatts_stop_service(service_handle)) }
+    }
+
+    fn delete_service(&self, service_handle: u16) -> Result<(), EspError> {
+        unsafe { esp!(esp_ble_gatts_delete_service(service_handle)) }
+    }
+
+    fn send_service_change_indication(
+        &self,
+        gatts_if: esp_gatt_if_t,
+        address: Option<esp_bd_addr_t>,
+    ) -> Result<(), EspError> {
+        // A null address indicates the change to every connected client.
+        let mut address = address;
+        let address = address
+            .as_mut()
+            .map_or(std::ptr::null_mut(), |address| address.as_mut_ptr());
+
+        unsafe {
+            esp!(esp_ble_gatts_send_service_change_indication(
+                gatts_if, address
+            ))
+        }
+    }
+
+    fn add_included_service(
+        &self,
+        service_handle: u16,
+        included_service_handle: u16,
+    ) -> Result<(), EspError> {
+        unsafe {
+            esp!(esp_ble_gatts_add_included_service(
+                service_handle,
+                included_service_handle
+            ))
+        }
+    }
+
+    fn add_char(
+        &self,
+        service_handle: u16,
+        uuid: esp_bt_uuid_t,
+        permissions: esp_gatt_perm_t,
+        properties: esp_gatt_char_prop_t,
+        value: esp_attr_value_t,
+        control: esp_attr_control_t,
+    ) -> Result<(), EspError> {
+        unsafe {
+            esp!(esp_ble_gatts_add_char(
+                service_handle,
+                leaky_box_raw!(uuid),
+                permissions,
+                properties,
+                leaky_box_raw!(value),
+                leaky_box_raw!(control),
+            ))
+        }
+    }
+
+    fn add_char_descr(
+        &self,
+        service_handle: u16,
+        uuid: esp_bt_uuid_t,
+        permissions: esp_gatt_perm_t,
+        value: esp_attr_value_t,
+        control: esp_attr_control_t,
+    ) -> Result<(), EspError> {
+        unsafe {
+            esp!(esp_ble_gatts_add_char_descr(
+                service_handle,
+                leaky_box_raw!(uuid),
+                permissions,
+                leaky_box_raw!(value),
+                leaky_box_raw!(control),
+            ))
+        }
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn set_attr_value(&self, attribute_handle: u16, value: &[u8]) -> Result<(), EspError> {
+        unsafe {
+            esp!(esp_ble_gatts_set_attr_value(
+                attribute_handle,
+                value.len() as u16,
+                value.as_ptr()
+            ))
+        }
+    }
+
+    fn get_attr_value(&self, attribute_handle: u16) -> Result<Vec<u8>, EspError> {
+        let mut value: *const u8 = std::ptr::null();
+        let mut len = 0;
+
+        unsafe {
+            esp!(esp_ble_gatts_get_attr_value(
+                attribute_handle,
+                &mut len,
+                &mut value,
+            ))?;
+
+            Ok(std::slice::from_raw_parts(value, len as usize).to_vec())
+        }
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn send_indicate(
+        &self,
+        gatts_if: esp_gatt_if_t,
+        conn_id: u16,
+        attribute_handle: u16,
+        value: &[u8],
+        need_confirm: bool,
+    ) -> Result<(), EspError> {
+        let mut value = value.to_vec();
+
+        unsafe {
+            esp!(esp_ble_gatts_send_indicate(
+                gatts_if,
+                conn_id,
+                attribute_handle,
+                value.len() as u16,
+                value.as_mut_ptr(),
+                need_confirm
+            ))
+        }
+    }
+
+    fn send_response(
+        &self,
+        gatts_if: esp_gatt_if_t,
+        conn_id: u16,
+        trans_id: u32,
+        status: esp_gatt_status_t,
+        response: &mut esp_gatt_rsp_t,
+    ) -> Result<(), EspError> {
+        unsafe {
+            esp!(esp_ble_gatts_send_response(
+                gatts_if, conn_id, trans_id, status, response
+            ))
+        }
+    }
+
+    fn set_device_name(&self, name: &str) -> Result<(), EspError> {
+        let Ok(name) = std::ffi::CString::new(name) else {
+            return Err(EspError::from_infallible::<ESP_ERR_INVALID_ARG>());
+        };
+
+        unsafe { esp!(esp_ble_gap_set_device_name(name.as_ptr())) }
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn config_adv_data_raw(&self, data: &[u8]) -> Result<(), EspError> {
+        // The stack copies the data.
+        let mut data = data.to_vec();
+        unsafe {
+            esp!(esp_ble_gap_config_adv_data_raw(
+                data.as_mut_ptr(),
+                data.len() as u32
+            ))
+        }
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn config_scan_rsp_data_raw(&self, data: &[u8]) -> Result<(), EspError> {
+        // The stack copies the data.
+        let mut data = data.to_vec();
+        unsafe {
+            esp!(esp_ble_gap_config_scan_rsp_data_raw(
+                data.as_mut_ptr(),
+                data.len() as u32
+            ))
+        }
+    }
+
+    fn start_advertising(&self, parameters: &mut esp_ble_adv_params_t) -> Result<(), EspError> {
+        unsafe { esp!(esp_ble_gap_start_advertising(parameters)) }
+    }
+
+    fn stop_advertising(&self) -> Result<(), EspError> {
+        unsafe { esp!(esp_ble_gap_stop_advertising()) }
+    }
+
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    fn ext_adv_set_params(
+        &self,
+        instance: u8,
+        parameters: &esp_ble_gap_ext_adv_params_t,
+    ) -> Result<(), EspError> {
+        unsafe { esp!(esp_ble_gap_ext_adv_set_params(instance, parameters)) }
+    }
+
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    #[allow(clippy::cast_possible_truncation)]
+    fn config_ext_adv_data_raw(&self, instance: u8, data: &[u8]) -> Result<(), EspError> {
+        unsafe {
+            esp!(esp_ble_gap_config_ext_adv_data_raw(
+                instance,
+                data.len() as u16,
+                data.as_ptr()
+            ))
+        }
+    }
+
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    #[allow(clippy::cast_possible_truncation)]
+    fn config_ext_scan_rsp_data_raw(&self, instance: u8, data: &[u8]) -> Result<(), EspError> {
+        unsafe {
+            esp!(esp_ble_gap_config_ext_scan_rsp_data_raw(
+                instance,
+                data.len() as u16,
+                data.as_ptr()
+            ))
+        }
+    }
+
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    #[allow(clippy::cast_possible_truncation)]
+    fn ext_adv_start(&self, sets: &[esp_ble_gap_ext_adv_t]) -> Result<(), EspError> {
+        unsafe { esp!(esp_ble_gap_ext_adv_start(sets.len() as u8, sets.as_ptr())) }
+    }
+
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    #[allow(clippy::cast_possible_truncation)]
+    fn ext_adv_stop(&self, instances: &[u8]) -> Result<(), EspError> {
+        unsafe {
+            esp!(esp_ble_gap_ext_adv_stop(
+                instances.len() as u8,
+                instances.as_ptr()
+            ))
+        }
+    }
+
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    fn ext_adv_set_remove(&self, instance: u8) -> Result<(), EspError> {
+        unsafe { esp!(esp_ble_gap_ext_adv_set_remove(instance)) }
+    }
+
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    fn periodic_adv_set_params(
+        &self,
+        instance: u8,
+        parameters: esp_ble_gap_periodic_adv_params_t,
+    ) -> Result<(), EspError> {
+        unsafe { esp!(esp_ble_gap_periodic_adv_set_params(instance, &parameters)) }
+    }
+
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    #[allow(clippy::cast_possible_truncation)]
+    fn config_periodic_adv_data_raw(&self, instance: u8, data: &[u8]) -> Result<(), EspError> {
+        // Newer stacks can update only the data identifier, to signal unchanged data.
+        #[cfg(esp_idf_bt_ble_feat_periodic_adv_enh)]
+        let result = unsafe {
+            esp_ble_gap_config_periodic_adv_data_raw(
+                instance,
+                data.len() as u16,
+                data.as_ptr(),
+                false,
+            )
+        };
+
+        #[cfg(not(esp_idf_bt_ble_feat_periodic_adv_enh))]
+        let result = unsafe {
+            esp_ble_gap_config_periodic_adv_data_raw(instance, data.len() as u16, data.as_ptr())
+        };
+
+        esp!(result)
+    }
+
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    fn periodic_adv_start(&self, instance: u8) -> Result<(), EspError> {
+        // Newer stacks can include the advertising data info in the periodic packets.
+        #[cfg(esp_idf_bt_ble_feat_periodic_adv_enh)]
+        let result = unsafe { esp_ble_gap_periodic_adv_start(instance, false) };
+
+        #[cfg(not(esp_idf_bt_ble_feat_periodic_adv_enh))]
+        let result = unsafe { esp_ble_gap_periodic_adv_start(instance) };
+
+        esp!(result)
+    }
+
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    fn periodic_adv_stop(&self, instance: u8) -> Result<(), EspError> {
+        unsafe { esp!(esp_ble_gap_periodic_adv_stop(instance)) }
+    }
+
+    fn disconnect_device(&self, mut address: esp_bd_addr_t) -> Result<(), EspError> {
+        unsafe { esp!(esp_ble_gap_disconnect(address.as_mut_ptr())) }
+    }
+
+    fn read_rssi(&self, mut address: esp_bd_addr_t) -> Result<(), EspError> {
+        unsafe { esp!(esp_ble_gap_read_rssi(address.as_mut_ptr())) }
+    }
+
+    fn set_local_mtu(&self, mtu: u16) -> Result<(), EspError> {
+        unsafe { esp!(esp_ble_gatt_set_local_mtu(mtu)) }
+    }
+
+    fn set_pkt_data_len(&self, mut address: esp_bd_addr_t, tx_octets: u16) -> Result<(), EspError> {
+        unsafe {
+            esp!(esp_ble_gap_set_pkt_data_len(
+                address.as_mut_ptr(),
+                tx_octets
+            ))
+        }
+    }
+
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    #[allow(clippy::cast_possible_truncation)]
+    fn set_preferred_phy(
+        &self,
+        mut address: esp_bd_addr_t,
+        tx_phys: u8,
+        rx_phys: u8,
+    ) -> Result<(), EspError> {
+        unsafe {
+            esp!(esp_ble_gap_set_preferred_phy(
+                address.as_mut_ptr(),
+                0,
+                tx_phys,
+                rx_phys,
+                ESP_BLE_GAP_PHY_OPTIONS_NO_PREF as esp_ble_gap_prefer_phy_options_t
+            ))
+        }
+    }
+
+    fn update_conn_params(&self, params: &esp_ble_conn_update_params_t) -> Result<(), EspError> {
+        let mut params = *params;
+        unsafe { esp!(esp_ble_gap_update_conn_params(&mut params)) }
+    }
+
+    #[cfg(feature = "gap-preferred-parameters")]
+    fn set_preferred_conn_params(
+        &self,
+        params: &esp_ble_conn_update_params_t,
+    ) -> Result<(), EspError> {
+        let mut value = GapPreferredParameters {
+            int_min: params.min_int,
+            int_max: params.max_int,
+            latency: params.latency,
+            sp_tout: params.timeout,
+        };
+
+        // The stack copies the value.
+        unsafe { GAP_BleAttrDBUpdate(PREFERRED_CONNECTION_PARAMETERS_UUID, &mut value) };
+        Ok(())
+    }
+
+    #[cfg(not(feature = "gap-preferred-parameters"))]
+    fn set_preferred_conn_params(
+        &self,
+        _params: &esp_ble_conn_update_params_t,
+    ) -> Result<(), EspError> {
+        Err(EspError::from_infallible::<ESP_ERR_NOT_SUPPORTED>())
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn set_security_param(&self, param: esp_ble_sm_param_t, value: &[u8]) -> Result<(), EspError> {
+        unsafe {
+            esp!(esp_ble_gap_set_security_param(
+                param,
+                value.as_ptr() as *mut std::ffi::c_void,
+                value.len() as u8
+            ))
+        }
+    }
+
+    fn security_response(&self, mut address: esp_bd_addr_t, accept: bool) -> Result<(), EspError> {
+        unsafe { esp!(esp_ble_gap_security_rsp(address.as_mut_ptr(), accept)) }
+    }
+
+    fn passkey_reply(
+        &self,
+        mut address: esp_bd_addr_t,
+        accept: bool,
+        passkey: u32,
+    ) -> Result<(), EspError> {
+        unsafe { esp!(esp_ble_passkey_reply(address.as_mut_ptr(), accept, passkey)) }
+    }
+
+    fn confirm_reply(&self, mut address: esp_bd_addr_t, accept: bool) -> Result<(), EspError> {
+        unsafe { esp!(esp_ble_confirm_reply(address.as_mut_ptr(), accept)) }
+    }
+
+    fn remove_bond_device(&self, mut address: esp_bd_addr_t) -> Result<(), EspError> {
+        unsafe { esp!(esp_ble_remove_bond_device(address.as_mut_ptr())) }
+    }
+
+    fn bonded_devices(&self) -> Result<Vec<esp_bd_addr_t>, EspError> {
+        Ok(bond_devices()?
+            .iter()
+            .map(|device| device.bd_addr)
+            .collect())
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn identity_keys(&self) -> Result<Vec<(esp_bd_addr_t, [u8; 16])>, EspError> {
+        Ok(bond_devices()?
+            .iter()
+            .filter(|device| device.bond_key.key_mask & ESP_LE_KEY_PID as u8 != 0)
+            .map(|device| (device.bd_addr, device.bond_key.pid_key.irk))
+            .collect())
+    }
+
+    fn storage_get(&self, key: &str) -> Result<Option<Vec<u8>>, EspError> {
+        with_storage(|storage| {
+            let Some(len) = storage.len(key)? else {
+                return Ok(None);
+            };
+
+            let mut buffer = vec![0u8; len];
+            Ok(storage.get_raw(key, &mut buffer)?.map(<[u8]>::to_vec))
+        })
+    }
+
+    fn storage_set(&self, key: &str, value: &[u8]) -> Result<(), EspError> {
+        with_storage(|storage| {
+            storage.set_raw(key, value)?;
+            Ok(())
+        })
+    }
+
+    fn storage_remove(&self, key: &str) -> Result<(), EspError> {
+        with_storage(|storage| {
+            storage.remove(key)?;
+            Ok(())
+        })
+    }
+
+    fn storage_clear(&self) -> Result<(), EspError> {
+        // The NVS wrapper cannot erase a namespace: it is opened a second time.
+        with_storage(|_| {
+            let namespace = CString::new(NAMESPACE).unwrap();
+            let mut handle: nvs_handle_t = 0;
+            esp!(unsafe {
+                nvs_open(
+                    namespace.as_ptr(),
+                    nvs_open_mode_t_NVS_READWRITE,
+                    &mut handle,
+                )
+            })?;
+
+            let result = esp!(unsafe { nvs_erase_all(handle) })
+                .and_then(|()| esp!(unsafe { nvs_commit(handle) }));
+            unsafe { nvs_close(handle) };
+
+            result
+        })
+    }
+}
@@ -0,0 +1,283 @@
+//! Platform abstraction for the Bluedroid stack.
+//!
+//! Every call that the GATT server makes into Bluedroid goes through the [`Backend`] trait.
+//! On ESP-IDF targets, the backend forwards calls to `esp-idf-sys`.
+//! On any other target, the backend is an in-process [simulated stack](simulated::SimulatedStack)
+//! that assigns handles, emits the same events and can be driven by tests acting as a client.
+
+use crate::sys::{
+    esp_attr_control_t, esp_attr_value_t, esp_bd_addr_t, esp_ble_adv_params_t,
+    esp_ble_conn_update_params_t, esp_ble_sm_param_t, esp_bt_uuid_t, esp_gap_ble_cb_t,
+    esp_gatt_char_prop_t, esp_gatt_if_t, esp_gatt_perm_t, esp_gatt_rsp_t, esp_gatt_srvc_id_t,
+    esp_gatt_status_t, esp_gatts_attr_db_t, esp_gatts_cb_t, EspError,
+};
+
+#[cfg(esp_idf_bt_ble_50_features_supported)]
+use crate::sys::{
+    esp_ble_gap_ext_adv_params_t, esp_ble_gap_ext_adv_t, esp_ble_gap_periodic_adv_params_t,
+};
+
+#[cfg(target_os = "espidf")]
+mod esp;
+
+#[cfg(not(target_os = "espidf"))]
+pub mod simulated;
+
+#[cfg(not(target_os = "espidf"))]
+pub mod sys;
+
+/// The calls that the GATT server makes into the Bluetooth stack.
+///
+/// Completion of every asynchronous operation is reported through the callbacks
+/// passed to [`Backend::initialise`], exactly like Bluedroid does.
+pub(crate) trait Backend: Send + Sync {
+    /// Initialises NVS, the controller and Bluedroid, and registers the event callbacks.
+    fn initialise(
+        &self,
+        gatts_callback: esp_gatts_cb_t,
+        gap_callback: esp_gap_ble_cb_t,
+    ) -> Result<(), EspError>;
+
+    /// Disables and deinitialises Bluedroid and the controller.
+    fn deinitialise(&self) -> Result<(), EspError>;
+
+    /// Registers a GATT application (profile).
+    fn app_register(&self, app_id: u16) -> Result<(), EspError>;
+
+    /// Creates a service on a registered interface.
+    fn create_service(
+        &self,
+        gatts_if: esp_gatt_if_t,
+        service_id: esp_gatt_srvc_id_t,
+        num_handles: u16,
+    ) -> Result<(), EspError>;
+
+    /// Creates a service with all its attributes, from a table that starts with the service declaration.
+    ///
+    /// The UUIDs and the values that the table points to only need to be valid during the call.
+    fn create_attr_tab(
+        &self,
+        gatts_if: esp_gatt_if_t,
+        table: &[esp_gatts_attr_db_t],
+        service_instance: u8,
+    ) -> Result<(), EspError>;
+
+    /// Starts a created service.
+    fn start_service(&self, service_handle: u16) -> Result<(), EspError>;
+
+    /// Stops a started service, hiding its attributes from the clients.
+    fn stop_service(&self, service_handle: u16) -> Result<(), EspError>;
+
+    /// Deletes a service and its attributes.
+    fn delete_service(&self, service_handle: u16) -> Result<(), EspError>;
+
+    /// Indicates the Service Changed characteristic of the GATT service to a connected client,
+    /// or to all of them if no address is given.
+    fn send_service_change_indication(
+        &self,
+        gatts_if: esp_gatt_if_t,
+        address: Option<esp_bd_addr_t>,
+    ) -> Result<(), EspError>;
+
+    /// Adds an include declaration, pointing to another service, to a service.
+    fn add_included_service(
+        &self,
+        service_handle: u16,
+        included_service_handle: u16,
+    ) -> Result<(), EspError>;
+
+    /// Adds a characteristic to a service.
+    fn add_char(
+        &self,
+        service_handle: u16,
+        uuid: esp_bt_uuid_t,
+        permissions: esp_gatt_perm_t,
+        properties: esp_gatt_char_prop_t,
+        value: esp_attr_value_t,
+        control: esp_attr_control_t,
+    ) -> Result<(), EspError>;
+
+    /// Adds a descriptor to the last characteristic of a service.
+    fn add_char_descr(
+        &self,
+        service_handle: u16,
+        uuid: esp_bt_uuid_t,
+        permissions: esp_gatt_perm_t,
+        value: esp_attr_value_t,
+        control: esp_attr_control_t,
+    ) -> Result<(), EspError>;
+
+    /// Sets the value of an attribute in the stack's database.
+    fn set_attr_value(&self, attribute_handle: u16, value: &[u8]) -> Result<(), EspError>;
+
+    /// Gets the value of an attribute from the stack's database.
+    fn get_attr_value(&self, attribute_handle: u16) -> Result<Vec<u8>, EspError>;
+
+    /// Sends a notification or an indication to a connected client.
+    fn send_indicate(
+        &self,
+        gatts_if: esp_gatt_if_t,
+        conn_id: u16,
+        attribute_handle: u16,
+        value: &[u8],
+        need_confirm: bool,
+    ) -> Result<(), EspError>;
+
+    /// Responds to a read or write request.
+    fn send_response(
+        &self,
+        gatts_if: esp_gatt_if_t,
+        conn_id: u16,
+        trans_id: u32,
+        status: esp_gatt_status_t,
+        response: &mut esp_gatt_rsp_t,
+    ) -> Result<(), EspError>;
+
+    /// Sets the GAP device name.
+    fn set_device_name(&self, name: &str) -> Result<(), EspError>;
+
+    /// Configures the advertising data, encoded as AD structures.
+    fn config_adv_data_raw(&self, data: &[u8]) -> Result<(), EspError>;
+
+    /// Configures the scan response data, encoded as AD structures.
+    fn config_scan_rsp_data_raw(&self, data: &[u8]) -> Result<(), EspError>;
+
+    /// Starts advertising.
+    fn start_advertising(&self, parameters: &mut esp_ble_adv_params_t) -> Result<(), EspError>;
+
+    /// Stops advertising.
+    fn stop_advertising(&self) -> Result<(), EspError>;
+
+    /// Sets the parameters of an extended advertising set, creating it if needed.
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    fn ext_adv_set_params(
+        &self,
+        instance: u8,
+        parameters: &esp_ble_gap_ext_adv_params_t,
+    ) -> Result<(), EspError>;
+
+    /// Configures the advertising data of an extended advertising set.
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    fn config_ext_adv_data_raw(&self, instance: u8, data: &[u8]) -> Result<(), EspError>;
+
+    /// Configures the scan response data of an extended advertising set.
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    fn config_ext_scan_rsp_data_raw(&self, instance: u8, data: &[u8]) -> Result<(), EspError>;
+
+    /// Starts extended advertising sets, each with its duration and event limit.
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    fn ext_adv_start(&self, sets: &[esp_ble_gap_ext_adv_t]) -> Result<(), EspError>;
+
+    /// Stops extended advertising sets.
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    fn ext_adv_stop(&self, instances: &[u8]) -> Result<(), EspError>;
+
+    /// Stops and removes an extended advertising set.
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    fn ext_adv_set_remove(&self, instance: u8) -> Result<(), EspError>;
+
+    /// Sets the periodic advertising parameters of an extended advertising set.
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    fn periodic_adv_set_params(
+        &self,
+        instance: u8,
+        parameters: esp_ble_gap_periodic_adv_params_t,
+    ) -> Result<(), EspError>;
+
+    /// Configures the periodic advertising data of an extended advertising set.
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    fn config_periodic_adv_data_raw(&self, instance: u8, data: &[u8]) -> Result<(), EspError>;
+
+    /// Starts periodic advertising on an extended advertising set.
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    fn periodic_adv_start(&self, instance: u8) -> Result<(), EspError>;
+
+    /// Stops periodic advertising on an extended advertising set.
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    fn periodic_adv_stop(&self, instance: u8) -> Result<(), EspError>;
+
+    /// Terminates the connection with a device.
+    fn disconnect_device(&self, address: esp_bd_addr_t) -> Result<(), EspError>;
+
+    /// Reads the signal strength of the connection with a device.
+    fn read_rssi(&self, address: esp_bd_addr_t) -> Result<(), EspError>;
+
+    /// Requests new parameters for the connection with a device.
+    fn update_conn_params(&self, params: &esp_ble_conn_update_params_t) -> Result<(), EspError>;
+
+    /// Sets the value of the Peripheral Preferred Connection Parameters characteristic
+    /// of the GAP service. The address is not used.
+    ///
+    /// It must be called from the events of the stack. On ESP-IDF, it fails with `ESP_ERR_NOT_SUPPORTED`
+    /// without the `gap-preferred-parameters` feature.
+    fn set_preferred_conn_params(
+        &self,
+        params: &esp_ble_conn_update_params_t,
+    ) -> Result<(), EspError>;
+
+    /// Sets the largest MTU accepted in MTU exchanges.
+    fn set_local_mtu(&self, mtu: u16) -> Result<(), EspError>;
+
+    /// Requests a maximum data length for the packets sent to a device.
+    fn set_pkt_data_len(&self, address: esp_bd_addr_t, tx_octets: u16) -> Result<(), EspError>;
+
+    /// Requests the PHYs of the connection with a device, as masks of `ESP_BLE_GAP_PHY_*_PREF_MASK`.
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    fn set_preferred_phy(
+        &self,
+        address: esp_bd_addr_t,
+        tx_phys: u8,
+        rx_phys: u8,
+    ) -> Result<(), EspError>;
+
+    /// Sets a parameter of the security manager.
+    fn set_security_param(&self, param: esp_ble_sm_param_t, value: &[u8]) -> Result<(), EspError>;
+
+    /// Accepts or rejects a pairing request.
+    fn security_response(&self, address: esp_bd_addr_t, accept: bool) -> Result<(), EspError>;
+
+    /// Replies to a passkey request, with the passkey entered by the user.
+    fn passkey_reply(
+        &self,
+        address: esp_bd_addr_t,
+        accept: bool,
+        passkey: u32,
+    ) -> Result<(), EspError>;
+
+    /// Replies to a numeric comparison request.
+    fn confirm_reply(&self, address: esp_bd_addr_t, accept: bool) -> Result<(), EspError>;
+
+    /// Removes a bonded device.
+    fn remove_bond_device(&self, address: esp_bd_addr_t) -> Result<(), EspError>;
+
+    /// Returns the identity addresses of the bonded devices.
+    fn bonded_devices(&self) -> Result<Vec<esp_bd_addr_t>, EspError>;
+
+    /// Returns the identity addresses of the bonded devices that distributed an IRK,
+    /// with the IRK stored least significant byte first.
+    fn identity_keys(&self) -> Result<Vec<(esp_bd_addr_t, [u8; 16])>, EspError>;
+
+    /// Reads a value from the persistent storage.
+    fn storage_get(&self, key: &str) -> Result<Option<Vec<u8>>, EspError>;
+
+    /// Writes a value to the persistent storage.
+    fn storage_set(&self, key: &str, value: &[u8]) -> Result<(), EspError>;
+
+    /// Removes a value from the persistent storage, if present.
+    fn storage_remove(&self, key: &str) -> Result<(), EspError>;
+
+    /// Removes all the values from the persistent storage.
+    fn storage_clear(&self) -> Result<(), EspError>;
+}
+
+/// Returns the backend for the current target.
+#[cfg(target_os = "espidf")]
+pub(crate) fn backend() -> &'static dyn Backend {
+    &esp::EspBackend
+}
+
+/// Returns the backend for the current target.
+#[cfg(not(target_os = "espidf"))]
+pub(crate) fn backend() -> &'static dyn Backend {
+    simulated::SimulatedStack::global()
+}
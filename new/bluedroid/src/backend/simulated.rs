@@ -0,0 +1,3538 @@
+//! An in-process simulation of the Bluedroid stack.
+//!
+//! The simulated stack implements the same asynchronous contract as Bluedroid:
+//! every call is acknowledged by an event that is delivered from a dedicated thread
+//! through the callbacks registered by the GATT server. Handles are assigned in the same
+//! way as the real stack, so a [`GattServer`] can be started, interrogated and driven on a host.
+//!
+//! Tests can act as a client by connecting, pairing, reading, writing, subscribing and collecting
+//! the notifications and indications sent by the server.
+//!
+//! Like Bluedroid with robust caching, the stack provides a Generic Attribute service with
+//! the Service Changed, Client Supported Features and Database Hash characteristics.
+//!
+//! # Notes
+//!
+//! Client operations block until the server answers, so they must not be called
+//! while holding the lock of the [`GattServer`], nor from a server callback.
+//!
+//! [`GattServer`]: crate::gatt_server::GattServer
+
+#![allow(clippy::cast_possible_truncation)]
+
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    sync::{Condvar, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use lazy_static::lazy_static;
+use log::{debug, warn};
+
+use crate::{
+    backend::Backend,
+    sys::*,
+    utilities::{private_address, BleUuid},
+};
+
+mod cmac;
+
+/// How long a client operation waits for the server to respond.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The default ATT MTU of a new connection.
+const DEFAULT_MTU: u16 = 23;
+
+/// The largest ATT MTU, accepted in MTU exchanges unless the server sets a smaller one.
+const MAX_MTU: u16 = 517;
+
+/// The largest data length of the packets of a connection, in bytes.
+const MAX_DATA_LENGTH: u16 = 251;
+
+/// The parameters of a new connection: a 30 ms interval and a 4 s supervision timeout.
+const DEFAULT_CONNECTION_PARAMETERS: esp_gatt_conn_params_t = esp_gatt_conn_params_t {
+    interval: 0x18,
+    latency: 0,
+    timeout: 0x190,
+};
+
+/// The signal strength of a new connection, in dBm.
+const DEFAULT_RSSI: i8 = -60;
+
+/// The maximum length of legacy advertising and scan response data.
+const LEGACY_PAYLOAD_LENGTH: usize = 31;
+
+/// The maximum length of extended advertising, scan response and periodic advertising data.
+const EXTENDED_PAYLOAD_LENGTH: usize = 1650;
+
+/// The number of advertising sets supported by the controller.
+const MAX_ADVERTISING_SETS: u8 = 10;
+
+/// The first attribute handle available to applications.
+///
+/// Bluedroid reserves the lower handles for the built-in GAP and GATT services.
+const FIRST_APPLICATION_HANDLE: u16 = 40;
+
+/// The handles of the Generic Attribute service, that starts at the first handle.
+const SERVICE_CHANGED_HANDLE: u16 = 3;
+const SERVICE_CHANGED_CCCD_HANDLE: u16 = 4;
+const CLIENT_SUPPORTED_FEATURES_HANDLE: u16 = 6;
+const DATABASE_HASH_HANDLE: u16 = 8;
+
+/// The first interface assigned to a registered application.
+const FIRST_INTERFACE: esp_gatt_if_t = 3;
+
+/// The passkey displayed by the server when it has no static passkey.
+const RANDOM_PASSKEY: u32 = 123_456;
+
+/// The SMP pairing failure reasons reported by the simulated stack.
+const SMP_PASSKEY_ENTRY_FAILED: u8 = 0x01;
+const SMP_AUTHENTICATION_REQUIREMENTS: u8 = 0x03;
+const SMP_PAIRING_NOT_SUPPORTED: u8 = 0x05;
+const SMP_NUMERIC_COMPARISON_FAILED: u8 = 0x0C;
+
+/// The kind of an attribute in the simulated database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttributeKind {
+    /// A primary service declaration.
+    PrimaryService,
+    /// A secondary service declaration.
+    SecondaryService,
+    /// An include declaration, pointing to another service.
+    Include,
+    /// A characteristic declaration.
+    CharacteristicDeclaration,
+    /// A characteristic value.
+    CharacteristicValue,
+    /// A characteristic descriptor.
+    Descriptor,
+}
+
+/// An attribute in the simulated database.
+#[derive(Debug, Clone)]
+pub struct SimulatedAttribute {
+    /// The attribute handle.
+    pub handle: u16,
+    /// The attribute type.
+    pub uuid: BleUuid,
+    /// The kind of attribute.
+    pub kind: AttributeKind,
+    /// The raw access permissions.
+    pub permissions: esp_gatt_perm_t,
+    /// The raw characteristic properties, for characteristic values.
+    pub properties: esp_gatt_char_prop_t,
+    /// The current value stored by the stack.
+    pub value: Vec<u8>,
+    /// The maximum length of the value.
+    pub max_length: u16,
+    /// Whether the stack answers requests for this attribute by itself.
+    pub automatic_response: bool,
+}
+
+/// A notification or an indication received by a simulated client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notification {
+    /// The handle of the characteristic value.
+    pub handle: u16,
+    /// The notified value.
+    pub value: Vec<u8>,
+    /// Whether this was an indication.
+    pub indication: bool,
+}
+
+/// An extended advertising set configured by the server.
+#[derive(Debug, Clone, Default)]
+pub struct SimulatedAdvertisingSet {
+    /// The extended advertising parameters.
+    pub parameters: esp_ble_gap_ext_adv_params_t,
+    /// The advertising data.
+    pub advertising_data: Vec<u8>,
+    /// The scan response data.
+    pub scan_response: Vec<u8>,
+    /// The instance, duration and event limit with which the set was last started.
+    pub start_parameters: esp_ble_gap_ext_adv_t,
+    /// Whether the set is advertising.
+    pub advertising: bool,
+    /// The periodic advertising parameters, if set.
+    pub periodic_parameters: Option<esp_ble_gap_periodic_adv_params_t>,
+    /// The periodic advertising data.
+    pub periodic_data: Vec<u8>,
+    /// Whether periodic advertising is enabled.
+    pub periodic_advertising: bool,
+}
+
+impl SimulatedAdvertisingSet {
+    /// Whether the set has the given advertising event properties.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn has_property(&self, property: i32) -> bool {
+        self.parameters.type_ & property as esp_ble_ext_adv_type_mask_t != 0
+    }
+}
+
+/// An event waiting to be delivered to the registered callbacks.
+enum Event {
+    Gatts {
+        event: esp_gatts_cb_event_t,
+        gatts_if: esp_gatt_if_t,
+        param: esp_ble_gatts_cb_param_t,
+        // Keeps alive the buffer pointed by `param`, if any.
+        _payload: Payload,
+    },
+    Gap {
+        event: esp_gap_ble_cb_event_t,
+        param: esp_ble_gap_cb_param_t,
+    },
+}
+
+// The raw pointers in the event parameters only point into the event's own payload.
+unsafe impl Send for Event {}
+
+/// A buffer pointed by the parameters of an event, only kept alive until the event is dispatched.
+#[allow(dead_code)]
+enum Payload {
+    Bytes(Vec<u8>),
+    Handles(Vec<u16>),
+}
+
+struct SimulatedService {
+    interface: esp_gatt_if_t,
+    handle: u16,
+    end_handle: u16,
+    next_handle: u16,
+    last_characteristic: Option<u16>,
+    /// Whether the attributes are visible to the clients.
+    started: bool,
+}
+
+struct SimulatedConnection {
+    address: esp_bd_addr_t,
+    mtu: u16,
+    parameters: esp_gatt_conn_params_t,
+    rssi: i8,
+    /// Whether the link is encrypted.
+    encrypted: bool,
+    /// Whether the keys were exchanged in a pairing protected against MITM attacks.
+    authenticated: bool,
+    notifications: Vec<Notification>,
+    prepared_writes: Vec<PreparedWrite>,
+    /// The value of the CCCD of the Service Changed characteristic.
+    service_changed_cccd: u16,
+    /// The value of the Client Supported Features characteristic.
+    client_features: u8,
+}
+
+/// The way a pairing is authenticated, chosen from the input and output capabilities.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PairingMethod {
+    JustWorks,
+    ServerDisplays,
+    ServerEnters,
+    NumericComparison,
+}
+
+impl PairingMethod {
+    /// Chooses the method for LE Secure Connections, with a client that requests MITM protection.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn choose(server: esp_ble_io_cap_t, client: esp_ble_io_cap_t) -> Self {
+        let [out, io, input, kbdisp] = [
+            ESP_IO_CAP_OUT as esp_ble_io_cap_t,
+            ESP_IO_CAP_IO as esp_ble_io_cap_t,
+            ESP_IO_CAP_IN as esp_ble_io_cap_t,
+            ESP_IO_CAP_KBDISP as esp_ble_io_cap_t,
+        ];
+
+        match (server, client) {
+            (s, c) if s == out && (c == input || c == kbdisp) => Self::ServerDisplays,
+            (s, c) if (s == io || s == kbdisp) && c == input => Self::ServerDisplays,
+            (s, c) if (s == io || s == kbdisp) && (c == io || c == kbdisp) => {
+                Self::NumericComparison
+            }
+            (s, c) if s == input && c != ESP_IO_CAP_NONE as esp_ble_io_cap_t => Self::ServerEnters,
+            (s, c) if s == kbdisp && c == out => Self::ServerEnters,
+            _ => Self::JustWorks,
+        }
+    }
+}
+
+/// A pairing in progress with a client.
+struct SimulatedPairing {
+    method: PairingMethod,
+    /// The passkey that the client enters or displays.
+    passkey: u32,
+}
+
+/// A part of a long write queued by a client.
+struct PreparedWrite {
+    handle: u16,
+    offset: u16,
+    value: Vec<u8>,
+    /// The interface answering for the attribute, or `None` if the stack stores it.
+    interface: Option<esp_gatt_if_t>,
+}
+
+struct State {
+    gatts_callback: esp_gatts_cb_t,
+    gap_callback: esp_gap_ble_cb_t,
+    dispatcher_running: bool,
+    dispatching: bool,
+    queue: VecDeque<Event>,
+    interfaces: Vec<esp_gatt_if_t>,
+    next_handle: u16,
+    next_conn_id: u16,
+    next_trans_id: u32,
+    services: Vec<SimulatedService>,
+    attributes: BTreeMap<u16, SimulatedAttribute>,
+    connections: HashMap<u16, SimulatedConnection>,
+    responses: HashMap<u32, (esp_gatt_status_t, Vec<u8>)>,
+    storage: HashMap<String, Vec<u8>>,
+    bonds: Vec<esp_bd_addr_t>,
+    /// The IRKs distributed by the bonded clients, by identity address.
+    irks: HashMap<esp_bd_addr_t, [u8; 16]>,
+    /// The identity addresses of the clients, by the private addresses they used.
+    private_addresses: HashMap<esp_bd_addr_t, esp_bd_addr_t>,
+    security_parameters: HashMap<esp_ble_sm_param_t, Vec<u8>>,
+    pairings: HashMap<esp_bd_addr_t, SimulatedPairing>,
+    device_name: String,
+    local_mtu: u16,
+    preferred_connection_parameters: Option<esp_ble_conn_update_params_t>,
+    advertising_data: Vec<u8>,
+    scan_response: Vec<u8>,
+    advertising_parameters: esp_ble_adv_params_t,
+    advertising: bool,
+    advertising_sets: BTreeMap<u8, SimulatedAdvertisingSet>,
+    /// The statuses of the next failing events, by kind.
+    failures: HashMap<esp_gatts_cb_event_t, esp_gatt_status_t>,
+}
+
+impl State {
+    fn new() -> Self {
+        Self {
+            gatts_callback: None,
+            gap_callback: None,
+            dispatcher_running: false,
+            dispatching: false,
+            queue: VecDeque::new(),
+            interfaces: Vec::new(),
+            next_handle: FIRST_APPLICATION_HANDLE,
+            next_conn_id: 0,
+            next_trans_id: 1,
+            services: Vec::new(),
+            attributes: generic_attribute_service(),
+            connections: HashMap::new(),
+            responses: HashMap::new(),
+            storage: HashMap::new(),
+            bonds: Vec::new(),
+            irks: HashMap::new(),
+            private_addresses: HashMap::new(),
+            security_parameters: HashMap::new(),
+            pairings: HashMap::new(),
+            device_name: String::new(),
+            local_mtu: MAX_MTU,
+            preferred_connection_parameters: None,
+            advertising_data: Vec::new(),
+            scan_response: Vec::new(),
+            advertising_parameters: esp_ble_adv_params_t::default(),
+            advertising: false,
+            advertising_sets: BTreeMap::new(),
+            failures: HashMap::new(),
+        }
+    }
+
+    fn service_containing(&self, handle: u16) -> Option<&SimulatedService> {
+        self.services
+            .iter()
+            .find(|service| (service.handle..=service.end_handle).contains(&handle))
+    }
+
+    /// Returns whether the clients can access an attribute: the stack's own, or one of a started service.
+    fn is_visible(&self, handle: u16) -> bool {
+        handle < FIRST_APPLICATION_HANDLE
+            || self
+                .service_containing(handle)
+                .map_or(false, |service| service.started)
+    }
+
+    /// Returns the value of an attribute of the Generic Attribute service, as read by a connection.
+    fn stack_value(&self, conn_id: u16, attribute: &SimulatedAttribute) -> Vec<u8> {
+        let connection = &self.connections[&conn_id];
+
+        match attribute.handle {
+            SERVICE_CHANGED_CCCD_HANDLE => connection.service_changed_cccd.to_le_bytes().to_vec(),
+            CLIENT_SUPPORTED_FEATURES_HANDLE => vec![connection.client_features],
+            DATABASE_HASH_HANDLE => self.database_hash().to_vec(),
+            _ => attribute.value.clone(),
+        }
+    }
+
+    /// Writes an attribute of the Generic Attribute service for a connection.
+    fn write_stack_value(
+        &mut self,
+        conn_id: u16,
+        handle: u16,
+        value: &[u8],
+    ) -> Result<(), esp_gatt_status_t> {
+        let connection = self
+            .connections
+            .get_mut(&conn_id)
+            .ok_or(esp_gatt_status_t_ESP_GATT_ILLEGAL_PARAMETER)?;
+
+        match (handle, value) {
+            (SERVICE_CHANGED_CCCD_HANDLE, [low, high]) => {
+                connection.service_changed_cccd = u16::from_le_bytes([*low, *high]);
+                Ok(())
+            }
+            // Clients cannot disable the features they enabled.
+            (CLIENT_SUPPORTED_FEATURES_HANDLE, [features, ..]) => {
+                connection.client_features |= features;
+                Ok(())
+            }
+            _ => Err(esp_gatt_status_t_ESP_GATT_INVALID_ATTR_LEN),
+        }
+    }
+
+    /// Computes the Database Hash: the AES-CMAC, with a zero key, of the handles, types and values
+    /// of the declarations, and of the handles and types of the descriptors that define them.
+    ///
+    /// The hash is sent least significant byte first, like the other values.
+    fn database_hash(&self) -> [u8; 16] {
+        let mut message = Vec::new();
+
+        for (handle, attribute) in &self.attributes {
+            if !self.is_visible(*handle) {
+                continue;
+            }
+
+            let BleUuid::Uuid16(uuid) = attribute.uuid else {
+                continue;
+            };
+
+            match uuid {
+                0x2800..=0x2803 | 0x2900 => {
+                    message.extend(handle.to_le_bytes());
+                    message.extend(uuid.to_le_bytes());
+                    message.extend(&attribute.value);
+                }
+                0x2901..=0x2905 => {
+                    message.extend(handle.to_le_bytes());
+                    message.extend(uuid.to_le_bytes());
+                }
+                _ => {}
+            }
+        }
+
+        let mut hash = cmac::aes_cmac(&[0; 16], &message);
+        hash.reverse();
+        hash
+    }
+
+    fn push_gatts(
+        &mut self,
+        event: esp_gatts_cb_event_t,
+        gatts_if: esp_gatt_if_t,
+        param: esp_ble_gatts_cb_param_t,
+        payload: Vec<u8>,
+    ) {
+        self.push_gatts_payload(event, gatts_if, param, Payload::Bytes(payload));
+    }
+
+    fn push_gatts_payload(
+        &mut self,
+        event: esp_gatts_cb_event_t,
+        gatts_if: esp_gatt_if_t,
+        mut param: esp_ble_gatts_cb_param_t,
+        payload: Payload,
+    ) {
+        if let Some(status) = self.failures.remove(&event) {
+            #[allow(non_upper_case_globals)]
+            match event {
+                esp_gatts_cb_event_t_ESP_GATTS_REG_EVT => param.reg.status = status,
+                esp_gatts_cb_event_t_ESP_GATTS_CREATE_EVT => param.create.status = status,
+                esp_gatts_cb_event_t_ESP_GATTS_CREAT_ATTR_TAB_EVT => {
+                    param.add_attr_tab.status = status;
+                }
+                esp_gatts_cb_event_t_ESP_GATTS_ADD_INCL_SRVC_EVT => {
+                    param.add_incl_srvc.status = status;
+                }
+                esp_gatts_cb_event_t_ESP_GATTS_ADD_CHAR_EVT => param.add_char.status = status,
+                esp_gatts_cb_event_t_ESP_GATTS_ADD_CHAR_DESCR_EVT => {
+                    param.add_char_descr.status = status;
+                }
+                esp_gatts_cb_event_t_ESP_GATTS_START_EVT => param.start.status = status,
+                esp_gatts_cb_event_t_ESP_GATTS_STOP_EVT => param.stop.status = status,
+                esp_gatts_cb_event_t_ESP_GATTS_DELETE_EVT => param.del.status = status,
+                esp_gatts_cb_event_t_ESP_GATTS_CONF_EVT => param.conf.status = status,
+                _ => warn!("Cannot fail event {}.", event),
+            }
+        }
+
+        self.queue.push_back(Event::Gatts {
+            event,
+            gatts_if,
+            param,
+            _payload: payload,
+        });
+    }
+
+    fn broadcast_gatts(&mut self, event: esp_gatts_cb_event_t, param: esp_ble_gatts_cb_param_t) {
+        for gatts_if in self.interfaces.clone() {
+            self.push_gatts(event, gatts_if, param, Vec::new());
+        }
+    }
+
+    fn push_gap(&mut self, event: esp_gap_ble_cb_event_t, param: esp_ble_gap_cb_param_t) {
+        self.queue.push_back(Event::Gap { event, param });
+    }
+
+    /// Stores the bond with a client, under its identity address, with the IRK it distributed.
+    fn store_bond(&mut self, address: esp_bd_addr_t) {
+        let identity = self
+            .private_addresses
+            .get(&address)
+            .copied()
+            .unwrap_or(address);
+
+        if !self.bonds.contains(&identity) {
+            self.bonds.push(identity);
+        }
+        self.irks.insert(identity, client_irk(identity));
+    }
+
+    /// Encrypts the links with a device after it paired.
+    fn secure_links(&mut self, address: esp_bd_addr_t, authenticated: bool) {
+        for connection in self.connections.values_mut() {
+            if connection.address == address {
+                connection.encrypted = true;
+                connection.authenticated = authenticated;
+            }
+        }
+    }
+
+    /// Returns the first byte of a security parameter.
+    fn security_parameter(&self, param: esp_ble_sm_param_t) -> Option<u8> {
+        self.security_parameters
+            .get(&param)
+            .and_then(|value| value.first())
+            .copied()
+    }
+
+    /// Completes a pairing, storing the keys if the server requested bonding.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn complete_pairing(&mut self, address: esp_bd_addr_t, result: Result<(), u8>) {
+        let method = self.pairings.remove(&address).map(|pairing| pairing.method);
+        let authenticated = method.map_or(false, |method| method != PairingMethod::JustWorks);
+
+        if result.is_ok() {
+            self.secure_links(address, authenticated);
+        }
+
+        let authentication_requirements = self
+            .security_parameter(esp_ble_sm_param_t_ESP_BLE_SM_AUTHEN_REQ_MODE)
+            .unwrap_or_default();
+        // The MITM flag reports whether the keys are authenticated.
+        let authentication_mode = if authenticated {
+            authentication_requirements | ESP_LE_AUTH_REQ_MITM as u8
+        } else {
+            authentication_requirements & !(ESP_LE_AUTH_REQ_MITM as u8)
+        };
+        let bonded = result.is_ok() && authentication_requirements & ESP_LE_AUTH_BOND as u8 != 0;
+
+        if bonded {
+            self.store_bond(address);
+        }
+
+        let param = esp_ble_gap_cb_param_t {
+            ble_security: esp_ble_sec_t {
+                auth_cmpl: esp_ble_auth_cmpl_t {
+                    bd_addr: address,
+                    key_present: bonded,
+                    success: result.is_ok(),
+                    fail_reason: result.err().unwrap_or_default(),
+                    addr_type: esp_ble_addr_type_t_BLE_ADDR_TYPE_PUBLIC,
+                    auth_mode: authentication_mode,
+                    ..Default::default()
+                },
+            },
+        };
+
+        self.push_gap(esp_gap_ble_cb_event_t_ESP_GAP_BLE_AUTH_CMPL_EVT, param);
+    }
+
+    fn next_trans_id(&mut self) -> u32 {
+        let trans_id = self.next_trans_id;
+        self.next_trans_id = self.next_trans_id.wrapping_add(1);
+        trans_id
+    }
+}
+
+/// An in-process simulation of the Bluedroid stack.
+///
+/// See the [module documentation](self) for an overview.
+pub struct SimulatedStack {
+    state: Mutex<State>,
+    signal: Condvar,
+}
+
+lazy_static! {
+    static ref SIMULATED_STACK: SimulatedStack = SimulatedStack {
+        state: Mutex::new(State::new()),
+        signal: Condvar::new(),
+    };
+}
+
+impl SimulatedStack {
+    /// Returns the simulated stack used by the GATT server on this target.
+    #[must_use]
+    pub fn global() -> &'static Self {
+        &SIMULATED_STACK
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().expect("Simulated stack lock poisoned.")
+    }
+
+    fn enqueue(&self, mut state: MutexGuard<'_, State>, push: impl FnOnce(&mut State)) {
+        push(&mut state);
+        drop(state);
+        self.signal.notify_all();
+    }
+
+    fn dispatch_loop(&self) {
+        loop {
+            let mut state = self.state();
+            while state.queue.is_empty() {
+                state = self
+                    .signal
+                    .wait(state)
+                    .expect("Simulated stack lock poisoned.");
+            }
+
+            let event = state.queue.pop_front().unwrap();
+            let (gatts_callback, gap_callback) = (state.gatts_callback, state.gap_callback);
+            state.dispatching = true;
+            drop(state);
+
+            match event {
+                Event::Gatts {
+                    event,
+                    gatts_if,
+                    mut param,
+                    _payload,
+                } => {
+                    if let Some(callback) = gatts_callback {
+                        unsafe { callback(event, gatts_if, std::ptr::addr_of_mut!(param)) };
+                    }
+                }
+                Event::Gap { event, mut param } => {
+                    if let Some(callback) = gap_callback {
+                        unsafe { callback(event, std::ptr::addr_of_mut!(param)) };
+                    }
+                }
+            }
+
+            self.state().dispatching = false;
+            self.signal.notify_all();
+        }
+    }
+
+    /// Blocks until every pending event has been delivered, including the events
+    /// of the calls issued by the server while handling them.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the stack's lock is poisoned.
+    pub fn wait_idle(&self) {
+        let mut state = self.state();
+        while !state.queue.is_empty() || state.dispatching {
+            state = self
+                .signal
+                .wait(state)
+                .expect("Simulated stack lock poisoned.");
+        }
+    }
+
+    /// Makes the next GATT server event of the given kind report a failure status,
+    /// to script how the stack rejects a registration.
+    ///
+    /// Registration, service creation, characteristic, descriptor, service start, stop and deletion events can fail.
+    /// The request itself is still applied to the simulated database.
+    /// Failing a confirmation event reports an indication that the client did not confirm.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the stack's lock is poisoned.
+    pub fn fail_next(&self, event: esp_gatts_cb_event_t, status: esp_gatt_status_t) {
+        self.state().failures.insert(event, status);
+    }
+
+    /// Returns a snapshot of the attribute database.
+    #[must_use]
+    pub fn attributes(&self) -> Vec<SimulatedAttribute> {
+        self.state().attributes.values().cloned().collect()
+    }
+
+    /// Returns the value handle of the first characteristic with the given UUID.
+    #[must_use]
+    pub fn find_characteristic(&self, uuid: BleUuid) -> Option<u16> {
+        self.state()
+            .attributes
+            .values()
+            .find(|attribute| {
+                attribute.kind == AttributeKind::CharacteristicValue && attribute.uuid == uuid
+            })
+            .map(|attribute| attribute.handle)
+    }
+
+    /// Returns the device name set by the server.
+    #[must_use]
+    pub fn device_name(&self) -> String {
+        self.state().device_name.clone()
+    }
+
+    /// Returns the Peripheral Preferred Connection Parameters set by the server, if any.
+    #[must_use]
+    pub fn preferred_connection_parameters(&self) -> Option<esp_ble_conn_update_params_t> {
+        self.state().preferred_connection_parameters
+    }
+
+    /// Returns the advertising data set by the server.
+    #[must_use]
+    pub fn advertising_data(&self) -> Vec<u8> {
+        self.state().advertising_data.clone()
+    }
+
+    /// Returns the scan response data set by the server.
+    #[must_use]
+    pub fn scan_response(&self) -> Vec<u8> {
+        self.state().scan_response.clone()
+    }
+
+    /// Returns the parameters with which the server last started advertising.
+    #[must_use]
+    pub fn advertising_parameters(&self) -> esp_ble_adv_params_t {
+        self.state().advertising_parameters
+    }
+
+    /// Returns whether the server is advertising.
+    #[must_use]
+    pub fn is_advertising(&self) -> bool {
+        self.state().advertising
+    }
+
+    /// Returns the extended advertising set with the given instance, if the server configured it.
+    #[must_use]
+    pub fn advertising_set(&self, instance: u8) -> Option<SimulatedAdvertisingSet> {
+        self.state().advertising_sets.get(&instance).cloned()
+    }
+
+    /// Terminates an advertising set, like the controller does when its duration
+    /// (status `0x3C`) or its event limit (status `0x43`) is reached.
+    ///
+    /// Does nothing if the set is not advertising.
+    pub fn terminate_advertising_set(&self, instance: u8, status: u8) {
+        let mut state = self.state();
+        let Some(set) = state.advertising_sets.get_mut(&instance) else {
+            return;
+        };
+
+        if !set.advertising {
+            return;
+        }
+
+        set.advertising = false;
+
+        let param = esp_ble_gap_cb_param_t {
+            adv_terminate: esp_ble_gap_cb_param_t_ble_adv_terminate_evt_param {
+                status,
+                adv_instance: instance,
+                ..Default::default()
+            },
+        };
+
+        self.enqueue(state, |state| {
+            state.push_gap(esp_gap_ble_cb_event_t_ESP_GAP_BLE_ADV_TERMINATED_EVT, param);
+        });
+    }
+
+    /// Returns a new resolvable private address for a client, generated with the IRK
+    /// that it distributes when bonding.
+    ///
+    /// Clients that pair while connected with a private address bond with their identity address.
+    pub fn private_address(&self, identity: esp_bd_addr_t) -> esp_bd_addr_t {
+        let mut state = self.state();
+        let count = state.private_addresses.len() + 1;
+        let address = private_address::generate(
+            &client_irk(identity),
+            [(count >> 16) as u8, (count >> 8) as u8, count as u8],
+        );
+        state.private_addresses.insert(address, identity);
+
+        address
+    }
+
+    /// Connects a simulated client and returns its connection identifier.
+    pub fn connect(&self, address: esp_bd_addr_t) -> u16 {
+        let mut state = self.state();
+        let conn_id = state.next_conn_id;
+        state.next_conn_id += 1;
+
+        // The controller stops connectable advertising when a connection is established.
+        if [
+            esp_ble_adv_type_t_ADV_TYPE_IND,
+            esp_ble_adv_type_t_ADV_TYPE_DIRECT_IND_HIGH,
+            esp_ble_adv_type_t_ADV_TYPE_DIRECT_IND_LOW,
+        ]
+        .contains(&state.advertising_parameters.adv_type)
+        {
+            state.advertising = false;
+        }
+        state.connections.insert(
+            conn_id,
+            SimulatedConnection {
+                address,
+                mtu: DEFAULT_MTU,
+                parameters: DEFAULT_CONNECTION_PARAMETERS,
+                rssi: DEFAULT_RSSI,
+                encrypted: false,
+                authenticated: false,
+                notifications: Vec::new(),
+                prepared_writes: Vec::new(),
+                service_changed_cccd: 0,
+                client_features: 0,
+            },
+        );
+
+        let param = esp_ble_gatts_cb_param_t {
+            connect: esp_ble_gatts_cb_param_t_gatts_connect_evt_param {
+                conn_id,
+                link_role: 1,
+                remote_bda: address,
+                conn_params: DEFAULT_CONNECTION_PARAMETERS,
+            },
+        };
+
+        self.enqueue(state, |state| {
+            state.broadcast_gatts(esp_gatts_cb_event_t_ESP_GATTS_CONNECT_EVT, param);
+        });
+
+        conn_id
+    }
+
+    /// Disconnects a simulated client.
+    pub fn disconnect(&self, conn_id: u16) {
+        let mut state = self.state();
+        let Some(connection) = state.connections.remove(&conn_id) else {
+            warn!("Simulated connection {} does not exist.", conn_id);
+            return;
+        };
+
+        let param = esp_ble_gatts_cb_param_t {
+            disconnect: esp_ble_gatts_cb_param_t_gatts_disconnect_evt_param {
+                conn_id,
+                link_role: 1,
+                remote_bda: connection.address,
+                reason: esp_gatt_conn_reason_t_ESP_GATT_CONN_TERMINATE_PEER_USER,
+            },
+        };
+
+        self.enqueue(state, |state| {
+            state.broadcast_gatts(esp_gatts_cb_event_t_ESP_GATTS_DISCONNECT_EVT, param);
+        });
+    }
+
+    /// Simulates a connection parameters update, with an interval in units of 1.25 ms
+    /// and a supervision timeout in units of 10 ms.
+    pub fn update_connection_parameters(
+        &self,
+        conn_id: u16,
+        interval: u16,
+        latency: u16,
+        timeout: u16,
+    ) {
+        let mut state = self.state();
+        let Some(connection) = state.connections.get_mut(&conn_id) else {
+            warn!("Simulated connection {} does not exist.", conn_id);
+            return;
+        };
+        connection.parameters = esp_gatt_conn_params_t {
+            interval,
+            latency,
+            timeout,
+        };
+
+        let param = esp_ble_gap_cb_param_t {
+            update_conn_params: esp_ble_gap_cb_param_t_ble_update_conn_params_evt_param {
+                status: esp_bt_status_t_ESP_BT_STATUS_SUCCESS,
+                bda: connection.address,
+                min_int: interval,
+                max_int: interval,
+                latency,
+                conn_int: interval,
+                timeout,
+            },
+        };
+
+        self.enqueue(state, |state| {
+            state.push_gap(
+                esp_gap_ble_cb_event_t_ESP_GAP_BLE_UPDATE_CONN_PARAMS_EVT,
+                param,
+            );
+        });
+    }
+
+    /// Sets the signal strength of a simulated connection, in dBm.
+    pub fn set_rssi(&self, conn_id: u16, rssi: i8) {
+        if let Some(connection) = self.state().connections.get_mut(&conn_id) {
+            connection.rssi = rssi;
+        } else {
+            warn!("Simulated connection {} does not exist.", conn_id);
+        }
+    }
+
+    /// Simulates an MTU exchange initiated by the client.
+    pub fn exchange_mtu(&self, conn_id: u16, mtu: u16) {
+        let mut state = self.state();
+        let local_mtu = state.local_mtu;
+        let Some(connection) = state.connections.get_mut(&conn_id) else {
+            warn!("Simulated connection {} does not exist.", conn_id);
+            return;
+        };
+
+        // The smaller MTU of the client and of the server is used.
+        let mtu = mtu.min(local_mtu).max(DEFAULT_MTU);
+        connection.mtu = mtu;
+
+        let param = esp_ble_gatts_cb_param_t {
+            mtu: esp_ble_gatts_cb_param_t_gatts_mtu_evt_param { conn_id, mtu },
+        };
+
+        self.enqueue(state, |state| {
+            state.broadcast_gatts(esp_gatts_cb_event_t_ESP_GATTS_MTU_EVT, param);
+        });
+    }
+
+    /// Reads an attribute as a client.
+    ///
+    /// Like on a real link, the value is truncated to fit in the MTU.
+    /// See [`Self::read_long`] to read the whole value.
+    ///
+    /// # Errors
+    ///
+    /// Returns the ATT status code if the read is rejected by the stack or by the server.
+    pub fn read(&self, conn_id: u16, handle: u16) -> Result<Vec<u8>, esp_gatt_status_t> {
+        self.read_blob(conn_id, handle, 0)
+    }
+
+    /// Reads a long attribute as a client, with as many read blob requests as needed.
+    ///
+    /// # Errors
+    ///
+    /// Returns the ATT status code if one of the reads is rejected by the stack or by the server.
+    pub fn read_long(&self, conn_id: u16, handle: u16) -> Result<Vec<u8>, esp_gatt_status_t> {
+        let part_length = self.mtu(conn_id)? as usize - 1;
+        let mut value = Vec::new();
+
+        loop {
+            let offset = u16::try_from(value.len())
+                .map_err(|_| esp_gatt_status_t_ESP_GATT_INVALID_OFFSET)?;
+            let part = self.read_blob(conn_id, handle, offset)?;
+            let last = part.len() < part_length;
+            value.extend(part);
+
+            if last {
+                return Ok(value);
+            }
+        }
+    }
+
+    /// Reads a part of an attribute as a client, starting at the given offset.
+    ///
+    /// # Errors
+    ///
+    /// Returns the ATT status code if the read is rejected by the stack or by the server.
+    pub fn read_blob(
+        &self,
+        conn_id: u16,
+        handle: u16,
+        offset: u16,
+    ) -> Result<Vec<u8>, esp_gatt_status_t> {
+        let mut state = self.state();
+        let address = Self::connection_address(&state, conn_id)?;
+        let attribute = Self::attribute(&state, handle)?;
+        let part_length = state.connections[&conn_id].mtu as usize - 1;
+
+        Self::check_permissions(
+            &state.connections[&conn_id],
+            attribute.permissions,
+            [
+                ESP_GATT_PERM_READ,
+                ESP_GATT_PERM_READ_ENCRYPTED,
+                ESP_GATT_PERM_READ_ENC_MITM,
+                ESP_GATT_PERM_READ_AUTHORIZATION,
+            ],
+        )
+        .map_err(|status| status.unwrap_or(esp_gatt_status_t_ESP_GATT_READ_NOT_PERMIT))?;
+
+        // The stack answers for its own attributes, without telling the server.
+        if handle < FIRST_APPLICATION_HANDLE {
+            let mut value = state
+                .stack_value(conn_id, &attribute)
+                .get(offset as usize..)
+                .ok_or(esp_gatt_status_t_ESP_GATT_INVALID_OFFSET)?
+                .to_vec();
+
+            value.truncate(part_length);
+            return Ok(value);
+        }
+
+        let trans_id = state.next_trans_id();
+        let service_interface = Self::interface_of(&state, handle)?;
+        let param = esp_ble_gatts_cb_param_t {
+            read: esp_ble_gatts_cb_param_t_gatts_read_evt_param {
+                conn_id,
+                trans_id,
+                bda: address,
+                handle,
+                offset,
+                is_long: offset > 0,
+                need_rsp: !attribute.automatic_response,
+            },
+        };
+
+        self.enqueue(state, |state| {
+            state.push_gatts(
+                esp_gatts_cb_event_t_ESP_GATTS_READ_EVT,
+                service_interface,
+                param,
+                Vec::new(),
+            );
+        });
+
+        let mut value = if attribute.automatic_response {
+            attribute
+                .value
+                .get(offset as usize..)
+                .ok_or(esp_gatt_status_t_ESP_GATT_INVALID_OFFSET)?
+                .to_vec()
+        } else {
+            self.wait_response(trans_id)?
+        };
+
+        value.truncate(part_length);
+        Ok(value)
+    }
+
+    /// Writes an attribute as a client, with a write request.
+    ///
+    /// # Errors
+    ///
+    /// Returns the ATT status code if the write is rejected by the stack or by the server.
+    pub fn write(&self, conn_id: u16, handle: u16, value: &[u8]) -> Result<(), esp_gatt_status_t> {
+        self.write_attribute(conn_id, handle, value, true)
+    }
+
+    /// Writes an attribute as a client, with a write command.
+    ///
+    /// # Errors
+    ///
+    /// Returns the ATT status code if the write is rejected by the stack.
+    pub fn write_without_response(
+        &self,
+        conn_id: u16,
+        handle: u16,
+        value: &[u8],
+    ) -> Result<(), esp_gatt_status_t> {
+        self.write_attribute(conn_id, handle, value, false)
+    }
+
+    /// Writes a long attribute as a client, with as many prepared writes as needed.
+    ///
+    /// The prepared writes are cancelled if one of them is rejected.
+    ///
+    /// # Errors
+    ///
+    /// Returns the ATT status code if the write is rejected by the stack or by the server.
+    pub fn write_long(
+        &self,
+        conn_id: u16,
+        handle: u16,
+        value: &[u8],
+    ) -> Result<(), esp_gatt_status_t> {
+        let part_length = self.mtu(conn_id)? as usize - 5;
+
+        for (index, part) in value.chunks(part_length).enumerate() {
+            let offset = u16::try_from(index * part_length)
+                .map_err(|_| esp_gatt_status_t_ESP_GATT_INVALID_OFFSET)?;
+
+            if let Err(status) = self.prepare_write(conn_id, handle, offset, part) {
+                self.execute_write(conn_id, false)?;
+                return Err(status);
+            }
+        }
+
+        self.execute_write(conn_id, true)
+    }
+
+    /// Queues a part of a long write as a client.
+    ///
+    /// Attributes answered by the stack are only checked when executing the write.
+    ///
+    /// # Errors
+    ///
+    /// Returns the ATT status code if the part is rejected by the stack or by the server.
+    pub fn prepare_write(
+        &self,
+        conn_id: u16,
+        handle: u16,
+        offset: u16,
+        value: &[u8],
+    ) -> Result<(), esp_gatt_status_t> {
+        let mut state = self.state();
+        let address = Self::connection_address(&state, conn_id)?;
+        let attribute = Self::writable_attribute(&state, conn_id, handle)?;
+        let service_interface = Self::interface_of(&state, handle)?;
+
+        let prepared_write = PreparedWrite {
+            handle,
+            offset,
+            value: value.to_vec(),
+            interface: (!attribute.automatic_response).then_some(service_interface),
+        };
+
+        if let Some(connection) = state.connections.get_mut(&conn_id) {
+            connection.prepared_writes.push(prepared_write);
+        }
+
+        if attribute.automatic_response {
+            return Ok(());
+        }
+
+        let trans_id = state.next_trans_id();
+        let mut payload = value.to_vec();
+
+        let param = esp_ble_gatts_cb_param_t {
+            write: esp_ble_gatts_cb_param_t_gatts_write_evt_param {
+                conn_id,
+                trans_id,
+                bda: address,
+                handle,
+                offset,
+                need_rsp: true,
+                is_prep: true,
+                len: payload.len() as u16,
+                value: payload.as_mut_ptr(),
+            },
+        };
+
+        self.enqueue(state, |state| {
+            state.push_gatts(
+                esp_gatts_cb_event_t_ESP_GATTS_WRITE_EVT,
+                service_interface,
+                param,
+                payload,
+            );
+        });
+
+        let response = self.wait_response(trans_id);
+
+        // Rejected parts are not part of the queue.
+        if response.is_err() {
+            if let Some(connection) = self.state().connections.get_mut(&conn_id) {
+                connection.prepared_writes.pop();
+            }
+        }
+
+        response.map(|_| ())
+    }
+
+    /// Executes or cancels the long writes queued by a client.
+    ///
+    /// # Errors
+    ///
+    /// Returns the ATT status code if one of the writes is rejected by the stack or by the server.
+    pub fn execute_write(&self, conn_id: u16, execute: bool) -> Result<(), esp_gatt_status_t> {
+        let mut state = self.state();
+        let address = Self::connection_address(&state, conn_id)?;
+        let prepared_writes = state
+            .connections
+            .get_mut(&conn_id)
+            .map(|connection| std::mem::take(&mut connection.prepared_writes))
+            .unwrap_or_default();
+
+        if execute {
+            Self::execute_stored_writes(&mut state, &prepared_writes)?;
+        }
+
+        // Each application answering for one of the attributes executes its own writes.
+        let mut interfaces: Vec<esp_gatt_if_t> = prepared_writes
+            .iter()
+            .filter_map(|prepared_write| prepared_write.interface)
+            .collect();
+        interfaces.sort_unstable();
+        interfaces.dedup();
+
+        let mut transactions = Vec::new();
+        for interface in interfaces {
+            let trans_id = state.next_trans_id();
+            let param = esp_ble_gatts_cb_param_t {
+                exec_write: esp_ble_gatts_cb_param_t_gatts_exec_write_evt_param {
+                    conn_id,
+                    trans_id,
+                    bda: address,
+                    exec_write_flag: if execute {
+                        ESP_GATT_PREP_WRITE_EXEC as u8
+                    } else {
+                        ESP_GATT_PREP_WRITE_CANCEL as u8
+                    },
+                },
+            };
+
+            state.push_gatts(
+                esp_gatts_cb_event_t_ESP_GATTS_EXEC_WRITE_EVT,
+                interface,
+                param,
+                Vec::new(),
+            );
+            transactions.push(trans_id);
+        }
+
+        drop(state);
+        self.signal.notify_all();
+
+        transactions
+            .into_iter()
+            .try_for_each(|trans_id| self.wait_response(trans_id).map(|_| ()))
+    }
+
+    /// Writes the values of the attributes stored by the stack, assembled from their parts.
+    fn execute_stored_writes(
+        state: &mut State,
+        prepared_writes: &[PreparedWrite],
+    ) -> Result<(), esp_gatt_status_t> {
+        let mut values: BTreeMap<u16, Vec<u8>> = BTreeMap::new();
+
+        for prepared_write in prepared_writes
+            .iter()
+            .filter(|prepared_write| prepared_write.interface.is_none())
+        {
+            let value = values.entry(prepared_write.handle).or_default();
+            let start = prepared_write.offset as usize;
+            let end = start + prepared_write.value.len();
+
+            if start > value.len() {
+                return Err(esp_gatt_status_t_ESP_GATT_INVALID_OFFSET);
+            }
+
+            if value.len() < end {
+                value.resize(end, 0);
+            }
+
+            value[start..end].copy_from_slice(&prepared_write.value);
+        }
+
+        for (handle, value) in &values {
+            let attribute = Self::attribute(state, *handle)?;
+            if value.len() > attribute.max_length as usize {
+                return Err(esp_gatt_status_t_ESP_GATT_INVALID_ATTR_LEN);
+            }
+        }
+
+        for (handle, value) in values {
+            if let Some(attribute) = state.attributes.get_mut(&handle) {
+                attribute.value = value;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Starts a pairing requested by a connected client, which requests MITM protection and bonding.
+    ///
+    /// The method is chosen from the input and output capabilities of the server and of the client.
+    /// The client enters or displays the given passkey: entering a passkey that differs from the one
+    /// displayed by the server, or replying with a passkey that differs from the one displayed by
+    /// the client, fails the pairing. Numeric comparisons display the given passkey.
+    ///
+    /// The outcome is reported to the server with an authentication complete event.
+    pub fn pair(&self, conn_id: u16, io_capabilities: esp_ble_io_cap_t, passkey: u32) {
+        let mut state = self.state();
+        let Some(address) = state
+            .connections
+            .get(&conn_id)
+            .map(|connection| connection.address)
+        else {
+            warn!("Simulated connection {} does not exist.", conn_id);
+            return;
+        };
+
+        let server = state
+            .security_parameter(esp_ble_sm_param_t_ESP_BLE_SM_IOCAP_MODE)
+            .unwrap_or(ESP_IO_CAP_NONE as esp_ble_io_cap_t);
+        let method = PairingMethod::choose(server, io_capabilities);
+        debug!(
+            "Simulated pairing with {:02X?} using {:?}.",
+            address, method
+        );
+
+        state
+            .pairings
+            .insert(address, SimulatedPairing { method, passkey });
+
+        let param = esp_ble_gap_cb_param_t {
+            ble_security: esp_ble_sec_t {
+                ble_req: esp_ble_sec_req_t { bd_addr: address },
+            },
+        };
+
+        self.enqueue(state, |state| {
+            state.push_gap(esp_gap_ble_cb_event_t_ESP_GAP_BLE_SEC_REQ_EVT, param);
+        });
+    }
+
+    /// Returns a parameter of the security manager set by the server.
+    #[must_use]
+    pub fn security_parameter(&self, param: esp_ble_sm_param_t) -> Option<Vec<u8>> {
+        self.state().security_parameters.get(&param).cloned()
+    }
+
+    /// Completes a pairing that bonds with a connected client, without user interaction.
+    pub fn bond(&self, conn_id: u16) {
+        let mut state = self.state();
+        let Some(address) = state
+            .connections
+            .get(&conn_id)
+            .map(|connection| connection.address)
+        else {
+            warn!("Simulated connection {} does not exist.", conn_id);
+            return;
+        };
+
+        state.store_bond(address);
+        state.secure_links(address, false);
+
+        let param = esp_ble_gap_cb_param_t {
+            ble_security: esp_ble_sec_t {
+                auth_cmpl: esp_ble_auth_cmpl_t {
+                    bd_addr: address,
+                    key_present: true,
+                    success: true,
+                    addr_type: esp_ble_addr_type_t_BLE_ADDR_TYPE_PUBLIC,
+                    ..Default::default()
+                },
+            },
+        };
+
+        self.enqueue(state, |state| {
+            state.push_gap(esp_gap_ble_cb_event_t_ESP_GAP_BLE_AUTH_CMPL_EVT, param);
+        });
+    }
+
+    /// Removes the bond with a device, like the application would.
+    pub fn unbond(&self, address: esp_bd_addr_t) {
+        if self.remove_bond_device(address).is_err() {
+            warn!("Simulated device {:02X?} is not bonded.", address);
+        }
+    }
+
+    /// Returns the keys of the values in the persistent storage.
+    #[must_use]
+    pub fn storage_keys(&self) -> Vec<String> {
+        self.state().storage.keys().cloned().collect()
+    }
+
+    /// Writes a value to the persistent storage, or removes it, like an earlier firmware would have.
+    pub fn set_storage(&self, key: &str, value: Option<&[u8]>) {
+        let mut state = self.state();
+        match value {
+            Some(value) => state.storage.insert(key.to_string(), value.to_vec()),
+            None => state.storage.remove(key),
+        };
+    }
+
+    /// Subscribes to notifications or indications by writing the characteristic's CCCD.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`esp_gatt_status_t_ESP_GATT_NOT_FOUND`] if the characteristic has no CCCD,
+    /// or the ATT status code if the write is rejected.
+    pub fn subscribe(
+        &self,
+        conn_id: u16,
+        value_handle: u16,
+        notifications: bool,
+        indications: bool,
+    ) -> Result<(), esp_gatt_status_t> {
+        let cccd = self
+            .state()
+            .attributes
+            .range(value_handle + 1..)
+            .take_while(|(_, attribute)| attribute.kind == AttributeKind::Descriptor)
+            .find(|(_, attribute)| attribute.uuid == BleUuid::Uuid16(0x2902))
+            .map(|(handle, _)| *handle)
+            .ok_or(esp_gatt_status_t_ESP_GATT_NOT_FOUND)?;
+
+        let value = u16::from(notifications) | u16::from(indications) << 1;
+        self.write(conn_id, cccd, &value.to_le_bytes())
+    }
+
+    /// Returns and clears the notifications and indications received by a client.
+    #[must_use]
+    pub fn notifications(&self, conn_id: u16) -> Vec<Notification> {
+        self.state()
+            .connections
+            .get_mut(&conn_id)
+            .map(|connection| std::mem::take(&mut connection.notifications))
+            .unwrap_or_default()
+    }
+
+    fn write_attribute(
+        &self,
+        conn_id: u16,
+        handle: u16,
+        value: &[u8],
+        need_rsp: bool,
+    ) -> Result<(), esp_gatt_status_t> {
+        let mut state = self.state();
+        let address = Self::connection_address(&state, conn_id)?;
+        let attribute = Self::writable_attribute(&state, conn_id, handle)?;
+
+        if handle < FIRST_APPLICATION_HANDLE {
+            return state.write_stack_value(conn_id, handle, value);
+        }
+
+        if attribute.automatic_response {
+            if value.len() > attribute.max_length as usize {
+                return Err(esp_gatt_status_t_ESP_GATT_INVALID_ATTR_LEN);
+            }
+
+            if let Some(attribute) = state.attributes.get_mut(&handle) {
+                attribute.value = value.to_vec();
+            }
+        }
+
+        let trans_id = state.next_trans_id();
+        let service_interface = Self::interface_of(&state, handle)?;
+        let mut payload = value.to_vec();
+
+        let param = esp_ble_gatts_cb_param_t {
+            write: esp_ble_gatts_cb_param_t_gatts_write_evt_param {
+                conn_id,
+                trans_id,
+                bda: address,
+                handle,
+                offset: 0,
+                need_rsp: need_rsp && !attribute.automatic_response,
+                is_prep: false,
+                len: payload.len() as u16,
+                value: payload.as_mut_ptr(),
+            },
+        };
+
+        self.enqueue(state, |state| {
+            state.push_gatts(
+                esp_gatts_cb_event_t_ESP_GATTS_WRITE_EVT,
+                service_interface,
+                param,
+                payload,
+            );
+        });
+
+        if attribute.automatic_response || !need_rsp {
+            return Ok(());
+        }
+
+        self.wait_response(trans_id).map(|_| ())
+    }
+
+    /// Returns an attribute that the client is allowed to write.
+    fn writable_attribute(
+        state: &State,
+        conn_id: u16,
+        handle: u16,
+    ) -> Result<SimulatedAttribute, esp_gatt_status_t> {
+        let attribute = Self::attribute(state, handle)?;
+
+        // Simulated clients never sign writes: signed permissions need an encrypted link.
+        Self::check_permissions(
+            &state.connections[&conn_id],
+            attribute.permissions,
+            [
+                ESP_GATT_PERM_WRITE,
+                ESP_GATT_PERM_WRITE_ENCRYPTED | ESP_GATT_PERM_WRITE_SIGNED,
+                ESP_GATT_PERM_WRITE_ENC_MITM | ESP_GATT_PERM_WRITE_SIGNED_MITM,
+                ESP_GATT_PERM_WRITE_AUTHORIZATION,
+            ],
+        )
+        .map_err(|status| status.unwrap_or(esp_gatt_status_t_ESP_GATT_WRITE_NOT_PERMIT))?;
+
+        Ok(attribute)
+    }
+
+    /// Checks an access against the security of the link, like Bluedroid.
+    ///
+    /// The permissions are the open, encrypted, MITM-protected and authorized bits of the access.
+    /// Returns `Err(None)` if the access is not permitted at all.
+    /// Authorization is never granted.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn check_permissions(
+        connection: &SimulatedConnection,
+        permissions: esp_gatt_perm_t,
+        [open, encrypted, mitm, authorization]: [i32; 4],
+    ) -> Result<(), Option<esp_gatt_status_t>> {
+        let has = |bits: i32| permissions & bits as esp_gatt_perm_t != 0;
+
+        if !has(open | encrypted | mitm | authorization) {
+            return Err(None);
+        }
+
+        if (has(encrypted) && !connection.encrypted) || (has(mitm) && !connection.authenticated) {
+            return Err(Some(esp_gatt_status_t_ESP_GATT_INSUF_AUTHENTICATION));
+        }
+
+        if has(authorization) {
+            return Err(Some(esp_gatt_status_t_ESP_GATT_INSUF_AUTHORIZATION));
+        }
+
+        Ok(())
+    }
+
+    fn mtu(&self, conn_id: u16) -> Result<u16, esp_gatt_status_t> {
+        self.state()
+            .connections
+            .get(&conn_id)
+            .map(|connection| connection.mtu)
+            .ok_or(esp_gatt_status_t_ESP_GATT_ILLEGAL_PARAMETER)
+    }
+
+    fn wait_response(&self, trans_id: u32) -> Result<Vec<u8>, esp_gatt_status_t> {
+        let (mut state, timeout) = self
+            .signal
+            .wait_timeout_while(self.state(), RESPONSE_TIMEOUT, |state| {
+                !state.responses.contains_key(&trans_id)
+            })
+            .expect("Simulated stack lock poisoned.");
+
+        if timeout.timed_out() {
+            warn!("Simulated request {} timed out.", trans_id);
+            return Err(esp_gatt_status_t_ESP_GATT_ERROR);
+        }
+
+        let (status, value) = state.responses.remove(&trans_id).unwrap();
+        if status == esp_gatt_status_t_ESP_GATT_OK {
+            Ok(value)
+        } else {
+            Err(status)
+        }
+    }
+
+    fn connection_address(state: &State, conn_id: u16) -> Result<esp_bd_addr_t, esp_gatt_status_t> {
+        state
+            .connections
+            .get(&conn_id)
+            .map(|connection| connection.address)
+            .ok_or(esp_gatt_status_t_ESP_GATT_ILLEGAL_PARAMETER)
+    }
+
+    /// Returns an attribute that the clients can access.
+    fn attribute(state: &State, handle: u16) -> Result<SimulatedAttribute, esp_gatt_status_t> {
+        state
+            .attributes
+            .get(&handle)
+            .filter(|_| state.is_visible(handle))
+            .cloned()
+            .ok_or(esp_gatt_status_t_ESP_GATT_INVALID_HANDLE)
+    }
+
+    fn interface_of(state: &State, handle: u16) -> Result<esp_gatt_if_t, esp_gatt_status_t> {
+        state
+            .service_containing(handle)
+            .map(|service| service.interface)
+            .ok_or(esp_gatt_status_t_ESP_GATT_INVALID_HANDLE)
+    }
+
+    /// Allocates the next handle of a service, or returns `None` if the service is full.
+    fn allocate_handle(state: &mut State, service_handle: u16) -> Option<u16> {
+        let service = state
+            .services
+            .iter_mut()
+            .find(|service| service.handle == service_handle)?;
+
+        if service.next_handle > service.end_handle {
+            return None;
+        }
+
+        let handle = service.next_handle;
+        service.next_handle += 1;
+        Some(handle)
+    }
+
+    fn interface_of_service(state: &State, service_handle: u16) -> Option<esp_gatt_if_t> {
+        state
+            .services
+            .iter()
+            .find(|service| service.handle == service_handle)
+            .map(|service| service.interface)
+    }
+
+    fn invalid_argument() -> EspError {
+        EspError::from_infallible::<ESP_ERR_INVALID_ARG>()
+    }
+
+    /// Copies the initial value of an attribute passed to the stack.
+    fn initial_value(value: &esp_attr_value_t) -> Vec<u8> {
+        if value.attr_value.is_null() {
+            return Vec::new();
+        }
+
+        unsafe { std::slice::from_raw_parts(value.attr_value, value.attr_len as usize) }.to_vec()
+    }
+
+    /// Builds the attribute of an entry of an attribute table, given the UUIDs and the values of all the entries.
+    fn table_attribute(
+        entries: &[(BleUuid, Vec<u8>)],
+        index: usize,
+        entry: &esp_gatts_attr_db_t,
+        handle: u16,
+    ) -> Option<SimulatedAttribute> {
+        let (uuid, value) = &entries[index];
+        let previous = index.checked_sub(1).map(|previous| &entries[previous]);
+        let read = ESP_GATT_PERM_READ as esp_gatt_perm_t;
+
+        let (kind, permissions, properties, value, max_length, automatic_response) =
+            if *uuid == BleUuid::Uuid16(0x2800) || *uuid == BleUuid::Uuid16(0x2801) {
+                let kind = if *uuid == BleUuid::Uuid16(0x2800) {
+                    AttributeKind::PrimaryService
+                } else {
+                    AttributeKind::SecondaryService
+                };
+                (kind, read, 0, value.clone(), 16, true)
+            } else if *uuid == BleUuid::Uuid16(0x2802) {
+                // The value holds the handles of the included service, and its UUID if it is 16-bit.
+                let length = value.len() as u16;
+                (AttributeKind::Include, read, 0, value.clone(), length, true)
+            } else if *uuid == BleUuid::Uuid16(0x2803) {
+                // The table only holds the properties: the handle and the UUID of the value follow them.
+                let (value_uuid, _) = entries.get(index + 1)?;
+                let mut declaration = vec![*value.first()?];
+                declaration.extend_from_slice(&(handle + 1).to_le_bytes());
+                declaration.extend(value_uuid.to_le_bytes());
+
+                let length = declaration.len() as u16;
+                (
+                    AttributeKind::CharacteristicDeclaration,
+                    read,
+                    0,
+                    declaration,
+                    length,
+                    true,
+                )
+            } else {
+                let kind = match previous {
+                    Some((previous, _)) if *previous == BleUuid::Uuid16(0x2803) => {
+                        AttributeKind::CharacteristicValue
+                    }
+                    _ => AttributeKind::Descriptor,
+                };
+                let properties = match (kind, previous) {
+                    (AttributeKind::CharacteristicValue, Some((_, declaration))) => declaration[0],
+                    _ => 0,
+                };
+                (
+                    kind,
+                    entry.att_desc.perm,
+                    properties,
+                    value.clone(),
+                    entry.att_desc.max_length,
+                    entry.attr_control.auto_rsp == ESP_GATT_AUTO_RSP as u8,
+                )
+            };
+
+        Some(SimulatedAttribute {
+            handle,
+            uuid: *uuid,
+            kind,
+            permissions,
+            properties,
+            value,
+            max_length,
+            automatic_response,
+        })
+    }
+
+    /// Copies the UUID and the initial value of an entry of an attribute table passed to the stack.
+    fn table_entry(entry: &esp_gatts_attr_db_t) -> Option<(BleUuid, Vec<u8>)> {
+        let description = &entry.att_desc;
+        if description.uuid_p.is_null() {
+            return None;
+        }
+
+        let uuid = unsafe {
+            std::slice::from_raw_parts(description.uuid_p, usize::from(description.uuid_length))
+        };
+        let uuid = match uuid.len() {
+            2 => BleUuid::Uuid16(u16::from_le_bytes([uuid[0], uuid[1]])),
+            4 => BleUuid::Uuid32(u32::from_le_bytes([uuid[0], uuid[1], uuid[2], uuid[3]])),
+            16 => BleUuid::Uuid128(uuid.try_into().ok()?),
+            _ => return None,
+        };
+
+        let value = Self::initial_value(&esp_attr_value_t {
+            attr_max_len: description.max_length,
+            attr_len: description.length,
+            attr_value: description.value,
+        });
+
+        Some((uuid, value))
+    }
+}
+
+impl Backend for SimulatedStack {
+    fn initialise(
+        &self,
+        gatts_callback: esp_gatts_cb_t,
+        gap_callback: esp_gap_ble_cb_t,
+    ) -> Result<(), EspError> {
+        debug!("Initialising simulated BLE stack.");
+
+        let mut state = self.state();
+        if state.gatts_callback.is_some() {
+            return Err(EspError::from_infallible::<ESP_ERR_INVALID_STATE>());
+        }
+
+        state.gatts_callback = gatts_callback;
+        state.gap_callback = gap_callback;
+
+        if !state.dispatcher_running {
+            state.dispatcher_running = true;
+            std::thread::Builder::new()
+                .name("simulated-bluedroid".to_string())
+                .spawn(|| Self::global().dispatch_loop())
+                .map_err(|_| EspError::from_infallible::<ESP_ERR_NO_MEM>())?;
+        }
+
+        Ok(())
+    }
+
+    fn deinitialise(&self) -> Result<(), EspError> {
+        debug!("Deinitialising simulated BLE stack.");
+
+        let mut state = self.state();
+        if state.gatts_callback.is_none() {
+            return Err(EspError::from_infallible::<ESP_ERR_INVALID_STATE>());
+        }
+
+        // Like the real stack, only the persistent storage and the bonds survive.
+        *state = State {
+            dispatcher_running: state.dispatcher_running,
+            dispatching: state.dispatching,
+            storage: std::mem::take(&mut state.storage),
+            bonds: std::mem::take(&mut state.bonds),
+            irks: std::mem::take(&mut state.irks),
+            private_addresses: std::mem::take(&mut state.private_addresses),
+            ..State::new()
+        };
+        drop(state);
+
+        self.signal.notify_all();
+
+        Ok(())
+    }
+
+    fn app_register(&self, app_id: u16) -> Result<(), EspError> {
+        let mut state = self.state();
+
+        let gatts_if = FIRST_INTERFACE + state.interfaces.len() as esp_gatt_if_t;
+        state.interfaces.push(gatts_if);
+
+        let param = esp_ble_gatts_cb_param_t {
+            reg: esp_ble_gatts_cb_param_t_gatts_reg_evt_param {
+                status: esp_gatt_status_t_ESP_GATT_OK,
+                app_id,
+            },
+        };
+
+        self.enqueue(state, |state| {
+            state.push_gatts(
+                esp_gatts_cb_event_t_ESP_GATTS_REG_EVT,
+                gatts_if,
+                param,
+                Vec::new(),
+            );
+        });
+
+        Ok(())
+    }
+
+    fn create_service(
+        &self,
+        gatts_if: esp_gatt_if_t,
+        service_id: esp_gatt_srvc_id_t,
+        num_handles: u16,
+    ) -> Result<(), EspError> {
+        let mut state = self.state();
+
+        if !state.interfaces.contains(&gatts_if) || num_handles == 0 {
+            return Err(Self::invalid_argument());
+        }
+
+        let handle = state.next_handle;
+        let end_handle = handle
+            .checked_add(num_handles - 1)
+            .ok_or_else(Self::invalid_argument)?;
+        state.next_handle = end_handle.wrapping_add(1);
+
+        let uuid = BleUuid::from(service_id.id);
+        state.services.push(SimulatedService {
+            interface: gatts_if,
+            handle,
+            end_handle,
+            next_handle: handle + 1,
+            last_characteristic: None,
+            started: false,
+        });
+        state.attributes.insert(
+            handle,
+            SimulatedAttribute {
+                handle,
+                uuid: BleUuid::Uuid16(if service_id.is_primary {
+                    0x2800
+                } else {
+                    0x2801
+                }),
+                kind: if service_id.is_primary {
+                    AttributeKind::PrimaryService
+                } else {
+                    AttributeKind::SecondaryService
+                },
+                permissions: ESP_GATT_PERM_READ as esp_gatt_perm_t,
+                properties: 0,
+                value: uuid.to_le_bytes(),
+                max_length: 16,
+                automatic_response: true,
+            },
+        );
+
+        let param = esp_ble_gatts_cb_param_t {
+            create: esp_ble_gatts_cb_param_t_gatts_create_evt_param {
+                status: esp_gatt_status_t_ESP_GATT_OK,
+                service_handle: handle,
+                service_id,
+            },
+        };
+
+        self.enqueue(state, |state| {
+            state.push_gatts(
+                esp_gatts_cb_event_t_ESP_GATTS_CREATE_EVT,
+                gatts_if,
+                param,
+                Vec::new(),
+            );
+        });
+
+        Ok(())
+    }
+
+    fn create_attr_tab(
+        &self,
+        gatts_if: esp_gatt_if_t,
+        table: &[esp_gatts_attr_db_t],
+        service_instance: u8,
+    ) -> Result<(), EspError> {
+        let mut state = self.state();
+
+        let entries = table
+            .iter()
+            .map(Self::table_entry)
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(Self::invalid_argument)?;
+
+        // Like Bluedroid, the table starts with the service declaration, whose value is the UUID of the service.
+        if !matches!(entries.first(), Some((uuid, _))
+            if *uuid == BleUuid::Uuid16(0x2800) || *uuid == BleUuid::Uuid16(0x2801))
+        {
+            return Err(Self::invalid_argument());
+        }
+        let service_uuid = match entries[0].1.as_slice() {
+            [a, b] => BleUuid::Uuid16(u16::from_le_bytes([*a, *b])),
+            [a, b, c, d] => BleUuid::Uuid32(u32::from_le_bytes([*a, *b, *c, *d])),
+            bytes => BleUuid::Uuid128(bytes.try_into().map_err(|_| Self::invalid_argument())?),
+        };
+
+        if !state.interfaces.contains(&gatts_if) {
+            return Err(Self::invalid_argument());
+        }
+
+        let count = u16::try_from(entries.len()).map_err(|_| Self::invalid_argument())?;
+        let handle = state.next_handle;
+        let end_handle = handle
+            .checked_add(count - 1)
+            .ok_or_else(Self::invalid_argument)?;
+        state.next_handle = end_handle.wrapping_add(1);
+
+        let handles: Vec<u16> = (handle..=end_handle).collect();
+        let mut last_characteristic = None;
+
+        for (index, entry) in table.iter().enumerate() {
+            let attribute = Self::table_attribute(&entries, index, entry, handles[index])
+                .ok_or_else(Self::invalid_argument)?;
+            if attribute.kind == AttributeKind::CharacteristicValue {
+                last_characteristic = Some(attribute.handle);
+            }
+
+            state.attributes.insert(attribute.handle, attribute);
+        }
+
+        state.services.push(SimulatedService {
+            interface: gatts_if,
+            handle,
+            end_handle,
+            next_handle: end_handle + 1,
+            last_characteristic,
+            started: false,
+        });
+
+        let mut handles = handles;
+        let param = esp_ble_gatts_cb_param_t {
+            add_attr_tab: esp_ble_gatts_cb_param_t_gatts_add_attr_tab_evt_param {
+                status: esp_gatt_status_t_ESP_GATT_OK,
+                svc_uuid: service_uuid.into(),
+                svc_inst_id: service_instance,
+                num_handle: count,
+                handles: handles.as_mut_ptr(),
+            },
+        };
+
+        self.enqueue(state, |state| {
+            state.push_gatts_payload(
+                esp_gatts_cb_event_t_ESP_GATTS_CREAT_ATTR_TAB_EVT,
+                gatts_if,
+                param,
+                Payload::Handles(handles),
+            );
+        });
+
+        Ok(())
+    }
+
+    fn start_service(&self, service_handle: u16) -> Result<(), EspError> {
+        let mut state = self.state();
+        let service = state
+            .services
+            .iter_mut()
+            .find(|service| service.handle == service_handle)
+            .ok_or_else(Self::invalid_argument)?;
+        service.started = true;
+        let gatts_if = service.interface;
+
+        let param = esp_ble_gatts_cb_param_t {
+            start: esp_ble_gatts_cb_param_t_gatts_start_evt_param {
+                status: esp_gatt_status_t_ESP_GATT_OK,
+                service_handle,
+            },
+        };
+
+        self.enqueue(state, |state| {
+            state.push_gatts(
+                esp_gatts_cb_event_t_ESP_GATTS_START_EVT,
+                gatts_if,
+                param,
+                Vec::new(),
+            );
+        });
+
+        Ok(())
+    }
+
+    fn stop_service(&self, service_handle: u16) -> Result<(), EspError> {
+        let mut state = self.state();
+        let service = state
+            .services
+            .iter_mut()
+            .find(|service| service.handle == service_handle)
+            .ok_or_else(Self::invalid_argument)?;
+        service.started = false;
+        let gatts_if = service.interface;
+
+        let param = esp_ble_gatts_cb_param_t {
+            stop: esp_ble_gatts_cb_param_t_gatts_stop_evt_param {
+                status: esp_gatt_status_t_ESP_GATT_OK,
+                service_handle,
+            },
+        };
+
+        self.enqueue(state, |state| {
+            state.push_gatts(
+                esp_gatts_cb_event_t_ESP_GATTS_STOP_EVT,
+                gatts_if,
+                param,
+                Vec::new(),
+            );
+        });
+
+        Ok(())
+    }
+
+    fn delete_service(&self, service_handle: u16) -> Result<(), EspError> {
+        let mut state = self.state();
+        let index = state
+            .services
+            .iter()
+            .position(|service| service.handle == service_handle)
+            .ok_or_else(Self::invalid_argument)?;
+
+        // The handles of deleted services are not assigned again.
+        let service = state.services.remove(index);
+        state
+            .attributes
+            .retain(|handle, _| !(service.handle..=service.end_handle).contains(handle));
+
+        let param = esp_ble_gatts_cb_param_t {
+            del: esp_ble_gatts_cb_param_t_gatts_delete_evt_param {
+                status: esp_gatt_status_t_ESP_GATT_OK,
+                service_handle,
+            },
+        };
+
+        self.enqueue(state, |state| {
+            state.push_gatts(
+                esp_gatts_cb_event_t_ESP_GATTS_DELETE_EVT,
+                service.interface,
+                param,
+                Vec::new(),
+            );
+        });
+
+        Ok(())
+    }
+
+    fn send_service_change_indication(
+        &self,
+        gatts_if: esp_gatt_if_t,
+        address: Option<esp_bd_addr_t>,
+    ) -> Result<(), EspError> {
+        let mut state = self.state();
+        if !state.interfaces.contains(&gatts_if) {
+            return Err(Self::invalid_argument());
+        }
+
+        // Like Bluedroid, the whole range of handles is reported as changed.
+        let mut value = 0x0001u16.to_le_bytes().to_vec();
+        value.extend(0xFFFFu16.to_le_bytes());
+
+        let mut found = false;
+        for connection in state.connections.values_mut() {
+            if address.is_none() || address == Some(connection.address) {
+                found = true;
+                connection.notifications.push(Notification {
+                    handle: SERVICE_CHANGED_HANDLE,
+                    value: value.clone(),
+                    indication: true,
+                });
+            }
+        }
+
+        let param = esp_ble_gatts_cb_param_t {
+            service_change: esp_ble_gatts_cb_param_t_gatts_send_service_change_evt_param {
+                status: if found || address.is_none() {
+                    esp_gatt_status_t_ESP_GATT_OK
+                } else {
+                    esp_gatt_status_t_ESP_GATT_NOT_FOUND
+                },
+            },
+        };
+
+        self.enqueue(state, |state| {
+            state.push_gatts(
+                esp_gatts_cb_event_t_ESP_GATTS_SEND_SERVICE_CHANGE_EVT,
+                gatts_if,
+                param,
+                Vec::new(),
+            );
+        });
+
+        Ok(())
+    }
+
+    fn add_included_service(
+        &self,
+        service_handle: u16,
+        included_service_handle: u16,
+    ) -> Result<(), EspError> {
+        let mut state = self.state();
+        let gatts_if = Self::interface_of_service(&state, service_handle)
+            .ok_or_else(Self::invalid_argument)?;
+
+        let included = state
+            .services
+            .iter()
+            .find(|service| service.handle == included_service_handle)
+            .map(|service| (service.handle, service.end_handle))
+            .ok_or_else(Self::invalid_argument)?;
+        let included_uuid = state
+            .attributes
+            .get(&included_service_handle)
+            .map(|declaration| declaration.value.clone())
+            .ok_or_else(Self::invalid_argument)?;
+
+        let (status, attr_handle) =
+            if let Some(handle) = Self::allocate_handle(&mut state, service_handle) {
+                // Like Bluedroid, the UUID of the included service is only part of the value if it is 16-bit.
+                let mut value = Vec::new();
+                value.extend_from_slice(&included.0.to_le_bytes());
+                value.extend_from_slice(&included.1.to_le_bytes());
+                if included_uuid.len() == 2 {
+                    value.extend(included_uuid);
+                }
+
+                state.attributes.insert(
+                    handle,
+                    SimulatedAttribute {
+                        handle,
+                        uuid: BleUuid::Uuid16(0x2802),
+                        kind: AttributeKind::Include,
+                        permissions: ESP_GATT_PERM_READ as esp_gatt_perm_t,
+                        properties: 0,
+                        max_length: value.len() as u16,
+                        value,
+                        automatic_response: true,
+                    },
+                );
+
+                (esp_gatt_status_t_ESP_GATT_OK, handle)
+            } else {
+                (esp_gatt_status_t_ESP_GATT_NO_RESOURCES, 0)
+            };
+
+        let param = esp_ble_gatts_cb_param_t {
+            add_incl_srvc: esp_ble_gatts_cb_param_t_gatts_add_incl_srvc_evt_param {
+                status,
+                attr_handle,
+                service_handle,
+            },
+        };
+
+        self.enqueue(state, |state| {
+            state.push_gatts(
+                esp_gatts_cb_event_t_ESP_GATTS_ADD_INCL_SRVC_EVT,
+                gatts_if,
+                param,
+                Vec::new(),
+            );
+        });
+
+        Ok(())
+    }
+
+    fn add_char(
+        &self,
+        service_handle: u16,
+        uuid: esp_bt_uuid_t,
+        permissions: esp_gatt_perm_t,
+        properties: esp_gatt_char_prop_t,
+        value: esp_attr_value_t,
+        control: esp_attr_control_t,
+    ) -> Result<(), EspError> {
+        let mut state = self.state();
+        let gatts_if = Self::interface_of_service(&state, service_handle)
+            .ok_or_else(Self::invalid_argument)?;
+
+        let handles = Self::allocate_handle(&mut state, service_handle)
+            .zip(Self::allocate_handle(&mut state, service_handle));
+
+        let (status, attr_handle) = if let Some((declaration_handle, value_handle)) = handles {
+            let characteristic_uuid = BleUuid::from(uuid);
+
+            let mut declaration = vec![properties];
+            declaration.extend_from_slice(&value_handle.to_le_bytes());
+            declaration.extend(characteristic_uuid.to_le_bytes());
+
+            state.attributes.insert(
+                declaration_handle,
+                SimulatedAttribute {
+                    handle: declaration_handle,
+                    uuid: BleUuid::Uuid16(0x2803),
+                    kind: AttributeKind::CharacteristicDeclaration,
+                    permissions: ESP_GATT_PERM_READ as esp_gatt_perm_t,
+                    properties: 0,
+                    max_length: declaration.len() as u16,
+                    value: declaration,
+                    automatic_response: true,
+                },
+            );
+            state.attributes.insert(
+                value_handle,
+                SimulatedAttribute {
+                    handle: value_handle,
+                    uuid: characteristic_uuid,
+                    kind: AttributeKind::CharacteristicValue,
+                    permissions,
+                    properties,
+                    value: Self::initial_value(&value),
+                    max_length: value.attr_max_len,
+                    automatic_response: control.auto_rsp == ESP_GATT_AUTO_RSP as u8,
+                },
+            );
+
+            if let Some(service) = state
+                .services
+                .iter_mut()
+                .find(|service| service.handle == service_handle)
+            {
+                service.last_characteristic = Some(value_handle);
+            }
+
+            (esp_gatt_status_t_ESP_GATT_OK, value_handle)
+        } else {
+            (esp_gatt_status_t_ESP_GATT_NO_RESOURCES, 0)
+        };
+
+        let param = esp_ble_gatts_cb_param_t {
+            add_char: esp_ble_gatts_cb_param_t_gatts_add_char_evt_param {
+                status,
+                attr_handle,
+                service_handle,
+                char_uuid: uuid,
+            },
+        };
+
+        self.enqueue(state, |state| {
+            state.push_gatts(
+                esp_gatts_cb_event_t_ESP_GATTS_ADD_CHAR_EVT,
+                gatts_if,
+                param,
+                Vec::new(),
+            );
+        });
+
+        Ok(())
+    }
+
+    fn add_char_descr(
+        &self,
+        service_handle: u16,
+        uuid: esp_bt_uuid_t,
+        permissions: esp_gatt_perm_t,
+        value: esp_attr_value_t,
+        control: esp_attr_control_t,
+    ) -> Result<(), EspError> {
+        let mut state = self.state();
+        let gatts_if = Self::interface_of_service(&state, service_handle)
+            .ok_or_else(Self::invalid_argument)?;
+
+        let has_characteristic = state.services.iter().any(|service| {
+            service.handle == service_handle && service.last_characteristic.is_some()
+        });
+
+        let handle = has_characteristic
+            .then(|| Self::allocate_handle(&mut state, service_handle))
+            .flatten();
+
+        let (status, attr_handle) = if let Some(handle) = handle {
+            state.attributes.insert(
+                handle,
+                SimulatedAttribute {
+                    handle,
+                    uuid: BleUuid::from(uuid),
+                    kind: AttributeKind::Descriptor,
+                    permissions,
+                    properties: 0,
+                    value: Self::initial_value(&value),
+                    max_length: value.attr_max_len,
+                    automatic_response: control.auto_rsp == ESP_GATT_AUTO_RSP as u8,
+                },
+            );
+
+            (esp_gatt_status_t_ESP_GATT_OK, handle)
+        } else {
+            (esp_gatt_status_t_ESP_GATT_NO_RESOURCES, 0)
+        };
+
+        let param = esp_ble_gatts_cb_param_t {
+            add_char_descr: esp_ble_gatts_cb_param_t_gatts_add_char_descr_evt_param {
+                status,
+                attr_handle,
+                service_handle,
+                descr_uuid: uuid,
+            },
+        };
+
+        self.enqueue(state, |state| {
+            state.push_gatts(
+                esp_gatts_cb_event_t_ESP_GATTS_ADD_CHAR_DESCR_EVT,
+                gatts_if,
+                param,
+                Vec::new(),
+            );
+        });
+
+        Ok(())
+    }
+
+    fn set_attr_value(&self, attribute_handle: u16, value: &[u8]) -> Result<(), EspError> {
+        let mut state = self.state();
+        let service = state
+            .service_containing(attribute_handle)
+            .map(|service| (service.interface, service.handle));
+        let Some((gatts_if, srvc_handle)) = service else {
+            return Err(Self::invalid_argument());
+        };
+
+        let status = match state.attributes.get_mut(&attribute_handle) {
+            Some(attribute) if value.len() <= attribute.max_length as usize => {
+                attribute.value = value.to_vec();
+                esp_gatt_status_t_ESP_GATT_OK
+            }
+            Some(_) => esp_gatt_status_t_ESP_GATT_INVALID_ATTR_LEN,
+            None => esp_gatt_status_t_ESP_GATT_INVALID_HANDLE,
+        };
+
+        let param = esp_ble_gatts_cb_param_t {
+            set_attr_val: esp_ble_gatts_cb_param_t_gatts_set_attr_val_evt_param {
+                srvc_handle,
+                attr_handle: attribute_handle,
+                status,
+            },
+        };
+
+        self.enqueue(state, |state| {
+            state.push_gatts(
+                esp_gatts_cb_event_t_ESP_GATTS_SET_ATTR_VAL_EVT,
+                gatts_if,
+                param,
+                Vec::new(),
+            );
+        });
+
+        Ok(())
+    }
+
+    fn get_attr_value(&self, attribute_handle: u16) -> Result<Vec<u8>, EspError> {
+        self.state()
+            .attributes
+            .get(&attribute_handle)
+            .map(|attribute| attribute.value.clone())
+            .ok_or_else(Self::invalid_argument)
+    }
+
+    fn send_indicate(
+        &self,
+        gatts_if: esp_gatt_if_t,
+        conn_id: u16,
+        attribute_handle: u16,
+        value: &[u8],
+        need_confirm: bool,
+    ) -> Result<(), EspError> {
+        let mut state = self.state();
+        let Some(connection) = state.connections.get_mut(&conn_id) else {
+            return Err(EspError::from_infallible::<ESP_ERR_INVALID_STATE>());
+        };
+
+        // Like Bluedroid, silently truncate values that do not fit in the MTU.
+        let length = value.len().min(connection.mtu as usize - 3);
+        connection.notifications.push(Notification {
+            handle: attribute_handle,
+            value: value[..length].to_vec(),
+            indication: need_confirm,
+        });
+
+        if need_confirm {
+            let param = esp_ble_gatts_cb_param_t {
+                conf: esp_ble_gatts_cb_param_t_gatts_conf_evt_param {
+                    status: esp_gatt_status_t_ESP_GATT_OK,
+                    conn_id,
+                    handle: attribute_handle,
+                    len: length as u16,
+                    value: std::ptr::null_mut(),
+                },
+            };
+
+            self.enqueue(state, |state| {
+                state.push_gatts(
+                    esp_gatts_cb_event_t_ESP_GATTS_CONF_EVT,
+                    gatts_if,
+                    param,
+                    Vec::new(),
+                );
+            });
+        }
+
+        Ok(())
+    }
+
+    fn send_response(
+        &self,
+        gatts_if: esp_gatt_if_t,
+        _conn_id: u16,
+        trans_id: u32,
+        status: esp_gatt_status_t,
+        response: &mut esp_gatt_rsp_t,
+    ) -> Result<(), EspError> {
+        let mut state = self.state();
+        let attr_value = unsafe { response.attr_value };
+        let length = (attr_value.len as usize).min(attr_value.value.len());
+
+        state
+            .responses
+            .insert(trans_id, (status, attr_value.value[..length].to_vec()));
+
+        let param = esp_ble_gatts_cb_param_t {
+            rsp: esp_ble_gatts_cb_param_t_gatts_rsp_evt_param {
+                status,
+                handle: attr_value.handle,
+            },
+        };
+
+        self.enqueue(state, |state| {
+            state.push_gatts(
+                esp_gatts_cb_event_t_ESP_GATTS_RESPONSE_EVT,
+                gatts_if,
+                param,
+                Vec::new(),
+            );
+        });
+
+        Ok(())
+    }
+
+    fn set_device_name(&self, name: &str) -> Result<(), EspError> {
+        self.state().device_name = name.to_string();
+        Ok(())
+    }
+
+    fn config_adv_data_raw(&self, data: &[u8]) -> Result<(), EspError> {
+        let mut state = self.state();
+        if data.len() > LEGACY_PAYLOAD_LENGTH {
+            return Err(EspError::from_infallible::<ESP_ERR_INVALID_ARG>());
+        }
+
+        state.advertising_data = data.to_vec();
+
+        let param = esp_ble_gap_cb_param_t {
+            adv_data_raw_cmpl: esp_ble_gap_cb_param_t_ble_adv_data_raw_cmpl_evt_param {
+                status: esp_bt_status_t_ESP_BT_STATUS_SUCCESS,
+            },
+        };
+
+        self.enqueue(state, |state| {
+            state.push_gap(
+                esp_gap_ble_cb_event_t_ESP_GAP_BLE_ADV_DATA_RAW_SET_COMPLETE_EVT,
+                param,
+            );
+        });
+
+        Ok(())
+    }
+
+    fn config_scan_rsp_data_raw(&self, data: &[u8]) -> Result<(), EspError> {
+        let mut state = self.state();
+        if data.len() > LEGACY_PAYLOAD_LENGTH {
+            return Err(EspError::from_infallible::<ESP_ERR_INVALID_ARG>());
+        }
+
+        state.scan_response = data.to_vec();
+
+        let param = esp_ble_gap_cb_param_t {
+            scan_rsp_data_raw_cmpl: esp_ble_gap_cb_param_t_ble_scan_rsp_data_raw_cmpl_evt_param {
+                status: esp_bt_status_t_ESP_BT_STATUS_SUCCESS,
+            },
+        };
+
+        self.enqueue(state, |state| {
+            state.push_gap(
+                esp_gap_ble_cb_event_t_ESP_GAP_BLE_SCAN_RSP_DATA_RAW_SET_COMPLETE_EVT,
+                param,
+            );
+        });
+
+        Ok(())
+    }
+
+    fn start_advertising(&self, parameters: &mut esp_ble_adv_params_t) -> Result<(), EspError> {
+        let mut state = self.state();
+        state.advertising = true;
+        state.advertising_parameters = *parameters;
+
+        let param = esp_ble_gap_cb_param_t {
+            adv_start_cmpl: esp_ble_gap_cb_param_t_ble_adv_start_cmpl_evt_param {
+                status: esp_bt_status_t_ESP_BT_STATUS_SUCCESS,
+            },
+        };
+
+        self.enqueue(state, |state| {
+            state.push_gap(
+                esp_gap_ble_cb_event_t_ESP_GAP_BLE_ADV_START_COMPLETE_EVT,
+                param,
+            );
+        });
+
+        Ok(())
+    }
+
+    fn stop_advertising(&self) -> Result<(), EspError> {
+        let mut state = self.state();
+        state.advertising = false;
+
+        let param = esp_ble_gap_cb_param_t {
+            adv_stop_cmpl: esp_ble_gap_cb_param_t_ble_adv_stop_cmpl_evt_param {
+                status: esp_bt_status_t_ESP_BT_STATUS_SUCCESS,
+            },
+        };
+
+        self.enqueue(state, |state| {
+            state.push_gap(
+                esp_gap_ble_cb_event_t_ESP_GAP_BLE_ADV_STOP_COMPLETE_EVT,
+                param,
+            );
+        });
+
+        Ok(())
+    }
+
+    fn ext_adv_set_params(
+        &self,
+        instance: u8,
+        parameters: &esp_ble_gap_ext_adv_params_t,
+    ) -> Result<(), EspError> {
+        let mut state = self.state();
+        if instance >= MAX_ADVERTISING_SETS {
+            return Err(Self::invalid_argument());
+        }
+
+        // Parameters cannot change while the set is advertising.
+        let set = state.advertising_sets.entry(instance).or_default();
+        let success = !set.advertising;
+        if success {
+            set.parameters = *parameters;
+        }
+
+        let param = esp_ble_gap_cb_param_t {
+            ext_adv_set_params: esp_ble_gap_cb_param_t_ble_ext_adv_set_params_cmpl_evt_param {
+                status: bt_status(success),
+            },
+        };
+
+        self.enqueue(state, |state| {
+            state.push_gap(
+                esp_gap_ble_cb_event_t_ESP_GAP_BLE_EXT_ADV_SET_PARAMS_COMPLETE_EVT,
+                param,
+            );
+        });
+
+        Ok(())
+    }
+
+    fn config_ext_adv_data_raw(&self, instance: u8, data: &[u8]) -> Result<(), EspError> {
+        let mut state = self.state();
+        if data.len() > EXTENDED_PAYLOAD_LENGTH {
+            return Err(Self::invalid_argument());
+        }
+
+        let success = state
+            .advertising_sets
+            .get_mut(&instance)
+            .filter(|set| {
+                !set.has_property(ESP_BLE_GAP_SET_EXT_ADV_PROP_LEGACY)
+                    || data.len() <= LEGACY_PAYLOAD_LENGTH
+            })
+            .map(|set| set.advertising_data = data.to_vec())
+            .is_some();
+
+        let param = esp_ble_gap_cb_param_t {
+            ext_adv_data_set: esp_ble_gap_cb_param_t_ble_ext_adv_data_set_cmpl_evt_param {
+                status: bt_status(success),
+            },
+        };
+
+        self.enqueue(state, |state| {
+            state.push_gap(
+                esp_gap_ble_cb_event_t_ESP_GAP_BLE_EXT_ADV_DATA_SET_COMPLETE_EVT,
+                param,
+            );
+        });
+
+        Ok(())
+    }
+
+    fn config_ext_scan_rsp_data_raw(&self, instance: u8, data: &[u8]) -> Result<(), EspError> {
+        let mut state = self.state();
+        if data.len() > EXTENDED_PAYLOAD_LENGTH {
+            return Err(Self::invalid_argument());
+        }
+
+        let success = state
+            .advertising_sets
+            .get_mut(&instance)
+            .filter(|set| set.has_property(ESP_BLE_GAP_SET_EXT_ADV_PROP_SCANNABLE))
+            .filter(|set| {
+                !set.has_property(ESP_BLE_GAP_SET_EXT_ADV_PROP_LEGACY)
+                    || data.len() <= LEGACY_PAYLOAD_LENGTH
+            })
+            .map(|set| set.scan_response = data.to_vec())
+            .is_some();
+
+        let param = esp_ble_gap_cb_param_t {
+            scan_rsp_set: esp_ble_gap_cb_param_t_ble_ext_adv_scan_rsp_set_cmpl_evt_param {
+                status: bt_status(success),
+            },
+        };
+
+        self.enqueue(state, |state| {
+            state.push_gap(
+                esp_gap_ble_cb_event_t_ESP_GAP_BLE_EXT_SCAN_RSP_DATA_SET_COMPLETE_EVT,
+                param,
+            );
+        });
+
+        Ok(())
+    }
+
+    fn ext_adv_start(&self, sets: &[esp_ble_gap_ext_adv_t]) -> Result<(), EspError> {
+        let mut state = self.state();
+        if sets.is_empty() {
+            return Err(Self::invalid_argument());
+        }
+
+        let success = sets
+            .iter()
+            .all(|set| state.advertising_sets.contains_key(&set.instance));
+        if success {
+            for start_parameters in sets {
+                let set = state
+                    .advertising_sets
+                    .get_mut(&start_parameters.instance)
+                    .unwrap();
+                set.advertising = true;
+                set.start_parameters = *start_parameters;
+            }
+        }
+
+        let param = esp_ble_gap_cb_param_t {
+            ext_adv_start: esp_ble_gap_cb_param_t_ble_ext_adv_start_cmpl_evt_param {
+                status: bt_status(success),
+            },
+        };
+
+        self.enqueue(state, |state| {
+            state.push_gap(
+                esp_gap_ble_cb_event_t_ESP_GAP_BLE_EXT_ADV_START_COMPLETE_EVT,
+                param,
+            );
+        });
+
+        Ok(())
+    }
+
+    fn ext_adv_stop(&self, instances: &[u8]) -> Result<(), EspError> {
+        let mut state = self.state();
+        for instance in instances {
+            if let Some(set) = state.advertising_sets.get_mut(instance) {
+                set.advertising = false;
+            }
+        }
+
+        let param = esp_ble_gap_cb_param_t {
+            ext_adv_stop: esp_ble_gap_cb_param_t_ble_ext_adv_stop_cmpl_evt_param {
+                status: esp_bt_status_t_ESP_BT_STATUS_SUCCESS,
+            },
+        };
+
+        self.enqueue(state, |state| {
+            state.push_gap(
+                esp_gap_ble_cb_event_t_ESP_GAP_BLE_EXT_ADV_STOP_COMPLETE_EVT,
+                param,
+            );
+        });
+
+        Ok(())
+    }
+
+    fn ext_adv_set_remove(&self, instance: u8) -> Result<(), EspError> {
+        let mut state = self.state();
+        let success = state.advertising_sets.remove(&instance).is_some();
+
+        let param = esp_ble_gap_cb_param_t {
+            ext_adv_remove: esp_ble_gap_cb_param_t_ble_ext_adv_set_remove_cmpl_evt_param {
+                status: bt_status(success),
+            },
+        };
+
+        self.enqueue(state, |state| {
+            state.push_gap(
+                esp_gap_ble_cb_event_t_ESP_GAP_BLE_EXT_ADV_SET_REMOVE_COMPLETE_EVT,
+                param,
+            );
+        });
+
+        Ok(())
+    }
+
+    fn periodic_adv_set_params(
+        &self,
+        instance: u8,
+        parameters: esp_ble_gap_periodic_adv_params_t,
+    ) -> Result<(), EspError> {
+        let mut state = self.state();
+
+        // Periodic advertising requires a non-connectable, non-scannable extended set.
+        let success = state
+            .advertising_sets
+            .get_mut(&instance)
+            .filter(|set| {
+                !set.has_property(
+                    ESP_BLE_GAP_SET_EXT_ADV_PROP_CONNECTABLE
+                        | ESP_BLE_GAP_SET_EXT_ADV_PROP_SCANNABLE
+                        | ESP_BLE_GAP_SET_EXT_ADV_PROP_LEGACY,
+                )
+            })
+            .map(|set| set.periodic_parameters = Some(parameters))
+            .is_some();
+
+        let param = esp_ble_gap_cb_param_t {
+            peroid_adv_set_params: esp_ble_gap_cb_param_t_ble_periodic_adv_set_params_cmpl_param {
+                status: bt_status(success),
+            },
+        };
+
+        self.enqueue(state, |state| {
+            state.push_gap(
+                esp_gap_ble_cb_event_t_ESP_GAP_BLE_PERIODIC_ADV_SET_PARAMS_COMPLETE_EVT,
+                param,
+            );
+        });
+
+        Ok(())
+    }
+
+    fn config_periodic_adv_data_raw(&self, instance: u8, data: &[u8]) -> Result<(), EspError> {
+        let mut state = self.state();
+        if data.len() > EXTENDED_PAYLOAD_LENGTH {
+            return Err(Self::invalid_argument());
+        }
+
+        let success = state
+            .advertising_sets
+            .get_mut(&instance)
+            .filter(|set| set.periodic_parameters.is_some())
+            .map(|set| set.periodic_data = data.to_vec())
+            .is_some();
+
+        let param = esp_ble_gap_cb_param_t {
+            period_adv_data_set: esp_ble_gap_cb_param_t_ble_periodic_adv_data_set_cmpl_param {
+                status: bt_status(success),
+            },
+        };
+
+        self.enqueue(state, |state| {
+            state.push_gap(
+                esp_gap_ble_cb_event_t_ESP_GAP_BLE_PERIODIC_ADV_DATA_SET_COMPLETE_EVT,
+                param,
+            );
+        });
+
+        Ok(())
+    }
+
+    fn periodic_adv_start(&self, instance: u8) -> Result<(), EspError> {
+        let mut state = self.state();
+        let success = state
+            .advertising_sets
+            .get_mut(&instance)
+            .filter(|set| set.periodic_parameters.is_some())
+            .map(|set| set.periodic_advertising = true)
+            .is_some();
+
+        let param = esp_ble_gap_cb_param_t {
+            period_adv_start: esp_ble_gap_cb_param_t_ble_periodic_adv_start_cmpl_param {
+                status: bt_status(success),
+            },
+        };
+
+        self.enqueue(state, |state| {
+            state.push_gap(
+                esp_gap_ble_cb_event_t_ESP_GAP_BLE_PERIODIC_ADV_START_COMPLETE_EVT,
+                param,
+            );
+        });
+
+        Ok(())
+    }
+
+    fn periodic_adv_stop(&self, instance: u8) -> Result<(), EspError> {
+        let mut state = self.state();
+        if let Some(set) = state.advertising_sets.get_mut(&instance) {
+            set.periodic_advertising = false;
+        }
+
+        let param = esp_ble_gap_cb_param_t {
+            period_adv_stop: esp_ble_gap_cb_param_t_ble_periodic_adv_stop_cmpl_param {
+                status: esp_bt_status_t_ESP_BT_STATUS_SUCCESS,
+            },
+        };
+
+        self.enqueue(state, |state| {
+            state.push_gap(
+                esp_gap_ble_cb_event_t_ESP_GAP_BLE_PERIODIC_ADV_STOP_COMPLETE_EVT,
+                param,
+            );
+        });
+
+        Ok(())
+    }
+
+    fn disconnect_device(&self, address: esp_bd_addr_t) -> Result<(), EspError> {
+        let mut state = self.state();
+        let Some(conn_id) = state
+            .connections
+            .iter()
+            .find(|(_, connection)| connection.address == address)
+            .map(|(conn_id, _)| *conn_id)
+        else {
+            return Err(EspError::from_infallible::<ESP_FAIL>());
+        };
+        state.connections.remove(&conn_id);
+
+        let param = esp_ble_gatts_cb_param_t {
+            disconnect: esp_ble_gatts_cb_param_t_gatts_disconnect_evt_param {
+                conn_id,
+                link_role: 1,
+                remote_bda: address,
+                reason: esp_gatt_conn_reason_t_ESP_GATT_CONN_TERMINATE_LOCAL_HOST,
+            },
+        };
+
+        self.enqueue(state, |state| {
+            state.broadcast_gatts(esp_gatts_cb_event_t_ESP_GATTS_DISCONNECT_EVT, param);
+        });
+
+        Ok(())
+    }
+
+    fn read_rssi(&self, address: esp_bd_addr_t) -> Result<(), EspError> {
+        let state = self.state();
+        let Some(connection) = state
+            .connections
+            .values()
+            .find(|connection| connection.address == address)
+        else {
+            return Err(EspError::from_infallible::<ESP_FAIL>());
+        };
+
+        let param = esp_ble_gap_cb_param_t {
+            read_rssi_cmpl: esp_ble_gap_cb_param_t_ble_read_rssi_cmpl_evt_param {
+                status: esp_bt_status_t_ESP_BT_STATUS_SUCCESS,
+                rssi: connection.rssi,
+                remote_addr: address,
+            },
+        };
+
+        self.enqueue(state, |state| {
+            state.push_gap(
+                esp_gap_ble_cb_event_t_ESP_GAP_BLE_READ_RSSI_COMPLETE_EVT,
+                param,
+            );
+        });
+
+        Ok(())
+    }
+
+    fn set_local_mtu(&self, mtu: u16) -> Result<(), EspError> {
+        let mut state = self.state();
+        if state.gatts_callback.is_none() {
+            return Err(EspError::from_infallible::<ESP_ERR_INVALID_STATE>());
+        }
+
+        if !(DEFAULT_MTU..=MAX_MTU).contains(&mtu) {
+            return Err(Self::invalid_argument());
+        }
+
+        state.local_mtu = mtu;
+        Ok(())
+    }
+
+    fn set_pkt_data_len(&self, address: esp_bd_addr_t, tx_octets: u16) -> Result<(), EspError> {
+        let state = self.state();
+        if !state
+            .connections
+            .values()
+            .any(|connection| connection.address == address)
+        {
+            return Err(EspError::from_infallible::<ESP_FAIL>());
+        }
+
+        // Like most clients, the simulated one accepts the same length in both directions.
+        let length = tx_octets.min(MAX_DATA_LENGTH);
+        let param = esp_ble_gap_cb_param_t {
+            pkt_data_length_cmpl: esp_ble_gap_cb_param_t_ble_pkt_data_length_cmpl_evt_param {
+                status: esp_bt_status_t_ESP_BT_STATUS_SUCCESS,
+                params: esp_ble_pkt_data_length_params_t {
+                    rx_len: length,
+                    tx_len: length,
+                },
+            },
+        };
+
+        self.enqueue(state, |state| {
+            state.push_gap(
+                esp_gap_ble_cb_event_t_ESP_GAP_BLE_SET_PKT_LENGTH_COMPLETE_EVT,
+                param,
+            );
+        });
+
+        Ok(())
+    }
+
+    fn set_preferred_phy(
+        &self,
+        address: esp_bd_addr_t,
+        tx_phys: u8,
+        rx_phys: u8,
+    ) -> Result<(), EspError> {
+        let state = self.state();
+        if !state
+            .connections
+            .values()
+            .any(|connection| connection.address == address)
+        {
+            return Err(EspError::from_infallible::<ESP_FAIL>());
+        }
+
+        // The simulated client supports every PHY, and prefers the fastest one.
+        let choose = |phys: u8| match phys {
+            phys if phys & 0b010 != 0 => 2,
+            phys if phys & 0b001 != 0 => 1,
+            _ => 3,
+        };
+
+        let param = esp_ble_gap_cb_param_t {
+            phy_update: esp_ble_gap_cb_param_t_ble_phy_update_cmpl_evt_param {
+                status: esp_bt_status_t_ESP_BT_STATUS_SUCCESS,
+                bda: address,
+                tx_phy: choose(tx_phys),
+                rx_phy: choose(rx_phys),
+            },
+        };
+
+        self.enqueue(state, |state| {
+            state.push_gap(
+                esp_gap_ble_cb_event_t_ESP_GAP_BLE_SET_PREFERED_PHY_COMPLETE_EVT,
+                esp_ble_gap_cb_param_t {
+                    set_perf_phy: esp_ble_gap_cb_param_t_ble_set_perf_phy_cmpl_evt_param {
+                        status: esp_bt_status_t_ESP_BT_STATUS_SUCCESS,
+                    },
+                },
+            );
+            state.push_gap(
+                esp_gap_ble_cb_event_t_ESP_GAP_BLE_PHY_UPDATE_COMPLETE_EVT,
+                param,
+            );
+        });
+
+        Ok(())
+    }
+
+    fn update_conn_params(&self, params: &esp_ble_conn_update_params_t) -> Result<(), EspError> {
+        let mut state = self.state();
+        let Some(connection) = state
+            .connections
+            .values_mut()
+            .find(|connection| connection.address == params.bda)
+        else {
+            return Err(EspError::from_infallible::<ESP_FAIL>());
+        };
+
+        // Like most centrals, the client keeps its interval if it is in the requested range.
+        let interval = connection
+            .parameters
+            .interval
+            .clamp(params.min_int, params.max_int);
+        connection.parameters = esp_gatt_conn_params_t {
+            interval,
+            latency: params.latency,
+            timeout: params.timeout,
+        };
+
+        let param = esp_ble_gap_cb_param_t {
+            update_conn_params: esp_ble_gap_cb_param_t_ble_update_conn_params_evt_param {
+                status: esp_bt_status_t_ESP_BT_STATUS_SUCCESS,
+                bda: params.bda,
+                min_int: params.min_int,
+                max_int: params.max_int,
+                latency: params.latency,
+                conn_int: interval,
+                timeout: params.timeout,
+            },
+        };
+
+        self.enqueue(state, |state| {
+            state.push_gap(
+                esp_gap_ble_cb_event_t_ESP_GAP_BLE_UPDATE_CONN_PARAMS_EVT,
+                param,
+            );
+        });
+
+        Ok(())
+    }
+
+    fn set_preferred_conn_params(
+        &self,
+        params: &esp_ble_conn_update_params_t,
+    ) -> Result<(), EspError> {
+        let mut state = self.state();
+        if state.gatts_callback.is_none() {
+            return Err(EspError::from_infallible::<ESP_ERR_INVALID_STATE>());
+        }
+
+        state.preferred_connection_parameters = Some(esp_ble_conn_update_params_t {
+            bda: [0; 6],
+            ..*params
+        });
+        Ok(())
+    }
+
+    fn set_security_param(&self, param: esp_ble_sm_param_t, value: &[u8]) -> Result<(), EspError> {
+        let mut state = self.state();
+        if state.gatts_callback.is_none() {
+            return Err(EspError::from_infallible::<ESP_ERR_INVALID_STATE>());
+        }
+
+        state.security_parameters.insert(param, value.to_vec());
+        Ok(())
+    }
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn security_response(&self, address: esp_bd_addr_t, accept: bool) -> Result<(), EspError> {
+        let state = self.state();
+        let Some(method) = state.pairings.get(&address).map(|pairing| pairing.method) else {
+            return Err(Self::invalid_argument());
+        };
+
+        let authentication_requirements = state
+            .security_parameter(esp_ble_sm_param_t_ESP_BLE_SM_AUTHEN_REQ_MODE)
+            .unwrap_or_default();
+        let mitm_enforced = authentication_requirements & ESP_LE_AUTH_REQ_MITM as u8 != 0
+            && state
+                .security_parameter(esp_ble_sm_param_t_ESP_BLE_SM_ONLY_ACCEPT_SPECIFIED_SEC_AUTH)
+                == Some(ESP_BLE_ONLY_ACCEPT_SPECIFIED_AUTH_ENABLE as u8);
+
+        self.enqueue(state, |state| {
+            if !accept {
+                state.complete_pairing(address, Err(SMP_PAIRING_NOT_SUPPORTED));
+                return;
+            }
+
+            if mitm_enforced && method == PairingMethod::JustWorks {
+                state.complete_pairing(address, Err(SMP_AUTHENTICATION_REQUIREMENTS));
+                return;
+            }
+
+            let request = esp_ble_gap_cb_param_t {
+                ble_security: esp_ble_sec_t {
+                    ble_req: esp_ble_sec_req_t { bd_addr: address },
+                },
+            };
+            let notification = |passkey| esp_ble_gap_cb_param_t {
+                ble_security: esp_ble_sec_t {
+                    key_notif: esp_ble_sec_key_notif_t {
+                        bd_addr: address,
+                        passkey,
+                    },
+                },
+            };
+            let client_passkey = state.pairings[&address].passkey;
+
+            match method {
+                PairingMethod::JustWorks => state.complete_pairing(address, Ok(())),
+                PairingMethod::ServerDisplays => {
+                    let passkey = state
+                        .security_parameters
+                        .get(&esp_ble_sm_param_t_ESP_BLE_SM_SET_STATIC_PASSKEY)
+                        .and_then(|value| value.as_slice().try_into().ok())
+                        .map_or(RANDOM_PASSKEY, u32::from_ne_bytes);
+
+                    state.push_gap(
+                        esp_gap_ble_cb_event_t_ESP_GAP_BLE_PASSKEY_NOTIF_EVT,
+                        notification(passkey),
+                    );
+
+                    let result = if passkey == client_passkey {
+                        Ok(())
+                    } else {
+                        Err(SMP_PASSKEY_ENTRY_FAILED)
+                    };
+                    state.complete_pairing(address, result);
+                }
+                PairingMethod::ServerEnters => {
+                    state.push_gap(esp_gap_ble_cb_event_t_ESP_GAP_BLE_PASSKEY_REQ_EVT, request);
+                }
+                PairingMethod::NumericComparison => {
+                    state.push_gap(
+                        esp_gap_ble_cb_event_t_ESP_GAP_BLE_NC_REQ_EVT,
+                        notification(client_passkey),
+                    );
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    fn passkey_reply(
+        &self,
+        address: esp_bd_addr_t,
+        accept: bool,
+        passkey: u32,
+    ) -> Result<(), EspError> {
+        let state = self.state();
+        let Some(pairing) = state
+            .pairings
+            .get(&address)
+            .filter(|pairing| pairing.method == PairingMethod::ServerEnters)
+        else {
+            return Err(Self::invalid_argument());
+        };
+
+        let result = if accept && passkey == pairing.passkey {
+            Ok(())
+        } else {
+            Err(SMP_PASSKEY_ENTRY_FAILED)
+        };
+
+        self.enqueue(state, |state| state.complete_pairing(address, result));
+        Ok(())
+    }
+
+    fn confirm_reply(&self, address: esp_bd_addr_t, accept: bool) -> Result<(), EspError> {
+        let state = self.state();
+        if !state.pairings.get(&address).map_or(false, |pairing| {
+            pairing.method == PairingMethod::NumericComparison
+        }) {
+            return Err(Self::invalid_argument());
+        }
+
+        let result = if accept {
+            Ok(())
+        } else {
+            Err(SMP_NUMERIC_COMPARISON_FAILED)
+        };
+
+        self.enqueue(state, |state| state.complete_pairing(address, result));
+        Ok(())
+    }
+
+    fn remove_bond_device(&self, address: esp_bd_addr_t) -> Result<(), EspError> {
+        let mut state = self.state();
+        let Some(index) = state.bonds.iter().position(|bond| *bond == address) else {
+            return Err(EspError::from_infallible::<ESP_FAIL>());
+        };
+        state.bonds.remove(index);
+        state.irks.remove(&address);
+
+        let param = esp_ble_gap_cb_param_t {
+            remove_bond_dev_cmpl: esp_ble_gap_cb_param_t_ble_remove_bond_dev_cmpl_evt_param {
+                status: esp_bt_status_t_ESP_BT_STATUS_SUCCESS,
+                bd_addr: address,
+            },
+        };
+
+        self.enqueue(state, |state| {
+            state.push_gap(
+                esp_gap_ble_cb_event_t_ESP_GAP_BLE_REMOVE_BOND_DEV_COMPLETE_EVT,
+                param,
+            );
+        });
+
+        Ok(())
+    }
+
+    fn bonded_devices(&self) -> Result<Vec<esp_bd_addr_t>, EspError> {
+        Ok(self.state().bonds.clone())
+    }
+
+    fn identity_keys(&self) -> Result<Vec<(esp_bd_addr_t, [u8; 16])>, EspError> {
+        let state = self.state();
+
+        Ok(state
+            .bonds
+            .iter()
+            .filter_map(|bond| state.irks.get(bond).map(|irk| (*bond, *irk)))
+            .collect())
+    }
+
+    fn storage_get(&self, key: &str) -> Result<Option<Vec<u8>>, EspError> {
+        Ok(self.state().storage.get(key).cloned())
+    }
+
+    fn storage_set(&self, key: &str, value: &[u8]) -> Result<(), EspError> {
+        self.state().storage.insert(key.to_string(), value.to_vec());
+        Ok(())
+    }
+
+    fn storage_remove(&self, key: &str) -> Result<(), EspError> {
+        self.state().storage.remove(key);
+        Ok(())
+    }
+
+    fn storage_clear(&self) -> Result<(), EspError> {
+        self.state().storage.clear();
+        Ok(())
+    }
+}
+
+/// Returns the attributes of the Generic Attribute service provided by the stack.
+#[allow(clippy::cast_sign_loss)]
+fn generic_attribute_service() -> BTreeMap<u16, SimulatedAttribute> {
+    let read = ESP_GATT_PERM_READ as esp_gatt_perm_t;
+    let read_write = (ESP_GATT_PERM_READ | ESP_GATT_PERM_WRITE) as esp_gatt_perm_t;
+
+    let declaration = |handle: u16, properties: i32, uuid: u16| {
+        let mut value = vec![properties as u8];
+        value.extend((handle + 1).to_le_bytes());
+        value.extend(uuid.to_le_bytes());
+
+        SimulatedAttribute {
+            handle,
+            uuid: BleUuid::Uuid16(0x2803),
+            kind: AttributeKind::CharacteristicDeclaration,
+            permissions: read,
+            properties: 0,
+            max_length: value.len() as u16,
+            value,
+            automatic_response: true,
+        }
+    };
+
+    let attribute = |handle: u16, uuid: u16, kind, permissions, properties: i32, length: u16| {
+        SimulatedAttribute {
+            handle,
+            uuid: BleUuid::Uuid16(uuid),
+            kind,
+            permissions,
+            properties: properties as esp_gatt_char_prop_t,
+            value: vec![0; length as usize],
+            max_length: length,
+            automatic_response: true,
+        }
+    };
+
+    let mut service = attribute(1, 0x2800, AttributeKind::PrimaryService, read, 0, 2);
+    service.value = 0x1801u16.to_le_bytes().to_vec();
+
+    [
+        service,
+        declaration(2, ESP_GATT_CHAR_PROP_BIT_INDICATE, 0x2A05),
+        attribute(
+            SERVICE_CHANGED_HANDLE,
+            0x2A05,
+            AttributeKind::CharacteristicValue,
+            0,
+            ESP_GATT_CHAR_PROP_BIT_INDICATE,
+            4,
+        ),
+        attribute(
+            SERVICE_CHANGED_CCCD_HANDLE,
+            0x2902,
+            AttributeKind::Descriptor,
+            read_write,
+            0,
+            2,
+        ),
+        declaration(
+            5,
+            ESP_GATT_CHAR_PROP_BIT_READ | ESP_GATT_CHAR_PROP_BIT_WRITE,
+            0x2B29,
+        ),
+        attribute(
+            CLIENT_SUPPORTED_FEATURES_HANDLE,
+            0x2B29,
+            AttributeKind::CharacteristicValue,
+            read_write,
+            ESP_GATT_CHAR_PROP_BIT_READ | ESP_GATT_CHAR_PROP_BIT_WRITE,
+            1,
+        ),
+        declaration(7, ESP_GATT_CHAR_PROP_BIT_READ, 0x2B2A),
+        attribute(
+            DATABASE_HASH_HANDLE,
+            0x2B2A,
+            AttributeKind::CharacteristicValue,
+            read,
+            ESP_GATT_CHAR_PROP_BIT_READ,
+            16,
+        ),
+    ]
+    .into_iter()
+    .map(|attribute| (attribute.handle, attribute))
+    .collect()
+}
+
+/// Returns the IRK of a simulated client, derived from its identity address.
+fn client_irk(identity: esp_bd_addr_t) -> [u8; 16] {
+    let mut irk = [0x5A; 16];
+    irk[..6].copy_from_slice(&identity);
+    irk
+}
+
+/// Returns the status reported for an operation.
+fn bt_status(success: bool) -> esp_bt_status_t {
+    if success {
+        esp_bt_status_t_ESP_BT_STATUS_SUCCESS
+    } else {
+        esp_bt_status_t_ESP_BT_STATUS_FAIL
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Once;
+
+    /// The application registered by the tests, next to the ones of a GATT server.
+    const APP_ID: u16 = 0x0B1D;
+
+    /// The value that the application answers to the reads it is asked for.
+    const RESPONSE: &[u8] = b"app";
+
+    static INTERFACE: Mutex<Option<esp_gatt_if_t>> = Mutex::new(None);
+
+    /// Records the interface of the application, and answers the reads that need a response.
+    unsafe extern "C" fn gatts_callback(
+        event: esp_gatts_cb_event_t,
+        gatts_if: esp_gatt_if_t,
+        param: *mut esp_ble_gatts_cb_param_t,
+    ) {
+        #[allow(non_upper_case_globals)]
+        match event {
+            esp_gatts_cb_event_t_ESP_GATTS_REG_EVT if (*param).reg.app_id == APP_ID => {
+                *INTERFACE.lock().unwrap() = Some(gatts_if);
+            }
+            esp_gatts_cb_event_t_ESP_GATTS_READ_EVT if (*param).read.need_rsp => {
+                let read = (*param).read;
+                let mut response = esp_gatt_rsp_t::default();
+                response.attr_value.handle = read.handle;
+                response.attr_value.len = RESPONSE.len() as u16;
+                response.attr_value.value[..RESPONSE.len()].copy_from_slice(RESPONSE);
+
+                SimulatedStack::global()
+                    .send_response(
+                        gatts_if,
+                        read.conn_id,
+                        read.trans_id,
+                        esp_gatt_status_t_ESP_GATT_OK,
+                        &mut response,
+                    )
+                    .unwrap();
+            }
+            _ => {}
+        }
+    }
+
+    /// Registers the application once, and returns its interface.
+    fn interface() -> esp_gatt_if_t {
+        static REGISTER: Once = Once::new();
+        let stack = SimulatedStack::global();
+
+        REGISTER.call_once(|| {
+            stack.initialise(Some(gatts_callback), None).unwrap();
+            stack.app_register(APP_ID).unwrap();
+        });
+
+        stack.wait_idle();
+        INTERFACE
+            .lock()
+            .unwrap()
+            .expect("The application is not registered.")
+    }
+
+    fn value(value: &mut [u8], max_length: u16) -> esp_attr_value_t {
+        esp_attr_value_t {
+            attr_max_len: max_length,
+            attr_len: value.len() as u16,
+            attr_value: value.as_mut_ptr(),
+        }
+    }
+
+    fn control(automatic_response: bool) -> esp_attr_control_t {
+        esp_attr_control_t {
+            auto_rsp: if automatic_response {
+                ESP_GATT_AUTO_RSP as u8
+            } else {
+                ESP_GATT_RSP_BY_APP as u8
+            },
+        }
+    }
+
+    /// Creates and starts a service with two characteristics: the first one answered by the stack,
+    /// with a CCCD, and the second one answered by the application. Returns the service handle.
+    fn service(uuid: u16) -> u16 {
+        let stack = SimulatedStack::global();
+        let read = ESP_GATT_PERM_READ as esp_gatt_perm_t;
+        let read_write = (ESP_GATT_PERM_READ | ESP_GATT_PERM_WRITE) as esp_gatt_perm_t;
+
+        let mut service_id = esp_gatt_srvc_id_t::default();
+        service_id.id.uuid = BleUuid::Uuid16(uuid).into();
+        service_id.is_primary = true;
+        stack.create_service(interface(), service_id, 6).unwrap();
+        stack.wait_idle();
+
+        let handle = stack
+            .attributes()
+            .iter()
+            .find(|attribute| attribute.value == uuid.to_le_bytes())
+            .map(|attribute| attribute.handle)
+            .unwrap();
+
+        stack
+            .add_char(
+                handle,
+                BleUuid::Uuid16(uuid + 1).into(),
+                read_write,
+                (ESP_GATT_CHAR_PROP_BIT_READ
+                    | ESP_GATT_CHAR_PROP_BIT_WRITE
+                    | ESP_GATT_CHAR_PROP_BIT_NOTIFY) as esp_gatt_char_prop_t,
+                value(&mut [1, 2], 4),
+                control(true),
+            )
+            .unwrap();
+        stack
+            .add_char_descr(
+                handle,
+                BleUuid::Uuid16(0x2902).into(),
+                read_write,
+                value(&mut [0, 0], 2),
+                control(true),
+            )
+            .unwrap();
+        stack
+            .add_char(
+                handle,
+                BleUuid::Uuid16(uuid + 2).into(),
+                read,
+                ESP_GATT_CHAR_PROP_BIT_READ as esp_gatt_char_prop_t,
+                value(&mut [], 0),
+                control(false),
+            )
+            .unwrap();
+        stack.start_service(handle).unwrap();
+        stack.wait_idle();
+
+        handle
+    }
+
+    #[test]
+    fn database() {
+        let stack = SimulatedStack::global();
+        let handle = service(0xB100);
+
+        let attributes: Vec<_> = stack
+            .attributes()
+            .into_iter()
+            .filter(|attribute| (handle..handle + 6).contains(&attribute.handle))
+            .map(|attribute| (attribute.kind, attribute.uuid, attribute.value))
+            .collect();
+        let value_handle = (handle + 2).to_le_bytes();
+
+        assert_eq!(
+            attributes,
+            [
+                (
+                    AttributeKind::PrimaryService,
+                    BleUuid::Uuid16(0x2800),
+                    vec![0x00, 0xB1]
+                ),
+                (
+                    AttributeKind::CharacteristicDeclaration,
+                    BleUuid::Uuid16(0x2803),
+                    vec![0x1A, value_handle[0], value_handle[1], 0x01, 0xB1]
+                ),
+                (
+                    AttributeKind::CharacteristicValue,
+                    BleUuid::Uuid16(0xB101),
+                    vec![1, 2]
+                ),
+                (
+                    AttributeKind::Descriptor,
+                    BleUuid::Uuid16(0x2902),
+                    vec![0, 0]
+                ),
+                (
+                    AttributeKind::CharacteristicDeclaration,
+                    BleUuid::Uuid16(0x2803),
+                    vec![0x02, value_handle[0] + 3, value_handle[1], 0x02, 0xB1]
+                ),
+                (
+                    AttributeKind::CharacteristicValue,
+                    BleUuid::Uuid16(0xB102),
+                    vec![]
+                ),
+            ]
+        );
+        assert_eq!(
+            stack.find_characteristic(BleUuid::Uuid16(0xB102)),
+            Some(handle + 5)
+        );
+    }
+
+    #[test]
+    fn client() {
+        let stack = SimulatedStack::global();
+        let handle = service(0xB200);
+        let (automatic, by_application) = (handle + 2, handle + 5);
+        let connection = stack.connect([0xB2, 0, 0, 0, 0, 1]);
+        stack.wait_idle();
+
+        // The stack answers the first characteristic, and checks the length of the written values.
+        assert_eq!(stack.read(connection, automatic), Ok(vec![1, 2]));
+        assert_eq!(stack.write(connection, automatic, &[3, 4, 5]), Ok(()));
+        assert_eq!(stack.get_attr_value(automatic).unwrap(), [3, 4, 5]);
+        assert_eq!(
+            stack.write(connection, automatic, &[0; 5]),
+            Err(esp_gatt_status_t_ESP_GATT_INVALID_ATTR_LEN)
+        );
+
+        // The application answers the second one, which is read-only.
+        assert_eq!(
+            stack.read(connection, by_application),
+            Ok(RESPONSE.to_vec())
+        );
+        assert_eq!(
+            stack.write(connection, by_application, &[1]),
+            Err(esp_gatt_status_t_ESP_GATT_WRITE_NOT_PERMIT)
+        );
+        assert_eq!(
+            stack.read(connection, 0xFFFF),
+            Err(esp_gatt_status_t_ESP_GATT_INVALID_HANDLE)
+        );
+
+        // Notifications are truncated to the MTU of the connection.
+        stack.subscribe(connection, automatic, true, false).unwrap();
+        assert_eq!(stack.get_attr_value(automatic + 1).unwrap(), [1, 0]);
+        stack
+            .send_indicate(interface(), connection, automatic, &[7; 30], false)
+            .unwrap();
+        stack.exchange_mtu(connection, 30);
+        stack.wait_idle();
+        stack
+            .send_indicate(interface(), connection, automatic, &[7; 30], false)
+            .unwrap();
+        assert_eq!(
+            stack.notifications(connection),
+            [
+                Notification {
+                    handle: automatic,
+                    value: vec![7; 20],
+                    indication: false,
+                },
+                Notification {
+                    handle: automatic,
+                    value: vec![7; 27],
+                    indication: false,
+                },
+            ]
+        );
+
+        stack.disconnect(connection);
+        stack.wait_idle();
+        assert_eq!(
+            stack.read(connection, automatic),
+            Err(esp_gatt_status_t_ESP_GATT_ILLEGAL_PARAMETER)
+        );
+    }
+}
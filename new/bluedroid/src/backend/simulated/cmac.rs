@@ -0,0 +1,97 @@
+//! AES-CMAC, as used by the Database Hash characteristic (Core Specification, Vol 3, Part G, 7.3.1).
+//!
+//! This is a plain implementation of CMAC (RFC 4493), on the AES-128 of the crate.
+
+use crate::utilities::aes::aes128_encrypt;
+
+/// Computes the AES-CMAC of a message, most significant byte first.
+pub(super) fn aes_cmac(key: &[u8; 16], message: &[u8]) -> [u8; 16] {
+    // Subkeys, derived from the encryption of the zero block.
+    let first_subkey = double(aes128_encrypt(key, [0; 16]));
+    let second_subkey = double(first_subkey);
+
+    // An empty message is a single incomplete block.
+    let mut chunks: Vec<&[u8]> = message.chunks(16).collect();
+    if chunks.is_empty() {
+        chunks.push(&[]);
+    }
+
+    let last = chunks.len() - 1;
+    let mut mac = [0; 16];
+
+    for (index, chunk) in chunks.into_iter().enumerate() {
+        let mut block = [0; 16];
+        block[..chunk.len()].copy_from_slice(chunk);
+
+        if index == last {
+            let subkey = if chunk.len() == 16 {
+                first_subkey
+            } else {
+                // Incomplete blocks are padded with a single one bit.
+                block[chunk.len()] = 0x80;
+                second_subkey
+            };
+            xor(&mut block, &subkey);
+        }
+
+        xor(&mut block, &mac);
+        mac = aes128_encrypt(key, block);
+    }
+
+    mac
+}
+
+/// Multiplies a block by two in GF(2^128), to derive the CMAC subkeys.
+fn double(block: [u8; 16]) -> [u8; 16] {
+    let mut result = [0; 16];
+    for index in 0..16 {
+        let carry = block.get(index + 1).map_or(0, |next| next >> 7);
+        result[index] = block[index] << 1 | carry;
+    }
+
+    if block[0] & 0x80 != 0 {
+        result[15] ^= 0x87;
+    }
+
+    result
+}
+
+fn xor(block: &mut [u8; 16], other: &[u8; 16]) {
+    for (byte, other) in block.iter_mut().zip(other) {
+        *byte ^= other;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The key of the examples of RFC 4493.
+    const KEY: [u8; 16] = [
+        0x2B, 0x7E, 0x15, 0x16, 0x28, 0xAE, 0xD2, 0xA6, 0xAB, 0xF7, 0x15, 0x88, 0x09, 0xCF, 0x4F,
+        0x3C,
+    ];
+
+    #[test]
+    fn rfc_4493() {
+        assert_eq!(
+            aes_cmac(&KEY, &[]),
+            [
+                0xBB, 0x1D, 0x69, 0x29, 0xE9, 0x59, 0x37, 0x28, 0x7F, 0xA3, 0x7D, 0x12, 0x9B, 0x75,
+                0x67, 0x46
+            ]
+        );
+
+        let message = [
+            0x6B, 0xC1, 0xBE, 0xE2, 0x2E, 0x40, 0x9F, 0x96, 0xE9, 0x3D, 0x7E, 0x11, 0x73, 0x93,
+            0x17, 0x2A,
+        ];
+        assert_eq!(
+            aes_cmac(&KEY, &message),
+            [
+                0x07, 0x0A, 0x16, 0xB4, 0x6B, 0x4D, 0x41, 0x44, 0xF7, 0x9B, 0xDD, 0x9D, 0xD0, 0x4A,
+                0x28, 0x7C
+            ]
+        );
+    }
+}
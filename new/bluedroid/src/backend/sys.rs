@@ -0,0 +1,1001 @@
+//! Host-side mirror of the `esp-idf-sys` Bluedroid bindings.
+//!
+//! On ESP-IDF targets, `crate::sys` is a re-export of `esp_idf_sys`.
+//! On any other target, this module provides the subset of types and constants
+//! used by this crate, with the same names and layouts as the ESP-IDF v4.4 bindings,
+//! so that the GATT server can run against the [simulated stack](crate::simulated).
+
+#![allow(missing_docs)]
+#![allow(non_camel_case_types)]
+#![allow(non_upper_case_globals)]
+#![allow(clippy::module_name_repetitions)]
+
+// Base types.
+
+pub type esp_err_t = i32;
+pub type esp_bd_addr_t = [u8; 6];
+pub type esp_gatt_if_t = u8;
+pub type esp_gatt_perm_t = u16;
+pub type esp_gatt_char_prop_t = u8;
+pub type esp_gatt_status_t = u32;
+pub type esp_bt_status_t = u32;
+pub type esp_gatts_cb_event_t = u32;
+pub type esp_gap_ble_cb_event_t = u32;
+pub type esp_gatt_conn_reason_t = u32;
+pub type esp_ble_adv_type_t = u32;
+pub type esp_ble_addr_type_t = u32;
+pub type esp_ble_adv_channel_t = u32;
+pub type esp_ble_adv_filter_t = u32;
+pub type esp_bt_dev_type_t = u32;
+pub type esp_ble_auth_req_t = u8;
+pub type esp_link_key = [u8; 16];
+pub type esp_ble_io_cap_t = u8;
+pub type esp_ble_sm_param_t = u32;
+pub type esp_ble_ext_adv_type_mask_t = u16;
+pub type esp_ble_gap_pri_phy_t = u8;
+pub type esp_ble_gap_phy_t = u8;
+
+// Error codes.
+
+pub const ESP_OK: esp_err_t = 0;
+pub const ESP_FAIL: esp_err_t = -1;
+pub const ESP_ERR_NO_MEM: esp_err_t = 0x101;
+pub const ESP_ERR_INVALID_ARG: esp_err_t = 0x102;
+pub const ESP_ERR_INVALID_STATE: esp_err_t = 0x103;
+pub const ESP_ERR_INVALID_SIZE: esp_err_t = 0x104;
+pub const ESP_ERR_NOT_FOUND: esp_err_t = 0x105;
+pub const ESP_ERR_NOT_SUPPORTED: esp_err_t = 0x106;
+pub const ESP_ERR_TIMEOUT: esp_err_t = 0x107;
+pub const ESP_ERR_NVS_NO_FREE_PAGES: esp_err_t = 0x110d;
+pub const ESP_ERR_NVS_NEW_VERSION_FOUND: esp_err_t = 0x1110;
+
+/// A wrapper for a non-zero `esp_err_t`, mirroring `esp_idf_sys::EspError`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct EspError(std::num::NonZeroI32);
+
+impl EspError {
+    /// Wraps an error code, returning `None` for `ESP_OK`.
+    #[must_use]
+    pub fn from(error: esp_err_t) -> Option<Self> {
+        std::num::NonZeroI32::new(error).map(Self)
+    }
+
+    /// Wraps an error code that is known to be non-zero.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `E` is `ESP_OK`.
+    #[must_use]
+    pub fn from_infallible<const E: esp_err_t>() -> Self {
+        Self::from(E).expect("ESP_OK is not an error")
+    }
+
+    /// Converts an error code into a `Result`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `error` is not `ESP_OK`.
+    pub fn convert(error: esp_err_t) -> Result<(), Self> {
+        Self::from(error).map_or(Ok(()), Err)
+    }
+
+    /// Returns the raw error code.
+    #[must_use]
+    pub const fn code(&self) -> esp_err_t {
+        self.0.get()
+    }
+}
+
+impl std::fmt::Display for EspError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ESP error 0x{:x}", self.code())
+    }
+}
+
+impl std::error::Error for EspError {}
+
+// Bluetooth status codes.
+
+pub const esp_bt_status_t_ESP_BT_STATUS_SUCCESS: esp_bt_status_t = 0;
+pub const esp_bt_status_t_ESP_BT_STATUS_FAIL: esp_bt_status_t = 1;
+
+// GATT status codes.
+
+pub const esp_gatt_status_t_ESP_GATT_OK: esp_gatt_status_t = 0x00;
+pub const esp_gatt_status_t_ESP_GATT_INVALID_HANDLE: esp_gatt_status_t = 0x01;
+pub const esp_gatt_status_t_ESP_GATT_READ_NOT_PERMIT: esp_gatt_status_t = 0x02;
+pub const esp_gatt_status_t_ESP_GATT_WRITE_NOT_PERMIT: esp_gatt_status_t = 0x03;
+pub const esp_gatt_status_t_ESP_GATT_INVALID_PDU: esp_gatt_status_t = 0x04;
+pub const esp_gatt_status_t_ESP_GATT_INSUF_AUTHENTICATION: esp_gatt_status_t = 0x05;
+pub const esp_gatt_status_t_ESP_GATT_REQ_NOT_SUPPORTED: esp_gatt_status_t = 0x06;
+pub const esp_gatt_status_t_ESP_GATT_INVALID_OFFSET: esp_gatt_status_t = 0x07;
+pub const esp_gatt_status_t_ESP_GATT_INSUF_AUTHORIZATION: esp_gatt_status_t = 0x08;
+pub const esp_gatt_status_t_ESP_GATT_PREPARE_Q_FULL: esp_gatt_status_t = 0x09;
+pub const esp_gatt_status_t_ESP_GATT_NOT_FOUND: esp_gatt_status_t = 0x0a;
+pub const esp_gatt_status_t_ESP_GATT_NOT_LONG: esp_gatt_status_t = 0x0b;
+pub const esp_gatt_status_t_ESP_GATT_INSUF_KEY_SIZE: esp_gatt_status_t = 0x0c;
+pub const esp_gatt_status_t_ESP_GATT_INVALID_ATTR_LEN: esp_gatt_status_t = 0x0d;
+pub const esp_gatt_status_t_ESP_GATT_ERR_UNLIKELY: esp_gatt_status_t = 0x0e;
+pub const esp_gatt_status_t_ESP_GATT_INSUF_ENCRYPTION: esp_gatt_status_t = 0x0f;
+pub const esp_gatt_status_t_ESP_GATT_UNSUPPORT_GRP_TYPE: esp_gatt_status_t = 0x10;
+pub const esp_gatt_status_t_ESP_GATT_INSUF_RESOURCE: esp_gatt_status_t = 0x11;
+pub const esp_gatt_status_t_ESP_GATT_NO_RESOURCES: esp_gatt_status_t = 0x80;
+pub const esp_gatt_status_t_ESP_GATT_INTERNAL_ERROR: esp_gatt_status_t = 0x81;
+pub const esp_gatt_status_t_ESP_GATT_WRONG_STATE: esp_gatt_status_t = 0x82;
+pub const esp_gatt_status_t_ESP_GATT_DB_FULL: esp_gatt_status_t = 0x83;
+pub const esp_gatt_status_t_ESP_GATT_BUSY: esp_gatt_status_t = 0x84;
+pub const esp_gatt_status_t_ESP_GATT_ERROR: esp_gatt_status_t = 0x85;
+pub const esp_gatt_status_t_ESP_GATT_ILLEGAL_PARAMETER: esp_gatt_status_t = 0x87;
+pub const esp_gatt_status_t_ESP_GATT_AUTH_FAIL: esp_gatt_status_t = 0x89;
+pub const esp_gatt_status_t_ESP_GATT_CONGESTED: esp_gatt_status_t = 0x8f;
+pub const esp_gatt_status_t_ESP_GATT_UNKNOWN_ERROR: esp_gatt_status_t = 0xef;
+pub const esp_gatt_status_t_ESP_GATT_OUT_OF_RANGE: esp_gatt_status_t = 0xff;
+
+// GATT server events.
+
+pub const esp_gatts_cb_event_t_ESP_GATTS_REG_EVT: esp_gatts_cb_event_t = 0;
+pub const esp_gatts_cb_event_t_ESP_GATTS_READ_EVT: esp_gatts_cb_event_t = 1;
+pub const esp_gatts_cb_event_t_ESP_GATTS_WRITE_EVT: esp_gatts_cb_event_t = 2;
+pub const esp_gatts_cb_event_t_ESP_GATTS_EXEC_WRITE_EVT: esp_gatts_cb_event_t = 3;
+pub const esp_gatts_cb_event_t_ESP_GATTS_MTU_EVT: esp_gatts_cb_event_t = 4;
+pub const esp_gatts_cb_event_t_ESP_GATTS_CONF_EVT: esp_gatts_cb_event_t = 5;
+pub const esp_gatts_cb_event_t_ESP_GATTS_UNREG_EVT: esp_gatts_cb_event_t = 6;
+pub const esp_gatts_cb_event_t_ESP_GATTS_CREATE_EVT: esp_gatts_cb_event_t = 7;
+pub const esp_gatts_cb_event_t_ESP_GATTS_ADD_INCL_SRVC_EVT: esp_gatts_cb_event_t = 8;
+pub const esp_gatts_cb_event_t_ESP_GATTS_ADD_CHAR_EVT: esp_gatts_cb_event_t = 9;
+pub const esp_gatts_cb_event_t_ESP_GATTS_ADD_CHAR_DESCR_EVT: esp_gatts_cb_event_t = 10;
+pub const esp_gatts_cb_event_t_ESP_GATTS_DELETE_EVT: esp_gatts_cb_event_t = 11;
+pub const esp_gatts_cb_event_t_ESP_GATTS_START_EVT: esp_gatts_cb_event_t = 12;
+pub const esp_gatts_cb_event_t_ESP_GATTS_STOP_EVT: esp_gatts_cb_event_t = 13;
+pub const esp_gatts_cb_event_t_ESP_GATTS_CONNECT_EVT: esp_gatts_cb_event_t = 14;
+pub const esp_gatts_cb_event_t_ESP_GATTS_DISCONNECT_EVT: esp_gatts_cb_event_t = 15;
+pub const esp_gatts_cb_event_t_ESP_GATTS_OPEN_EVT: esp_gatts_cb_event_t = 16;
+pub const esp_gatts_cb_event_t_ESP_GATTS_CANCEL_OPEN_EVT: esp_gatts_cb_event_t = 17;
+pub const esp_gatts_cb_event_t_ESP_GATTS_CLOSE_EVT: esp_gatts_cb_event_t = 18;
+pub const esp_gatts_cb_event_t_ESP_GATTS_LISTEN_EVT: esp_gatts_cb_event_t = 19;
+pub const esp_gatts_cb_event_t_ESP_GATTS_CONGEST_EVT: esp_gatts_cb_event_t = 20;
+pub const esp_gatts_cb_event_t_ESP_GATTS_RESPONSE_EVT: esp_gatts_cb_event_t = 21;
+pub const esp_gatts_cb_event_t_ESP_GATTS_CREAT_ATTR_TAB_EVT: esp_gatts_cb_event_t = 22;
+pub const esp_gatts_cb_event_t_ESP_GATTS_SET_ATTR_VAL_EVT: esp_gatts_cb_event_t = 23;
+pub const esp_gatts_cb_event_t_ESP_GATTS_SEND_SERVICE_CHANGE_EVT: esp_gatts_cb_event_t = 24;
+
+// Disconnection reasons.
+
+pub const esp_gatt_conn_reason_t_ESP_GATT_CONN_UNKNOWN: esp_gatt_conn_reason_t = 0;
+pub const esp_gatt_conn_reason_t_ESP_GATT_CONN_L2C_FAILURE: esp_gatt_conn_reason_t = 1;
+pub const esp_gatt_conn_reason_t_ESP_GATT_CONN_TIMEOUT: esp_gatt_conn_reason_t = 0x08;
+pub const esp_gatt_conn_reason_t_ESP_GATT_CONN_TERMINATE_PEER_USER: esp_gatt_conn_reason_t = 0x13;
+pub const esp_gatt_conn_reason_t_ESP_GATT_CONN_TERMINATE_LOCAL_HOST: esp_gatt_conn_reason_t = 0x16;
+pub const esp_gatt_conn_reason_t_ESP_GATT_CONN_LMP_TIMEOUT: esp_gatt_conn_reason_t = 0x22;
+pub const esp_gatt_conn_reason_t_ESP_GATT_CONN_FAIL_ESTABLISH: esp_gatt_conn_reason_t = 0x3e;
+pub const esp_gatt_conn_reason_t_ESP_GATT_CONN_CONN_CANCEL: esp_gatt_conn_reason_t = 0x0100;
+pub const esp_gatt_conn_reason_t_ESP_GATT_CONN_NONE: esp_gatt_conn_reason_t = 0x0101;
+
+// GAP events.
+
+pub const esp_gap_ble_cb_event_t_ESP_GAP_BLE_ADV_DATA_SET_COMPLETE_EVT: esp_gap_ble_cb_event_t = 0;
+pub const esp_gap_ble_cb_event_t_ESP_GAP_BLE_SCAN_RSP_DATA_SET_COMPLETE_EVT:
+    esp_gap_ble_cb_event_t = 1;
+pub const esp_gap_ble_cb_event_t_ESP_GAP_BLE_ADV_DATA_RAW_SET_COMPLETE_EVT: esp_gap_ble_cb_event_t =
+    4;
+pub const esp_gap_ble_cb_event_t_ESP_GAP_BLE_SCAN_RSP_DATA_RAW_SET_COMPLETE_EVT:
+    esp_gap_ble_cb_event_t = 5;
+pub const esp_gap_ble_cb_event_t_ESP_GAP_BLE_ADV_START_COMPLETE_EVT: esp_gap_ble_cb_event_t = 6;
+pub const esp_gap_ble_cb_event_t_ESP_GAP_BLE_AUTH_CMPL_EVT: esp_gap_ble_cb_event_t = 8;
+pub const esp_gap_ble_cb_event_t_ESP_GAP_BLE_KEY_EVT: esp_gap_ble_cb_event_t = 9;
+pub const esp_gap_ble_cb_event_t_ESP_GAP_BLE_SEC_REQ_EVT: esp_gap_ble_cb_event_t = 10;
+pub const esp_gap_ble_cb_event_t_ESP_GAP_BLE_PASSKEY_NOTIF_EVT: esp_gap_ble_cb_event_t = 11;
+pub const esp_gap_ble_cb_event_t_ESP_GAP_BLE_PASSKEY_REQ_EVT: esp_gap_ble_cb_event_t = 12;
+pub const esp_gap_ble_cb_event_t_ESP_GAP_BLE_NC_REQ_EVT: esp_gap_ble_cb_event_t = 16;
+pub const esp_gap_ble_cb_event_t_ESP_GAP_BLE_ADV_STOP_COMPLETE_EVT: esp_gap_ble_cb_event_t = 17;
+pub const esp_gap_ble_cb_event_t_ESP_GAP_BLE_UPDATE_CONN_PARAMS_EVT: esp_gap_ble_cb_event_t = 20;
+pub const esp_gap_ble_cb_event_t_ESP_GAP_BLE_SET_PKT_LENGTH_COMPLETE_EVT: esp_gap_ble_cb_event_t =
+    21;
+pub const esp_gap_ble_cb_event_t_ESP_GAP_BLE_REMOVE_BOND_DEV_COMPLETE_EVT: esp_gap_ble_cb_event_t =
+    23;
+pub const esp_gap_ble_cb_event_t_ESP_GAP_BLE_CLEAR_BOND_DEV_COMPLETE_EVT: esp_gap_ble_cb_event_t =
+    24;
+pub const esp_gap_ble_cb_event_t_ESP_GAP_BLE_READ_RSSI_COMPLETE_EVT: esp_gap_ble_cb_event_t = 26;
+pub const esp_gap_ble_cb_event_t_ESP_GAP_BLE_SET_PREFERED_PHY_COMPLETE_EVT: esp_gap_ble_cb_event_t =
+    32;
+pub const esp_gap_ble_cb_event_t_ESP_GAP_BLE_EXT_ADV_SET_PARAMS_COMPLETE_EVT:
+    esp_gap_ble_cb_event_t = 34;
+pub const esp_gap_ble_cb_event_t_ESP_GAP_BLE_EXT_ADV_DATA_SET_COMPLETE_EVT: esp_gap_ble_cb_event_t =
+    35;
+pub const esp_gap_ble_cb_event_t_ESP_GAP_BLE_EXT_SCAN_RSP_DATA_SET_COMPLETE_EVT:
+    esp_gap_ble_cb_event_t = 36;
+pub const esp_gap_ble_cb_event_t_ESP_GAP_BLE_EXT_ADV_START_COMPLETE_EVT: esp_gap_ble_cb_event_t =
+    37;
+pub const esp_gap_ble_cb_event_t_ESP_GAP_BLE_EXT_ADV_STOP_COMPLETE_EVT: esp_gap_ble_cb_event_t = 38;
+pub const esp_gap_ble_cb_event_t_ESP_GAP_BLE_EXT_ADV_SET_REMOVE_COMPLETE_EVT:
+    esp_gap_ble_cb_event_t = 39;
+pub const esp_gap_ble_cb_event_t_ESP_GAP_BLE_PERIODIC_ADV_SET_PARAMS_COMPLETE_EVT:
+    esp_gap_ble_cb_event_t = 41;
+pub const esp_gap_ble_cb_event_t_ESP_GAP_BLE_PERIODIC_ADV_DATA_SET_COMPLETE_EVT:
+    esp_gap_ble_cb_event_t = 42;
+pub const esp_gap_ble_cb_event_t_ESP_GAP_BLE_PERIODIC_ADV_START_COMPLETE_EVT:
+    esp_gap_ble_cb_event_t = 43;
+pub const esp_gap_ble_cb_event_t_ESP_GAP_BLE_PERIODIC_ADV_STOP_COMPLETE_EVT:
+    esp_gap_ble_cb_event_t = 44;
+pub const esp_gap_ble_cb_event_t_ESP_GAP_BLE_PHY_UPDATE_COMPLETE_EVT: esp_gap_ble_cb_event_t = 55;
+pub const esp_gap_ble_cb_event_t_ESP_GAP_BLE_ADV_TERMINATED_EVT: esp_gap_ble_cb_event_t = 58;
+
+// Security manager.
+
+pub const esp_ble_sm_param_t_ESP_BLE_SM_PASSKEY: esp_ble_sm_param_t = 0;
+pub const esp_ble_sm_param_t_ESP_BLE_SM_AUTHEN_REQ_MODE: esp_ble_sm_param_t = 1;
+pub const esp_ble_sm_param_t_ESP_BLE_SM_IOCAP_MODE: esp_ble_sm_param_t = 2;
+pub const esp_ble_sm_param_t_ESP_BLE_SM_SET_INIT_KEY: esp_ble_sm_param_t = 3;
+pub const esp_ble_sm_param_t_ESP_BLE_SM_SET_RSP_KEY: esp_ble_sm_param_t = 4;
+pub const esp_ble_sm_param_t_ESP_BLE_SM_MAX_KEY_SIZE: esp_ble_sm_param_t = 5;
+pub const esp_ble_sm_param_t_ESP_BLE_SM_MIN_KEY_SIZE: esp_ble_sm_param_t = 6;
+pub const esp_ble_sm_param_t_ESP_BLE_SM_SET_STATIC_PASSKEY: esp_ble_sm_param_t = 7;
+pub const esp_ble_sm_param_t_ESP_BLE_SM_CLEAR_STATIC_PASSKEY: esp_ble_sm_param_t = 8;
+pub const esp_ble_sm_param_t_ESP_BLE_SM_ONLY_ACCEPT_SPECIFIED_SEC_AUTH: esp_ble_sm_param_t = 9;
+pub const esp_ble_sm_param_t_ESP_BLE_SM_OOB_SUPPORT: esp_ble_sm_param_t = 10;
+
+pub const ESP_IO_CAP_OUT: i32 = 0;
+pub const ESP_IO_CAP_IO: i32 = 1;
+pub const ESP_IO_CAP_IN: i32 = 2;
+pub const ESP_IO_CAP_NONE: i32 = 3;
+pub const ESP_IO_CAP_KBDISP: i32 = 4;
+
+pub const ESP_LE_AUTH_NO_BOND: i32 = 0x00;
+pub const ESP_LE_AUTH_BOND: i32 = 0x01;
+pub const ESP_LE_AUTH_REQ_MITM: i32 = 1 << 2;
+pub const ESP_LE_AUTH_REQ_SC_ONLY: i32 = 1 << 3;
+
+pub const ESP_BLE_ENC_KEY_MASK: i32 = 1 << 0;
+pub const ESP_BLE_ID_KEY_MASK: i32 = 1 << 1;
+pub const ESP_BLE_CSR_KEY_MASK: i32 = 1 << 2;
+pub const ESP_BLE_LINK_KEY_MASK: i32 = 1 << 3;
+
+pub const ESP_BLE_ONLY_ACCEPT_SPECIFIED_AUTH_DISABLE: i32 = 0;
+pub const ESP_BLE_ONLY_ACCEPT_SPECIFIED_AUTH_ENABLE: i32 = 1;
+
+// Attribute permissions.
+
+pub const ESP_GATT_PERM_READ: i32 = 1 << 0;
+pub const ESP_GATT_PERM_READ_ENCRYPTED: i32 = 1 << 1;
+pub const ESP_GATT_PERM_READ_ENC_MITM: i32 = 1 << 2;
+pub const ESP_GATT_PERM_WRITE: i32 = 1 << 4;
+pub const ESP_GATT_PERM_WRITE_ENCRYPTED: i32 = 1 << 5;
+pub const ESP_GATT_PERM_WRITE_ENC_MITM: i32 = 1 << 6;
+pub const ESP_GATT_PERM_WRITE_SIGNED: i32 = 1 << 7;
+pub const ESP_GATT_PERM_WRITE_SIGNED_MITM: i32 = 1 << 8;
+pub const ESP_GATT_PERM_READ_AUTHORIZATION: i32 = 1 << 9;
+pub const ESP_GATT_PERM_WRITE_AUTHORIZATION: i32 = 1 << 10;
+
+// Characteristic properties.
+
+pub const ESP_GATT_CHAR_PROP_BIT_BROADCAST: i32 = 1 << 0;
+pub const ESP_GATT_CHAR_PROP_BIT_READ: i32 = 1 << 1;
+pub const ESP_GATT_CHAR_PROP_BIT_WRITE_NR: i32 = 1 << 2;
+pub const ESP_GATT_CHAR_PROP_BIT_WRITE: i32 = 1 << 3;
+pub const ESP_GATT_CHAR_PROP_BIT_NOTIFY: i32 = 1 << 4;
+pub const ESP_GATT_CHAR_PROP_BIT_INDICATE: i32 = 1 << 5;
+pub const ESP_GATT_CHAR_PROP_BIT_AUTH: i32 = 1 << 6;
+pub const ESP_GATT_CHAR_PROP_BIT_EXT_PROP: i32 = 1 << 7;
+
+// Attribute control and UUID lengths.
+
+pub const ESP_GATT_RSP_BY_APP: i32 = 0;
+pub const ESP_GATT_AUTO_RSP: i32 = 1;
+pub const ESP_GATT_MAX_ATTR_LEN: i32 = 600;
+
+// Configuration, with the ESP-IDF defaults.
+
+pub const CONFIG_BT_GATT_MAX_SR_ATTRIBUTES: u32 = 100;
+
+pub const ESP_GATT_PREP_WRITE_CANCEL: i32 = 0x00;
+pub const ESP_GATT_PREP_WRITE_EXEC: i32 = 0x01;
+
+pub const ESP_UUID_LEN_16: i32 = 2;
+pub const ESP_UUID_LEN_32: i32 = 4;
+pub const ESP_UUID_LEN_128: i32 = 16;
+
+// Advertisement.
+
+pub const ESP_BLE_ADV_FLAG_LIMIT_DISC: i32 = 1 << 0;
+pub const ESP_BLE_ADV_FLAG_GEN_DISC: i32 = 1 << 1;
+pub const ESP_BLE_ADV_FLAG_BREDR_NOT_SPT: i32 = 1 << 2;
+pub const ESP_BLE_ADV_FLAG_DMT_CONTROLLER_SPT: i32 = 1 << 3;
+pub const ESP_BLE_ADV_FLAG_DMT_HOST_SPT: i32 = 1 << 4;
+
+pub const esp_ble_adv_type_t_ADV_TYPE_IND: esp_ble_adv_type_t = 0;
+pub const esp_ble_adv_type_t_ADV_TYPE_DIRECT_IND_HIGH: esp_ble_adv_type_t = 1;
+pub const esp_ble_adv_type_t_ADV_TYPE_SCAN_IND: esp_ble_adv_type_t = 2;
+pub const esp_ble_adv_type_t_ADV_TYPE_NONCONN_IND: esp_ble_adv_type_t = 3;
+pub const esp_ble_adv_type_t_ADV_TYPE_DIRECT_IND_LOW: esp_ble_adv_type_t = 4;
+
+pub const esp_ble_addr_type_t_BLE_ADDR_TYPE_PUBLIC: esp_ble_addr_type_t = 0;
+pub const esp_ble_addr_type_t_BLE_ADDR_TYPE_RANDOM: esp_ble_addr_type_t = 1;
+pub const esp_ble_addr_type_t_BLE_ADDR_TYPE_RPA_PUBLIC: esp_ble_addr_type_t = 2;
+pub const esp_ble_addr_type_t_BLE_ADDR_TYPE_RPA_RANDOM: esp_ble_addr_type_t = 3;
+
+pub const esp_ble_adv_channel_t_ADV_CHNL_37: esp_ble_adv_channel_t = 1;
+pub const esp_ble_adv_channel_t_ADV_CHNL_38: esp_ble_adv_channel_t = 2;
+pub const esp_ble_adv_channel_t_ADV_CHNL_39: esp_ble_adv_channel_t = 4;
+pub const esp_ble_adv_channel_t_ADV_CHNL_ALL: esp_ble_adv_channel_t = 7;
+
+pub const esp_ble_adv_filter_t_ADV_FILTER_ALLOW_SCAN_ANY_CON_ANY: esp_ble_adv_filter_t = 0;
+pub const esp_ble_adv_filter_t_ADV_FILTER_ALLOW_SCAN_WLST_CON_ANY: esp_ble_adv_filter_t = 1;
+pub const esp_ble_adv_filter_t_ADV_FILTER_ALLOW_SCAN_ANY_CON_WLST: esp_ble_adv_filter_t = 2;
+pub const esp_ble_adv_filter_t_ADV_FILTER_ALLOW_SCAN_WLST_CON_WLST: esp_ble_adv_filter_t = 3;
+
+// Extended and periodic advertising.
+
+pub const ESP_BLE_GAP_SET_EXT_ADV_PROP_NONCONN_NONSCANNABLE_UNDIRECTED: i32 = 0;
+pub const ESP_BLE_GAP_SET_EXT_ADV_PROP_CONNECTABLE: i32 = 1 << 0;
+pub const ESP_BLE_GAP_SET_EXT_ADV_PROP_SCANNABLE: i32 = 1 << 1;
+pub const ESP_BLE_GAP_SET_EXT_ADV_PROP_DIRECTED: i32 = 1 << 2;
+pub const ESP_BLE_GAP_SET_EXT_ADV_PROP_LEGACY: i32 = 1 << 4;
+pub const ESP_BLE_GAP_SET_EXT_ADV_PROP_INCLUDE_TX_PWR: i32 = 1 << 6;
+pub const ESP_BLE_GAP_PERIODIC_ADV_PROP_INCLUDE_TX_PWR: i32 = 1 << 6;
+pub const EXT_ADV_TX_PWR_NO_PREFERENCE: i32 = 127;
+
+// UUIDs and identifiers.
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub union esp_bt_uuid_t__bindgen_ty_1 {
+    pub uuid16: u16,
+    pub uuid32: u32,
+    pub uuid128: [u8; 16],
+}
+
+impl Default for esp_bt_uuid_t__bindgen_ty_1 {
+    fn default() -> Self {
+        Self { uuid128: [0; 16] }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+pub struct esp_bt_uuid_t {
+    pub len: u16,
+    pub uuid: esp_bt_uuid_t__bindgen_ty_1,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+pub struct esp_gatt_id_t {
+    pub uuid: esp_bt_uuid_t,
+    pub inst_id: u8,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+pub struct esp_gatt_srvc_id_t {
+    pub id: esp_gatt_id_t,
+    pub is_primary: bool,
+}
+
+// Attribute values.
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct esp_attr_value_t {
+    pub attr_max_len: u16,
+    pub attr_len: u16,
+    pub attr_value: *mut u8,
+}
+
+impl Default for esp_attr_value_t {
+    fn default() -> Self {
+        Self {
+            attr_max_len: 0,
+            attr_len: 0,
+            attr_value: std::ptr::null_mut(),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct esp_attr_control_t {
+    pub auto_rsp: u8,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct esp_attr_desc_t {
+    pub uuid_length: u16,
+    pub uuid_p: *mut u8,
+    pub perm: esp_gatt_perm_t,
+    pub max_length: u16,
+    pub length: u16,
+    pub value: *mut u8,
+}
+
+impl Default for esp_attr_desc_t {
+    fn default() -> Self {
+        Self {
+            uuid_length: 0,
+            uuid_p: std::ptr::null_mut(),
+            perm: 0,
+            max_length: 0,
+            length: 0,
+            value: std::ptr::null_mut(),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct esp_gatts_attr_db_t {
+    pub attr_control: esp_attr_control_t,
+    pub att_desc: esp_attr_desc_t,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct esp_gatt_value_t {
+    pub value: [u8; 600],
+    pub handle: u16,
+    pub offset: u16,
+    pub len: u16,
+    pub auth_req: u8,
+}
+
+impl Default for esp_gatt_value_t {
+    fn default() -> Self {
+        Self {
+            value: [0; 600],
+            handle: 0,
+            offset: 0,
+            len: 0,
+            auth_req: 0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub union esp_gatt_rsp_t {
+    pub attr_value: esp_gatt_value_t,
+    pub handle: u16,
+}
+
+impl Default for esp_gatt_rsp_t {
+    fn default() -> Self {
+        Self {
+            attr_value: esp_gatt_value_t::default(),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct esp_gatt_conn_params_t {
+    pub interval: u16,
+    pub latency: u16,
+    pub timeout: u16,
+}
+
+// GATT server event parameters.
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct esp_ble_gatts_cb_param_t_gatts_reg_evt_param {
+    pub status: esp_gatt_status_t,
+    pub app_id: u16,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct esp_ble_gatts_cb_param_t_gatts_read_evt_param {
+    pub conn_id: u16,
+    pub trans_id: u32,
+    pub bda: esp_bd_addr_t,
+    pub handle: u16,
+    pub offset: u16,
+    pub is_long: bool,
+    pub need_rsp: bool,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct esp_ble_gatts_cb_param_t_gatts_write_evt_param {
+    pub conn_id: u16,
+    pub trans_id: u32,
+    pub bda: esp_bd_addr_t,
+    pub handle: u16,
+    pub offset: u16,
+    pub need_rsp: bool,
+    pub is_prep: bool,
+    pub len: u16,
+    pub value: *mut u8,
+}
+
+impl Default for esp_ble_gatts_cb_param_t_gatts_write_evt_param {
+    fn default() -> Self {
+        Self {
+            conn_id: 0,
+            trans_id: 0,
+            bda: [0; 6],
+            handle: 0,
+            offset: 0,
+            need_rsp: false,
+            is_prep: false,
+            len: 0,
+            value: std::ptr::null_mut(),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct esp_ble_gatts_cb_param_t_gatts_exec_write_evt_param {
+    pub conn_id: u16,
+    pub trans_id: u32,
+    pub bda: esp_bd_addr_t,
+    pub exec_write_flag: u8,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct esp_ble_gatts_cb_param_t_gatts_mtu_evt_param {
+    pub conn_id: u16,
+    pub mtu: u16,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct esp_ble_gatts_cb_param_t_gatts_conf_evt_param {
+    pub status: esp_gatt_status_t,
+    pub conn_id: u16,
+    pub handle: u16,
+    pub len: u16,
+    pub value: *mut u8,
+}
+
+impl Default for esp_ble_gatts_cb_param_t_gatts_conf_evt_param {
+    fn default() -> Self {
+        Self {
+            status: 0,
+            conn_id: 0,
+            handle: 0,
+            len: 0,
+            value: std::ptr::null_mut(),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+pub struct esp_ble_gatts_cb_param_t_gatts_create_evt_param {
+    pub status: esp_gatt_status_t,
+    pub service_handle: u16,
+    pub service_id: esp_gatt_srvc_id_t,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct esp_ble_gatts_cb_param_t_gatts_add_attr_tab_evt_param {
+    pub status: esp_gatt_status_t,
+    pub svc_uuid: esp_bt_uuid_t,
+    pub svc_inst_id: u8,
+    pub num_handle: u16,
+    pub handles: *mut u16,
+}
+
+impl Default for esp_ble_gatts_cb_param_t_gatts_add_attr_tab_evt_param {
+    fn default() -> Self {
+        Self {
+            status: 0,
+            svc_uuid: esp_bt_uuid_t::default(),
+            svc_inst_id: 0,
+            num_handle: 0,
+            handles: std::ptr::null_mut(),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct esp_ble_gatts_cb_param_t_gatts_add_incl_srvc_evt_param {
+    pub status: esp_gatt_status_t,
+    pub attr_handle: u16,
+    pub service_handle: u16,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+pub struct esp_ble_gatts_cb_param_t_gatts_add_char_evt_param {
+    pub status: esp_gatt_status_t,
+    pub attr_handle: u16,
+    pub service_handle: u16,
+    pub char_uuid: esp_bt_uuid_t,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+pub struct esp_ble_gatts_cb_param_t_gatts_add_char_descr_evt_param {
+    pub status: esp_gatt_status_t,
+    pub attr_handle: u16,
+    pub service_handle: u16,
+    pub descr_uuid: esp_bt_uuid_t,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct esp_ble_gatts_cb_param_t_gatts_delete_evt_param {
+    pub status: esp_gatt_status_t,
+    pub service_handle: u16,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct esp_ble_gatts_cb_param_t_gatts_start_evt_param {
+    pub status: esp_gatt_status_t,
+    pub service_handle: u16,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct esp_ble_gatts_cb_param_t_gatts_stop_evt_param {
+    pub status: esp_gatt_status_t,
+    pub service_handle: u16,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct esp_ble_gatts_cb_param_t_gatts_connect_evt_param {
+    pub conn_id: u16,
+    pub link_role: u8,
+    pub remote_bda: esp_bd_addr_t,
+    pub conn_params: esp_gatt_conn_params_t,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct esp_ble_gatts_cb_param_t_gatts_disconnect_evt_param {
+    pub conn_id: u16,
+    pub link_role: u8,
+    pub remote_bda: esp_bd_addr_t,
+    pub reason: esp_gatt_conn_reason_t,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct esp_ble_gatts_cb_param_t_gatts_rsp_evt_param {
+    pub status: esp_gatt_status_t,
+    pub handle: u16,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct esp_ble_gatts_cb_param_t_gatts_set_attr_val_evt_param {
+    pub srvc_handle: u16,
+    pub attr_handle: u16,
+    pub status: esp_gatt_status_t,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct esp_ble_gatts_cb_param_t_gatts_send_service_change_evt_param {
+    pub status: esp_gatt_status_t,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub union esp_ble_gatts_cb_param_t {
+    pub reg: esp_ble_gatts_cb_param_t_gatts_reg_evt_param,
+    pub read: esp_ble_gatts_cb_param_t_gatts_read_evt_param,
+    pub write: esp_ble_gatts_cb_param_t_gatts_write_evt_param,
+    pub exec_write: esp_ble_gatts_cb_param_t_gatts_exec_write_evt_param,
+    pub mtu: esp_ble_gatts_cb_param_t_gatts_mtu_evt_param,
+    pub conf: esp_ble_gatts_cb_param_t_gatts_conf_evt_param,
+    pub create: esp_ble_gatts_cb_param_t_gatts_create_evt_param,
+    pub add_incl_srvc: esp_ble_gatts_cb_param_t_gatts_add_incl_srvc_evt_param,
+    pub add_char: esp_ble_gatts_cb_param_t_gatts_add_char_evt_param,
+    pub add_char_descr: esp_ble_gatts_cb_param_t_gatts_add_char_descr_evt_param,
+    pub del: esp_ble_gatts_cb_param_t_gatts_delete_evt_param,
+    pub start: esp_ble_gatts_cb_param_t_gatts_start_evt_param,
+    pub stop: esp_ble_gatts_cb_param_t_gatts_stop_evt_param,
+    pub connect: esp_ble_gatts_cb_param_t_gatts_connect_evt_param,
+    pub disconnect: esp_ble_gatts_cb_param_t_gatts_disconnect_evt_param,
+    pub rsp: esp_ble_gatts_cb_param_t_gatts_rsp_evt_param,
+    pub set_attr_val: esp_ble_gatts_cb_param_t_gatts_set_attr_val_evt_param,
+    pub service_change: esp_ble_gatts_cb_param_t_gatts_send_service_change_evt_param,
+    pub add_attr_tab: esp_ble_gatts_cb_param_t_gatts_add_attr_tab_evt_param,
+}
+
+pub type esp_gatts_cb_t = Option<
+    unsafe extern "C" fn(
+        event: esp_gatts_cb_event_t,
+        gatts_if: esp_gatt_if_t,
+        param: *mut esp_ble_gatts_cb_param_t,
+    ),
+>;
+
+// GAP event parameters.
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct esp_ble_gap_cb_param_t_ble_adv_data_cmpl_evt_param {
+    pub status: esp_bt_status_t,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct esp_ble_gap_cb_param_t_ble_scan_rsp_data_cmpl_evt_param {
+    pub status: esp_bt_status_t,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct esp_ble_gap_cb_param_t_ble_adv_data_raw_cmpl_evt_param {
+    pub status: esp_bt_status_t,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct esp_ble_gap_cb_param_t_ble_scan_rsp_data_raw_cmpl_evt_param {
+    pub status: esp_bt_status_t,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct esp_ble_gap_cb_param_t_ble_adv_start_cmpl_evt_param {
+    pub status: esp_bt_status_t,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct esp_ble_gap_cb_param_t_ble_adv_stop_cmpl_evt_param {
+    pub status: esp_bt_status_t,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct esp_ble_gap_cb_param_t_ble_update_conn_params_evt_param {
+    pub status: esp_bt_status_t,
+    pub bda: esp_bd_addr_t,
+    pub min_int: u16,
+    pub max_int: u16,
+    pub latency: u16,
+    pub conn_int: u16,
+    pub timeout: u16,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct esp_ble_conn_update_params_t {
+    pub bda: esp_bd_addr_t,
+    pub min_int: u16,
+    pub max_int: u16,
+    pub latency: u16,
+    pub timeout: u16,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct esp_ble_pkt_data_length_params_t {
+    pub rx_len: u16,
+    pub tx_len: u16,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct esp_ble_gap_cb_param_t_ble_pkt_data_length_cmpl_evt_param {
+    pub status: esp_bt_status_t,
+    pub params: esp_ble_pkt_data_length_params_t,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct esp_ble_gap_cb_param_t_ble_set_perf_phy_cmpl_evt_param {
+    pub status: esp_bt_status_t,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct esp_ble_gap_cb_param_t_ble_phy_update_cmpl_evt_param {
+    pub status: esp_bt_status_t,
+    pub bda: esp_bd_addr_t,
+    pub tx_phy: esp_ble_gap_phy_t,
+    pub rx_phy: esp_ble_gap_phy_t,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct esp_ble_gap_cb_param_t_ble_read_rssi_cmpl_evt_param {
+    pub status: esp_bt_status_t,
+    pub rssi: i8,
+    pub remote_addr: esp_bd_addr_t,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct esp_ble_auth_cmpl_t {
+    pub bd_addr: esp_bd_addr_t,
+    pub key_present: bool,
+    pub key: esp_link_key,
+    pub key_type: u8,
+    pub success: bool,
+    pub fail_reason: u8,
+    pub addr_type: esp_ble_addr_type_t,
+    pub dev_type: esp_bt_dev_type_t,
+    pub auth_mode: esp_ble_auth_req_t,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct esp_ble_sec_req_t {
+    pub bd_addr: esp_bd_addr_t,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct esp_ble_sec_key_notif_t {
+    pub bd_addr: esp_bd_addr_t,
+    pub passkey: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub union esp_ble_sec_t {
+    pub key_notif: esp_ble_sec_key_notif_t,
+    pub ble_req: esp_ble_sec_req_t,
+    pub auth_cmpl: esp_ble_auth_cmpl_t,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct esp_ble_gap_cb_param_t_ble_remove_bond_dev_cmpl_evt_param {
+    pub status: esp_bt_status_t,
+    pub bd_addr: esp_bd_addr_t,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct esp_ble_gap_cb_param_t_ble_ext_adv_set_params_cmpl_evt_param {
+    pub status: esp_bt_status_t,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct esp_ble_gap_cb_param_t_ble_ext_adv_data_set_cmpl_evt_param {
+    pub status: esp_bt_status_t,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct esp_ble_gap_cb_param_t_ble_ext_adv_scan_rsp_set_cmpl_evt_param {
+    pub status: esp_bt_status_t,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct esp_ble_gap_cb_param_t_ble_ext_adv_start_cmpl_evt_param {
+    pub status: esp_bt_status_t,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct esp_ble_gap_cb_param_t_ble_ext_adv_stop_cmpl_evt_param {
+    pub status: esp_bt_status_t,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct esp_ble_gap_cb_param_t_ble_ext_adv_set_remove_cmpl_evt_param {
+    pub status: esp_bt_status_t,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct esp_ble_gap_cb_param_t_ble_periodic_adv_set_params_cmpl_param {
+    pub status: esp_bt_status_t,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct esp_ble_gap_cb_param_t_ble_periodic_adv_data_set_cmpl_param {
+    pub status: esp_bt_status_t,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct esp_ble_gap_cb_param_t_ble_periodic_adv_start_cmpl_param {
+    pub status: esp_bt_status_t,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct esp_ble_gap_cb_param_t_ble_periodic_adv_stop_cmpl_param {
+    pub status: esp_bt_status_t,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct esp_ble_gap_cb_param_t_ble_adv_terminate_evt_param {
+    pub status: u8,
+    pub adv_instance: u8,
+    pub conn_idx: u16,
+    pub completed_event: u8,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub union esp_ble_gap_cb_param_t {
+    pub adv_data_cmpl: esp_ble_gap_cb_param_t_ble_adv_data_cmpl_evt_param,
+    pub scan_rsp_data_cmpl: esp_ble_gap_cb_param_t_ble_scan_rsp_data_cmpl_evt_param,
+    pub adv_data_raw_cmpl: esp_ble_gap_cb_param_t_ble_adv_data_raw_cmpl_evt_param,
+    pub scan_rsp_data_raw_cmpl: esp_ble_gap_cb_param_t_ble_scan_rsp_data_raw_cmpl_evt_param,
+    pub adv_start_cmpl: esp_ble_gap_cb_param_t_ble_adv_start_cmpl_evt_param,
+    pub adv_stop_cmpl: esp_ble_gap_cb_param_t_ble_adv_stop_cmpl_evt_param,
+    pub update_conn_params: esp_ble_gap_cb_param_t_ble_update_conn_params_evt_param,
+    pub pkt_data_length_cmpl: esp_ble_gap_cb_param_t_ble_pkt_data_length_cmpl_evt_param,
+    pub read_rssi_cmpl: esp_ble_gap_cb_param_t_ble_read_rssi_cmpl_evt_param,
+    pub set_perf_phy: esp_ble_gap_cb_param_t_ble_set_perf_phy_cmpl_evt_param,
+    pub phy_update: esp_ble_gap_cb_param_t_ble_phy_update_cmpl_evt_param,
+    pub ble_security: esp_ble_sec_t,
+    pub remove_bond_dev_cmpl: esp_ble_gap_cb_param_t_ble_remove_bond_dev_cmpl_evt_param,
+    pub ext_adv_set_params: esp_ble_gap_cb_param_t_ble_ext_adv_set_params_cmpl_evt_param,
+    pub ext_adv_data_set: esp_ble_gap_cb_param_t_ble_ext_adv_data_set_cmpl_evt_param,
+    pub scan_rsp_set: esp_ble_gap_cb_param_t_ble_ext_adv_scan_rsp_set_cmpl_evt_param,
+    pub ext_adv_start: esp_ble_gap_cb_param_t_ble_ext_adv_start_cmpl_evt_param,
+    pub ext_adv_stop: esp_ble_gap_cb_param_t_ble_ext_adv_stop_cmpl_evt_param,
+    pub ext_adv_remove: esp_ble_gap_cb_param_t_ble_ext_adv_set_remove_cmpl_evt_param,
+    pub peroid_adv_set_params: esp_ble_gap_cb_param_t_ble_periodic_adv_set_params_cmpl_param,
+    pub period_adv_data_set: esp_ble_gap_cb_param_t_ble_periodic_adv_data_set_cmpl_param,
+    pub period_adv_start: esp_ble_gap_cb_param_t_ble_periodic_adv_start_cmpl_param,
+    pub period_adv_stop: esp_ble_gap_cb_param_t_ble_periodic_adv_stop_cmpl_param,
+    pub adv_terminate: esp_ble_gap_cb_param_t_ble_adv_terminate_evt_param,
+}
+
+pub type esp_gap_ble_cb_t =
+    Option<unsafe extern "C" fn(event: esp_gap_ble_cb_event_t, param: *mut esp_ble_gap_cb_param_t)>;
+
+// GAP advertisement configuration.
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct esp_ble_adv_params_t {
+    pub adv_int_min: u16,
+    pub adv_int_max: u16,
+    pub adv_type: esp_ble_adv_type_t,
+    pub own_addr_type: esp_ble_addr_type_t,
+    pub peer_addr: esp_bd_addr_t,
+    pub peer_addr_type: esp_ble_addr_type_t,
+    pub channel_map: esp_ble_adv_channel_t,
+    pub adv_filter_policy: esp_ble_adv_filter_t,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct esp_ble_gap_ext_adv_params_t {
+    pub type_: esp_ble_ext_adv_type_mask_t,
+    pub interval_min: u32,
+    pub interval_max: u32,
+    pub channel_map: esp_ble_adv_channel_t,
+    pub own_addr_type: esp_ble_addr_type_t,
+    pub peer_addr_type: esp_ble_addr_type_t,
+    pub peer_addr: esp_bd_addr_t,
+    pub filter_policy: esp_ble_adv_filter_t,
+    pub tx_power: i8,
+    pub primary_phy: esp_ble_gap_pri_phy_t,
+    pub max_skip: u8,
+    pub secondary_phy: esp_ble_gap_phy_t,
+    pub sid: u8,
+    pub scan_req_notif: bool,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct esp_ble_gap_ext_adv_t {
+    pub instance: u8,
+    pub duration: i32,
+    pub max_events: i32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct esp_ble_gap_periodic_adv_params_t {
+    pub interval_min: u16,
+    pub interval_max: u16,
+    pub properties: u8,
+}
@@ -0,0 +1,173 @@
+//! The errors returned by this crate.
+
+use std::sync::PoisonError;
+
+use crate::sys::{esp_gatt_status_t, EspError};
+
+/// An error returned by the GATT server.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum Error {
+    /// The Bluetooth stack returned an error code.
+    Stack(EspError),
+    /// The value is longer than the maximum length of the attribute.
+    ValueTooLong {
+        /// The length of the rejected value.
+        length: usize,
+        /// The maximum length of the attribute.
+        max_length: usize,
+    },
+    /// The attribute is read by the stack, but it has no value.
+    EmptyValue,
+    /// The attribute has not been registered by the stack yet.
+    NotRegistered,
+    /// A lock was poisoned by a thread that panicked while holding it.
+    LockPoisoned,
+    /// The non-volatile storage cannot be accessed.
+    Nvs(EspError),
+    /// The stack did not complete the registration in time.
+    RegistrationTimeout,
+    /// The stack could not register an attribute, and returned this status.
+    RegistrationFailed(esp_gatt_status_t),
+    /// The server, or another one, is already started.
+    AlreadyStarted,
+    /// The server is not started.
+    NotStarted,
+    /// The client is not connected.
+    NotConnected,
+    /// The client is not subscribed to notifications or indications.
+    NotSubscribed,
+    /// The client did not confirm the indication in time.
+    IndicationTimeout,
+    /// The stack could not deliver the indication, and returned this status.
+    IndicationFailed(esp_gatt_status_t),
+    /// The passkey has more than six digits.
+    InvalidPasskey(u32),
+    /// The device has no pairing step waiting for this reply.
+    NoPairingRequest,
+    /// The permissions, the properties and the callbacks of an attribute do not match.
+    InconsistentPermissions(&'static str),
+    /// The advertising interval range is invalid.
+    InvalidAdvertisingInterval {
+        /// The minimum interval, in units of 0.625 ms.
+        min: u16,
+        /// The maximum interval, in units of 0.625 ms.
+        max: u16,
+    },
+    /// An AD structure does not fit in the advertising payloads, or holds more than 254 bytes of data.
+    AdvertisementTooLong {
+        /// The field encoded by the AD structure.
+        field: &'static str,
+        /// The length of the AD structure.
+        length: usize,
+    },
+    /// The configuration of an advertising set is invalid.
+    InvalidAdvertisingSet(&'static str),
+    /// No advertising set has this instance.
+    UnknownAdvertisingSet(u8),
+    /// The preferred connection parameters are invalid.
+    InvalidConnectionParameters(&'static str),
+    /// The MTU is not between 23 and 517 bytes.
+    InvalidMtu(u16),
+    /// The data length is not between 27 and 251 bytes.
+    InvalidDataLength(u16),
+    /// The services use more attribute handles than the Bluetooth stack can hold.
+    TooManyAttributes {
+        /// The number of attribute handles used.
+        count: usize,
+        /// The maximum number of attribute handles.
+        max_count: usize,
+    },
+    /// The value of a standard characteristic is out of its range.
+    InvalidValue(&'static str),
+    /// No profile of the server has this identifier.
+    UnknownProfile(u16),
+    /// The service is not part of a profile of the server.
+    UnknownService,
+    /// The service is already part of a profile of the server.
+    DuplicateService,
+    /// The declaration of a service is invalid.
+    InvalidService(&'static str),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Stack(error) => write!(f, "Bluetooth stack error: {error}"),
+            Self::ValueTooLong { length, max_length } => write!(
+                f,
+                "value is too long ({length} bytes, the maximum length is {max_length} bytes)"
+            ),
+            Self::EmptyValue => write!(f, "automatic response requires a value to be set"),
+            Self::NotRegistered => write!(f, "attribute not registered yet"),
+            Self::LockPoisoned => write!(f, "lock poisoned"),
+            Self::Nvs(error) => write!(f, "NVS error: {error}"),
+            Self::RegistrationTimeout => write!(f, "registration timed out"),
+            Self::RegistrationFailed(status) => {
+                write!(f, "registration failed with status 0x{status:02x}")
+            }
+            Self::AlreadyStarted => write!(f, "a GATT server is already started"),
+            Self::NotStarted => write!(f, "GATT server not started"),
+            Self::NotConnected => write!(f, "client not connected"),
+            Self::NotSubscribed => write!(f, "client not subscribed"),
+            Self::IndicationTimeout => write!(f, "indication not confirmed in time"),
+            Self::IndicationFailed(status) => {
+                write!(f, "indication failed with status 0x{status:02x}")
+            }
+            Self::InvalidPasskey(passkey) => write!(f, "invalid passkey {passkey}"),
+            Self::NoPairingRequest => write!(f, "no pending pairing request"),
+            Self::InconsistentPermissions(reason) => {
+                write!(f, "inconsistent permissions: {reason}")
+            }
+            Self::InvalidAdvertisingInterval { min, max } => write!(
+                f,
+                "invalid advertising interval range 0x{min:04x}-0x{max:04x}"
+            ),
+            Self::AdvertisementTooLong { field, length } => write!(
+                f,
+                "advertisement too long: the {field} ({length} bytes) does not fit in the payload"
+            ),
+            Self::InvalidAdvertisingSet(reason) => write!(f, "invalid advertising set: {reason}"),
+            Self::UnknownAdvertisingSet(instance) => {
+                write!(f, "unknown advertising set {instance}")
+            }
+            Self::InvalidConnectionParameters(reason) => {
+                write!(f, "invalid connection parameters: {reason}")
+            }
+            Self::InvalidMtu(mtu) => write!(f, "invalid MTU {mtu}"),
+            Self::InvalidDataLength(length) => write!(f, "invalid data length {length}"),
+            Self::TooManyAttributes { count, max_count } => write!(
+                f,
+                "too many attributes ({count} handles, the maximum is {max_count} handles)"
+            ),
+            Self::InvalidValue(reason) => write!(f, "invalid value: {reason}"),
+            Self::UnknownProfile(identifier) => {
+                write!(f, "unknown profile 0x{identifier:04x}")
+            }
+            Self::UnknownService => write!(f, "service not added to the server"),
+            Self::DuplicateService => write!(f, "service already added to the server"),
+            Self::InvalidService(reason) => write!(f, "invalid service: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Stack(error) | Self::Nvs(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<EspError> for Error {
+    fn from(error: EspError) -> Self {
+        Self::Stack(error)
+    }
+}
+
+impl<T> From<PoisonError<T>> for Error {
+    fn from(_: PoisonError<T>) -> Self {
+        Self::LockPoisoned
+    }
+}
@@ -0,0 +1,597 @@
+use crate::{
+    utilities::{Appearance, BleUuid},
+    Error,
+};
+
+/// The maximum length of legacy advertising data, and of a legacy scan response.
+pub(crate) const LEGACY_PAYLOAD_LENGTH: usize = 31;
+
+/// The maximum length of extended advertising data, of an extended scan response,
+/// and of periodic advertising data.
+pub(crate) const EXTENDED_PAYLOAD_LENGTH: usize = 1650;
+
+/// The AD types used by this crate, from the Bluetooth Core Specification Supplement.
+mod ad_type {
+    pub(super) const FLAGS: u8 = 0x01;
+    pub(super) const COMPLETE_UUID16: u8 = 0x03;
+    pub(super) const COMPLETE_UUID32: u8 = 0x05;
+    pub(super) const COMPLETE_UUID128: u8 = 0x07;
+    pub(super) const SHORTENED_NAME: u8 = 0x08;
+    pub(super) const COMPLETE_NAME: u8 = 0x09;
+    pub(super) const TX_POWER: u8 = 0x0A;
+    pub(super) const CONNECTION_INTERVAL: u8 = 0x12;
+    pub(super) const SERVICE_DATA16: u8 = 0x16;
+    pub(super) const APPEARANCE: u8 = 0x19;
+    pub(super) const SERVICE_DATA32: u8 = 0x20;
+    pub(super) const SERVICE_DATA128: u8 = 0x21;
+    pub(super) const URI: u8 = 0x24;
+    pub(super) const MANUFACTURER_DATA: u8 = 0xFF;
+}
+
+/// The URI schemes that are encoded as a single code point, from the Bluetooth Assigned Numbers.
+const URI_SCHEMES: [(&str, char); 2] = [("http:", '\u{16}'), ("https:", '\u{17}')];
+
+/// The local name of the device, as advertised.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum LocalName {
+    Complete(String),
+    Shortened(String),
+}
+
+/// Represents the content of the advertising data and of the scan response.
+///
+/// The content is encoded as AD structures. Structures that do not fit in the 31 bytes of the
+/// advertising data are moved to the scan response, in this order: flags, local name, appearance,
+/// TX power level, service UUIDs, service data, manufacturer data, connection interval range and URI.
+/// The flags are always kept in the advertising data.
+///
+/// ```rust,ignore
+/// let advertisement = Advertisement::new()
+///     .name("Color Lamp")
+///     .appearance(Appearance::LEDLamp)
+///     .service_uuid(BleUuid::from_uuid128_str("F9DFBD73-0181-433A-8091-372E0CA8A598"))
+///     .manufacturer_data(0x02E5, vec![0x01]);
+///
+/// let (advertising_data, scan_response) = advertisement.encode()?;
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Advertisement {
+    flags: u8,
+    name: Option<LocalName>,
+    appearance: Option<Appearance>,
+    tx_power: Option<i8>,
+    service_uuids: Vec<BleUuid>,
+    service_data: Vec<(BleUuid, Vec<u8>)>,
+    manufacturer_data: Option<(u16, Vec<u8>)>,
+    connection_interval: Option<(u16, u16)>,
+    uri: Option<String>,
+}
+
+impl Default for Advertisement {
+    fn default() -> Self {
+        Self {
+            // LE General Discoverable Mode, BR/EDR Not Supported.
+            flags: 0x06,
+            name: None,
+            appearance: None,
+            tx_power: None,
+            service_uuids: Vec::new(),
+            service_data: Vec::new(),
+            manufacturer_data: None,
+            connection_interval: None,
+            uri: None,
+        }
+    }
+}
+
+impl Advertisement {
+    /// Creates a new [`Advertisement`] containing only the flags for a general discoverable,
+    /// LE-only device.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the flags of the [`Advertisement`].
+    ///
+    /// The flags are omitted when set to zero.
+    #[must_use]
+    pub fn flags(mut self, flags: u8) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    /// Sets the complete local name of the [`Advertisement`].
+    #[must_use]
+    pub fn name<S: Into<String>>(mut self, name: S) -> Self {
+        self.name = Some(LocalName::Complete(name.into()));
+        self
+    }
+
+    /// Sets a shortened local name for the [`Advertisement`], replacing the complete one.
+    #[must_use]
+    pub fn shortened_name<S: Into<String>>(mut self, name: S) -> Self {
+        self.name = Some(LocalName::Shortened(name.into()));
+        self
+    }
+
+    /// Sets the appearance of the [`Advertisement`].
+    #[must_use]
+    pub fn appearance(mut self, appearance: Appearance) -> Self {
+        self.appearance = Some(appearance);
+        self
+    }
+
+    /// Sets the TX power level of the [`Advertisement`], in dBm.
+    #[must_use]
+    pub fn tx_power(mut self, tx_power: i8) -> Self {
+        self.tx_power = Some(tx_power);
+        self
+    }
+
+    /// Adds a service UUID to the [`Advertisement`].
+    ///
+    /// UUIDs are grouped by size, in complete lists.
+    #[must_use]
+    pub fn service_uuid(mut self, uuid: BleUuid) -> Self {
+        self.add_service_uuid(uuid);
+        self
+    }
+
+    /// Adds data associated to a service to the [`Advertisement`].
+    #[must_use]
+    pub fn service_data(mut self, uuid: BleUuid, data: Vec<u8>) -> Self {
+        self.service_data.push((uuid, data));
+        self
+    }
+
+    /// Sets the manufacturer specific data of the [`Advertisement`].
+    ///
+    /// The company identifier is assigned by the Bluetooth SIG.
+    #[must_use]
+    pub fn manufacturer_data(mut self, company_identifier: u16, data: Vec<u8>) -> Self {
+        self.manufacturer_data = Some((company_identifier, data));
+        self
+    }
+
+    /// Sets the preferred connection interval range of the [`Advertisement`], in units of 1.25 ms.
+    #[must_use]
+    pub fn connection_interval(mut self, min: u16, max: u16) -> Self {
+        self.connection_interval = Some((min, max));
+        self
+    }
+
+    /// Sets the URI of the [`Advertisement`].
+    ///
+    /// The `http:` and `https:` schemes are compressed.
+    #[must_use]
+    pub fn uri<S: Into<String>>(mut self, uri: S) -> Self {
+        self.uri = Some(uri.into());
+        self
+    }
+
+    /// Adds a service UUID, unless it is already advertised.
+    pub(crate) fn add_service_uuid(&mut self, uuid: BleUuid) {
+        if !self.service_uuids.contains(&uuid) {
+            self.service_uuids.push(uuid);
+        }
+    }
+
+    /// Sets the local name, keeping it shortened if it was.
+    pub(crate) fn set_name(&mut self, name: String) {
+        self.name = Some(match self.name {
+            Some(LocalName::Shortened(_)) => LocalName::Shortened(name),
+            _ => LocalName::Complete(name),
+        });
+    }
+
+    /// Sets the preferred connection interval range, in units of 1.25 ms.
+    pub(crate) fn set_connection_interval(&mut self, min: u16, max: u16) {
+        self.connection_interval = Some((min, max));
+    }
+
+    /// Sets the appearance, or removes it for [`Appearance::GenericUnknown`].
+    pub(crate) fn set_appearance(&mut self, appearance: Appearance) {
+        self.appearance = (appearance != Appearance::GenericUnknown).then_some(appearance);
+    }
+
+    /// Encodes the [`Advertisement`] into the legacy advertising data and scan response.
+    ///
+    /// Returns the advertising data and the scan response, which is empty if everything fits
+    /// in the advertising data.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::AdvertisementTooLong`] if an AD structure fits neither in the advertising data
+    /// nor in the scan response, or holds more than 254 bytes of data.
+    pub fn encode(&self) -> Result<(Vec<u8>, Vec<u8>), Error> {
+        let mut advertising_data = Vec::new();
+        let mut scan_response = Vec::new();
+
+        for (field, structure) in self.structures()? {
+            if advertising_data.len() + structure.len() <= LEGACY_PAYLOAD_LENGTH {
+                advertising_data.extend_from_slice(&structure);
+            } else if field != "flags"
+                && scan_response.len() + structure.len() <= LEGACY_PAYLOAD_LENGTH
+            {
+                scan_response.extend_from_slice(&structure);
+            } else {
+                return Err(Error::AdvertisementTooLong {
+                    field,
+                    length: structure.len(),
+                });
+            }
+        }
+
+        Ok((advertising_data, scan_response))
+    }
+
+    /// Encodes the [`Advertisement`] into the data of an extended advertising set.
+    ///
+    /// Extended advertising data holds up to 1650 bytes, so there is no need for a scan response.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::AdvertisementTooLong`] if the AD structures do not fit in 1650 bytes,
+    /// or if one of them holds more than 254 bytes of data.
+    pub fn encode_extended(&self) -> Result<Vec<u8>, Error> {
+        concatenate(self.structures()?)
+    }
+
+    /// Encodes the [`Advertisement`] without the flags, for scan responses and periodic advertising.
+    pub(crate) fn encode_without_flags(&self) -> Result<Vec<u8>, Error> {
+        concatenate(
+            self.structures()?
+                .into_iter()
+                .filter(|(field, _)| *field != "flags")
+                .collect(),
+        )
+    }
+
+    /// Returns the AD structures, with the name of the field they encode, in placement order.
+    fn structures(&self) -> Result<Vec<(&'static str, Vec<u8>)>, Error> {
+        let mut structures = Vec::new();
+
+        if self.flags != 0 {
+            structures.push(structure("flags", ad_type::FLAGS, &[self.flags])?);
+        }
+
+        match &self.name {
+            Some(LocalName::Complete(name)) => structures.push(structure(
+                "local name",
+                ad_type::COMPLETE_NAME,
+                name.as_bytes(),
+            )?),
+            Some(LocalName::Shortened(name)) => structures.push(structure(
+                "local name",
+                ad_type::SHORTENED_NAME,
+                name.as_bytes(),
+            )?),
+            None => {}
+        }
+
+        if let Some(appearance) = self.appearance {
+            #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+            let appearance = i32::from(appearance) as u16;
+            structures.push(structure(
+                "appearance",
+                ad_type::APPEARANCE,
+                &appearance.to_le_bytes(),
+            )?);
+        }
+
+        if let Some(tx_power) = self.tx_power {
+            structures.push(structure(
+                "TX power level",
+                ad_type::TX_POWER,
+                &tx_power.to_le_bytes(),
+            )?);
+        }
+
+        for (ad_type, length) in [
+            (ad_type::COMPLETE_UUID16, 2),
+            (ad_type::COMPLETE_UUID32, 4),
+            (ad_type::COMPLETE_UUID128, 16),
+        ] {
+            let uuids: Vec<u8> = self
+                .service_uuids
+                .iter()
+                .map(|uuid| uuid.to_le_bytes())
+                .filter(|bytes| bytes.len() == length)
+                .flatten()
+                .collect();
+
+            if !uuids.is_empty() {
+                structures.push(structure("service UUIDs", ad_type, &uuids)?);
+            }
+        }
+
+        for (uuid, data) in &self.service_data {
+            let mut value = uuid.to_le_bytes();
+            let ad_type = match value.len() {
+                2 => ad_type::SERVICE_DATA16,
+                4 => ad_type::SERVICE_DATA32,
+                _ => ad_type::SERVICE_DATA128,
+            };
+
+            value.extend_from_slice(data);
+            structures.push(structure("service data", ad_type, &value)?);
+        }
+
+        if let Some((company_identifier, data)) = &self.manufacturer_data {
+            let mut value = company_identifier.to_le_bytes().to_vec();
+            value.extend_from_slice(data);
+            structures.push(structure(
+                "manufacturer data",
+                ad_type::MANUFACTURER_DATA,
+                &value,
+            )?);
+        }
+
+        if let Some((min, max)) = self.connection_interval {
+            let mut value = min.to_le_bytes().to_vec();
+            value.extend_from_slice(&max.to_le_bytes());
+            structures.push(structure(
+                "connection interval range",
+                ad_type::CONNECTION_INTERVAL,
+                &value,
+            )?);
+        }
+
+        if let Some(uri) = &self.uri {
+            structures.push(structure("URI", ad_type::URI, &encode_uri(uri))?);
+        }
+
+        Ok(structures)
+    }
+}
+
+/// Concatenates AD structures into an extended payload.
+fn concatenate(structures: Vec<(&'static str, Vec<u8>)>) -> Result<Vec<u8>, Error> {
+    let mut payload = Vec::new();
+
+    for (field, structure) in structures {
+        if payload.len() + structure.len() > EXTENDED_PAYLOAD_LENGTH {
+            return Err(Error::AdvertisementTooLong {
+                field,
+                length: structure.len(),
+            });
+        }
+
+        payload.extend_from_slice(&structure);
+    }
+
+    Ok(payload)
+}
+
+/// Encodes an AD structure: its length, its type and its data, with the name of the field it encodes.
+///
+/// The length byte counts the type too, so an AD structure holds up to 254 bytes of data.
+fn structure(
+    field: &'static str,
+    ad_type: u8,
+    data: &[u8],
+) -> Result<(&'static str, Vec<u8>), Error> {
+    let Ok(length) = u8::try_from(data.len() + 1) else {
+        return Err(Error::AdvertisementTooLong {
+            field,
+            length: data.len() + 2,
+        });
+    };
+
+    let mut structure = Vec::with_capacity(data.len() + 2);
+    structure.push(length);
+    structure.push(ad_type);
+    structure.extend_from_slice(data);
+    Ok((field, structure))
+}
+
+/// Encodes a URI, replacing its scheme with its code point, or prefixing it with the empty scheme.
+fn encode_uri(uri: &str) -> Vec<u8> {
+    let mut encoded = String::new();
+
+    if let Some((scheme, code_point)) = URI_SCHEMES
+        .iter()
+        .find(|(scheme, _)| uri.starts_with(scheme))
+    {
+        encoded.push(*code_point);
+        encoded.push_str(&uri[scheme.len()..]);
+    } else {
+        encoded.push('\u{01}');
+        encoded.push_str(uri);
+    }
+
+    encoded.into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LAMP_SERVICE: &str = "F9DFBD73-0181-433A-8091-372E0CA8A598";
+
+    /// The little-endian bytes of the lamp service UUID.
+    const LAMP_SERVICE_BYTES: [u8; 16] = [
+        0x98, 0xA5, 0xA8, 0x0C, 0x2E, 0x37, 0x91, 0x80, 0x3A, 0x43, 0x81, 0x01, 0x73, 0xBD, 0xDF,
+        0xF9,
+    ];
+
+    fn encode(advertisement: &Advertisement) -> (Vec<u8>, Vec<u8>) {
+        advertisement.encode().unwrap()
+    }
+
+    #[test]
+    fn flags() {
+        assert_eq!(encode(&Advertisement::new()), (vec![2, 0x01, 0x06], vec![]));
+        assert_eq!(
+            encode(&Advertisement::new().flags(0x05)),
+            (vec![2, 0x01, 0x05], vec![])
+        );
+        assert_eq!(encode(&Advertisement::new().flags(0)), (vec![], vec![]));
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(
+            encode(&Advertisement::new().flags(0).name("Lamp")),
+            (vec![5, 0x09, b'L', b'a', b'm', b'p'], vec![])
+        );
+        assert_eq!(
+            encode(&Advertisement::new().flags(0).shortened_name("La")),
+            (vec![3, 0x08, b'L', b'a'], vec![])
+        );
+
+        // Renaming keeps a shortened name shortened.
+        let mut advertisement = Advertisement::new().flags(0).shortened_name("La");
+        advertisement.set_name(String::from("Lo"));
+        assert_eq!(encode(&advertisement), (vec![3, 0x08, b'L', b'o'], vec![]));
+    }
+
+    #[test]
+    fn fields() {
+        let advertisement = Advertisement::new()
+            .flags(0)
+            .appearance(crate::utilities::Appearance::LEDLamp)
+            .tx_power(-4)
+            .connection_interval(0x0006, 0x0010);
+
+        assert_eq!(
+            encode(&advertisement).0,
+            [
+                vec![3, 0x19, 0xC2, 0x07],
+                vec![2, 0x0A, 0xFC],
+                vec![5, 0x12, 0x06, 0x00, 0x10, 0x00],
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn service_uuids() {
+        let advertisement = Advertisement::new()
+            .flags(0)
+            .service_uuid(BleUuid::Uuid16(0x180F))
+            .service_uuid(BleUuid::from_uuid128_str(LAMP_SERVICE))
+            .service_uuid(BleUuid::Uuid32(0x0102_0304))
+            .service_uuid(BleUuid::Uuid16(0x180A))
+            .service_uuid(BleUuid::Uuid16(0x180F));
+
+        // One complete list per size, without duplicates.
+        let (advertising_data, scan_response) = encode(&advertisement);
+        assert_eq!(
+            advertising_data,
+            [
+                vec![5, 0x03, 0x0F, 0x18, 0x0A, 0x18],
+                vec![5, 0x05, 0x04, 0x03, 0x02, 0x01],
+                vec![17, 0x07],
+                LAMP_SERVICE_BYTES.to_vec(),
+            ]
+            .concat()
+        );
+        assert!(scan_response.is_empty());
+    }
+
+    #[test]
+    fn data() {
+        let advertisement = Advertisement::new()
+            .flags(0)
+            .service_data(BleUuid::Uuid16(0x180F), vec![80])
+            .manufacturer_data(0x02E5, vec![1, 2]);
+
+        assert_eq!(
+            encode(&advertisement).0,
+            [
+                vec![4, 0x16, 0x0F, 0x18, 80],
+                vec![5, 0xFF, 0xE5, 0x02, 1, 2],
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn uris() {
+        let uri = |uri: &str| encode(&Advertisement::new().flags(0).uri(uri)).0;
+
+        assert_eq!(
+            uri("https://a.io"),
+            [vec![8, 0x24, 0x17], b"//a.io".to_vec()].concat()
+        );
+        assert_eq!(
+            uri("http://a.io"),
+            [vec![8, 0x24, 0x16], b"//a.io".to_vec()].concat()
+        );
+        assert_eq!(
+            uri("ftp://a.io"),
+            [vec![12, 0x24, 0x01], b"ftp://a.io".to_vec()].concat()
+        );
+    }
+
+    #[test]
+    fn scan_response() {
+        let advertisement = Advertisement::new()
+            .name("Color Lamp")
+            .appearance(crate::utilities::Appearance::LEDLamp)
+            .service_uuid(BleUuid::from_uuid128_str(LAMP_SERVICE))
+            .manufacturer_data(0x02E5, vec![1, 2]);
+
+        // The UUID does not fit after the flags, the name and the appearance (19 bytes),
+        // but the manufacturer data does.
+        let (advertising_data, scan_response) = encode(&advertisement);
+        assert_eq!(
+            advertising_data,
+            [
+                vec![2, 0x01, 0x06],
+                vec![11, 0x09],
+                b"Color Lamp".to_vec(),
+                vec![3, 0x19, 0xC2, 0x07],
+                vec![5, 0xFF, 0xE5, 0x02, 1, 2],
+            ]
+            .concat()
+        );
+        assert_eq!(advertising_data.len(), 25);
+        assert_eq!(
+            scan_response,
+            [vec![17, 0x07], LAMP_SERVICE_BYTES.to_vec()].concat()
+        );
+
+        // Structures that fit in neither payload are rejected.
+        let error = advertisement
+            .manufacturer_data(0x02E5, vec![0; 20])
+            .service_data(BleUuid::Uuid16(0x180F), vec![0; 20])
+            .encode()
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            Error::AdvertisementTooLong {
+                field: "service data",
+                length: 24
+            }
+        ));
+    }
+
+    #[test]
+    fn too_long() {
+        let longest = Advertisement::new()
+            .flags(0)
+            .manufacturer_data(0x02E5, vec![0; 252]);
+        let encoded = longest.encode_extended().unwrap();
+        assert_eq!(encoded.len(), 256);
+        assert_eq!(encoded[..4], [255, 0xFF, 0xE5, 0x02]);
+
+        // Longer data does not fit in an AD structure, whatever the payload.
+        let too_long = Advertisement::new().manufacturer_data(0x02E5, vec![0; 253]);
+        for result in [
+            too_long.encode().map(|_| ()),
+            too_long.encode_extended().map(|_| ()),
+            too_long.encode_without_flags().map(|_| ()),
+        ] {
+            assert!(matches!(
+                result,
+                Err(Error::AdvertisementTooLong {
+                    field: "manufacturer data",
+                    length: 257
+                })
+            ));
+        }
+    }
+}
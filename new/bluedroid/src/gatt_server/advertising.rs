@@ -0,0 +1,484 @@
+use std::{
+    sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError, Weak},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    backend::backend,
+    gatt_server::{connection_state::connection_states, Advertisement, GattServer},
+    sys::*,
+    utilities::AddressType,
+    Error,
+};
+use log::{debug, info, warn};
+
+/// The shortest advertising interval, in units of 0.625 ms.
+const MIN_INTERVAL: u16 = 0x0020;
+
+/// The longest advertising interval, in units of 0.625 ms.
+const MAX_INTERVAL: u16 = 0x4000;
+
+/// How the server advertises, and which clients can respond.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AdvertisingMode {
+    /// Any client can request the scan response and connect.
+    #[default]
+    Connectable,
+    /// Any client can request the scan response, but not connect.
+    Scannable,
+    /// Clients can only receive the advertising data.
+    NonConnectable,
+    /// Only the given client can connect. The advertisement is not sent.
+    Directed {
+        /// The address of the client.
+        address: esp_bd_addr_t,
+        /// The type of the address of the client.
+        address_type: AddressType,
+    },
+}
+
+impl AdvertisingMode {
+    /// Whether the controller stops advertising when a client connects.
+    const fn is_connectable(self) -> bool {
+        matches!(self, Self::Connectable | Self::Directed { .. })
+    }
+}
+
+/// When advertising is resumed after the controller stops it because a client connected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ResumePolicy {
+    /// Advertising is not resumed. It can be started again with [`GattServer::start_advertising`].
+    Never,
+    /// Advertising is resumed when a client disconnects.
+    #[default]
+    OnDisconnect,
+    /// Advertising is resumed when the last client disconnects.
+    WhenIdle,
+    /// Advertising is resumed as soon as a client connects, so that other clients can connect too.
+    Always,
+}
+
+/// The advertising configuration of a [`GattServer`].
+#[derive(Debug, Clone)]
+pub struct AdvertisingConfig {
+    pub(crate) mode: AdvertisingMode,
+    pub(crate) min_interval: u16,
+    pub(crate) max_interval: u16,
+    pub(crate) duration: Option<Duration>,
+    pub(crate) resume: ResumePolicy,
+    pub(crate) autostart: bool,
+}
+
+impl Default for AdvertisingConfig {
+    fn default() -> Self {
+        Self {
+            mode: AdvertisingMode::default(),
+            min_interval: 0x20,
+            max_interval: 0x40,
+            duration: None,
+            resume: ResumePolicy::default(),
+            autostart: true,
+        }
+    }
+}
+
+impl AdvertisingConfig {
+    /// Creates a new [`AdvertisingConfig`] with the default values.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the advertising mode.
+    pub fn mode(&mut self, mode: AdvertisingMode) -> &mut Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Sets the advertising interval range, in units of 0.625 ms.
+    ///
+    /// The interval must be between 20 ms (`0x0020`) and 10.24 s (`0x4000`).
+    pub fn interval(&mut self, min: u16, max: u16) -> &mut Self {
+        self.min_interval = min;
+        self.max_interval = max;
+        self
+    }
+
+    /// Sets how long advertising lasts each time it is started or resumed.
+    ///
+    /// When the duration expires, advertising stops as if [`GattServer::stop_advertising`] was called.
+    /// By default, advertising lasts until it is stopped or a client connects.
+    pub fn duration(&mut self, duration: Duration) -> &mut Self {
+        self.duration = Some(duration);
+        self
+    }
+
+    /// Sets when advertising is resumed after a client connects.
+    pub fn resume(&mut self, policy: ResumePolicy) -> &mut Self {
+        self.resume = policy;
+        self
+    }
+
+    /// Sets whether advertising starts with the server.
+    ///
+    /// When disabled, advertising starts only when calling [`GattServer::start_advertising`].
+    pub fn autostart(&mut self, autostart: bool) -> &mut Self {
+        self.autostart = autostart;
+        self
+    }
+
+    /// Checks that the configuration can be applied.
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        if self.min_interval < MIN_INTERVAL
+            || self.max_interval > MAX_INTERVAL
+            || self.min_interval > self.max_interval
+        {
+            return Err(Error::InvalidAdvertisingInterval {
+                min: self.min_interval,
+                max: self.max_interval,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Returns the advertising parameters of the stack.
+    pub(crate) fn parameters(&self) -> esp_ble_adv_params_t {
+        let (adv_type, peer_addr, peer_addr_type) = match self.mode {
+            AdvertisingMode::Connectable => (
+                esp_ble_adv_type_t_ADV_TYPE_IND,
+                esp_bd_addr_t::default(),
+                AddressType::Public,
+            ),
+            AdvertisingMode::Scannable => (
+                esp_ble_adv_type_t_ADV_TYPE_SCAN_IND,
+                esp_bd_addr_t::default(),
+                AddressType::Public,
+            ),
+            AdvertisingMode::NonConnectable => (
+                esp_ble_adv_type_t_ADV_TYPE_NONCONN_IND,
+                esp_bd_addr_t::default(),
+                AddressType::Public,
+            ),
+            AdvertisingMode::Directed {
+                address,
+                address_type,
+            } => (
+                esp_ble_adv_type_t_ADV_TYPE_DIRECT_IND_LOW,
+                address,
+                address_type,
+            ),
+        };
+
+        esp_ble_adv_params_t {
+            adv_int_min: self.min_interval,
+            adv_int_max: self.max_interval,
+            adv_type,
+            own_addr_type: esp_ble_addr_type_t_BLE_ADDR_TYPE_RPA_PUBLIC,
+            peer_addr,
+            peer_addr_type: peer_addr_type.into(),
+            channel_map: esp_ble_adv_channel_t_ADV_CHNL_ALL,
+            adv_filter_policy: esp_ble_adv_filter_t_ADV_FILTER_ALLOW_SCAN_ANY_CON_ANY,
+        }
+    }
+}
+
+/// The state of the advertising of a [`GattServer`].
+#[derive(Debug, Default)]
+pub(crate) struct AdvertisingState {
+    /// Whether advertising is wanted: started, and not stopped since.
+    pub(crate) enabled: bool,
+    /// Whether the stack is advertising, or was asked to.
+    pub(crate) active: bool,
+    /// Incremented each time advertising starts or stops, so that stale timeouts are ignored.
+    pub(crate) generation: u32,
+    /// Stops advertising when its duration expires.
+    timer: AdvertisingTimer,
+}
+
+/// The timeout of the advertising started with a duration.
+#[derive(Debug, Default)]
+struct TimerState {
+    /// When advertising expires, with the generation of the advertising it stops.
+    deadline: Option<(Instant, u32)>,
+    /// Whether the server was dropped.
+    closed: bool,
+}
+
+/// Stops advertising when its duration expires, from a single thread per server.
+///
+/// The thread is spawned the first time advertising starts with a duration,
+/// and exits when the server is dropped.
+#[derive(Debug, Default)]
+struct AdvertisingTimer {
+    shared: Arc<(Mutex<TimerState>, Condvar)>,
+    running: bool,
+}
+
+impl AdvertisingTimer {
+    fn state(&self) -> MutexGuard<'_, TimerState> {
+        self.shared.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Sets when the advertising of the given generation expires, replacing the previous timeout.
+    fn arm(&mut self, server: &Weak<Mutex<GattServer>>, deadline: Instant, generation: u32) {
+        self.state().deadline = Some((deadline, generation));
+        self.shared.1.notify_one();
+
+        if self.running {
+            return;
+        }
+
+        let shared = self.shared.clone();
+        let server = server.clone();
+        let spawned = std::thread::Builder::new()
+            .name(String::from("advertising timer"))
+            .spawn(move || Self::run(&shared, &server));
+
+        match spawned {
+            Ok(_) => self.running = true,
+            Err(error) => warn!("Cannot start the advertising timer: {}.", error),
+        }
+    }
+
+    /// Waits for the timeouts, until the server is dropped.
+    ///
+    /// The timer is never locked while the server is.
+    fn run(shared: &(Mutex<TimerState>, Condvar), server: &Weak<Mutex<GattServer>>) {
+        let (state, signal) = shared;
+        let mut guard = state.lock().unwrap_or_else(PoisonError::into_inner);
+
+        loop {
+            if guard.closed {
+                return;
+            }
+
+            let Some((deadline, generation)) = guard.deadline else {
+                guard = signal.wait(guard).unwrap_or_else(PoisonError::into_inner);
+                continue;
+            };
+
+            let now = Instant::now();
+            if now < deadline {
+                guard = signal
+                    .wait_timeout(guard, deadline - now)
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .0;
+                continue;
+            }
+
+            guard.deadline = None;
+            drop(guard);
+
+            let Some(server) = server.upgrade() else {
+                return;
+            };
+
+            GattServer::on_advertising_timeout(&server, generation);
+            drop(server);
+
+            guard = state.lock().unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+}
+
+impl Drop for AdvertisingTimer {
+    fn drop(&mut self) {
+        self.state().closed = true;
+        self.shared.1.notify_one();
+    }
+}
+
+impl GattServer {
+    /// Sets the advertising configuration of the server.
+    ///
+    /// If the server is advertising, advertising restarts with the new configuration.
+    pub fn advertising(&mut self, config: AdvertisingConfig) -> &mut Self {
+        if let Err(error) = config.validate() {
+            warn!("Ignoring advertising configuration: {}.", error);
+            return self;
+        }
+
+        self.advertising = config;
+
+        if self.advertising_state.active {
+            if let Err(error) = self.halt_advertising() {
+                warn!("Cannot stop advertising: {}.", error);
+            }
+
+            self.resume_advertising();
+        }
+
+        self
+    }
+
+    /// Starts advertising, or resumes it after it was stopped.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server is not started.
+    pub fn start_advertising(&mut self) -> Result<(), Error> {
+        if !self.started {
+            return Err(Error::NotStarted);
+        }
+
+        self.advertising_state.enabled = true;
+
+        // Otherwise, advertising starts when the advertisement is set.
+        if self.advertisement_configured {
+            self.resume_advertising();
+        }
+
+        Ok(())
+    }
+
+    /// Stops advertising, until [`GattServer::start_advertising`] is called.
+    ///
+    /// Advertising is not resumed when clients disconnect.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server is not started, or if the stack cannot stop advertising.
+    pub fn stop_advertising(&mut self) -> Result<(), Error> {
+        if !self.started {
+            return Err(Error::NotStarted);
+        }
+
+        self.advertising_state.enabled = false;
+
+        if self.advertising_state.active {
+            self.halt_advertising()?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns whether the server is advertising.
+    #[must_use]
+    pub fn is_advertising(&self) -> bool {
+        self.advertising_state.active
+    }
+
+    /// Replaces the advertisement, and updates the advertised data if the server is started.
+    ///
+    /// Advertising continues with the new data.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the advertisement does not fit, in which case the previous one is kept,
+    /// or if the stack cannot set the data.
+    pub fn update_advertisement(&mut self, advertisement: Advertisement) -> Result<(), Error> {
+        advertisement.encode()?;
+        self.advertisement = advertisement;
+
+        if self.advertisement_configured {
+            self.configure_advertisement()?;
+        }
+
+        Ok(())
+    }
+
+    /// Sets the advertising data and the scan response from the advertisement.
+    ///
+    /// Advertising starts when the stack has set the scan response, if it is enabled.
+    pub(crate) fn configure_advertisement(&mut self) -> Result<(), Error> {
+        let (advertising_data, scan_response) = self.advertisement.encode()?;
+
+        backend().config_adv_data_raw(&advertising_data)?;
+
+        // Set even if empty, to replace the previous one.
+        backend().config_scan_rsp_data_raw(&scan_response)?;
+
+        Ok(())
+    }
+
+    /// Starts advertising if it is enabled and not already running.
+    pub(crate) fn resume_advertising(&mut self) {
+        if !self.advertising_state.enabled || self.advertising_state.active {
+            return;
+        }
+
+        info!("Starting BLE GAP advertisement.");
+
+        let mut parameters = self.advertising.parameters();
+        if let Err(error) = backend().start_advertising(&mut parameters) {
+            warn!("Cannot start advertising: {}.", error);
+            return;
+        }
+
+        self.advertising_state.active = true;
+    }
+
+    /// Stops advertising, without changing whether it is enabled.
+    fn halt_advertising(&mut self) -> Result<(), Error> {
+        backend().stop_advertising()?;
+
+        self.advertising_state.active = false;
+        self.advertising_state.generation = self.advertising_state.generation.wrapping_add(1);
+
+        Ok(())
+    }
+
+    /// Updates the advertising state after the stack started advertising.
+    pub(crate) fn on_advertising_started(&mut self, success: bool) {
+        if !success {
+            warn!("BLE GAP advertisement start failed.");
+            self.advertising_state.active = false;
+            return;
+        }
+
+        debug!("BLE GAP advertisement started.");
+
+        let Some(duration) = self.advertising.duration else {
+            return;
+        };
+
+        self.advertising_state.generation = self.advertising_state.generation.wrapping_add(1);
+        let generation = self.advertising_state.generation;
+        self.advertising_state
+            .timer
+            .arm(&self.this, Instant::now() + duration, generation);
+    }
+
+    /// Stops advertising when its duration expires.
+    fn on_advertising_timeout(server: &Mutex<Self>, generation: u32) {
+        let mut server = server.lock().expect("Cannot lock GATT server.");
+
+        // Advertising was stopped or restarted in the meantime.
+        if server.advertising_state.generation != generation || !server.advertising_state.active {
+            return;
+        }
+
+        info!("BLE GAP advertisement timed out.");
+        if let Err(error) = server.stop_advertising() {
+            warn!("Cannot stop advertising: {}.", error);
+        }
+    }
+
+    /// Updates the advertising state after a client connected.
+    pub(crate) fn on_advertising_connect(&mut self) {
+        if !self.advertising.mode.is_connectable() {
+            return;
+        }
+
+        // The controller stops advertising when a connection is established.
+        self.advertising_state.active = false;
+
+        match self.advertising.resume {
+            ResumePolicy::Never => self.advertising_state.enabled = false,
+            ResumePolicy::Always => self.resume_advertising(),
+            ResumePolicy::OnDisconnect | ResumePolicy::WhenIdle => {}
+        }
+    }
+
+    /// Updates the advertising state after a client disconnected.
+    pub(crate) fn on_advertising_disconnect(&mut self) {
+        match self.advertising.resume {
+            ResumePolicy::Never => {}
+            ResumePolicy::WhenIdle if !connection_states().is_empty() => {}
+            ResumePolicy::OnDisconnect | ResumePolicy::WhenIdle | ResumePolicy::Always => {
+                self.resume_advertising();
+            }
+        }
+    }
+}
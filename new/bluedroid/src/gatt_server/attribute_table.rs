@@ -0,0 +1,425 @@
+use crate::{
+    backend::backend,
+    gatt_server::{Descriptor, Service},
+    sys::{
+        esp_attr_control_t, esp_attr_desc_t, esp_gatt_char_prop_t, esp_gatt_if_t, esp_gatt_perm_t,
+        esp_gatts_attr_db_t, ESP_GATT_AUTO_RSP, ESP_GATT_RSP_BY_APP,
+    },
+    utilities::{AttributeControl, AttributePermissions, BleUuid},
+    Error,
+};
+
+/// An attribute of an [`AttributeTable`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableEntry {
+    /// The type of the attribute.
+    pub uuid: BleUuid,
+    /// The permissions of the attribute, as passed to the Bluetooth stack.
+    pub permissions: esp_gatt_perm_t,
+    /// The maximum length of the value.
+    pub max_length: u16,
+    /// The initial value.
+    pub value: Vec<u8>,
+    /// Whether the Bluetooth stack answers the requests, instead of the application.
+    pub automatic_response: bool,
+}
+
+/// The attributes of a [`Service`], in the order of their handles,
+/// as registered at once by `esp_ble_gatts_create_attr_tab`.
+///
+/// It is converted from a [`Service`], and starts with the service declaration, followed by the include declarations.
+/// Their values hold the first and last handles of the included services, and their UUIDs if they are 16-bit,
+/// so the included services must be registered first.
+/// Each characteristic is made of a declaration, whose value only holds the properties,
+/// followed by its value and its descriptors, including the CCCD added when it is registered.
+///
+/// See [`Service::attribute_table`] to register a service this way.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AttributeTable {
+    entries: Vec<TableEntry>,
+}
+
+impl AttributeTable {
+    /// Returns the attributes of the table.
+    #[must_use]
+    pub fn entries(&self) -> &[TableEntry] {
+        &self.entries
+    }
+
+    /// Registers the table on an interface. The registration is completed by `ESP_GATTS_CREAT_ATTR_TAB_EVT`.
+    #[allow(clippy::cast_possible_truncation)]
+    pub(crate) fn register(&self, interface: esp_gatt_if_t) -> Result<(), Error> {
+        let mut uuids: Vec<Vec<u8>> = self
+            .entries
+            .iter()
+            .map(|entry| entry.uuid.to_le_bytes())
+            .collect();
+        let mut values: Vec<Vec<u8>> = self
+            .entries
+            .iter()
+            .map(|entry| entry.value.clone())
+            .collect();
+
+        let database: Vec<esp_gatts_attr_db_t> = self
+            .entries
+            .iter()
+            .zip(uuids.iter_mut().zip(values.iter_mut()))
+            .map(|(entry, (uuid, value))| esp_gatts_attr_db_t {
+                attr_control: esp_attr_control_t {
+                    auto_rsp: if entry.automatic_response {
+                        ESP_GATT_AUTO_RSP as u8
+                    } else {
+                        ESP_GATT_RSP_BY_APP as u8
+                    },
+                },
+                att_desc: esp_attr_desc_t {
+                    uuid_length: uuid.len() as u16,
+                    uuid_p: uuid.as_mut_ptr(),
+                    perm: entry.permissions,
+                    max_length: entry.max_length,
+                    length: value.len() as u16,
+                    value: value.as_mut_ptr(),
+                },
+            })
+            .collect();
+
+        backend().create_attr_tab(interface, &database, 0)?;
+
+        Ok(())
+    }
+}
+
+impl TryFrom<&Service> for AttributeTable {
+    type Error = Error;
+
+    /// Converts a [`Service`] to the table of its attributes.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the service cannot be registered,
+    /// or [`Error::NotRegistered`] if a service it includes is not registered yet.
+    #[allow(clippy::cast_possible_truncation)]
+    fn try_from(service: &Service) -> Result<Self, Self::Error> {
+        service.validate()?;
+
+        let declaration = |uuid: u16, value: Vec<u8>| TableEntry {
+            uuid: BleUuid::from_uuid16(uuid),
+            permissions: AttributePermissions::new().read().into(),
+            max_length: value.len() as u16,
+            value,
+            automatic_response: true,
+        };
+
+        let mut entries = vec![declaration(
+            if service.primary { 0x2800 } else { 0x2801 },
+            service.uuid.to_le_bytes(),
+        )];
+
+        for include in &service.includes {
+            let included = include.service.read()?;
+            let start = included.handle.ok_or(Error::NotRegistered)?;
+            let end = start + (included.handle_count()? - 1) as u16;
+
+            let mut value = [start.to_le_bytes(), end.to_le_bytes()].concat();
+            if let BleUuid::Uuid16(uuid) = included.uuid {
+                value.extend(uuid.to_le_bytes());
+            }
+            entries.push(declaration(0x2802, value));
+        }
+
+        for characteristic in &service.characteristics {
+            let characteristic = characteristic.read()?;
+
+            entries.push(declaration(
+                0x2803,
+                vec![esp_gatt_char_prop_t::from(characteristic.properties)],
+            ));
+            entries.push(TableEntry {
+                uuid: characteristic.uuid,
+                permissions: characteristic.permissions.into(),
+                max_length: characteristic
+                    .max_value_length
+                    .unwrap_or(characteristic.internal_value.len() as u16),
+                value: characteristic.internal_value.clone(),
+                automatic_response: matches!(
+                    characteristic.control,
+                    AttributeControl::AutomaticResponse
+                ),
+            });
+
+            for descriptor in &characteristic.descriptors {
+                entries.push(descriptor_entry(&*descriptor.read()?));
+            }
+
+            if characteristic.needs_cccd() {
+                entries.push(descriptor_entry(&Descriptor::cccd()));
+            }
+        }
+
+        Ok(Self { entries })
+    }
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn descriptor_entry(descriptor: &Descriptor) -> TableEntry {
+    TableEntry {
+        uuid: descriptor.uuid,
+        permissions: descriptor.permissions.into(),
+        max_length: descriptor.value.len() as u16,
+        value: descriptor.value.clone(),
+        automatic_response: matches!(descriptor.control, AttributeControl::AutomaticResponse),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        gatt_server::Characteristic,
+        sys::{ESP_GATT_PERM_READ, ESP_GATT_PERM_READ_ENCRYPTED, ESP_GATT_PERM_WRITE},
+        utilities::{CharacteristicProperties, SecurityLevel},
+    };
+
+    fn uuid16(entry: &TableEntry) -> u16 {
+        match entry.uuid {
+            BleUuid::Uuid16(uuid) => uuid,
+            uuid => panic!("unexpected UUID {uuid:?}"),
+        }
+    }
+
+    #[test]
+    fn notify_and_user_descriptions() {
+        let level = Characteristic::new(BleUuid::from_uuid16(0x7001))
+            .permissions(AttributePermissions::new().read_with(SecurityLevel::Encrypted))
+            .properties(CharacteristicProperties::new().read().notify())
+            .max_value_length(20)
+            .set_value(vec![42])
+            .descriptor(&Descriptor::user_description("Level").build())
+            .build();
+        let target = Characteristic::new(BleUuid::from_uuid16(0x7002))
+            .permissions(AttributePermissions::new().read().write())
+            .properties(CharacteristicProperties::new().read().write())
+            .set_value(vec![1, 2])
+            .descriptor(&Descriptor::user_description("Target").build())
+            .build();
+        let service = Service::new(BleUuid::from_uuid16(0x7000))
+            .primary()
+            .characteristic(&level)
+            .characteristic(&target)
+            .clone();
+
+        let table = AttributeTable::try_from(&service).unwrap();
+        let entries = table.entries();
+        let read = ESP_GATT_PERM_READ as esp_gatt_perm_t;
+        let read_write = (ESP_GATT_PERM_READ | ESP_GATT_PERM_WRITE) as esp_gatt_perm_t;
+
+        // The declaration, the value and the user description of each characteristic come in order,
+        // and the CCCD of the notify characteristic follows its descriptors.
+        let uuids: Vec<u16> = entries.iter().map(uuid16).collect();
+        assert_eq!(
+            uuids,
+            [0x2800, 0x2803, 0x7001, 0x2901, 0x2902, 0x2803, 0x7002, 0x2901]
+        );
+        assert_eq!(entries.len(), service.handle_count().unwrap());
+
+        assert_eq!(
+            entries[0],
+            TableEntry {
+                uuid: BleUuid::from_uuid16(0x2800),
+                permissions: read,
+                max_length: 2,
+                value: vec![0x00, 0x70],
+                automatic_response: true,
+            }
+        );
+        assert_eq!(
+            (entries[1].value.as_slice(), entries[1].permissions),
+            ([0x12].as_slice(), read)
+        );
+        assert_eq!(
+            entries[2],
+            TableEntry {
+                uuid: BleUuid::from_uuid16(0x7001),
+                permissions: ESP_GATT_PERM_READ_ENCRYPTED as esp_gatt_perm_t,
+                max_length: 20,
+                value: vec![42],
+                automatic_response: true,
+            }
+        );
+        assert_eq!(
+            entries[3],
+            TableEntry {
+                uuid: BleUuid::from_uuid16(0x2901),
+                permissions: read,
+                max_length: 5,
+                value: b"Level".to_vec(),
+                automatic_response: true,
+            }
+        );
+
+        // The CCCD is answered by the server, by connection, so the stack holds no value.
+        assert_eq!(
+            entries[4],
+            TableEntry {
+                uuid: BleUuid::from_uuid16(0x2902),
+                permissions: read_write,
+                max_length: 0,
+                value: vec![],
+                automatic_response: false,
+            }
+        );
+
+        assert_eq!(
+            (entries[5].value.as_slice(), entries[5].permissions),
+            ([0x0A].as_slice(), read)
+        );
+        assert_eq!(
+            (
+                entries[6].permissions,
+                entries[6].max_length,
+                entries[6].value.as_slice()
+            ),
+            (read_write, 2, [1, 2].as_slice())
+        );
+        assert_eq!(entries[7].value, b"Target");
+    }
+
+    #[test]
+    fn response_control() {
+        let characteristic = Characteristic::new(BleUuid::from_uuid16(0x7001))
+            .permissions(AttributePermissions::new().read().write())
+            .properties(CharacteristicProperties::new().read().write())
+            .on_write(|_, _| Ok(()))
+            .descriptor(
+                &Descriptor::new(BleUuid::from_uuid16(0x2904))
+                    .set_value([4])
+                    .build(),
+            )
+            .descriptor(
+                &Descriptor::new(BleUuid::from_uuid16(0x7101))
+                    .permissions(AttributePermissions::new().read())
+                    .on_read(|_| Ok(vec![1]))
+                    .build(),
+            )
+            .build();
+        let service = Service::new(BleUuid::from_uuid16(0x7000))
+            .characteristic(&characteristic)
+            .clone();
+
+        // A value with a callback is answered by the application, one without it by the stack.
+        let table = AttributeTable::try_from(&service).unwrap();
+        let controls: Vec<(u16, bool)> = table
+            .entries()
+            .iter()
+            .map(|entry| (uuid16(entry), entry.automatic_response))
+            .collect();
+        assert_eq!(
+            controls,
+            [
+                (0x2801, true),
+                (0x2803, true),
+                (0x7001, false),
+                (0x2904, true),
+                (0x7101, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn max_value_length() {
+        let implicit = Characteristic::new(BleUuid::from_uuid16(0x7001))
+            .set_value(vec![1, 2, 3])
+            .build();
+        let explicit = Characteristic::new(BleUuid::from_uuid16(0x7002))
+            .max_value_length(100)
+            .set_value(vec![1])
+            .build();
+        let service = Service::new(BleUuid::from_uuid16(0x7000))
+            .primary()
+            .characteristic(&implicit)
+            .characteristic(&explicit)
+            .clone();
+
+        // Without an explicit maximum, the length of the value is the maximum.
+        let table = AttributeTable::try_from(&service).unwrap();
+        let lengths: Vec<(u16, u16, usize)> = table
+            .entries()
+            .iter()
+            .map(|entry| (uuid16(entry), entry.max_length, entry.value.len()))
+            .collect();
+        assert_eq!(
+            lengths,
+            [
+                (0x2800, 2, 2),
+                (0x2803, 1, 1),
+                (0x7001, 3, 3),
+                (0x2803, 1, 1),
+                (0x7002, 100, 1),
+            ]
+        );
+
+        // Preparing the characteristic for its registration keeps the same maximum.
+        implicit.write().unwrap().prepare().unwrap();
+        assert_eq!(AttributeTable::try_from(&service).unwrap(), table);
+    }
+
+    #[test]
+    fn uuid128() {
+        let service_uuid = BleUuid::from_uuid128_string("F9DFBD73-0181-433A-8091-372E0CA8A598");
+        let characteristic_uuid =
+            BleUuid::from_uuid128_string("F9DFBD73-0181-433A-8091-372E0CA8A599");
+        let characteristic = Characteristic::new(characteristic_uuid)
+            .permissions(AttributePermissions::new().read())
+            .properties(CharacteristicProperties::new().read())
+            .set_value(vec![7])
+            .build();
+        let service = Service::new(service_uuid)
+            .primary()
+            .characteristic(&characteristic)
+            .clone();
+
+        // The service declaration holds the 16 little-endian bytes of the UUID.
+        let table = AttributeTable::try_from(&service).unwrap();
+        let entries = table.entries();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].uuid, BleUuid::from_uuid16(0x2800));
+        assert_eq!(entries[0].value, service_uuid.to_le_bytes());
+        assert_eq!(entries[0].value.len(), 16);
+        assert_eq!(entries[0].max_length, 16);
+        assert_eq!(entries[2].uuid, characteristic_uuid);
+        assert_eq!(entries[2].value, [7]);
+    }
+
+    #[test]
+    fn handle_count() {
+        let included = Service::new(BleUuid::from_uuid16(0x7100)).build();
+        included.write().unwrap().handle = Some(0x0010);
+        let notified = Characteristic::new(BleUuid::from_uuid16(0x7001))
+            .properties(CharacteristicProperties::new().notify())
+            .descriptor(&Descriptor::user_description("Notified").build())
+            .build();
+        let indicated = Characteristic::new(BleUuid::from_uuid16(0x7002))
+            .properties(CharacteristicProperties::new().indicate())
+            .descriptor(&Descriptor::cccd().build())
+            .build();
+        let service = Service::new(BleUuid::from_uuid16(0x7000))
+            .include(&included)
+            .characteristic(&notified)
+            .characteristic(&indicated)
+            .clone();
+
+        // The registration expects a handle per entry, before and after the implicit CCCD is added.
+        let table = AttributeTable::try_from(&service).unwrap();
+        assert_eq!(table.entries().len(), 9);
+        assert_eq!(table.entries().len(), service.handle_count().unwrap());
+        notified.write().unwrap().prepare().unwrap();
+        assert_eq!(AttributeTable::try_from(&service).unwrap(), table);
+        assert_eq!(table.entries().len(), service.handle_count().unwrap());
+
+        // The include declaration holds the handles and the UUID of the included service.
+        assert_eq!(
+            table.entries()[1].value,
+            [0x10, 0x00, 0x10, 0x00, 0x00, 0x71]
+        );
+    }
+}
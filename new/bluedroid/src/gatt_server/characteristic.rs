@@ -1,13 +1,16 @@
 use crate::{
+    backend::backend,
     gatt_server::descriptor::Descriptor,
-    leaky_box_raw,
-    utilities::{AttributeControl, AttributePermissions, BleUuid, CharacteristicProperties},
+    utilities::{
+        AttError, AttributeControl, AttributePermissions, BleUuid, CharacteristicProperties,
+        SecurityLevel,
+    },
+    Error,
 };
 
-use esp_idf_sys::{
-    esp_attr_control_t, esp_attr_value_t, esp_ble_gatts_add_char,
-    esp_ble_gatts_cb_param_t_gatts_read_evt_param, esp_ble_gatts_cb_param_t_gatts_write_evt_param,
-    esp_ble_gatts_set_attr_value, esp_nofail,
+use crate::sys::{
+    esp_attr_control_t, esp_attr_value_t, esp_ble_gatts_cb_param_t_gatts_read_evt_param,
+    esp_ble_gatts_cb_param_t_gatts_write_evt_param, esp_gatt_if_t, ESP_GATT_MAX_ATTR_LEN,
 };
 use log::{debug, warn};
 use std::{
@@ -15,7 +18,9 @@ use std::{
     sync::{Arc, RwLock},
 };
 
-type WriteCallback = dyn Fn(Vec<u8>, esp_ble_gatts_cb_param_t_gatts_write_evt_param) + Send + Sync;
+pub(crate) type WriteCallback = dyn Fn(Vec<u8>, esp_ble_gatts_cb_param_t_gatts_write_evt_param) -> Result<(), AttError>
+    + Send
+    + Sync;
 
 /// Represents a GATT characteristic.
 #[derive(Clone)]
@@ -31,9 +36,11 @@ pub struct Characteristic {
     /// The handle that the Bluetooth stack assigned to this characteristic.
     pub(crate) attribute_handle: Option<u16>,
     /// The handle of the containing service.
-    service_handle: Option<u16>,
+    pub(crate) service_handle: Option<u16>,
+    /// The interface of the profile that registered this characteristic.
+    pub(crate) interface: Option<esp_gatt_if_t>,
     /// The access permissions for this characteristic.
-    permissions: AttributePermissions,
+    pub(crate) permissions: AttributePermissions,
     /// The properties that are announced for this characteristic.
     pub(crate) properties: CharacteristicProperties,
     /// The way this characteristic is read.
@@ -41,7 +48,7 @@ pub struct Characteristic {
     /// A buffer for keeping in memory the actual value of this characteristic.
     pub(crate) internal_value: Vec<u8>,
     /// The maximum length of the characteristic value.
-    max_value_length: Option<u16>,
+    pub(crate) max_value_length: Option<u16>,
     /// A copy of the `control` property, in the `esp_attr_control_t` type, passed directly to the Bluetooth stack.
     internal_control: esp_attr_control_t,
 }
@@ -58,10 +65,11 @@ impl Characteristic {
             descriptors: Vec::new(),
             attribute_handle: None,
             service_handle: None,
+            interface: None,
             permissions: AttributePermissions::default(),
             properties: CharacteristicProperties::default(),
-            control: AttributeControl::AutomaticResponse(vec![0]),
-            internal_control: AttributeControl::AutomaticResponse(vec![0]).into(),
+            control: AttributeControl::AutomaticResponse,
+            internal_control: AttributeControl::AutomaticResponse.into(),
             max_value_length: None,
         }
     }
@@ -101,27 +109,26 @@ impl Characteristic {
     /// Sets the read callback for this characteristic.
     /// The callback will be called when a client reads the value of this characteristic.
     ///
-    /// The callback must return a `Vec<u8>` containing the value to be put into the response to the read request.
+    /// The callback must return a `Vec<u8>` containing the value to be put into the response to the read request,
+    /// or an [`AttError`] to reject the request.
+    ///
+    /// Values longer than the MTU are read in parts: the callback is called for each of them,
+    /// and only the value past the requested offset is sent.
     ///
     /// # Notes
     ///
     /// The callback will be called from the Bluetooth stack's context, so it must not block.
+    /// The characteristic needs the read property and read access, otherwise validating it fails.
     pub fn on_read<
-        C: Fn(esp_ble_gatts_cb_param_t_gatts_read_evt_param) -> Vec<u8> + Send + Sync + 'static,
+        C: Fn(esp_ble_gatts_cb_param_t_gatts_read_evt_param) -> Result<Vec<u8>, AttError>
+            + Send
+            + Sync
+            + 'static,
     >(
         &mut self,
         callback: C,
     ) -> &mut Self {
-        if !self.properties.read || !self.permissions.read_access {
-            warn!(
-                "Characteristic {} does not have read permissions. Ignoring read callback.",
-                self
-            );
-
-            return self;
-        }
-
-        self.control = AttributeControl::ResponseByApp(Arc::new(callback));
+        self.control = AttributeControl::ResponseByApp(Some(Arc::new(callback)));
         self.internal_control = self.control.clone().into();
 
         self
@@ -131,26 +138,38 @@ impl Characteristic {
     /// The callback will be called when a client writes to this characteristic.
     ///
     /// The callback receives a `Vec<u8>` with the written value.
-    /// It is up to the library user to decode the data into a meaningful format.
+    /// It is up to the library user to decode the data into a meaningful format,
+    /// see [`TypedCharacteristic`] for a characteristic that does it automatically.
+    ///
+    /// The callback returns an [`AttError`] to reject the value.
+    /// Write commands, that have no response, cannot be rejected.
+    ///
+    /// # Notes
+    ///
+    /// Writes to a characteristic with a write callback are answered by the application,
+    /// and the written value is stored in memory if no read callback is set.
+    ///
+    /// Long writes are assembled before calling the callback, which receives the whole value
+    /// once the client executes them. Cancelled long writes never reach the callback.
+    ///
+    /// The characteristic needs a write property and write access, otherwise validating it fails.
+    ///
+    /// [`TypedCharacteristic`]: crate::gatt_server::TypedCharacteristic
     pub fn on_write(
         &mut self,
-        callback: impl Fn(Vec<u8>, esp_ble_gatts_cb_param_t_gatts_write_evt_param)
+        callback: impl Fn(Vec<u8>, esp_ble_gatts_cb_param_t_gatts_write_evt_param) -> Result<(), AttError>
             + Send
             + Sync
             + 'static,
     ) -> &mut Self {
-        if !((self.properties.write || self.properties.write_without_response)
-            && self.permissions.write_access)
-        {
-            warn!(
-                "Characteristic {} does not have write permissions. Ignoring write callback.",
-                self
-            );
+        self.write_callback = Some(Arc::new(callback));
 
-            return self;
+        // The stack cannot reject a write on behalf of the application.
+        if let AttributeControl::AutomaticResponse = self.control {
+            self.control = AttributeControl::ResponseByApp(None);
+            self.internal_control = self.control.clone().into();
         }
 
-        self.write_callback = Some(Arc::new(callback));
         self
     }
 
@@ -171,58 +190,53 @@ impl Characteristic {
     /// Sets the value of this [`Characteristic`].
     ///
     /// Sends notifications and indications to all subscribed clients.
-    ///
-    /// # Panics
-    ///
-    /// Panics if the value is too long and the characteristic is already registered.
+    /// Errors are logged, see [`Self::try_set_value`] for a fallible version.
     ///
     /// # Notes
     ///
     /// Before starting the server, you can freely set the value of a characteristic.
     /// The maximum value length will be derived from the length of the initial value.
     /// If you plan to expose only one data type for all the lifetime of the characteristic,
-    /// then you'll never need to use the [`Self.value_length`] method, because
+    /// then you'll never need to use the [`Self::max_value_length`] method, because
     /// the maximum size will be automatically set to the length of the latest value
     /// set before starting the server.
     pub fn set_value<T: Into<Vec<u8>>>(&mut self, value: T) -> &mut Self {
+        if let Err(error) = self.try_set_value(value).map(|_| ()) {
+            warn!("Cannot set the value of {}: {}.", self, error);
+        }
+
+        self
+    }
+
+    /// Sets the value of this [`Characteristic`].
+    ///
+    /// Sends notifications and indications to all subscribed clients.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the value is longer than the maximum length,
+    /// or if the Bluetooth stack rejects the value.
+    pub fn try_set_value<T: Into<Vec<u8>>>(&mut self, value: T) -> Result<&mut Self, Error> {
         let value: Vec<u8> = value.into();
 
-        #[allow(clippy::manual_assert)]
-        if let Some(max_value_length) = self.max_value_length {
-            if value.len() > max_value_length as usize {
-                panic!(
-                    "Value is too long for characteristic {self}. The explicitly set maximum length is {max_value_length} bytes."
-                );
+        if let Some(max_length) = self.max_length() {
+            if value.len() > max_length {
+                return Err(Error::ValueTooLong {
+                    length: value.len(),
+                    max_length,
+                });
             }
-        } else if self.attribute_handle.is_some() && value.len() > self.internal_value.len() {
-            panic!(
-                "Value is too long for characteristic {}. The implicitly set maximum length is {} bytes.",
-                self,
-                self.internal_value.len()
-            );
         }
 
-        self.internal_value = value;
-        self.control = AttributeControl::AutomaticResponse(self.internal_value.clone());
-        self.internal_control = self.control.clone().into();
-
-        debug!(
-            "Trying to set value of {} to {:02X?}.",
-            self, self.internal_value
-        );
+        debug!("Trying to set value of {} to {:02X?}.", self, value);
 
         if let Some(handle) = self.attribute_handle {
-            #[allow(clippy::cast_possible_truncation)]
-            unsafe {
-                esp_nofail!(esp_ble_gatts_set_attr_value(
-                    handle,
-                    self.internal_value.len() as u16,
-                    self.internal_value.as_slice().as_ptr()
-                ));
-            }
+            backend().set_attr_value(handle, &value)?;
         }
 
-        self
+        self.internal_value = value;
+
+        Ok(self)
     }
 
     /// Returns a reference to the built [`Characteristic`] behind an `Arc` and an `RwLock`.
@@ -234,89 +248,182 @@ impl Characteristic {
         Arc::new(RwLock::new(self.clone()))
     }
 
-    /// Registers the [`Characteristic`] at the given service handle.
-    pub(crate) fn register_self(&mut self, service_handle: u16) {
-        debug!(
-            "Registering {} into service at handle 0x{:04x}.",
-            self, service_handle
-        );
-        self.service_handle = Some(service_handle);
+    /// Validates the [`Characteristic`] and returns a reference to it, like [`Self::build`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the value does not fit in the characteristic,
+    /// if a value is required but not set,
+    /// or if the permissions do not match the properties and the callbacks.
+    pub fn try_build(&self) -> Result<Arc<RwLock<Self>>, Error> {
+        self.validate()?;
+        Ok(self.build())
+    }
+
+    /// Returns the maximum length of the value, if it is fixed.
+    ///
+    /// The length is fixed either explicitly or by registering the characteristic.
+    pub(crate) fn max_length(&self) -> Option<usize> {
+        self.max_value_length.map(usize::from)
+    }
 
-        #[allow(clippy::manual_assert)]
-        if let AttributeControl::AutomaticResponse(_) = self.control {
+    /// Checks that the [`Characteristic`] can be registered.
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        if let AttributeControl::AutomaticResponse = self.control {
             if self.internal_value.is_empty() {
-                panic!("Automatic response requires a value to be set.");
+                return Err(Error::EmptyValue);
             }
         }
 
-        // Register a CCCD if needed.
-        if self.properties.notify || self.properties.indicate {
-            self.descriptor(&Descriptor::cccd().build());
+        let max_length = self
+            .max_value_length
+            .map_or(self.internal_value.len(), usize::from);
+
+        if max_length > ESP_GATT_MAX_ATTR_LEN as usize {
+            return Err(Error::ValueTooLong {
+                length: max_length,
+                max_length: ESP_GATT_MAX_ATTR_LEN as usize,
+            });
         }
 
-        #[allow(clippy::cast_possible_truncation)]
-        unsafe {
-            esp_nofail!(esp_ble_gatts_add_char(
-                service_handle,
-                leaky_box_raw!(self.uuid.into()),
-                self.permissions.into(),
-                self.properties.into(),
-                leaky_box_raw!(esp_attr_value_t {
-                    attr_max_len: self
-                        .max_value_length
-                        .unwrap_or(self.internal_value.len() as u16),
-                    attr_len: self.internal_value.len() as u16,
-                    attr_value: self.internal_value.as_mut_slice().as_mut_ptr(),
-                }),
-                &mut self.internal_control,
+        if self.internal_value.len() > max_length {
+            return Err(Error::ValueTooLong {
+                length: self.internal_value.len(),
+                max_length,
+            });
+        }
+
+        self.validate_permissions()?;
+
+        for descriptor in &self.descriptors {
+            descriptor.read()?.validate()?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the permissions match the properties and the callbacks.
+    fn validate_permissions(&self) -> Result<(), Error> {
+        let (properties, permissions) = (self.properties, self.permissions);
+        permissions.validate()?;
+
+        let readable = properties.read && permissions.read_access;
+        let writable =
+            (properties.write || properties.write_without_response) && permissions.write_access;
+        let signed = permissions.write_access
+            && matches!(
+                permissions.write_level,
+                SecurityLevel::Signed | SecurityLevel::SignedMitm
+            );
+
+        if properties.read && !permissions.read_access {
+            return Err(Error::InconsistentPermissions(
+                "the read property needs read access",
+            ));
+        }
+
+        if (properties.write || properties.write_without_response) && !permissions.write_access {
+            return Err(Error::InconsistentPermissions(
+                "the write properties need write access",
             ));
         }
+
+        if properties.authenticated_signed_writes && !signed {
+            return Err(Error::InconsistentPermissions(
+                "the authenticated signed writes property needs a signed write level",
+            ));
+        }
+
+        if signed && !properties.authenticated_signed_writes {
+            return Err(Error::InconsistentPermissions(
+                "a signed write level needs the authenticated signed writes property",
+            ));
+        }
+
+        if matches!(self.control, AttributeControl::ResponseByApp(Some(_))) && !readable {
+            return Err(Error::InconsistentPermissions(
+                "a read callback needs the read property and read access",
+            ));
+        }
+
+        if self.write_callback.is_some() && !writable {
+            return Err(Error::InconsistentPermissions(
+                "a write callback needs a write property and write access",
+            ));
+        }
+
+        Ok(())
     }
 
-    /// Registers the descriptors of this [`Characteristic`].
-    ///
-    /// This function should be called on the event of the characteristic being registered.
-    ///
-    /// # Panics
-    ///
-    /// Panics if the service handle is not registered.
-    ///
-    /// # Notes
+    /// Returns whether a CCCD is added to the [`Characteristic`] when it is registered.
+    pub(crate) fn needs_cccd(&self) -> bool {
+        (self.properties.notify || self.properties.indicate)
+            && !self
+                .descriptors
+                .iter()
+                .any(|descriptor| descriptor.read().unwrap().uuid == BleUuid::Uuid16(0x2902))
+    }
+
+    /// Returns the number of attribute handles used by the [`Characteristic`].
     ///
-    /// Bluedroid does not offer a way to register descriptors to a specific characteristic.
-    /// This is simply done by registering the characteristic and then registering its descriptors.
-    pub(crate) fn register_descriptors(&mut self) {
-        debug!("Registering {}'s descriptors.", &self);
-        self.descriptors.iter_mut().for_each(|descriptor| {
-            descriptor
-                .write()
-                .unwrap()
-                .register_self(self.service_handle.expect(
-                    "Cannot register a descriptor to a characteristic without a service handle.",
-                ));
-        });
+    /// The declaration and the value use a handle each, and every descriptor uses another one,
+    /// including the CCCD added when the characteristic is registered.
+    pub(crate) fn handle_count(&self) -> usize {
+        2 + self.descriptors.len() + usize::from(self.needs_cccd())
     }
 
-    pub(crate) fn get_cccd_status(
-        &self,
-        param: esp_ble_gatts_cb_param_t_gatts_read_evt_param,
-    ) -> Option<(bool, bool)> {
-        if let Some(cccd) = self
-            .descriptors
-            .iter()
-            .find(|desc| desc.read().unwrap().uuid == BleUuid::Uuid16(0x2902))
-        {
-            if let AttributeControl::ResponseByApp(callback) = &cccd.read().unwrap().control {
-                let value = callback(param);
-
-                return Some((
-                    value[0] & 0b0000_0001 == 0b0000_0001,
-                    value[0] & 0b0000_0010 == 0b0000_0010,
-                ));
-            }
+    /// Validates the [`Characteristic`] before its registration, and adds its CCCD if needed.
+    ///
+    /// Returns the maximum length of the value.
+    pub(crate) fn prepare(&mut self) -> Result<u16, Error> {
+        self.validate()?;
+
+        // Register a CCCD if needed.
+        if self.needs_cccd() {
+            self.descriptor(&Descriptor::cccd().build());
         }
 
-        None
+        // Without an explicit maximum length, the length of the current value is kept from now on.
+        #[allow(clippy::cast_possible_truncation)]
+        Ok(*self
+            .max_value_length
+            .get_or_insert(self.internal_value.len() as u16))
+    }
+
+    /// Registers the [`Characteristic`] at the given service handle.
+    pub(crate) fn register_self(&mut self, service_handle: u16) -> Result<(), Error> {
+        debug!(
+            "Registering {} into service at handle 0x{:04x}.",
+            self, service_handle
+        );
+        let max_length = self.prepare()?;
+        self.service_handle = Some(service_handle);
+
+        #[allow(clippy::cast_possible_truncation)]
+        backend().add_char(
+            service_handle,
+            self.uuid.into(),
+            self.permissions.into(),
+            self.properties.into(),
+            esp_attr_value_t {
+                attr_max_len: max_length,
+                attr_len: self.internal_value.len() as u16,
+                attr_value: self.internal_value.as_mut_slice().as_mut_ptr(),
+            },
+            self.internal_control,
+        )?;
+
+        Ok(())
+    }
+
+    /// Forgets the handles assigned by the stack, so that the [`Characteristic`] can be registered again.
+    pub(crate) fn unregister(&mut self) {
+        self.attribute_handle = None;
+        self.service_handle = None;
+        self.interface = None;
+        self.descriptors.iter().for_each(|descriptor| {
+            descriptor.write().unwrap().unregister();
+        });
     }
 }
 
@@ -343,6 +450,7 @@ impl std::fmt::Debug for Characteristic {
             .field("descriptors", &self.descriptors)
             .field("attribute_handle", &self.attribute_handle)
             .field("service_handle", &self.service_handle)
+            .field("interface", &self.interface)
             .field("permissions", &self.permissions)
             .field("properties", &self.properties)
             .field("control", &self.control)
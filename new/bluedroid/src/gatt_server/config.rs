@@ -0,0 +1,85 @@
+use crate::gatt_server::LongNotificationPolicy;
+use crate::utilities::{Appearance, PreferredConnectionParameters};
+
+/// The configuration of a [`GattServer`](crate::gatt_server::GattServer).
+#[derive(Clone)]
+pub struct GattServerConfig {
+    pub(crate) device_name: String,
+    pub(crate) appearance: Appearance,
+    pub(crate) persist_subscriptions: bool,
+    pub(crate) preferred_connection_parameters: Option<PreferredConnectionParameters>,
+    pub(crate) local_mtu: Option<u16>,
+    pub(crate) long_notifications: LongNotificationPolicy,
+}
+
+impl Default for GattServerConfig {
+    fn default() -> Self {
+        Self {
+            device_name: "ESP32".to_string(),
+            appearance: Appearance::GenericUnknown,
+            persist_subscriptions: true,
+            preferred_connection_parameters: None,
+            local_mtu: None,
+            long_notifications: LongNotificationPolicy::default(),
+        }
+    }
+}
+
+impl GattServerConfig {
+    /// Creates a new [`GattServerConfig`] with the default values.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the name to be advertised in GAP packets.
+    pub fn device_name<S: Into<String>>(&mut self, name: S) -> &mut Self {
+        self.device_name = name.into();
+        self
+    }
+
+    /// Sets the device appearance value to be advertised in GAP packets.
+    pub fn appearance(&mut self, appearance: Appearance) -> &mut Self {
+        self.appearance = appearance;
+        self
+    }
+
+    /// Sets whether the subscriptions of bonded clients are persisted in NVS.
+    ///
+    /// Subscriptions are always kept in memory for the duration of a connection.
+    /// When enabled, which is the default, bonded clients find their subscriptions
+    /// when they reconnect, even after a reboot.
+    /// When disabled, the CCCDs never access NVS.
+    pub fn persist_subscriptions(&mut self, persist: bool) -> &mut Self {
+        self.persist_subscriptions = persist;
+        self
+    }
+
+    /// Sets the connection parameters preferred by the server.
+    ///
+    /// See [`GattServer::preferred_connection_parameters`](crate::gatt_server::GattServer::preferred_connection_parameters).
+    pub fn preferred_connection_parameters(
+        &mut self,
+        parameters: PreferredConnectionParameters,
+    ) -> &mut Self {
+        self.preferred_connection_parameters = Some(parameters);
+        self
+    }
+
+    /// Sets the largest MTU accepted when a client requests a larger one, between 23 and 517 bytes.
+    ///
+    /// By default, the stack accepts the largest MTU it supports.
+    /// The MTU is validated when the server starts.
+    pub fn local_mtu(&mut self, mtu: u16) -> &mut Self {
+        self.local_mtu = Some(mtu);
+        self
+    }
+
+    /// Sets what to do with notified and indicated values that do not fit in the MTU of a connection.
+    ///
+    /// Values are rejected by default.
+    pub fn long_notifications(&mut self, policy: LongNotificationPolicy) -> &mut Self {
+        self.long_notifications = policy;
+        self
+    }
+}
@@ -0,0 +1,159 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Mutex, MutexGuard},
+};
+
+use crate::{
+    backend::backend,
+    gatt_server::profile::PreparedWrite,
+    sys::{esp_bd_addr_t, esp_gatt_if_t},
+    utilities::Connection,
+    Error,
+};
+use log::warn;
+
+/// The state of the open connections, by connection identifier.
+///
+/// It is not kept in the server, because the profile handlers run without locking it.
+/// Only one server is started at a time, and it resets the state when it starts and stops.
+static CONNECTION_STATES: Mutex<BTreeMap<u16, ConnectionState>> = Mutex::new(BTreeMap::new());
+
+/// Returns the state of the open connections, by connection identifier.
+///
+/// # Notes
+///
+/// Lock the server and the subscriptions first, if needed.
+pub(crate) fn connection_states() -> MutexGuard<'static, BTreeMap<u16, ConnectionState>> {
+    CONNECTION_STATES
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+/// The data length requests of the connected clients, by peer address.
+static DATA_LENGTH_REQUESTS: Mutex<DataLengthRequests> = Mutex::new(DataLengthRequests::new());
+
+/// Returns the data length requests of the connected clients.
+pub(crate) fn data_length_requests() -> MutexGuard<'static, DataLengthRequests> {
+    DATA_LENGTH_REQUESTS
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+/// The data length requests of the connected clients, by peer address.
+///
+/// The stack does not tell which peer a completed request belongs to, so a single request
+/// is sent at a time. The others wait for it to complete, one per peer: a newer request
+/// of a peer replaces the one that waits.
+#[derive(Debug)]
+pub(crate) struct DataLengthRequests {
+    /// The peer and the connection of the request sent to the stack.
+    sent: Option<(esp_bd_addr_t, u16)>,
+    /// The connection and the length of the requests to send, by peer address.
+    waiting: BTreeMap<esp_bd_addr_t, (u16, u16)>,
+}
+
+impl DataLengthRequests {
+    const fn new() -> Self {
+        Self {
+            sent: None,
+            waiting: BTreeMap::new(),
+        }
+    }
+
+    /// Sends a data length request, or queues it if another one is pending.
+    pub(crate) fn request(
+        &mut self,
+        address: esp_bd_addr_t,
+        conn_id: u16,
+        tx_octets: u16,
+    ) -> Result<(), Error> {
+        if self.sent.is_some() {
+            self.waiting.insert(address, (conn_id, tx_octets));
+            return Ok(());
+        }
+
+        backend().set_pkt_data_len(address, tx_octets)?;
+        self.sent = Some((address, conn_id));
+
+        Ok(())
+    }
+
+    /// Completes the sent request, sends the next one, and returns the peer and the connection
+    /// of the completed request.
+    pub(crate) fn complete(&mut self) -> Option<(esp_bd_addr_t, u16)> {
+        let completed = self.sent.take();
+        self.send_next();
+
+        completed
+    }
+
+    /// Drops the requests of a closed connection, which the stack never completes.
+    pub(crate) fn forget(&mut self, address: esp_bd_addr_t, conn_id: u16) {
+        if self
+            .waiting
+            .get(&address)
+            .map_or(false, |(waiting, _)| *waiting == conn_id)
+        {
+            self.waiting.remove(&address);
+        }
+
+        if self.sent == Some((address, conn_id)) {
+            self.sent = None;
+            self.send_next();
+        }
+    }
+
+    fn send_next(&mut self) {
+        while self.sent.is_none() {
+            let Some((address, (conn_id, tx_octets))) = self.waiting.pop_first() else {
+                return;
+            };
+
+            if let Err(error) = self.request(address, conn_id, tx_octets) {
+                warn!(
+                    "Cannot request the data length of connection {}: {}.",
+                    conn_id, error
+                );
+            }
+        }
+    }
+}
+
+/// Forgets the open connections and the pending data length requests.
+///
+/// Called when a server starts and stops, so that a server never sees the connections of another one.
+pub(crate) fn reset() {
+    connection_states().clear();
+    *data_length_requests() = DataLengthRequests::new();
+}
+
+/// The state of a connection, created when the client connects and dropped when it disconnects.
+#[derive(Debug)]
+pub(crate) struct ConnectionState {
+    /// The connection, with its MTU, parameters and security.
+    pub(crate) connection: Connection,
+    /// The CCCD values written by the client, by CCCD handle.
+    pub(crate) cccds: BTreeMap<u16, u16>,
+    /// The parts of the long writes queued by the client.
+    pub(crate) prepared_writes: Vec<PreparedWrite>,
+}
+
+impl ConnectionState {
+    pub(crate) const fn new(connection: Connection) -> Self {
+        Self {
+            connection,
+            cccds: BTreeMap::new(),
+            prepared_writes: Vec::new(),
+        }
+    }
+
+    /// Removes and returns the writes prepared for the attributes of an interface.
+    pub(crate) fn take_prepared_writes(&mut self, interface: esp_gatt_if_t) -> Vec<PreparedWrite> {
+        let (taken, kept) = std::mem::take(&mut self.prepared_writes)
+            .into_iter()
+            .partition(|write| write.interface == interface);
+        self.prepared_writes = kept;
+
+        taken
+    }
+}
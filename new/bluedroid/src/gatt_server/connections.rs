@@ -0,0 +1,515 @@
+use std::sync::Arc;
+
+use crate::{
+    backend::backend,
+    gatt_server::{
+        connection_state::{connection_states, data_length_requests, ConnectionState},
+        GattServer,
+    },
+    sys::*,
+    utilities::{
+        private_address, AddressType, Connection, DataLength, DisconnectReason,
+        PreferredConnectionParameters, SecurityLevel,
+    },
+    Error,
+};
+use log::{debug, warn};
+
+type ConnectionCallback = dyn Fn(&Connection) + Send + Sync;
+type DisconnectCallback = dyn Fn(&Connection, DisconnectReason) + Send + Sync;
+
+/// The callbacks called when clients connect, disconnect, or change their MTU, their parameters,
+/// their data length or their PHYs.
+#[derive(Default)]
+pub(crate) struct ConnectionCallbacks {
+    connect: Option<Arc<ConnectionCallback>>,
+    disconnect: Option<Arc<DisconnectCallback>>,
+    mtu_change: Option<Arc<ConnectionCallback>>,
+    parameters_change: Option<Arc<ConnectionCallback>>,
+    data_length_change: Option<Arc<ConnectionCallback>>,
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    phy_change: Option<Arc<ConnectionCallback>>,
+}
+
+impl GattServer {
+    /// Sets the callback that is called when a client connects.
+    ///
+    /// # Notes
+    ///
+    /// The connection callbacks are called from the Bluetooth stack's context, once the server is released:
+    /// they must not block, but they can access the server.
+    pub fn on_connect<C: Fn(&Connection) + Send + Sync + 'static>(
+        &mut self,
+        callback: C,
+    ) -> &mut Self {
+        self.connection_callbacks.connect = Some(Arc::new(callback));
+        self
+    }
+
+    /// Sets the callback that is called when a client disconnects, with the reason of the disconnection.
+    ///
+    /// The callback is not called for the connections closed by [`GattServer::stop`].
+    pub fn on_disconnect<C: Fn(&Connection, DisconnectReason) + Send + Sync + 'static>(
+        &mut self,
+        callback: C,
+    ) -> &mut Self {
+        self.connection_callbacks.disconnect = Some(Arc::new(callback));
+        self
+    }
+
+    /// Sets the callback that is called when a client negotiates a new MTU.
+    pub fn on_mtu_change<C: Fn(&Connection) + Send + Sync + 'static>(
+        &mut self,
+        callback: C,
+    ) -> &mut Self {
+        self.connection_callbacks.mtu_change = Some(Arc::new(callback));
+        self
+    }
+
+    /// Sets the callback that is called when the parameters of a connection change,
+    /// with the parameters applied by the client.
+    ///
+    /// Changes requested by the server and by the client are reported.
+    pub fn on_parameters_change<C: Fn(&Connection) + Send + Sync + 'static>(
+        &mut self,
+        callback: C,
+    ) -> &mut Self {
+        self.connection_callbacks.parameters_change = Some(Arc::new(callback));
+        self
+    }
+
+    /// Sets the callback that is called when a data length requested with
+    /// [`Connection::request_data_length`] is negotiated, with the new data length.
+    pub fn on_data_length_change<C: Fn(&Connection) + Send + Sync + 'static>(
+        &mut self,
+        callback: C,
+    ) -> &mut Self {
+        self.connection_callbacks.data_length_change = Some(Arc::new(callback));
+        self
+    }
+
+    /// Sets the callback that is called when the PHYs of a connection change, with the new PHYs.
+    ///
+    /// Changes requested with [`Connection::request_phy`] and by the client are reported.
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    pub fn on_phy_change<C: Fn(&Connection) + Send + Sync + 'static>(
+        &mut self,
+        callback: C,
+    ) -> &mut Self {
+        self.connection_callbacks.phy_change = Some(Arc::new(callback));
+        self
+    }
+
+    /// Sets the connection parameters preferred by the server.
+    ///
+    /// They are published in the Peripheral Preferred Connection Parameters characteristic
+    /// of the GAP service, and replace the connection interval range of the [`Advertisement`].
+    /// When a client connects with other parameters, the server requests the preferred ones.
+    ///
+    /// The parameters are validated when the server starts. If the server is started,
+    /// the advertised data is updated, and the characteristic when the next client connects.
+    ///
+    /// On ESP-IDF, the characteristic is only published with the `gap-preferred-parameters` feature.
+    ///
+    /// [`Advertisement`]: crate::gatt_server::Advertisement
+    pub fn preferred_connection_parameters(
+        &mut self,
+        parameters: PreferredConnectionParameters,
+    ) -> &mut Self {
+        self.preferred_parameters = Some(parameters);
+        self.advertisement
+            .set_connection_interval(parameters.min_interval, parameters.max_interval);
+
+        if self.advertisement_configured {
+            self.reconfigure_advertisement();
+        }
+
+        self
+    }
+
+    /// Returns the connected clients, ordered by connection identifier.
+    #[must_use]
+    pub fn connections(&self) -> Vec<Connection> {
+        connection_states()
+            .values()
+            .map(|state| state.connection)
+            .collect()
+    }
+
+    /// Terminates the connection with a client.
+    ///
+    /// The disconnection callback is called when the stack closes the connection.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server is not started, if the client is not connected,
+    /// or if the Bluetooth stack rejects the request.
+    pub fn disconnect(&self, connection: &Connection) -> Result<(), Error> {
+        let connection = self.connected(connection)?;
+
+        Ok(backend().disconnect_device(connection.remote_bda)?)
+    }
+
+    /// Reads the signal strength of a connection.
+    ///
+    /// The value is available from [`Connection::rssi`] when the stack completes the reading.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server is not started, if the client is not connected,
+    /// or if the Bluetooth stack rejects the request.
+    pub fn read_rssi(&self, connection: &Connection) -> Result<(), Error> {
+        let connection = self.connected(connection)?;
+
+        Ok(backend().read_rssi(connection.remote_bda)?)
+    }
+
+    /// Returns the current state of a connection, if it is still open.
+    ///
+    /// Connection identifiers are reused after a disconnection, so the address must match too.
+    fn connected(&self, connection: &Connection) -> Result<Connection, Error> {
+        if !self.started {
+            return Err(Error::NotStarted);
+        }
+
+        connection_states()
+            .get(&connection.id)
+            .map(|state| state.connection)
+            .filter(|active| active.remote_bda == connection.remote_bda)
+            .ok_or(Error::NotConnected)
+    }
+
+    /// Updates the matching connections, and returns their new state.
+    fn update_connections(
+        matches: impl Fn(&Connection) -> bool,
+        update: impl Fn(&mut Connection),
+    ) -> Vec<Connection> {
+        let mut states = connection_states();
+        let mut updated = Vec::new();
+
+        for state in states.values_mut() {
+            if matches(&state.connection) {
+                update(&mut state.connection);
+                updated.push(state.connection);
+            }
+        }
+
+        updated
+    }
+
+    /// Publishes the preferred connection parameters in the GAP service, if they changed.
+    ///
+    /// It is only called from the events of the stack: when the profile is registered, before advertising,
+    /// and when a client connects, before it can discover the GAP service.
+    pub(crate) fn configure_preferred_parameters(&mut self) {
+        let Some(parameters) = self.preferred_parameters else {
+            return;
+        };
+
+        if self.published_parameters == Some(parameters) {
+            return;
+        }
+
+        self.published_parameters = Some(parameters);
+
+        if let Err(error) = parameters.validate() {
+            warn!("Cannot set the preferred connection parameters: {}.", error);
+            return;
+        }
+
+        if let Err(error) = backend().set_preferred_conn_params(&parameters.update_params([0; 6])) {
+            warn!("Cannot set the preferred connection parameters: {}.", error);
+        }
+    }
+
+    /// Requests the preferred connection parameters, if those of a new connection do not satisfy them.
+    pub(crate) fn request_preferred_parameters(&self, connection: &Connection) {
+        let Some(parameters) = self.preferred_parameters else {
+            return;
+        };
+
+        if parameters.accepts(connection.parameters) {
+            return;
+        }
+
+        debug!(
+            "Requesting the preferred connection parameters from {}.",
+            connection
+        );
+
+        if let Err(error) = connection.request_parameters(parameters) {
+            warn!(
+                "Cannot request the preferred connection parameters: {}.",
+                error
+            );
+        }
+    }
+
+    /// Returns the identity address of a bonded client, from the address of one of its connections.
+    ///
+    /// Resolvable private addresses are resolved with the IRKs distributed by the bonded clients.
+    pub(crate) fn bonded_identity(address: esp_bd_addr_t) -> Option<esp_bd_addr_t> {
+        let bonds = backend()
+            .bonded_devices()
+            .map_err(|error| warn!("Cannot get the bonded devices: {}.", error))
+            .ok()?;
+
+        if bonds.contains(&address) {
+            return Some(address);
+        }
+
+        if !private_address::is_resolvable(address) {
+            return None;
+        }
+
+        Self::identity_keys()
+            .into_iter()
+            .find(|(_, irk)| private_address::resolves(address, irk))
+            .map(|(identity, _)| identity)
+    }
+
+    /// Returns the identity addresses and the IRKs of the bonded clients.
+    fn identity_keys() -> Vec<(esp_bd_addr_t, [u8; 16])> {
+        backend()
+            .identity_keys()
+            .map_err(|error| warn!("Cannot get the identity keys: {}.", error))
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn on_connection_open(&mut self, connection: Connection) {
+        let replaced = connection_states().insert(connection.id, ConnectionState::new(connection));
+
+        if let Some(replaced) = replaced {
+            warn!(
+                "Connection {} replaces {}, which was not closed.",
+                connection, replaced.connection
+            );
+        }
+
+        if let Some(callback) = self.connection_callbacks.connect.clone() {
+            self.defer(move || callback(&connection));
+        }
+    }
+
+    pub(crate) fn on_connection_close(&mut self, conn_id: u16, reason: esp_gatt_conn_reason_t) {
+        let Some(state) = connection_states().remove(&conn_id) else {
+            warn!("Cannot find connection {} to close.", conn_id);
+            return;
+        };
+
+        // Pairings and data length requests interrupted by the disconnection wait for nothing anymore.
+        self.pairing_requests.remove(&state.connection.remote_bda);
+        data_length_requests().forget(state.connection.remote_bda, conn_id);
+
+        // Long writes that were never executed are discarded with the connection.
+        if !state.prepared_writes.is_empty() {
+            debug!(
+                "Discarding {} prepared writes.",
+                state.prepared_writes.len()
+            );
+        }
+
+        if let Some(callback) = self.connection_callbacks.disconnect.clone() {
+            let (connection, reason) = (state.connection, reason.into());
+            self.defer(move || callback(&connection, reason));
+        }
+    }
+
+    pub(crate) fn on_connection_mtu(&mut self, conn_id: u16, mtu: u16) {
+        for connection in Self::update_connections(
+            |connection| connection.id == conn_id,
+            |connection| connection.mtu = mtu,
+        ) {
+            if let Some(callback) = self.connection_callbacks.mtu_change.clone() {
+                self.defer(move || callback(&connection));
+            }
+        }
+    }
+
+    pub(crate) fn on_connection_parameters(
+        &mut self,
+        param: esp_ble_gap_cb_param_t_ble_update_conn_params_evt_param,
+    ) {
+        if param.status != esp_bt_status_t_ESP_BT_STATUS_SUCCESS {
+            warn!(
+                "Connection parameters update with {:02X?} failed.",
+                param.bda
+            );
+            return;
+        }
+
+        debug!(
+            "Connection parameters with {:02X?} updated: interval {}, latency {}, timeout {}.",
+            param.bda, param.conn_int, param.latency, param.timeout
+        );
+
+        for connection in Self::update_connections(
+            |connection| connection.remote_bda == param.bda,
+            |connection| {
+                connection.parameters.interval = param.conn_int;
+                connection.parameters.latency = param.latency;
+                connection.parameters.timeout = param.timeout;
+            },
+        ) {
+            if let Some(callback) = self.connection_callbacks.parameters_change.clone() {
+                self.defer(move || callback(&connection));
+            }
+        }
+    }
+
+    pub(crate) fn on_data_length(
+        &mut self,
+        param: esp_ble_gap_cb_param_t_ble_pkt_data_length_cmpl_evt_param,
+    ) {
+        // A single request is sent at a time, so the event belongs to it.
+        let Some((address, conn_id)) = data_length_requests().complete() else {
+            debug!("Received data length event without a pending request.");
+            return;
+        };
+
+        if param.status != esp_bt_status_t_ESP_BT_STATUS_SUCCESS {
+            warn!("Data length request of connection {} failed.", conn_id);
+            return;
+        }
+
+        debug!(
+            "Data length of connection {} updated: {} bytes sent, {} bytes received.",
+            conn_id, param.params.tx_len, param.params.rx_len
+        );
+
+        for connection in Self::update_connections(
+            |connection| connection.id == conn_id && connection.remote_bda == address,
+            |connection| {
+                connection.data_length = DataLength {
+                    tx_octets: param.params.tx_len,
+                    rx_octets: param.params.rx_len,
+                };
+            },
+        ) {
+            if let Some(callback) = self.connection_callbacks.data_length_change.clone() {
+                self.defer(move || callback(&connection));
+            }
+        }
+    }
+
+    /// Handles the events of PHY requests and updates.
+    ///
+    /// Returns `false` if the event is not related to PHYs.
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    pub(crate) fn on_phy_event(
+        &mut self,
+        event: esp_gap_ble_cb_event_t,
+        param: *mut esp_ble_gap_cb_param_t,
+    ) -> bool {
+        #[allow(non_upper_case_globals)]
+        match event {
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_SET_PREFERED_PHY_COMPLETE_EVT => {
+                let status = unsafe { (*param).set_perf_phy.status };
+                if status != esp_bt_status_t_ESP_BT_STATUS_SUCCESS {
+                    warn!("PHY request failed, error code: {}.", status);
+                }
+            }
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_PHY_UPDATE_COMPLETE_EVT => {
+                self.on_phy_update(unsafe { (*param).phy_update });
+            }
+            _ => return false,
+        }
+
+        true
+    }
+
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    fn on_phy_update(&mut self, param: esp_ble_gap_cb_param_t_ble_phy_update_cmpl_evt_param) {
+        if param.status != esp_bt_status_t_ESP_BT_STATUS_SUCCESS {
+            warn!("PHY update with {:02X?} failed.", param.bda);
+            return;
+        }
+
+        let (Some(tx_phy), Some(rx_phy)) = (
+            crate::utilities::Phy::from_value(param.tx_phy),
+            crate::utilities::Phy::from_value(param.rx_phy),
+        ) else {
+            warn!(
+                "Received unknown PHYs {} and {} for {:02X?}.",
+                param.tx_phy, param.rx_phy, param.bda
+            );
+            return;
+        };
+
+        debug!(
+            "PHYs with {:02X?} updated: {:?} sent, {:?} received.",
+            param.bda, tx_phy, rx_phy
+        );
+
+        for connection in Self::update_connections(
+            |connection| connection.remote_bda == param.bda,
+            |connection| {
+                connection.tx_phy = tx_phy;
+                connection.rx_phy = rx_phy;
+            },
+        ) {
+            if let Some(callback) = self.connection_callbacks.phy_change.clone() {
+                self.defer(move || callback(&connection));
+            }
+        }
+    }
+
+    /// Updates the security of the connections with a client that paired,
+    /// and their identity if the client bonded.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub(crate) fn on_connection_security(
+        param: esp_ble_auth_cmpl_t,
+        identity: Option<esp_bd_addr_t>,
+    ) {
+        let security = if param.auth_mode & ESP_LE_AUTH_REQ_MITM as u8 == 0 {
+            SecurityLevel::Encrypted
+        } else {
+            SecurityLevel::EncryptedMitm
+        };
+
+        // The stack can report the identity address of a client connected with a private address.
+        let irk = identity.and_then(|identity| {
+            Self::identity_keys()
+                .into_iter()
+                .find(|(bond, _)| *bond == identity)
+                .map(|(_, irk)| irk)
+        });
+
+        Self::update_connections(
+            |connection| {
+                connection.remote_bda == param.bd_addr
+                    || connection.identity == param.bd_addr
+                    || irk.map_or(false, |irk| {
+                        private_address::resolves(connection.remote_bda, &irk)
+                    })
+            },
+            |connection| {
+                if connection.remote_bda == param.bd_addr {
+                    connection.address_type = AddressType::from(param.addr_type);
+                }
+
+                if let Some(identity) = identity {
+                    connection.identity = identity;
+                }
+
+                connection.security = security;
+                connection.bonded = identity.is_some();
+            },
+        );
+    }
+
+    pub(crate) fn on_rssi_read(param: esp_ble_gap_cb_param_t_ble_read_rssi_cmpl_evt_param) {
+        if param.status != esp_bt_status_t_ESP_BT_STATUS_SUCCESS {
+            warn!(
+                "Cannot read the signal strength of {:02X?}.",
+                param.remote_addr
+            );
+            return;
+        }
+
+        Self::update_connections(
+            |connection| connection.remote_bda == param.remote_addr,
+            |connection| connection.rssi = Some(param.rssi),
+        );
+    }
+}
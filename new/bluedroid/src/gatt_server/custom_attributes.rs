@@ -1,26 +1,9 @@
 use crate::{
-    gatt_server::Descriptor,
-    utilities::{AttributePermissions, BleUuid},
+    gatt_server::{subscriptions::subscriptions, Descriptor},
+    utilities::{AttError, AttributePermissions, BleUuid},
 };
 
-use std::sync::{Arc, Mutex};
-
-use embedded_svc::storage::RawStorage;
-use esp_idf_svc::nvs::{EspDefaultNvs, EspDefaultNvsPartition};
-use lazy_static::lazy_static;
-use log::debug;
-
-lazy_static! {
-    static ref STORAGE: Arc<Mutex<EspDefaultNvs>> = Arc::new(Mutex::new(
-        EspDefaultNvs::new(
-            EspDefaultNvsPartition::take()
-                .expect("Cannot initialise the default NVS. Did you declare an NVS partition?"),
-            "ble",
-            true
-        )
-        .expect("Cannot create a new NVS storage. Did you declare an NVS partition?")
-    ));
-}
+use log::{debug, warn};
 
 impl Descriptor {
     /// Creates a new descriptor with the `0x2901` UUID, and the description string as its value.
@@ -40,65 +23,47 @@ impl Descriptor {
 
     /// Creates a CCCD.
     ///
-    /// The contents of the CCCD are stored in NVS and persisted across reboots.
-    ///
-    /// # Panics
+    /// The contents of the CCCD are kept for each connection, and read by the server:
+    /// the Bluetooth stack holds no value for it.
+    /// The contents written by bonded clients are also stored in NVS, by characteristic,
+    /// and persisted across reconnections and reboots until the bond is removed,
+    /// unless disabled with [`GattServerConfig::persist_subscriptions`].
     ///
-    /// Panics if the NVS is not configured.
+    /// [`GattServerConfig::persist_subscriptions`]: crate::gatt_server::GattServerConfig::persist_subscriptions
     #[must_use]
     pub fn cccd() -> Self {
         Self::new(BleUuid::from_uuid16(0x2902))
             .name("Client Characteristic Configuration")
             .permissions(AttributePermissions::new().read().write())
+            .set_value(Vec::new())
             .on_read(
-                |param: esp_idf_sys::esp_ble_gatts_cb_param_t_gatts_read_evt_param| {
-                    let storage = STORAGE.lock().unwrap();
-
-                    // Get the descriptor handle.
-
-                    // TODO: Find the characteristic that contains the handle.
-                    // WARNING: Using the handle is incredibly stupid as the NVS is not erased across flashes.
-
-                    // Create a key from the connection address.
-                    let key = format!(
-                        "{:02X}{:02X}{:02X}{:02X}-{:04X}",
-                        /* param.bda[1], */ param.bda[2],
-                        param.bda[3],
-                        param.bda[4],
-                        param.bda[5],
-                        param.handle
+                |param: crate::sys::esp_ble_gatts_cb_param_t_gatts_read_evt_param| {
+                    let value = subscriptions().read(param.conn_id, param.handle);
+                    debug!(
+                        "Read CCCD value 0x{:04x} for connection {}.",
+                        value, param.conn_id
                     );
 
-                    // Prepare buffer and read correct CCCD value from non-volatile storage.
-                    let mut buf: [u8; 2] = [0; 2];
-                    if let Some(value) = storage.get_raw(&key, &mut buf).unwrap() {
-                        debug!("Read CCCD value: {:?} for key {}.", value, key);
-                        value.to_vec()
-                    } else {
-                        debug!("No CCCD value found for key {}.", key);
-                        vec![0, 0]
-                    }
+                    Ok(value.to_le_bytes().to_vec())
                 },
             )
             .on_write(|value, param| {
-                let mut storage = STORAGE.lock().unwrap();
+                let Ok(value) = <[u8; 2]>::try_from(value.as_slice()) else {
+                    return Err(AttError::InvalidAttributeValueLength);
+                };
+                let value = u16::from_le_bytes(value);
 
-                // Create a key from the connection address.
-                let key = format!(
-                    "{:02X}{:02X}{:02X}{:02X}-{:04X}",
-                    /* param.bda[1], */ param.bda[2],
-                    param.bda[3],
-                    param.bda[4],
-                    param.bda[5],
-                    param.handle
+                debug!(
+                    "Write CCCD value 0x{:04x} for connection {}.",
+                    value, param.conn_id
                 );
 
-                debug!("Write CCCD value: {:?} at key {}", value, key);
+                // The subscription is effective even if it cannot be persisted.
+                if let Err(error) = subscriptions().write(param.conn_id, param.handle, value) {
+                    warn!("Cannot persist CCCD value: {}.", error);
+                }
 
-                // Write CCCD value to non-volatile storage.
-                storage
-                    .set_raw(&key, &value)
-                    .expect("Cannot put raw value to the NVS. Did you declare an NVS partition?");
+                Ok(())
             })
             .clone()
     }
@@ -0,0 +1,403 @@
+use std::sync::{Arc, RwLock};
+
+use crate::{
+    backend::backend,
+    gatt_server::{connection_state::connection_states, GattServer, Profile, Service},
+    sys::{esp_bd_addr_t, esp_gatt_char_prop_t, esp_gatt_if_t},
+    utilities::Connection,
+    Error,
+};
+use log::{debug, info, warn};
+
+/// The storage key of the digest of the attributes registered last.
+const DIGEST_KEY: &str = "gatt_digest";
+
+/// The reason why a service that includes another one is invalid, if the other one is not added before it.
+const INCLUDE_ORDER: &str =
+    "an included service must be added to the server before the services that include it";
+
+/// The storage key of the bonded clients that were not told about the last change to the attributes.
+const PENDING_KEY: &str = "gatt_changed";
+
+impl GattServer {
+    /// Adds a [`Service`] to a [`Profile`] of the server.
+    ///
+    /// If the server is started, the service is registered after the pending attributes,
+    /// and the clients are told that the attributes changed once it is.
+    /// The indication covers the whole attribute table, so the clients discover all the services again.
+    /// A secondary service added this way must be included by a service added after it.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::UnknownProfile`] if the profile is not part of the server,
+    /// [`Error::DuplicateService`] if the service already is,
+    /// [`Error::InvalidService`] if a service it includes is not,
+    /// [`Error::TooManyAttributes`] if the Bluetooth stack cannot hold its attributes,
+    /// an error if the service is invalid, or the error of the stack if it rejects the service.
+    pub fn add_service(
+        &mut self,
+        profile: &Arc<RwLock<Profile>>,
+        service: &Arc<RwLock<Service>>,
+    ) -> Result<(), Error> {
+        if !self
+            .profiles
+            .iter()
+            .any(|candidate| Arc::ptr_eq(candidate, profile))
+        {
+            return Err(Error::UnknownProfile(profile.read()?.identifier));
+        }
+
+        if self.profile_of(service)?.is_some() {
+            return Err(Error::DuplicateService);
+        }
+
+        {
+            let service = service.read()?;
+            service.validate()?;
+
+            for include in &service.includes {
+                if self.profile_of(&include.service)?.is_none() {
+                    return Err(Error::InvalidService(INCLUDE_ORDER));
+                }
+            }
+
+            self.check_attribute_count(service.handle_count()?)?;
+        }
+
+        let interface = {
+            let mut profile = profile.write()?;
+            profile.services.push(service.clone());
+            profile.interface
+        };
+
+        // The services of a profile that is not registered yet are created with it.
+        match interface {
+            Some(interface) if self.started => self.registration.add_service(interface, service),
+            _ => Ok(()),
+        }
+    }
+
+    /// Removes a [`Service`] from the server.
+    ///
+    /// If the server is started, the service is stopped and deleted after the pending attributes,
+    /// and the clients are told that the attributes changed once it is.
+    /// The indication covers the whole attribute table, so the clients discover all the services again.
+    /// The service keeps its characteristics and their values, so that it can be added again.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::UnknownService`] if the service is not part of the server,
+    /// [`Error::InvalidService`] if another service of the server includes it,
+    /// [`Error::NotRegistered`] if the server is started but the service is not registered yet,
+    /// or the error of the stack if it rejects the request.
+    pub fn remove_service(&mut self, service: &Arc<RwLock<Service>>) -> Result<(), Error> {
+        let profile = self.profile_of(service)?.ok_or(Error::UnknownService)?;
+
+        for candidate in self.services()? {
+            if candidate
+                .read()?
+                .includes
+                .iter()
+                .any(|include| Arc::ptr_eq(&include.service, service))
+            {
+                return Err(Error::InvalidService(
+                    "an included service cannot be removed before the services that include it",
+                ));
+            }
+        }
+
+        if self.started && service.read()?.handle.is_none() {
+            return Err(Error::NotRegistered);
+        }
+
+        profile
+            .write()?
+            .services
+            .retain(|candidate| !Arc::ptr_eq(candidate, service));
+
+        if self.started {
+            self.registration.remove_service(service)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Checks that the services included by other services are added to the server before them,
+    /// so that they are registered first, and that each secondary service is included by another service:
+    /// clients cannot find it otherwise.
+    pub(crate) fn check_includes(&self) -> Result<(), Error> {
+        let services = self.services()?;
+        let mut included: Vec<Arc<RwLock<Service>>> = Vec::new();
+
+        for (index, service) in services.iter().enumerate() {
+            for include in &service.read()?.includes {
+                if !services[..index]
+                    .iter()
+                    .any(|candidate| Arc::ptr_eq(candidate, &include.service))
+                {
+                    return Err(Error::InvalidService(INCLUDE_ORDER));
+                }
+
+                included.push(include.service.clone());
+            }
+        }
+
+        for service in &services {
+            if !service.read()?.primary
+                && !included
+                    .iter()
+                    .any(|candidate| Arc::ptr_eq(candidate, service))
+            {
+                return Err(Error::InvalidService(
+                    "a secondary service must be included by another service",
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the services of all the profiles, in the order they are registered.
+    fn services(&self) -> Result<Vec<Arc<RwLock<Service>>>, Error> {
+        let mut services = Vec::new();
+
+        for profile in &self.profiles {
+            services.extend(profile.read()?.services.iter().cloned());
+        }
+
+        Ok(services)
+    }
+
+    /// Returns the profile that holds a service, if any.
+    fn profile_of(
+        &self,
+        service: &Arc<RwLock<Service>>,
+    ) -> Result<Option<Arc<RwLock<Profile>>>, Error> {
+        for profile in &self.profiles {
+            if profile
+                .read()?
+                .services
+                .iter()
+                .any(|candidate| Arc::ptr_eq(candidate, service))
+            {
+                return Ok(Some(profile.clone()));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+/// Warns when the Bluetooth stack is not configured to tell the clients about the changes to the attributes.
+///
+/// The server indicates the Service Changed characteristic itself with `CONFIG_BT_GATTS_SEND_SERVICE_CHANGE_MANUAL`,
+/// and the Database Hash and Client Supported Features characteristics need `CONFIG_BT_GATTS_ROBUST_CACHING_ENABLED`.
+pub(crate) fn check_config() {
+    if !cfg!(esp_idf_bt_gatts_send_service_change_manual) {
+        warn!("CONFIG_BT_GATTS_SEND_SERVICE_CHANGE_MANUAL is not set: the clients cannot be told when the attributes change.");
+    }
+
+    if !cfg!(esp_idf_bt_gatts_robust_caching_enabled) {
+        warn!("CONFIG_BT_GATTS_ROBUST_CACHING_ENABLED is not set: the stack provides no Database Hash.");
+    }
+}
+
+/// Tells the clients about the changes to the attributes, once they are all registered.
+///
+/// Bluedroid provides the Generic Attribute service, with its Service Changed characteristic,
+/// but lets the application decide when to indicate it. The attributes are compared with the ones
+/// registered last, before a reboot too: if they changed, the connected clients are told now,
+/// and the bonded ones when they reconnect, so that they discard the attributes they cached.
+pub(crate) fn on_registered(profiles: &[Arc<RwLock<Profile>>]) {
+    let digest = match digest(profiles) {
+        Ok(digest) => digest.to_le_bytes(),
+        Err(error) => {
+            warn!("Cannot compare the attributes: {}.", error);
+            return;
+        }
+    };
+
+    match backend().storage_get(DIGEST_KEY) {
+        Ok(Some(stored)) if stored == digest => {
+            debug!("The attributes did not change.");
+            return;
+        }
+        Ok(_) => {}
+        Err(error) => warn!(
+            "Cannot read the digest of the attributes: {}.",
+            Error::Nvs(error)
+        ),
+    }
+
+    info!("The attributes changed, telling the clients.");
+
+    let connected: Vec<esp_bd_addr_t> = connection_states()
+        .values()
+        .map(|state| state.connection.identity)
+        .collect();
+
+    // Any interface can indicate the Service Changed characteristic.
+    let interface = profiles
+        .iter()
+        .find_map(|profile| profile.read().ok()?.interface)
+        .filter(|_| !connected.is_empty());
+
+    if let Some(interface) = interface {
+        if let Err(error) = backend().send_service_change_indication(interface, None) {
+            warn!(
+                "Cannot indicate the Service Changed characteristic: {}.",
+                Error::from(error)
+            );
+        }
+    }
+
+    let result = backend()
+        .bonded_devices()
+        .map_err(Error::from)
+        .and_then(|bonds| {
+            let pending: Vec<esp_bd_addr_t> = bonds
+                .into_iter()
+                .filter(|identity| !connected.contains(identity))
+                .collect();
+
+            store_pending(&pending)
+        })
+        .and_then(|()| {
+            backend()
+                .storage_set(DIGEST_KEY, &digest)
+                .map_err(Error::Nvs)
+        });
+
+    if let Err(error) = result {
+        warn!("Cannot persist the changes to the attributes: {}.", error);
+    }
+}
+
+/// Tells a bonded client that reconnects about the last change to the attributes, if it missed it.
+pub(crate) fn on_connect(gatts_if: esp_gatt_if_t, connection: &Connection) {
+    if !connection.bonded {
+        return;
+    }
+
+    let mut pending = match load_pending() {
+        Ok(pending) => pending,
+        Err(error) => {
+            warn!("Cannot read the clients to tell about changes: {}.", error);
+            return;
+        }
+    };
+
+    let Some(index) = pending
+        .iter()
+        .position(|identity| *identity == connection.identity)
+    else {
+        return;
+    };
+
+    info!("Telling {} that the attributes changed.", connection);
+    pending.remove(index);
+
+    if let Err(error) =
+        backend().send_service_change_indication(gatts_if, Some(connection.remote_bda))
+    {
+        warn!(
+            "Cannot indicate the Service Changed characteristic: {}.",
+            Error::from(error)
+        );
+    }
+
+    if let Err(error) = store_pending(&pending) {
+        warn!(
+            "Cannot update the clients to tell about changes: {}.",
+            error
+        );
+    }
+}
+
+/// Forgets a client that is no longer bonded.
+pub(crate) fn unbond(identity: esp_bd_addr_t) {
+    let result = load_pending().and_then(|mut pending| {
+        pending.retain(|candidate| *candidate != identity);
+        store_pending(&pending)
+    });
+
+    if let Err(error) = result {
+        warn!(
+            "Cannot update the clients to tell about changes: {}.",
+            error
+        );
+    }
+}
+
+/// Returns a digest of the registered attributes: their types, handles and properties,
+/// and the services they include.
+///
+/// The digest is only compared with the ones computed by this crate,
+/// unlike the Database Hash that Bluedroid computes for the clients.
+fn digest(profiles: &[Arc<RwLock<Profile>>]) -> Result<u64, Error> {
+    let mut bytes = Vec::new();
+
+    for profile in profiles {
+        for service in &profile.read()?.services {
+            let service = service.read()?;
+            let Some(handle) = service.handle else {
+                continue;
+            };
+
+            bytes.extend(handle.to_le_bytes());
+            bytes.push(u8::from(service.primary));
+            bytes.extend(service.uuid.to_le_bytes());
+
+            for include in &service.includes {
+                bytes.extend(include.handle.unwrap_or(0).to_le_bytes());
+                bytes.extend(include.service.read()?.uuid.to_le_bytes());
+            }
+
+            for characteristic in &service.characteristics {
+                let characteristic = characteristic.read()?;
+                bytes.extend(characteristic.attribute_handle.unwrap_or(0).to_le_bytes());
+                bytes.push(esp_gatt_char_prop_t::from(characteristic.properties));
+                bytes.extend(characteristic.uuid.to_le_bytes());
+
+                for descriptor in &characteristic.descriptors {
+                    let descriptor = descriptor.read()?;
+                    bytes.extend(descriptor.attribute_handle.unwrap_or(0).to_le_bytes());
+                    bytes.extend(descriptor.uuid.to_le_bytes());
+                }
+            }
+        }
+    }
+
+    // 64-bit FNV-1a.
+    Ok(bytes.iter().fold(0xCBF2_9CE4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01B3)
+    }))
+}
+
+/// Reads the identity addresses of the bonded clients that were not told about the last change.
+fn load_pending() -> Result<Vec<esp_bd_addr_t>, Error> {
+    let bytes = backend()
+        .storage_get(PENDING_KEY)
+        .map_err(Error::Nvs)?
+        .unwrap_or_default();
+
+    Ok(bytes
+        .chunks_exact(6)
+        .map(|address| {
+            let mut identity = esp_bd_addr_t::default();
+            identity.copy_from_slice(address);
+            identity
+        })
+        .collect())
+}
+
+/// Persists the identity addresses of the bonded clients that were not told about the last change.
+fn store_pending(pending: &[esp_bd_addr_t]) -> Result<(), Error> {
+    if pending.is_empty() {
+        return backend().storage_remove(PENDING_KEY).map_err(Error::Nvs);
+    }
+
+    backend()
+        .storage_set(PENDING_KEY, &pending.concat())
+        .map_err(Error::Nvs)
+}
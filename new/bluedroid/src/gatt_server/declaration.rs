@@ -0,0 +1,945 @@
+//! Support for the [`gatt_service!`](crate::gatt_service) macro.
+//!
+//! The declarations are evaluated at compile time, so that inconsistent attributes
+//! and duplicate UUIDs are reported by the compiler.
+
+#![allow(clippy::struct_excessive_bools)]
+
+use crate::utilities::{AttributePermissions, BleUuid, CharacteristicProperties, SecurityLevel};
+
+/// The base UUID of the 16-bit and 32-bit Bluetooth UUIDs, as a 128-bit integer.
+const BASE_UUID: u128 = 0x0000_0000_0000_1000_8000_0080_5F9B_34FB;
+
+/// Returns the 128-bit form of a [`BleUuid`].
+const fn uuid128(uuid: BleUuid) -> u128 {
+    match uuid {
+        BleUuid::Uuid16(uuid) => BASE_UUID | (uuid as u128) << 96,
+        BleUuid::Uuid32(uuid) => BASE_UUID | (uuid as u128) << 96,
+        BleUuid::Uuid128(uuid) => u128::from_le_bytes(uuid),
+    }
+}
+
+/// Returns whether all the UUIDs are different, comparing them in their 128-bit form.
+#[must_use]
+pub const fn unique_uuids(uuids: &[BleUuid]) -> bool {
+    let mut i = 0;
+    while i < uuids.len() {
+        let mut j = i + 1;
+        while j < uuids.len() {
+            if uuid128(uuids[i]) == uuid128(uuids[j]) {
+                return false;
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+
+    true
+}
+
+/// The properties, security levels and callbacks of a declared characteristic.
+///
+/// The permissions are inferred from the properties.
+#[derive(Clone, Copy, Default)]
+pub struct CharacteristicDeclaration {
+    broadcast: bool,
+    read: bool,
+    write_without_response: bool,
+    write: bool,
+    notify: bool,
+    indicate: bool,
+    authenticated_signed_writes: bool,
+    extended_properties: bool,
+    read_level: SecurityLevel,
+    write_level: SecurityLevel,
+    read_callback: bool,
+    write_callback: bool,
+}
+
+impl CharacteristicDeclaration {
+    /// Creates a declaration without properties.
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            broadcast: false,
+            read: false,
+            write_without_response: false,
+            write: false,
+            notify: false,
+            indicate: false,
+            authenticated_signed_writes: false,
+            extended_properties: false,
+            read_level: SecurityLevel::Open,
+            write_level: SecurityLevel::Open,
+            read_callback: false,
+            write_callback: false,
+        }
+    }
+
+    /// Sets the "broadcast" property.
+    #[must_use]
+    pub const fn broadcast(mut self) -> Self {
+        self.broadcast = true;
+        self
+    }
+
+    /// Sets the "read" property.
+    #[must_use]
+    pub const fn read(mut self) -> Self {
+        self.read = true;
+        self
+    }
+
+    /// Sets the "write without response" property.
+    #[must_use]
+    pub const fn write_without_response(mut self) -> Self {
+        self.write_without_response = true;
+        self
+    }
+
+    /// Sets the "write" property.
+    #[must_use]
+    pub const fn write(mut self) -> Self {
+        self.write = true;
+        self
+    }
+
+    /// Sets the "notify" property.
+    #[must_use]
+    pub const fn notify(mut self) -> Self {
+        self.notify = true;
+        self
+    }
+
+    /// Sets the "indicate" property.
+    #[must_use]
+    pub const fn indicate(mut self) -> Self {
+        self.indicate = true;
+        self
+    }
+
+    /// Sets the "authenticated signed writes" property.
+    #[must_use]
+    pub const fn authenticated_signed_writes(mut self) -> Self {
+        self.authenticated_signed_writes = true;
+        self
+    }
+
+    /// Sets the "extended properties" property.
+    #[must_use]
+    pub const fn extended_properties(mut self) -> Self {
+        self.extended_properties = true;
+        self
+    }
+
+    /// Sets the security level of reads.
+    #[must_use]
+    pub const fn read_security(mut self, level: SecurityLevel) -> Self {
+        self.read_level = level;
+        self
+    }
+
+    /// Sets the security level of writes.
+    #[must_use]
+    pub const fn write_security(mut self, level: SecurityLevel) -> Self {
+        self.write_level = level;
+        self
+    }
+
+    /// Records that the characteristic has a read callback.
+    #[must_use]
+    pub const fn on_read(mut self) -> Self {
+        self.read_callback = true;
+        self
+    }
+
+    /// Records that the characteristic has a write callback.
+    #[must_use]
+    pub const fn on_write(mut self) -> Self {
+        self.write_callback = true;
+        self
+    }
+
+    const fn writable(self) -> bool {
+        self.write || self.write_without_response || self.authenticated_signed_writes
+    }
+
+    /// Checks the declaration, failing the compilation when evaluated in a constant.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the properties, the security levels and the callbacks do not match.
+    pub const fn check(self) {
+        let signed = matches!(
+            self.write_level,
+            SecurityLevel::Signed | SecurityLevel::SignedMitm
+        );
+
+        assert!(
+            !(self.notify && self.indicate),
+            "a characteristic cannot both notify and indicate"
+        );
+        assert!(
+            !matches!(
+                self.read_level,
+                SecurityLevel::Signed | SecurityLevel::SignedMitm
+            ),
+            "reads cannot be signed"
+        );
+        assert!(
+            self.read || matches!(self.read_level, SecurityLevel::Open),
+            "a read security level needs the read property"
+        );
+        assert!(
+            self.writable() || matches!(self.write_level, SecurityLevel::Open),
+            "a write security level needs a write property"
+        );
+        assert!(
+            self.authenticated_signed_writes == signed,
+            "the authenticated signed writes property needs a signed write level, and vice versa"
+        );
+        assert!(
+            self.read || !self.read_callback,
+            "a read callback needs the read property"
+        );
+        assert!(
+            self.write || self.write_without_response || !self.write_callback,
+            "a write callback needs a write property"
+        );
+    }
+
+    /// Returns the properties of the characteristic.
+    #[must_use]
+    pub fn properties(self) -> CharacteristicProperties {
+        let mut properties = CharacteristicProperties::new();
+
+        if self.broadcast {
+            properties = properties.broadcast();
+        }
+        if self.read {
+            properties = properties.read();
+        }
+        if self.write_without_response {
+            properties = properties.write_without_response();
+        }
+        if self.write {
+            properties = properties.write();
+        }
+        if self.authenticated_signed_writes {
+            properties = properties.authenticated_signed_writes();
+        }
+        if self.extended_properties {
+            properties = properties.extended_properties();
+        }
+        properties.notify = self.notify;
+        properties.indicate = self.indicate;
+
+        properties
+    }
+
+    /// Returns the permissions matching the properties.
+    #[must_use]
+    pub fn permissions(self) -> AttributePermissions {
+        let mut permissions = AttributePermissions::new();
+
+        if self.read {
+            permissions = permissions.read_with(self.read_level);
+        }
+        if self.writable() {
+            permissions = permissions.write_with(self.write_level);
+        }
+
+        permissions
+    }
+}
+
+/// The access, security levels and callbacks of a declared descriptor.
+#[derive(Clone, Copy, Default)]
+pub struct DescriptorDeclaration {
+    read: bool,
+    write: bool,
+    read_level: SecurityLevel,
+    write_level: SecurityLevel,
+    read_callback: bool,
+    write_callback: bool,
+}
+
+impl DescriptorDeclaration {
+    /// Creates a declaration without access.
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            read: false,
+            write: false,
+            read_level: SecurityLevel::Open,
+            write_level: SecurityLevel::Open,
+            read_callback: false,
+            write_callback: false,
+        }
+    }
+
+    /// Sets the read access.
+    #[must_use]
+    pub const fn read(mut self) -> Self {
+        self.read = true;
+        self
+    }
+
+    /// Sets the write access.
+    #[must_use]
+    pub const fn write(mut self) -> Self {
+        self.write = true;
+        self
+    }
+
+    /// Sets the security level of reads.
+    #[must_use]
+    pub const fn read_security(mut self, level: SecurityLevel) -> Self {
+        self.read_level = level;
+        self
+    }
+
+    /// Sets the security level of writes.
+    #[must_use]
+    pub const fn write_security(mut self, level: SecurityLevel) -> Self {
+        self.write_level = level;
+        self
+    }
+
+    /// Records that the descriptor has a read callback.
+    #[must_use]
+    pub const fn on_read(mut self) -> Self {
+        self.read_callback = true;
+        self
+    }
+
+    /// Records that the descriptor has a write callback.
+    #[must_use]
+    pub const fn on_write(mut self) -> Self {
+        self.write_callback = true;
+        self
+    }
+
+    /// Checks the declaration, failing the compilation when evaluated in a constant.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the access, the security levels and the callbacks do not match.
+    pub const fn check(self) {
+        assert!(
+            !matches!(
+                self.read_level,
+                SecurityLevel::Signed | SecurityLevel::SignedMitm
+            ),
+            "reads cannot be signed"
+        );
+        assert!(
+            self.read || matches!(self.read_level, SecurityLevel::Open),
+            "a read security level needs read access"
+        );
+        assert!(
+            self.write || matches!(self.write_level, SecurityLevel::Open),
+            "a write security level needs write access"
+        );
+        assert!(
+            self.read || !self.read_callback,
+            "a read callback needs read access"
+        );
+        assert!(
+            self.write || !self.write_callback,
+            "a write callback needs write access"
+        );
+    }
+
+    /// Returns the permissions of the descriptor.
+    #[must_use]
+    pub fn permissions(self) -> AttributePermissions {
+        let mut permissions = AttributePermissions::new();
+
+        if self.read {
+            permissions = permissions.read_with(self.read_level);
+        }
+        if self.write {
+            permissions = permissions.write_with(self.write_level);
+        }
+
+        permissions
+    }
+}
+
+/// Declares GATT services, with their characteristics and descriptors, in one place.
+///
+/// Each `service` binds a built [`Service`] to a variable, and each `characteristic`
+/// binds a [`TypedCharacteristic`] of the given type, that can be used to set its value later.
+/// The permissions of characteristics are inferred from their properties,
+/// with an optional security level for reads and writes.
+///
+/// The compilation fails if a characteristic notifies and indicates, if a callback or a security level
+/// needs a missing property, or if two services, two characteristics of a service or two descriptors
+/// of a characteristic have the same UUID. UUIDs must therefore be constant expressions.
+///
+/// ```rust,ignore
+/// gatt_service! {
+///     service lamp_service(uuid128!("4E0F5E1E-FC5B-4D67-8E30-2A83B336476B")) {
+///         name: "Color Lamp Service",
+///         primary: true,
+///
+///         characteristic brightness_characteristic(uuid128!("F9DFBD73-0181-433A-8091-372E0CA8A598")): u8 {
+///             name: "Brightness",
+///             show_name: true,
+///             properties: [read, write, notify],
+///             write_security: Encrypted,
+///             value: 50,
+///             on_write: |brightness, _| {
+///                 info!("Received brightness: {:?}", brightness);
+///                 Ok(())
+///             },
+///
+///             descriptor(BleUuid::Uuid16(0x2904)) {
+///                 access: [read],
+///                 value: vec![0x04, 0x00, 0xAD, 0x27, 0x01, 0x00, 0x00],
+///             }
+///         }
+///     }
+/// }
+///
+/// brightness_characteristic.set_value(75);
+/// ```
+///
+/// The fields of services are `name`, `primary`, `include`, `reserve_handles` and `attribute_table`.
+/// `include` takes a service declared before, and can be repeated.
+/// The fields of characteristics are `name`, `show_name`, `max_value_length`, `properties`,
+/// `read_security`, `write_security`, `value`, `on_read` and `on_write`, with the callbacks
+/// of [`TypedCharacteristic`].
+/// The fields of descriptors are `name`, `access`, `read_security`, `write_security`, `value`,
+/// `on_read` and `on_write`, with the callbacks of [`Descriptor`].
+///
+/// # Compile errors
+///
+/// A characteristic cannot both notify and indicate:
+///
+/// ```compile_fail,E0080
+/// # use bluedroid::{gatt_service, utilities::BleUuid};
+/// gatt_service! {
+///     service battery(BleUuid::Uuid16(0x180F)) {
+///         characteristic level(BleUuid::Uuid16(0x2A19)): u8 {
+///             properties: [read, notify, indicate],
+///         }
+///     }
+/// }
+/// ```
+///
+/// A security level needs the matching property, and reads cannot be signed:
+///
+/// ```compile_fail,E0080
+/// # use bluedroid::{gatt_service, utilities::BleUuid};
+/// gatt_service! {
+///     service battery(BleUuid::Uuid16(0x180F)) {
+///         characteristic level(BleUuid::Uuid16(0x2A19)): u8 {
+///             properties: [write],
+///             read_security: Encrypted,
+///         }
+///     }
+/// }
+/// ```
+///
+/// ```compile_fail,E0080
+/// # use bluedroid::{gatt_service, utilities::BleUuid};
+/// gatt_service! {
+///     service battery(BleUuid::Uuid16(0x180F)) {
+///         characteristic level(BleUuid::Uuid16(0x2A19)): u8 {
+///             properties: [read],
+///             write_security: Encrypted,
+///         }
+///     }
+/// }
+/// ```
+///
+/// ```compile_fail,E0080
+/// # use bluedroid::{gatt_service, utilities::BleUuid};
+/// gatt_service! {
+///     service battery(BleUuid::Uuid16(0x180F)) {
+///         characteristic level(BleUuid::Uuid16(0x2A19)): u8 {
+///             properties: [read],
+///             read_security: Signed,
+///         }
+///     }
+/// }
+/// ```
+///
+/// Signed writes need both the authenticated signed writes property and a signed write level:
+///
+/// ```compile_fail,E0080
+/// # use bluedroid::{gatt_service, utilities::BleUuid};
+/// gatt_service! {
+///     service battery(BleUuid::Uuid16(0x180F)) {
+///         characteristic level(BleUuid::Uuid16(0x2A19)): u8 {
+///             properties: [authenticated_signed_writes],
+///         }
+///     }
+/// }
+/// ```
+///
+/// ```compile_fail,E0080
+/// # use bluedroid::{gatt_service, utilities::BleUuid};
+/// gatt_service! {
+///     service battery(BleUuid::Uuid16(0x180F)) {
+///         characteristic level(BleUuid::Uuid16(0x2A19)): u8 {
+///             properties: [write],
+///             write_security: Signed,
+///         }
+///     }
+/// }
+/// ```
+///
+/// A callback needs the matching property:
+///
+/// ```compile_fail,E0080
+/// # use bluedroid::{gatt_service, utilities::BleUuid};
+/// gatt_service! {
+///     service battery(BleUuid::Uuid16(0x180F)) {
+///         characteristic level(BleUuid::Uuid16(0x2A19)): u8 {
+///             properties: [write],
+///             on_read: |_| Ok(100),
+///         }
+///     }
+/// }
+/// ```
+///
+/// ```compile_fail,E0080
+/// # use bluedroid::{gatt_service, utilities::BleUuid};
+/// gatt_service! {
+///     service battery(BleUuid::Uuid16(0x180F)) {
+///         characteristic level(BleUuid::Uuid16(0x2A19)): u8 {
+///             properties: [read],
+///             on_write: |_, _| Ok(()),
+///         }
+///     }
+/// }
+/// ```
+///
+/// The same goes for the access of descriptors:
+///
+/// ```compile_fail,E0080
+/// # use bluedroid::{gatt_service, utilities::BleUuid};
+/// gatt_service! {
+///     service battery(BleUuid::Uuid16(0x180F)) {
+///         characteristic level(BleUuid::Uuid16(0x2A19)): u8 {
+///             properties: [read],
+///             descriptor(BleUuid::Uuid16(0x2904)) {
+///                 read_security: Encrypted,
+///             }
+///         }
+///     }
+/// }
+/// ```
+///
+/// ```compile_fail,E0080
+/// # use bluedroid::{gatt_service, utilities::BleUuid};
+/// gatt_service! {
+///     service battery(BleUuid::Uuid16(0x180F)) {
+///         characteristic level(BleUuid::Uuid16(0x2A19)): u8 {
+///             properties: [read],
+///             descriptor(BleUuid::Uuid16(0x2904)) {
+///                 access: [read],
+///                 on_write: |_, _| Ok(()),
+///             }
+///         }
+///     }
+/// }
+/// ```
+///
+/// The UUIDs of services, of the characteristics of a service and of the descriptors of a characteristic
+/// must be different, even if one of them is written as a 128-bit UUID:
+///
+/// ```compile_fail,E0080
+/// # use bluedroid::{gatt_service, utilities::BleUuid};
+/// gatt_service! {
+///     service battery(BleUuid::Uuid16(0x180F)) {}
+///     service other_battery(BleUuid::Uuid16(0x180F)) {}
+/// }
+/// ```
+///
+/// ```compile_fail,E0080
+/// # use bluedroid::{gatt_service, utilities::BleUuid, uuid128};
+/// gatt_service! {
+///     service battery(BleUuid::Uuid16(0x180F)) {
+///         characteristic level(BleUuid::Uuid16(0x2A19)): u8 {
+///             properties: [read],
+///         }
+///
+///         characteristic other_level(uuid128!("00002A19-0000-1000-8000-00805F9B34FB")): u8 {
+///             properties: [read],
+///         }
+///     }
+/// }
+/// ```
+///
+/// ```compile_fail,E0080
+/// # use bluedroid::{gatt_service, utilities::BleUuid};
+/// gatt_service! {
+///     service battery(BleUuid::Uuid16(0x180F)) {
+///         characteristic level(BleUuid::Uuid16(0x2A19)): u8 {
+///             properties: [read],
+///             descriptor(BleUuid::Uuid16(0x2904)) {
+///                 access: [read],
+///             }
+///
+///             descriptor(BleUuid::Uuid16(0x2904)) {
+///                 access: [read],
+///             }
+///         }
+///     }
+/// }
+/// ```
+///
+/// [`Service`]: crate::gatt_server::Service
+/// [`TypedCharacteristic`]: crate::gatt_server::TypedCharacteristic
+/// [`Descriptor`]: crate::gatt_server::Descriptor
+#[macro_export]
+macro_rules! gatt_service {
+    // Services.
+    ($(service $service:ident ($uuid:expr) { $($body:tt)* })+) => {
+        const _: () = assert!(
+            $crate::gatt_server::declaration::unique_uuids(&[$($uuid),+]),
+            "the services must have different UUIDs"
+        );
+
+        $(
+            let mut service = $crate::gatt_server::Service::new($uuid);
+            $crate::gatt_service!(@service service [] $($body)*);
+            let $service = service.build();
+        )+
+    };
+
+    (@service $service:ident [$($uuids:expr),*]) => {
+        const _: () = assert!(
+            $crate::gatt_server::declaration::unique_uuids(&[$($uuids),*]),
+            "the characteristics of a service must have different UUIDs"
+        );
+    };
+    (@service $service:ident [$($uuids:expr),*] name: $name:expr $(, $($rest:tt)*)?) => {
+        $service.name($name);
+        $crate::gatt_service!(@service $service [$($uuids),*] $($($rest)*)?);
+    };
+    (@service $service:ident [$($uuids:expr),*] primary: $primary:expr $(, $($rest:tt)*)?) => {
+        if $primary {
+            $service.primary();
+        }
+        $crate::gatt_service!(@service $service [$($uuids),*] $($($rest)*)?);
+    };
+    (@service $service:ident [$($uuids:expr),*] include: $included:expr $(, $($rest:tt)*)?) => {
+        $service.include(&$included);
+        $crate::gatt_service!(@service $service [$($uuids),*] $($($rest)*)?);
+    };
+    (@service $service:ident [$($uuids:expr),*] attribute_table: $table:expr $(, $($rest:tt)*)?) => {
+        if $table {
+            $service.attribute_table();
+        }
+        $crate::gatt_service!(@service $service [$($uuids),*] $($($rest)*)?);
+    };
+    (@service $service:ident [$($uuids:expr),*] reserve_handles: $count:expr $(, $($rest:tt)*)?) => {
+        $service.reserve_handles($count);
+        $crate::gatt_service!(@service $service [$($uuids),*] $($($rest)*)?);
+    };
+    (@service $service:ident [$($uuids:expr),*]
+        characteristic $characteristic:ident ($uuid:expr): $value_type:ty { $($body:tt)* }
+        $($rest:tt)*
+    ) => {
+        let $characteristic = $crate::gatt_service!(
+            @characteristic [characteristic ($uuid): $value_type] [] [] [] [] [] $($body)*
+        );
+        $service.characteristic(&$characteristic);
+        $crate::gatt_service!(@service $service [$($uuids,)* $uuid] $($rest)*);
+    };
+    (@service $service:ident [$($uuids:expr),*] $field:ident : $($rest:tt)*) => {
+        compile_error!(concat!("unknown service field `", stringify!($field), "`"));
+    };
+
+    // Characteristics: the declaration, the statements on the builder, the typed callbacks,
+    // the statements on the typed characteristic and the UUIDs of the descriptors.
+    (@characteristic [$characteristic:ident ($uuid:expr): $value_type:ty]
+        [$($declaration:tt)*] [$($builder:tt)*] [$($callbacks:tt)*] [$($typed:tt)*] [$($uuids:expr),*]
+    ) => {{
+        const DECLARATION: $crate::gatt_server::declaration::CharacteristicDeclaration =
+            $crate::gatt_server::declaration::CharacteristicDeclaration::new() $($declaration)*;
+        const _: () = DECLARATION.check();
+        const _: () = assert!(
+            $crate::gatt_server::declaration::unique_uuids(&[$($uuids),*]),
+            "the descriptors of a characteristic must have different UUIDs"
+        );
+
+        let mut $characteristic = $crate::gatt_server::Characteristic::new($uuid);
+        $characteristic
+            .properties(DECLARATION.properties())
+            .permissions(DECLARATION.permissions());
+        $($builder)*
+
+        let $characteristic = $characteristic.typed::<$value_type>() $($callbacks)*;
+        $($typed)*
+        $characteristic
+    }};
+    (@characteristic [$characteristic:ident $($head:tt)*]
+        [$($declaration:tt)*] [$($builder:tt)*] [$($callbacks:tt)*] [$($typed:tt)*] [$($uuids:expr),*]
+        name: $name:expr $(, $($rest:tt)*)?
+    ) => {
+        $crate::gatt_service!(
+            @characteristic [$characteristic $($head)*]
+            [$($declaration)*] [$($builder)* $characteristic.name($name);] [$($callbacks)*] [$($typed)*] [$($uuids),*]
+            $($($rest)*)?
+        )
+    };
+    (@characteristic [$characteristic:ident $($head:tt)*]
+        [$($declaration:tt)*] [$($builder:tt)*] [$($callbacks:tt)*] [$($typed:tt)*] [$($uuids:expr),*]
+        show_name: $show_name:expr $(, $($rest:tt)*)?
+    ) => {
+        $crate::gatt_service!(
+            @characteristic [$characteristic $($head)*]
+            [$($declaration)*] [$($builder)* if $show_name { $characteristic.show_name(); }] [$($callbacks)*] [$($typed)*] [$($uuids),*]
+            $($($rest)*)?
+        )
+    };
+    (@characteristic [$characteristic:ident $($head:tt)*]
+        [$($declaration:tt)*] [$($builder:tt)*] [$($callbacks:tt)*] [$($typed:tt)*] [$($uuids:expr),*]
+        max_value_length: $length:expr $(, $($rest:tt)*)?
+    ) => {
+        $crate::gatt_service!(
+            @characteristic [$characteristic $($head)*]
+            [$($declaration)*] [$($builder)* $characteristic.max_value_length($length);] [$($callbacks)*] [$($typed)*] [$($uuids),*]
+            $($($rest)*)?
+        )
+    };
+    (@characteristic [$characteristic:ident $($head:tt)*]
+        [$($declaration:tt)*] [$($builder:tt)*] [$($callbacks:tt)*] [$($typed:tt)*] [$($uuids:expr),*]
+        properties: [$($property:ident),* $(,)?] $(, $($rest:tt)*)?
+    ) => {
+        $crate::gatt_service!(
+            @characteristic [$characteristic $($head)*]
+            [$($declaration)* $(.$property())*] [$($builder)*] [$($callbacks)*] [$($typed)*] [$($uuids),*]
+            $($($rest)*)?
+        )
+    };
+    (@characteristic [$characteristic:ident $($head:tt)*]
+        [$($declaration:tt)*] [$($builder:tt)*] [$($callbacks:tt)*] [$($typed:tt)*] [$($uuids:expr),*]
+        read_security: $level:ident $(, $($rest:tt)*)?
+    ) => {
+        $crate::gatt_service!(
+            @characteristic [$characteristic $($head)*]
+            [$($declaration)* .read_security($crate::utilities::SecurityLevel::$level)] [$($builder)*] [$($callbacks)*] [$($typed)*] [$($uuids),*]
+            $($($rest)*)?
+        )
+    };
+    (@characteristic [$characteristic:ident $($head:tt)*]
+        [$($declaration:tt)*] [$($builder:tt)*] [$($callbacks:tt)*] [$($typed:tt)*] [$($uuids:expr),*]
+        write_security: $level:ident $(, $($rest:tt)*)?
+    ) => {
+        $crate::gatt_service!(
+            @characteristic [$characteristic $($head)*]
+            [$($declaration)* .write_security($crate::utilities::SecurityLevel::$level)] [$($builder)*] [$($callbacks)*] [$($typed)*] [$($uuids),*]
+            $($($rest)*)?
+        )
+    };
+    (@characteristic [$characteristic:ident $($head:tt)*]
+        [$($declaration:tt)*] [$($builder:tt)*] [$($callbacks:tt)*] [$($typed:tt)*] [$($uuids:expr),*]
+        value: $value:expr $(, $($rest:tt)*)?
+    ) => {
+        $crate::gatt_service!(
+            @characteristic [$characteristic $($head)*]
+            [$($declaration)*] [$($builder)*] [$($callbacks)*] [$($typed)* $characteristic.set_value($value);] [$($uuids),*]
+            $($($rest)*)?
+        )
+    };
+    (@characteristic [$characteristic:ident $($head:tt)*]
+        [$($declaration:tt)*] [$($builder:tt)*] [$($callbacks:tt)*] [$($typed:tt)*] [$($uuids:expr),*]
+        on_read: $callback:expr $(, $($rest:tt)*)?
+    ) => {
+        $crate::gatt_service!(
+            @characteristic [$characteristic $($head)*]
+            [$($declaration)* .on_read()] [$($builder)*] [$($callbacks)* .on_read($callback)] [$($typed)*] [$($uuids),*]
+            $($($rest)*)?
+        )
+    };
+    (@characteristic [$characteristic:ident $($head:tt)*]
+        [$($declaration:tt)*] [$($builder:tt)*] [$($callbacks:tt)*] [$($typed:tt)*] [$($uuids:expr),*]
+        on_write: $callback:expr $(, $($rest:tt)*)?
+    ) => {
+        $crate::gatt_service!(
+            @characteristic [$characteristic $($head)*]
+            [$($declaration)* .on_write()] [$($builder)*] [$($callbacks)* .on_write($callback)] [$($typed)*] [$($uuids),*]
+            $($($rest)*)?
+        )
+    };
+    (@characteristic [$characteristic:ident $($head:tt)*]
+        [$($declaration:tt)*] [$($builder:tt)*] [$($callbacks:tt)*] [$($typed:tt)*] [$($uuids:expr),*]
+        descriptor ($uuid:expr) { $($body:tt)* } $($rest:tt)*
+    ) => {
+        $crate::gatt_service!(
+            @characteristic [$characteristic $($head)*]
+            [$($declaration)*]
+            [$($builder)* $characteristic.descriptor(&$crate::gatt_service!(@descriptor [descriptor ($uuid)] [] [] $($body)*));]
+            [$($callbacks)*] [$($typed)*] [$($uuids,)* $uuid]
+            $($rest)*
+        )
+    };
+    (@characteristic [$($head:tt)*] [$($declaration:tt)*] [$($builder:tt)*] [$($callbacks:tt)*] [$($typed:tt)*] [$($uuids:expr),*]
+        $field:ident : $($rest:tt)*
+    ) => {
+        compile_error!(concat!("unknown characteristic field `", stringify!($field), "`"))
+    };
+
+    // Descriptors: the declaration and the statements on the builder.
+    (@descriptor [$descriptor:ident ($uuid:expr)] [$($declaration:tt)*] [$($builder:tt)*]) => {{
+        const DECLARATION: $crate::gatt_server::declaration::DescriptorDeclaration =
+            $crate::gatt_server::declaration::DescriptorDeclaration::new() $($declaration)*;
+        const _: () = DECLARATION.check();
+
+        let mut $descriptor = $crate::gatt_server::Descriptor::new($uuid);
+        $descriptor.permissions(DECLARATION.permissions());
+        $($builder)*
+        $descriptor.build()
+    }};
+    (@descriptor [$descriptor:ident $($head:tt)*] [$($declaration:tt)*] [$($builder:tt)*]
+        name: $name:expr $(, $($rest:tt)*)?
+    ) => {
+        $crate::gatt_service!(
+            @descriptor [$descriptor $($head)*] [$($declaration)*] [$($builder)* $descriptor.name($name);]
+            $($($rest)*)?
+        )
+    };
+    (@descriptor [$descriptor:ident $($head:tt)*] [$($declaration:tt)*] [$($builder:tt)*]
+        access: [$($access:ident),* $(,)?] $(, $($rest:tt)*)?
+    ) => {
+        $crate::gatt_service!(
+            @descriptor [$descriptor $($head)*] [$($declaration)* $(.$access())*] [$($builder)*]
+            $($($rest)*)?
+        )
+    };
+    (@descriptor [$descriptor:ident $($head:tt)*] [$($declaration:tt)*] [$($builder:tt)*]
+        read_security: $level:ident $(, $($rest:tt)*)?
+    ) => {
+        $crate::gatt_service!(
+            @descriptor [$descriptor $($head)*]
+            [$($declaration)* .read_security($crate::utilities::SecurityLevel::$level)] [$($builder)*]
+            $($($rest)*)?
+        )
+    };
+    (@descriptor [$descriptor:ident $($head:tt)*] [$($declaration:tt)*] [$($builder:tt)*]
+        write_security: $level:ident $(, $($rest:tt)*)?
+    ) => {
+        $crate::gatt_service!(
+            @descriptor [$descriptor $($head)*]
+            [$($declaration)* .write_security($crate::utilities::SecurityLevel::$level)] [$($builder)*]
+            $($($rest)*)?
+        )
+    };
+    (@descriptor [$descriptor:ident $($head:tt)*] [$($declaration:tt)*] [$($builder:tt)*]
+        value: $value:expr $(, $($rest:tt)*)?
+    ) => {
+        $crate::gatt_service!(
+            @descriptor [$descriptor $($head)*] [$($declaration)*] [$($builder)* $descriptor.set_value($value);]
+            $($($rest)*)?
+        )
+    };
+    (@descriptor [$descriptor:ident $($head:tt)*] [$($declaration:tt)*] [$($builder:tt)*]
+        on_read: $callback:expr $(, $($rest:tt)*)?
+    ) => {
+        $crate::gatt_service!(
+            @descriptor [$descriptor $($head)*] [$($declaration)* .on_read()] [$($builder)* $descriptor.on_read($callback);]
+            $($($rest)*)?
+        )
+    };
+    (@descriptor [$descriptor:ident $($head:tt)*] [$($declaration:tt)*] [$($builder:tt)*]
+        on_write: $callback:expr $(, $($rest:tt)*)?
+    ) => {
+        $crate::gatt_service!(
+            @descriptor [$descriptor $($head)*] [$($declaration)* .on_write()] [$($builder)* $descriptor.on_write($callback);]
+            $($($rest)*)?
+        )
+    };
+    (@descriptor [$($head:tt)*] [$($declaration:tt)*] [$($builder:tt)*] $field:ident : $($rest:tt)*) => {
+        compile_error!(concat!("unknown descriptor field `", stringify!($field), "`"))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        gatt_server::AttributeTable,
+        sys::{
+            esp_gatt_perm_t, ESP_GATT_PERM_READ, ESP_GATT_PERM_READ_ENCRYPTED, ESP_GATT_PERM_WRITE,
+            ESP_GATT_PERM_WRITE_ENCRYPTED, ESP_GATT_PERM_WRITE_SIGNED,
+        },
+        utilities::{AttError, BleUuid},
+    };
+
+    #[test]
+    fn inferred_attributes() {
+        gatt_service! {
+            service battery(BleUuid::Uuid16(0x180F)) {
+                primary: true,
+
+                characteristic level(BleUuid::Uuid16(0x2A19)): u8 {
+                    properties: [read, notify],
+                    read_security: Encrypted,
+                    value: 100,
+                }
+
+                characteristic target(BleUuid::Uuid16(0x2A1A)): u8 {
+                    properties: [read, write_without_response, authenticated_signed_writes],
+                    write_security: Signed,
+                    on_write: |_, _| Err(AttError::OutOfRange),
+
+                    descriptor(BleUuid::Uuid16(0x2901)) {
+                        access: [read, write],
+                        write_security: Encrypted,
+                        value: "Target",
+                    }
+                }
+            }
+        }
+        let _ = (level, target);
+
+        // The permissions are inferred from the properties and the security levels.
+        let table = AttributeTable::try_from(&*battery.read().unwrap()).unwrap();
+        let attributes: Vec<(BleUuid, Vec<u8>, esp_gatt_perm_t)> = table
+            .entries()
+            .iter()
+            .filter(|entry| entry.uuid != BleUuid::Uuid16(0x2800))
+            .map(|entry| {
+                let value = match entry.uuid {
+                    BleUuid::Uuid16(0x2803) => entry.value.clone(),
+                    _ => Vec::new(),
+                };
+                (entry.uuid, value, entry.permissions)
+            })
+            .collect();
+        let read = ESP_GATT_PERM_READ as esp_gatt_perm_t;
+        assert_eq!(
+            attributes,
+            [
+                (BleUuid::Uuid16(0x2803), vec![0x12], read),
+                (
+                    BleUuid::Uuid16(0x2A19),
+                    vec![],
+                    ESP_GATT_PERM_READ_ENCRYPTED as esp_gatt_perm_t
+                ),
+                (
+                    BleUuid::Uuid16(0x2902),
+                    vec![],
+                    (ESP_GATT_PERM_READ | ESP_GATT_PERM_WRITE) as esp_gatt_perm_t
+                ),
+                (BleUuid::Uuid16(0x2803), vec![0x46], read),
+                (
+                    BleUuid::Uuid16(0x2A1A),
+                    vec![],
+                    (ESP_GATT_PERM_READ | ESP_GATT_PERM_WRITE_SIGNED) as esp_gatt_perm_t
+                ),
+                (
+                    BleUuid::Uuid16(0x2901),
+                    vec![],
+                    (ESP_GATT_PERM_READ | ESP_GATT_PERM_WRITE_ENCRYPTED) as esp_gatt_perm_t
+                ),
+            ]
+        );
+    }
+}
@@ -1,28 +1,31 @@
 use std::sync::{Arc, RwLock};
 
 use crate::{
-    leaky_box_raw,
-    utilities::{AttributeControl, AttributePermissions, BleUuid},
+    backend::backend,
+    utilities::{AttError, AttributeControl, AttributePermissions, BleUuid},
+    Error,
 };
 
-use esp_idf_sys::{
-    esp_attr_control_t, esp_attr_value_t, esp_ble_gatts_add_char_descr,
-    esp_ble_gatts_cb_param_t_gatts_read_evt_param, esp_ble_gatts_cb_param_t_gatts_write_evt_param,
-    esp_ble_gatts_set_attr_value, esp_nofail,
+use crate::sys::{
+    esp_attr_control_t, esp_attr_value_t, esp_ble_gatts_cb_param_t_gatts_read_evt_param,
+    esp_ble_gatts_cb_param_t_gatts_write_evt_param, ESP_GATT_MAX_ATTR_LEN,
 };
 use log::{debug, info, warn};
 
+type WriteCallback =
+    fn(Vec<u8>, esp_ble_gatts_cb_param_t_gatts_write_evt_param) -> Result<(), AttError>;
+
 /// Represents a GATT descriptor.
 #[derive(Debug, Clone)]
 pub struct Descriptor {
     name: Option<String>,
     pub(crate) uuid: BleUuid,
-    value: Vec<u8>,
+    pub(crate) value: Vec<u8>,
     pub(crate) attribute_handle: Option<u16>,
-    permissions: AttributePermissions,
+    pub(crate) permissions: AttributePermissions,
     pub(crate) control: AttributeControl,
     internal_control: esp_attr_control_t,
-    pub(crate) write_callback: Option<fn(Vec<u8>, esp_ble_gatts_cb_param_t_gatts_write_evt_param)>,
+    pub(crate) write_callback: Option<WriteCallback>,
 }
 
 impl Descriptor {
@@ -35,8 +38,8 @@ impl Descriptor {
             value: vec![0],
             attribute_handle: None,
             permissions: AttributePermissions::default(),
-            control: AttributeControl::AutomaticResponse(vec![0]),
-            internal_control: AttributeControl::AutomaticResponse(vec![0]).into(),
+            control: AttributeControl::AutomaticResponse,
+            internal_control: AttributeControl::AutomaticResponse.into(),
             write_callback: None,
         }
     }
@@ -56,68 +59,86 @@ impl Descriptor {
     }
 
     /// Sets the read callback for the [`Descriptor`].
+    ///
+    /// The callback returns the value of the descriptor, or an [`AttError`] to reject the request.
+    /// The descriptor needs read access, otherwise validating it fails.
     pub fn on_read<
-        C: Fn(esp_ble_gatts_cb_param_t_gatts_read_evt_param) -> Vec<u8> + Send + Sync + 'static,
+        C: Fn(esp_ble_gatts_cb_param_t_gatts_read_evt_param) -> Result<Vec<u8>, AttError>
+            + Send
+            + Sync
+            + 'static,
     >(
         &mut self,
         callback: C,
     ) -> &mut Self {
-        if !self.permissions.read_access {
-            warn!(
-                "Descriptor {} does not have read permissions. Ignoring read callback.",
-                self
-            );
-
-            return self;
-        }
-
-        self.control = AttributeControl::ResponseByApp(Arc::new(callback));
+        self.control = AttributeControl::ResponseByApp(Some(Arc::new(callback)));
         self.internal_control = self.control.clone().into();
 
         self
     }
 
     /// Sets the write callback for the [`Descriptor`].
+    ///
+    /// The callback returns an [`AttError`] to reject the value.
+    /// The descriptor needs write access, otherwise validating it fails.
     pub fn on_write(
         &mut self,
-        callback: fn(Vec<u8>, esp_ble_gatts_cb_param_t_gatts_write_evt_param),
+        callback: fn(
+            Vec<u8>,
+            esp_ble_gatts_cb_param_t_gatts_write_evt_param,
+        ) -> Result<(), AttError>,
     ) -> &mut Self {
-        if !self.permissions.write_access {
-            warn!(
-                "Descriptor {} does not have write permissions. Ignoring write callback.",
-                self
-            );
+        self.write_callback = Some(callback);
 
-            return self;
+        // The stack cannot reject a write on behalf of the application.
+        if let AttributeControl::AutomaticResponse = self.control {
+            self.control = AttributeControl::ResponseByApp(None);
+            self.internal_control = self.control.clone().into();
         }
 
-        self.write_callback = Some(callback);
-
         self
     }
 
     /// Sets the value of the [`Descriptor`].
+    ///
+    /// Errors are logged, see [`Self::try_set_value`] for a fallible version.
     pub fn set_value<T: Into<Vec<u8>>>(&mut self, value: T) -> &mut Self {
-        self.value = value.into();
+        if let Err(error) = self.try_set_value(value).map(|_| ()) {
+            warn!("Cannot set the value of {}: {}.", self, error);
+        }
 
-        debug!("Trying to set value of {} to {:02X?}.", self, self.value);
+        self
+    }
+
+    /// Sets the value of the [`Descriptor`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the descriptor is registered and the value is longer than the registered one,
+    /// or if the Bluetooth stack rejects the value.
+    pub fn try_set_value<T: Into<Vec<u8>>>(&mut self, value: T) -> Result<&mut Self, Error> {
+        let value: Vec<u8> = value.into();
+
+        debug!("Trying to set value of {} to {:02X?}.", self, value);
 
         if let Some(handle) = self.attribute_handle {
-            #[allow(clippy::cast_possible_truncation)]
-            unsafe {
-                esp_nofail!(esp_ble_gatts_set_attr_value(
-                    handle,
-                    self.value.len() as u16,
-                    self.value.as_slice().as_ptr()
-                ));
+            if value.len() > self.value.len() {
+                return Err(Error::ValueTooLong {
+                    length: value.len(),
+                    max_length: self.value.len(),
+                });
             }
+
+            backend().set_attr_value(handle, &value)?;
         } else {
             info!(
                 "Descriptor {} not registered yet, value will be set on registration.",
                 self
             );
         }
-        self
+
+        self.value = value;
+        Ok(self)
     }
 
     /// Returns a reference to the built [`Descriptor`] behind an `Arc` and an `RwLock`.
@@ -128,26 +149,71 @@ impl Descriptor {
     pub fn build(&self) -> Arc<RwLock<Self>> {
         Arc::new(RwLock::new(self.clone()))
     }
-    pub(crate) fn register_self(&mut self, service_handle: u16) {
+
+    /// Validates the [`Descriptor`] and returns a reference to it, like [`Self::build`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the value is too long,
+    /// or if the permissions do not match the callbacks.
+    pub fn try_build(&self) -> Result<Arc<RwLock<Self>>, Error> {
+        self.validate()?;
+        Ok(self.build())
+    }
+
+    /// Checks that the [`Descriptor`] can be registered.
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        if self.value.len() > ESP_GATT_MAX_ATTR_LEN as usize {
+            return Err(Error::ValueTooLong {
+                length: self.value.len(),
+                max_length: ESP_GATT_MAX_ATTR_LEN as usize,
+            });
+        }
+
+        self.permissions.validate()?;
+
+        if matches!(self.control, AttributeControl::ResponseByApp(Some(_)))
+            && !self.permissions.read_access
+        {
+            return Err(Error::InconsistentPermissions(
+                "a read callback needs read access",
+            ));
+        }
+
+        if self.write_callback.is_some() && !self.permissions.write_access {
+            return Err(Error::InconsistentPermissions(
+                "a write callback needs write access",
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Forgets the handle assigned by the stack, so that the [`Descriptor`] can be registered again.
+    pub(crate) fn unregister(&mut self) {
+        self.attribute_handle = None;
+    }
+
+    pub(crate) fn register_self(&mut self, service_handle: u16) -> Result<(), Error> {
         debug!(
             "Registering {} into service at handle 0x{:04x}.",
             self, service_handle
         );
 
         #[allow(clippy::cast_possible_truncation)]
-        unsafe {
-            esp_nofail!(esp_ble_gatts_add_char_descr(
-                service_handle,
-                leaky_box_raw!(self.uuid.into()),
-                self.permissions.into(),
-                leaky_box_raw!(esp_attr_value_t {
-                    attr_max_len: self.value.len() as u16,
-                    attr_len: self.value.len() as u16,
-                    attr_value: self.value.as_mut_slice().as_mut_ptr(),
-                }),
-                &mut self.internal_control,
-            ));
-        }
+        backend().add_char_descr(
+            service_handle,
+            self.uuid.into(),
+            self.permissions.into(),
+            esp_attr_value_t {
+                attr_max_len: self.value.len() as u16,
+                attr_len: self.value.len() as u16,
+                attr_value: self.value.as_mut_slice().as_mut_ptr(),
+            },
+            self.internal_control,
+        )?;
+
+        Ok(())
     }
 }
 
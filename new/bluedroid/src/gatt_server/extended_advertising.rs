@@ -0,0 +1,972 @@
+use std::time::Duration;
+
+use crate::{
+    backend::backend,
+    gatt_server::{Advertisement, GattServer},
+    sys::*,
+    utilities::Phy,
+    Error,
+};
+use log::{debug, info, warn};
+
+/// The number of advertising sets, and the highest instance plus one.
+const MAX_ADVERTISING_SETS: u8 = 10;
+
+/// The shortest extended advertising interval, in units of 0.625 ms.
+const MIN_INTERVAL: u32 = 0x0020;
+
+/// The longest extended advertising interval, in units of 0.625 ms.
+const MAX_INTERVAL: u32 = 0xFF_FFFF;
+
+/// The longest legacy advertising interval, in units of 0.625 ms.
+const MAX_LEGACY_INTERVAL: u32 = 0x4000;
+
+/// The shortest periodic advertising interval, in units of 1.25 ms.
+const MIN_PERIODIC_INTERVAL: u16 = 0x0006;
+
+/// The longest duration of an advertising set, in milliseconds.
+const MAX_DURATION: u128 = 655_350;
+
+/// The highest advertising set identifier.
+const MAX_SID: u8 = 0x0F;
+
+/// The reasons for which the controller terminates an advertising set.
+const TERMINATED_BY_CONNECTION: u8 = 0x00;
+const TERMINATED_BY_TIMEOUT: u8 = 0x3C;
+const TERMINATED_BY_EVENT_LIMIT: u8 = 0x43;
+
+/// An extended advertising set, advertised alongside the others with its own parameters and data.
+///
+/// An extended set carries up to 1650 bytes of data, on the 1M or coded primary PHY and on
+/// any secondary PHY. If the set is scannable, its advertisement is sent as the scan response.
+/// A legacy set is advertised like the advertisement of the [`GattServer`], on the 1M PHY.
+///
+/// ```rust,ignore
+/// let mut set = AdvertisingSet::new(1);
+/// set.phy(Phy::LeCoded, Phy::LeCoded)
+///     .duration(Duration::from_secs(30))
+///     .advertisement(Advertisement::new().name("Color Lamp"));
+///
+/// server.add_advertising_set(set.clone())?;
+/// server.start_advertising_set(1)?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct AdvertisingSet {
+    pub(crate) instance: u8,
+    pub(crate) connectable: bool,
+    pub(crate) scannable: bool,
+    pub(crate) legacy: bool,
+    pub(crate) min_interval: u32,
+    pub(crate) max_interval: u32,
+    pub(crate) primary_phy: Phy,
+    pub(crate) secondary_phy: Phy,
+    pub(crate) tx_power: Option<i8>,
+    pub(crate) sid: u8,
+    pub(crate) duration: Option<Duration>,
+    pub(crate) max_events: Option<u8>,
+    pub(crate) advertisement: Advertisement,
+    pub(crate) periodic: Option<PeriodicAdvertising>,
+}
+
+impl AdvertisingSet {
+    /// Creates a new non-connectable, non-scannable extended [`AdvertisingSet`].
+    ///
+    /// The instance identifies the set, and must be lower than 10.
+    /// It is also used as the advertising set identifier, unless [`AdvertisingSet::sid`] is called.
+    #[must_use]
+    pub fn new(instance: u8) -> Self {
+        Self {
+            instance,
+            connectable: false,
+            scannable: false,
+            legacy: false,
+            min_interval: 0x20,
+            max_interval: 0x40,
+            primary_phy: Phy::Le1M,
+            secondary_phy: Phy::Le1M,
+            tx_power: None,
+            sid: instance,
+            duration: None,
+            max_events: None,
+            advertisement: Advertisement::new(),
+            periodic: None,
+        }
+    }
+
+    /// Sets whether clients can connect to the server through this set.
+    pub fn connectable(&mut self, connectable: bool) -> &mut Self {
+        self.connectable = connectable;
+        self
+    }
+
+    /// Sets whether clients can request the scan response.
+    ///
+    /// The advertisement of a scannable extended set is sent as the scan response.
+    pub fn scannable(&mut self, scannable: bool) -> &mut Self {
+        self.scannable = scannable;
+        self
+    }
+
+    /// Sets whether the set uses legacy advertising PDUs, readable by Bluetooth 4 clients.
+    pub fn legacy(&mut self, legacy: bool) -> &mut Self {
+        self.legacy = legacy;
+        self
+    }
+
+    /// Sets the advertising interval range, in units of 0.625 ms.
+    ///
+    /// The interval must be at least 20 ms (`0x000020`), and at most 10.24 s (`0x004000`)
+    /// for legacy sets or 10485.76 s (`0xFFFFFF`) for extended ones.
+    pub fn interval(&mut self, min: u32, max: u32) -> &mut Self {
+        self.min_interval = min;
+        self.max_interval = max;
+        self
+    }
+
+    /// Sets the PHY of the advertising packets, and the one of the packets carrying the data.
+    ///
+    /// The primary PHY cannot be [`Phy::Le2M`]. Legacy sets only use [`Phy::Le1M`].
+    pub fn phy(&mut self, primary: Phy, secondary: Phy) -> &mut Self {
+        self.primary_phy = primary;
+        self.secondary_phy = secondary;
+        self
+    }
+
+    /// Sets the preferred transmission power, in dBm.
+    ///
+    /// By default, the controller chooses it.
+    pub fn tx_power(&mut self, tx_power: i8) -> &mut Self {
+        self.tx_power = Some(tx_power);
+        self
+    }
+
+    /// Sets the advertising set identifier sent to clients, from 0 to 15.
+    pub fn sid(&mut self, sid: u8) -> &mut Self {
+        self.sid = sid;
+        self
+    }
+
+    /// Sets how long the set advertises each time it is started, up to 655.35 s.
+    ///
+    /// By default, the set advertises until it is stopped.
+    pub fn duration(&mut self, duration: Duration) -> &mut Self {
+        self.duration = Some(duration);
+        self
+    }
+
+    /// Sets how many advertising events the set sends each time it is started.
+    ///
+    /// By default, the number of events is not limited.
+    pub fn max_events(&mut self, max_events: u8) -> &mut Self {
+        self.max_events = Some(max_events);
+        self
+    }
+
+    /// Sets the content of the set.
+    pub fn advertisement(&mut self, advertisement: Advertisement) -> &mut Self {
+        self.advertisement = advertisement;
+        self
+    }
+
+    /// Enables periodic advertising on the set.
+    ///
+    /// Periodic advertising requires a non-connectable, non-scannable extended set.
+    pub fn periodic(&mut self, periodic: PeriodicAdvertising) -> &mut Self {
+        self.periodic = Some(periodic);
+        self
+    }
+
+    /// Checks that the set can be configured, and that its content fits.
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        let reason = if self.instance >= MAX_ADVERTISING_SETS {
+            Some("the instance must be lower than 10")
+        } else if self.sid > MAX_SID {
+            Some("the advertising set identifier must be lower than 16")
+        } else if self.min_interval < MIN_INTERVAL
+            || self.max_interval > MAX_INTERVAL
+            || self.min_interval > self.max_interval
+        {
+            Some("the interval range is invalid")
+        } else if self.primary_phy == Phy::Le2M {
+            Some("the primary PHY cannot be 2M")
+        } else if self
+            .duration
+            .map_or(false, |duration| duration.as_millis() > MAX_DURATION)
+        {
+            Some("the duration cannot exceed 655.35 s")
+        } else if self.legacy {
+            if self.primary_phy != Phy::Le1M || self.secondary_phy != Phy::Le1M {
+                Some("legacy sets use the 1M PHY")
+            } else if self.max_interval > MAX_LEGACY_INTERVAL {
+                Some("the interval range is invalid")
+            } else if self.connectable && !self.scannable {
+                Some("legacy connectable sets must be scannable")
+            } else if self.periodic.is_some() {
+                Some("legacy sets cannot advertise periodically")
+            } else {
+                None
+            }
+        } else if self.connectable && self.scannable {
+            Some("extended sets cannot be both connectable and scannable")
+        } else if self.periodic.is_some() && (self.connectable || self.scannable) {
+            Some("periodic advertising requires a non-connectable, non-scannable set")
+        } else {
+            None
+        };
+
+        if let Some(reason) = reason {
+            return Err(Error::InvalidAdvertisingSet(reason));
+        }
+
+        self.payloads()?;
+
+        if let Some(periodic) = &self.periodic {
+            periodic.validate()?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the advertising data and the scan response of the set.
+    pub(crate) fn payloads(&self) -> Result<(Vec<u8>, Vec<u8>), Error> {
+        if self.legacy {
+            self.advertisement.encode()
+        } else if self.scannable {
+            Ok((Vec::new(), self.advertisement.encode_without_flags()?))
+        } else {
+            Ok((self.advertisement.encode_extended()?, Vec::new()))
+        }
+    }
+
+    /// Returns the extended advertising parameters of the stack.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub(crate) fn parameters(&self) -> esp_ble_gap_ext_adv_params_t {
+        let mut properties = ESP_BLE_GAP_SET_EXT_ADV_PROP_NONCONN_NONSCANNABLE_UNDIRECTED;
+        if self.connectable {
+            properties |= ESP_BLE_GAP_SET_EXT_ADV_PROP_CONNECTABLE;
+        }
+        if self.scannable {
+            properties |= ESP_BLE_GAP_SET_EXT_ADV_PROP_SCANNABLE;
+        }
+        if self.legacy {
+            properties |= ESP_BLE_GAP_SET_EXT_ADV_PROP_LEGACY;
+        }
+
+        esp_ble_gap_ext_adv_params_t {
+            type_: properties as esp_ble_ext_adv_type_mask_t,
+            interval_min: self.min_interval,
+            interval_max: self.max_interval,
+            channel_map: esp_ble_adv_channel_t_ADV_CHNL_ALL,
+            own_addr_type: esp_ble_addr_type_t_BLE_ADDR_TYPE_RPA_PUBLIC,
+            peer_addr_type: esp_ble_addr_type_t_BLE_ADDR_TYPE_PUBLIC,
+            peer_addr: esp_bd_addr_t::default(),
+            filter_policy: esp_ble_adv_filter_t_ADV_FILTER_ALLOW_SCAN_ANY_CON_ANY,
+            tx_power: self.tx_power.unwrap_or(EXT_ADV_TX_PWR_NO_PREFERENCE as i8),
+            primary_phy: self.primary_phy.into(),
+            max_skip: 0,
+            secondary_phy: self.secondary_phy.into(),
+            sid: self.sid,
+            scan_req_notif: false,
+        }
+    }
+
+    /// Returns the duration and the event limit with which the stack starts the set.
+    #[allow(clippy::cast_possible_truncation)]
+    pub(crate) fn start_parameters(&self) -> esp_ble_gap_ext_adv_t {
+        esp_ble_gap_ext_adv_t {
+            instance: self.instance,
+            // In units of 10 ms, zero meaning no limit.
+            duration: self
+                .duration
+                .map_or(0, |duration| (duration.as_millis() / 10) as _),
+            max_events: self.max_events.map_or(0, Into::into),
+        }
+    }
+}
+
+/// The periodic advertising of an [`AdvertisingSet`].
+///
+/// Synchronised clients receive the data at a fixed interval, without scanning.
+#[derive(Debug, Clone)]
+pub struct PeriodicAdvertising {
+    pub(crate) min_interval: u16,
+    pub(crate) max_interval: u16,
+    pub(crate) include_tx_power: bool,
+    pub(crate) data: Advertisement,
+}
+
+impl Default for PeriodicAdvertising {
+    fn default() -> Self {
+        Self {
+            min_interval: 0x50,
+            max_interval: 0x60,
+            include_tx_power: false,
+            data: Advertisement::new(),
+        }
+    }
+}
+
+impl PeriodicAdvertising {
+    /// Creates a new [`PeriodicAdvertising`] with the default values.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the periodic advertising interval range, in units of 1.25 ms.
+    ///
+    /// The interval must be at least 7.5 ms (`0x0006`).
+    pub fn interval(&mut self, min: u16, max: u16) -> &mut Self {
+        self.min_interval = min;
+        self.max_interval = max;
+        self
+    }
+
+    /// Sets whether the transmission power is included in the periodic packets.
+    pub fn include_tx_power(&mut self, include_tx_power: bool) -> &mut Self {
+        self.include_tx_power = include_tx_power;
+        self
+    }
+
+    /// Sets the periodic advertising data. The flags are not sent.
+    pub fn data(&mut self, data: Advertisement) -> &mut Self {
+        self.data = data;
+        self
+    }
+
+    /// Checks that the interval range is valid, and that the data fits.
+    fn validate(&self) -> Result<(), Error> {
+        if self.min_interval < MIN_PERIODIC_INTERVAL || self.min_interval > self.max_interval {
+            return Err(Error::InvalidAdvertisingSet(
+                "the periodic interval range is invalid",
+            ));
+        }
+
+        self.data.encode_without_flags()?;
+
+        Ok(())
+    }
+
+    /// Returns the periodic advertising parameters of the stack.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn parameters(&self) -> esp_ble_gap_periodic_adv_params_t {
+        esp_ble_gap_periodic_adv_params_t {
+            interval_min: self.min_interval,
+            interval_max: self.max_interval,
+            properties: if self.include_tx_power {
+                ESP_BLE_GAP_PERIODIC_ADV_PROP_INCLUDE_TX_PWR as u8
+            } else {
+                0
+            },
+        }
+    }
+}
+
+/// An advertising set added to a [`GattServer`], and its state.
+#[derive(Debug)]
+pub(crate) struct AdvertisingSetState {
+    pub(crate) set: AdvertisingSet,
+    /// Whether advertising is wanted: started, and not stopped or terminated since.
+    pub(crate) enabled: bool,
+    /// Whether the stack is advertising the set, or was asked to.
+    pub(crate) active: bool,
+}
+
+impl GattServer {
+    /// Adds an extended [`AdvertisingSet`], or replaces the one with the same instance.
+    ///
+    /// Sets are advertised alongside the advertisement of the server, each with its own
+    /// parameters. A set is configured when the server starts, or immediately if it is started,
+    /// and advertises once [`GattServer::start_advertising_set`] is called.
+    /// A replaced set that was advertising restarts with the new configuration.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the set is invalid, or if the stack cannot configure it.
+    pub fn add_advertising_set(&mut self, set: AdvertisingSet) -> Result<(), Error> {
+        set.validate()?;
+
+        let instance = set.instance;
+        let (enabled, active) = self
+            .advertising_sets
+            .get(&instance)
+            .map_or((false, false), |state| (state.enabled, state.active));
+
+        self.advertising_sets.insert(
+            instance,
+            AdvertisingSetState {
+                set,
+                enabled,
+                active: false,
+            },
+        );
+
+        if !self.advertisement_configured {
+            return Ok(());
+        }
+
+        // Parameters cannot change while the set is advertising.
+        if active {
+            backend().ext_adv_stop(&[instance])?;
+        }
+
+        self.configure_advertising_set(instance)?;
+        self.resume_advertising_set(instance)
+    }
+
+    /// Starts advertising a set, until it is stopped or reaches its duration or event limit.
+    ///
+    /// A connectable set also stops when a client connects through it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server is not started, if there is no set with this instance,
+    /// or if the stack cannot start it.
+    pub fn start_advertising_set(&mut self, instance: u8) -> Result<(), Error> {
+        if !self.started {
+            return Err(Error::NotStarted);
+        }
+
+        let state = self
+            .advertising_sets
+            .get_mut(&instance)
+            .ok_or(Error::UnknownAdvertisingSet(instance))?;
+        state.enabled = true;
+
+        // Otherwise, the set starts when it is configured.
+        if self.advertisement_configured {
+            self.resume_advertising_set(instance)?;
+        }
+
+        Ok(())
+    }
+
+    /// Stops advertising a set, until [`GattServer::start_advertising_set`] is called.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server is not started, if there is no set with this instance,
+    /// or if the stack cannot stop it.
+    pub fn stop_advertising_set(&mut self, instance: u8) -> Result<(), Error> {
+        if !self.started {
+            return Err(Error::NotStarted);
+        }
+
+        let state = self
+            .advertising_sets
+            .get_mut(&instance)
+            .ok_or(Error::UnknownAdvertisingSet(instance))?;
+        state.enabled = false;
+
+        if !state.active {
+            return Ok(());
+        }
+
+        state.active = false;
+        if state.set.periodic.is_some() {
+            backend().periodic_adv_stop(instance)?;
+        }
+        backend().ext_adv_stop(&[instance])?;
+
+        Ok(())
+    }
+
+    /// Stops and removes an advertising set.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is no set with this instance, or if the stack cannot remove it.
+    pub fn remove_advertising_set(&mut self, instance: u8) -> Result<(), Error> {
+        let state = self
+            .advertising_sets
+            .remove(&instance)
+            .ok_or(Error::UnknownAdvertisingSet(instance))?;
+
+        if self.advertisement_configured {
+            if state.active && state.set.periodic.is_some() {
+                backend().periodic_adv_stop(instance)?;
+            }
+            backend().ext_adv_set_remove(instance)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns whether the server is advertising a set.
+    #[must_use]
+    pub fn is_advertising_set_active(&self, instance: u8) -> bool {
+        self.advertising_sets
+            .get(&instance)
+            .map_or(false, |state| state.active)
+    }
+
+    /// Replaces the content of an advertising set, and updates its data if the server is started.
+    ///
+    /// The set continues advertising with the new data.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is no set with this instance, if the advertisement does not fit,
+    /// in which case the previous one is kept, or if the stack cannot set the data.
+    pub fn update_advertising_set(
+        &mut self,
+        instance: u8,
+        advertisement: Advertisement,
+    ) -> Result<(), Error> {
+        let state = self
+            .advertising_sets
+            .get_mut(&instance)
+            .ok_or(Error::UnknownAdvertisingSet(instance))?;
+
+        let previous = std::mem::replace(&mut state.set.advertisement, advertisement);
+        let payloads = match state.set.payloads() {
+            Ok(payloads) => payloads,
+            Err(error) => {
+                state.set.advertisement = previous;
+                return Err(error);
+            }
+        };
+
+        if self.advertisement_configured {
+            configure_payloads(&state.set, payloads)?;
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the periodic advertising data of a set, and updates it if the server is started.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is no set with this instance, if the set does not advertise
+    /// periodically, if the data does not fit, or if the stack cannot set it.
+    pub fn update_periodic_advertising(
+        &mut self,
+        instance: u8,
+        data: Advertisement,
+    ) -> Result<(), Error> {
+        let state = self
+            .advertising_sets
+            .get_mut(&instance)
+            .ok_or(Error::UnknownAdvertisingSet(instance))?;
+
+        let Some(periodic) = &mut state.set.periodic else {
+            return Err(Error::InvalidAdvertisingSet(
+                "the set does not advertise periodically",
+            ));
+        };
+
+        let encoded = data.encode_without_flags()?;
+        periodic.data = data;
+
+        if self.advertisement_configured {
+            backend().config_periodic_adv_data_raw(instance, &encoded)?;
+        }
+
+        Ok(())
+    }
+
+    /// Configures every advertising set, and starts those that are enabled.
+    pub(crate) fn configure_advertising_sets(&mut self) {
+        let instances: Vec<u8> = self.advertising_sets.keys().copied().collect();
+
+        for instance in instances {
+            if let Err(error) = self
+                .configure_advertising_set(instance)
+                .and_then(|()| self.resume_advertising_set(instance))
+            {
+                warn!("Cannot configure advertising set {}: {}.", instance, error);
+            }
+        }
+    }
+
+    /// Sets the parameters and the data of an advertising set.
+    fn configure_advertising_set(&self, instance: u8) -> Result<(), Error> {
+        let Some(state) = self.advertising_sets.get(&instance) else {
+            return Err(Error::UnknownAdvertisingSet(instance));
+        };
+
+        backend().ext_adv_set_params(instance, &state.set.parameters())?;
+        configure_payloads(&state.set, state.set.payloads()?)?;
+
+        if let Some(periodic) = &state.set.periodic {
+            backend().periodic_adv_set_params(instance, periodic.parameters())?;
+            backend()
+                .config_periodic_adv_data_raw(instance, &periodic.data.encode_without_flags()?)?;
+        }
+
+        Ok(())
+    }
+
+    /// Starts advertising a set if it is enabled and not already running.
+    fn resume_advertising_set(&mut self, instance: u8) -> Result<(), Error> {
+        let Some(state) = self.advertising_sets.get_mut(&instance) else {
+            return Err(Error::UnknownAdvertisingSet(instance));
+        };
+
+        if !state.enabled || state.active {
+            return Ok(());
+        }
+
+        info!("Starting BLE GAP advertising set {}.", instance);
+
+        if state.set.periodic.is_some() {
+            backend().periodic_adv_start(instance)?;
+        }
+        backend().ext_adv_start(&[state.set.start_parameters()])?;
+
+        state.active = true;
+
+        Ok(())
+    }
+
+    /// Updates the state of an advertising set after the controller terminated it.
+    fn on_advertising_set_terminated(&mut self, instance: u8, status: u8) {
+        let Some(state) = self.advertising_sets.get_mut(&instance) else {
+            return;
+        };
+
+        match status {
+            TERMINATED_BY_CONNECTION => info!("Client connected to advertising set {}.", instance),
+            TERMINATED_BY_TIMEOUT => info!("Advertising set {} timed out.", instance),
+            TERMINATED_BY_EVENT_LIMIT => {
+                info!("Advertising set {} reached its event limit.", instance);
+            }
+            _ => warn!(
+                "Advertising set {} terminated with status 0x{:02x}.",
+                instance, status
+            ),
+        }
+
+        state.enabled = false;
+        state.active = false;
+
+        if state.set.periodic.is_some() {
+            if let Err(error) = backend().periodic_adv_stop(instance) {
+                warn!("Cannot stop periodic advertising: {}.", error);
+            }
+        }
+    }
+
+    /// Handles the events of extended and periodic advertising.
+    ///
+    /// Returns `false` if the event is not related to advertising sets.
+    pub(crate) fn on_extended_advertising_event(
+        &mut self,
+        event: esp_gap_ble_cb_event_t,
+        param: *mut esp_ble_gap_cb_param_t,
+    ) -> bool {
+        #[allow(non_upper_case_globals)]
+        let (operation, status) = match event {
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_EXT_ADV_SET_PARAMS_COMPLETE_EVT => {
+                ("advertising set parameters", unsafe {
+                    (*param).ext_adv_set_params.status
+                })
+            }
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_EXT_ADV_DATA_SET_COMPLETE_EVT => {
+                ("advertising set data", unsafe {
+                    (*param).ext_adv_data_set.status
+                })
+            }
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_EXT_SCAN_RSP_DATA_SET_COMPLETE_EVT => {
+                ("advertising set scan response", unsafe {
+                    (*param).scan_rsp_set.status
+                })
+            }
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_EXT_ADV_START_COMPLETE_EVT => {
+                ("advertising set start", unsafe {
+                    (*param).ext_adv_start.status
+                })
+            }
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_EXT_ADV_STOP_COMPLETE_EVT => {
+                ("advertising set stop", unsafe {
+                    (*param).ext_adv_stop.status
+                })
+            }
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_EXT_ADV_SET_REMOVE_COMPLETE_EVT => {
+                ("advertising set removal", unsafe {
+                    (*param).ext_adv_remove.status
+                })
+            }
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_PERIODIC_ADV_SET_PARAMS_COMPLETE_EVT => {
+                ("periodic advertising parameters", unsafe {
+                    (*param).peroid_adv_set_params.status
+                })
+            }
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_PERIODIC_ADV_DATA_SET_COMPLETE_EVT => {
+                ("periodic advertising data", unsafe {
+                    (*param).period_adv_data_set.status
+                })
+            }
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_PERIODIC_ADV_START_COMPLETE_EVT => {
+                ("periodic advertising start", unsafe {
+                    (*param).period_adv_start.status
+                })
+            }
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_PERIODIC_ADV_STOP_COMPLETE_EVT => {
+                ("periodic advertising stop", unsafe {
+                    (*param).period_adv_stop.status
+                })
+            }
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_ADV_TERMINATED_EVT => {
+                let param = unsafe { (*param).adv_terminate };
+                self.on_advertising_set_terminated(param.adv_instance, param.status);
+                return true;
+            }
+            _ => return false,
+        };
+
+        if status == esp_bt_status_t_ESP_BT_STATUS_SUCCESS {
+            debug!("BLE GAP {} complete.", operation);
+        } else {
+            warn!("BLE GAP {} failed.", operation);
+        }
+
+        true
+    }
+}
+
+/// Sets the advertising data and the scan response of a set.
+fn configure_payloads(
+    set: &AdvertisingSet,
+    (advertising_data, scan_response): (Vec<u8>, Vec<u8>),
+) -> Result<(), Error> {
+    // Scannable extended sets carry no advertising data.
+    if set.legacy || !set.scannable {
+        backend().config_ext_adv_data_raw(set.instance, &advertising_data)?;
+    }
+
+    if set.scannable {
+        backend().config_ext_scan_rsp_data_raw(set.instance, &scan_response)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utilities::BleUuid;
+
+    /// Returns the reason for which a set is invalid, or `None` if it is valid.
+    fn rejection(set: &AdvertisingSet) -> Option<&'static str> {
+        match set.validate() {
+            Ok(()) => None,
+            Err(Error::InvalidAdvertisingSet(reason)) => Some(reason),
+            Err(error) => panic!("unexpected error: {error:?}"),
+        }
+    }
+
+    #[test]
+    fn parameters() {
+        let mut set = AdvertisingSet::new(3);
+        let parameters = set.parameters();
+        assert_eq!(parameters.type_, 0x00);
+        assert_eq!(parameters.tx_power, EXT_ADV_TX_PWR_NO_PREFERENCE as i8);
+        assert_eq!(parameters.sid, 3);
+
+        set.connectable(true)
+            .scannable(true)
+            .legacy(true)
+            .interval(0x30, 0x60)
+            .phy(Phy::LeCoded, Phy::Le2M)
+            .tx_power(-12)
+            .sid(9)
+            .duration(Duration::from_millis(1230))
+            .max_events(7);
+        let parameters = set.parameters();
+        // Connectable, scannable and legacy.
+        assert_eq!(parameters.type_, 0x13);
+        assert_eq!(
+            (parameters.interval_min, parameters.interval_max),
+            (0x30, 0x60)
+        );
+        assert_eq!((parameters.primary_phy, parameters.secondary_phy), (3, 2));
+        assert_eq!(parameters.tx_power, -12);
+        assert_eq!(parameters.sid, 9);
+
+        let start = set.start_parameters();
+        assert_eq!(
+            (start.instance, start.duration, start.max_events),
+            (3, 123, 7)
+        );
+        let start = AdvertisingSet::new(4).start_parameters();
+        assert_eq!(
+            (start.instance, start.duration, start.max_events),
+            (4, 0, 0)
+        );
+    }
+
+    #[test]
+    fn payloads() {
+        let advertisement = Advertisement::new().name("Lamp");
+        let with_flags = vec![2, 0x01, 0x06, 5, 0x09, b'L', b'a', b'm', b'p'];
+        let without_flags = with_flags[3..].to_vec();
+        let mut set = AdvertisingSet::new(0);
+        set.advertisement(advertisement);
+
+        // An extended set carries its advertisement as advertising data.
+        assert_eq!(set.payloads().unwrap(), (with_flags.clone(), vec![]));
+
+        // A scannable extended set sends it as the scan response.
+        set.scannable(true);
+        assert_eq!(set.payloads().unwrap(), (vec![], without_flags));
+
+        // A legacy set splits it like the advertisement of the server.
+        set.legacy(true);
+        assert_eq!(set.payloads().unwrap(), (with_flags, vec![]));
+    }
+
+    #[test]
+    fn phy() {
+        let mut set = AdvertisingSet::new(0);
+        for (primary, secondary) in [
+            (Phy::Le1M, Phy::Le1M),
+            (Phy::Le1M, Phy::Le2M),
+            (Phy::LeCoded, Phy::LeCoded),
+            (Phy::LeCoded, Phy::Le2M),
+        ] {
+            assert_eq!(rejection(set.phy(primary, secondary)), None);
+        }
+        assert_eq!(
+            rejection(set.phy(Phy::Le2M, Phy::Le1M)),
+            Some("the primary PHY cannot be 2M")
+        );
+
+        set.legacy(true);
+        assert_eq!(rejection(set.phy(Phy::Le1M, Phy::Le1M)), None);
+        for (primary, secondary) in [(Phy::Le1M, Phy::Le2M), (Phy::LeCoded, Phy::Le1M)] {
+            assert_eq!(
+                rejection(set.phy(primary, secondary)),
+                Some("legacy sets use the 1M PHY")
+            );
+        }
+    }
+
+    #[test]
+    fn limits() {
+        let interval = Some("the interval range is invalid");
+
+        assert_eq!(rejection(&AdvertisingSet::new(9)), None);
+        assert_eq!(
+            rejection(&AdvertisingSet::new(10)),
+            Some("the instance must be lower than 10")
+        );
+        assert_eq!(rejection(AdvertisingSet::new(0).sid(15)), None);
+        assert_eq!(
+            rejection(AdvertisingSet::new(0).sid(16)),
+            Some("the advertising set identifier must be lower than 16")
+        );
+
+        assert_eq!(
+            rejection(AdvertisingSet::new(0).interval(0x20, 0xFF_FFFF)),
+            None
+        );
+        for (min, max) in [(0x1F, 0x40), (0x20, 0x100_0000), (0x41, 0x40)] {
+            assert_eq!(
+                rejection(AdvertisingSet::new(0).interval(min, max)),
+                interval
+            );
+        }
+        let mut legacy = AdvertisingSet::new(0);
+        legacy.legacy(true);
+        assert_eq!(rejection(legacy.clone().interval(0x20, 0x4000)), None);
+        assert_eq!(rejection(legacy.clone().interval(0x20, 0x4001)), interval);
+
+        assert_eq!(
+            rejection(AdvertisingSet::new(0).duration(Duration::from_millis(655_350))),
+            None
+        );
+        assert_eq!(
+            rejection(AdvertisingSet::new(0).duration(Duration::from_millis(655_360))),
+            Some("the duration cannot exceed 655.35 s")
+        );
+    }
+
+    #[test]
+    fn kinds() {
+        let mut set = AdvertisingSet::new(0);
+        assert_eq!(rejection(set.connectable(true)), None);
+        assert_eq!(
+            rejection(set.scannable(true)),
+            Some("extended sets cannot be both connectable and scannable")
+        );
+        assert_eq!(rejection(set.legacy(true)), None);
+        assert_eq!(
+            rejection(set.scannable(false)),
+            Some("legacy connectable sets must be scannable")
+        );
+        assert_eq!(
+            rejection(set.connectable(false).periodic(PeriodicAdvertising::new())),
+            Some("legacy sets cannot advertise periodically")
+        );
+    }
+
+    #[test]
+    fn periodic() {
+        let mut set = AdvertisingSet::new(0);
+        set.periodic(PeriodicAdvertising::new());
+        assert_eq!(rejection(&set), None);
+        for (connectable, scannable) in [(true, false), (false, true)] {
+            assert_eq!(
+                rejection(set.clone().connectable(connectable).scannable(scannable)),
+                Some("periodic advertising requires a non-connectable, non-scannable set")
+            );
+        }
+
+        let interval = Some("the periodic interval range is invalid");
+        assert_eq!(
+            rejection(set.periodic(PeriodicAdvertising::new().interval(0x06, 0x06).clone())),
+            None
+        );
+        for (min, max) in [(0x05, 0x06), (0x07, 0x06)] {
+            assert_eq!(
+                rejection(set.periodic(PeriodicAdvertising::new().interval(min, max).clone())),
+                interval
+            );
+        }
+    }
+
+    #[test]
+    fn too_long() {
+        // The data of an extended set fits in 1650 bytes, but not the eighth 254-byte structure.
+        let mut advertisement = Advertisement::new().flags(0);
+        for uuid in 0..6 {
+            advertisement = advertisement.service_data(BleUuid::Uuid16(uuid), vec![0; 250]);
+        }
+        let mut set = AdvertisingSet::new(0);
+        set.advertisement(advertisement.clone());
+        assert_eq!(set.payloads().unwrap().0.len(), 6 * 254);
+        set.advertisement(advertisement.service_data(BleUuid::Uuid16(6), vec![0; 250]));
+        assert!(matches!(
+            set.validate(),
+            Err(Error::AdvertisementTooLong {
+                field: "service data",
+                length: 254
+            })
+        ));
+
+        // A single structure is limited to 254 bytes of data, in the set and in its periodic data.
+        let too_long = Advertisement::new().manufacturer_data(0x02E5, vec![0; 253]);
+        let mut periodic = PeriodicAdvertising::new();
+        periodic.data(too_long.clone());
+        for set in [
+            AdvertisingSet::new(0)
+                .advertisement(too_long.clone())
+                .clone(),
+            AdvertisingSet::new(0)
+                .scannable(true)
+                .advertisement(too_long)
+                .clone(),
+            AdvertisingSet::new(0).periodic(periodic).clone(),
+        ] {
+            assert!(matches!(
+                set.validate(),
+                Err(Error::AdvertisementTooLong {
+                    field: "manufacturer data",
+                    length: 257
+                })
+            ));
+        }
+    }
+}
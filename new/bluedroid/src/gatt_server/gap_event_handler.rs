@@ -1,16 +1,23 @@
-use esp_idf_sys::{
-    esp_ble_gap_cb_param_t, esp_ble_gap_start_advertising, esp_bt_status_t_ESP_BT_STATUS_SUCCESS,
-    esp_gap_ble_cb_event_t, esp_gap_ble_cb_event_t_ESP_GAP_BLE_ADV_DATA_SET_COMPLETE_EVT,
+use crate::sys::{
+    esp_ble_gap_cb_param_t, esp_bt_status_t_ESP_BT_STATUS_SUCCESS, esp_gap_ble_cb_event_t,
+    esp_gap_ble_cb_event_t_ESP_GAP_BLE_ADV_DATA_RAW_SET_COMPLETE_EVT,
     esp_gap_ble_cb_event_t_ESP_GAP_BLE_ADV_START_COMPLETE_EVT,
     esp_gap_ble_cb_event_t_ESP_GAP_BLE_ADV_STOP_COMPLETE_EVT,
-    esp_gap_ble_cb_event_t_ESP_GAP_BLE_SCAN_RSP_DATA_SET_COMPLETE_EVT,
-    esp_gap_ble_cb_event_t_ESP_GAP_BLE_UPDATE_CONN_PARAMS_EVT, esp_nofail,
+    esp_gap_ble_cb_event_t_ESP_GAP_BLE_AUTH_CMPL_EVT, esp_gap_ble_cb_event_t_ESP_GAP_BLE_KEY_EVT,
+    esp_gap_ble_cb_event_t_ESP_GAP_BLE_NC_REQ_EVT,
+    esp_gap_ble_cb_event_t_ESP_GAP_BLE_PASSKEY_NOTIF_EVT,
+    esp_gap_ble_cb_event_t_ESP_GAP_BLE_PASSKEY_REQ_EVT,
+    esp_gap_ble_cb_event_t_ESP_GAP_BLE_READ_RSSI_COMPLETE_EVT,
+    esp_gap_ble_cb_event_t_ESP_GAP_BLE_REMOVE_BOND_DEV_COMPLETE_EVT,
+    esp_gap_ble_cb_event_t_ESP_GAP_BLE_SCAN_RSP_DATA_RAW_SET_COMPLETE_EVT,
+    esp_gap_ble_cb_event_t_ESP_GAP_BLE_SEC_REQ_EVT,
+    esp_gap_ble_cb_event_t_ESP_GAP_BLE_SET_PKT_LENGTH_COMPLETE_EVT,
+    esp_gap_ble_cb_event_t_ESP_GAP_BLE_UPDATE_CONN_PARAMS_EVT,
 };
 
 use log::{debug, info, warn};
 
-use super::GattServer;
-use crate::leaky_box_raw;
+use super::{database, subscriptions::subscriptions, GattServer};
 
 impl GattServer {
     pub(crate) extern "C" fn gap_event_handler(
@@ -20,36 +27,30 @@ impl GattServer {
     ) {
         #[allow(non_upper_case_globals)]
         match event {
-            esp_gap_ble_cb_event_t_ESP_GAP_BLE_ADV_DATA_SET_COMPLETE_EVT => {
-                debug!("BLE GAP advertisement data set complete.");
-                info!("Starting BLE GAP advertisement.");
-
-                unsafe {
-                    esp_nofail!(esp_ble_gap_start_advertising(leaky_box_raw!(
-                        self.advertisement_parameters
-                    )));
-                }
-            }
-            esp_gap_ble_cb_event_t_ESP_GAP_BLE_SCAN_RSP_DATA_SET_COMPLETE_EVT => {
-                debug!("BLE GAP scan response data set complete.");
-                info!("Starting BLE GAP response advertisement.");
-
-                unsafe {
-                    esp_nofail!(esp_ble_gap_start_advertising(leaky_box_raw!(
-                        self.advertisement_parameters
-                    )));
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_ADV_DATA_RAW_SET_COMPLETE_EVT => {
+                let param = unsafe { (*param).adv_data_raw_cmpl };
+                if param.status == esp_bt_status_t_ESP_BT_STATUS_SUCCESS {
+                    debug!("BLE GAP advertisement data set complete.");
+                } else {
+                    warn!("BLE GAP advertisement data set failed.");
                 }
             }
-            esp_gap_ble_cb_event_t_ESP_GAP_BLE_ADV_START_COMPLETE_EVT => {
-                let param = unsafe { (*param).adv_data_cmpl };
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_SCAN_RSP_DATA_RAW_SET_COMPLETE_EVT => {
+                let param = unsafe { (*param).scan_rsp_data_raw_cmpl };
                 if param.status == esp_bt_status_t_ESP_BT_STATUS_SUCCESS {
-                    debug!("BLE GAP advertisement started.");
+                    debug!("BLE GAP scan response data set complete.");
                 } else {
-                    warn!("BLE GAP advertisement start failed.");
+                    warn!("BLE GAP scan response data set failed.");
                 }
+
+                self.resume_advertising();
+            }
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_ADV_START_COMPLETE_EVT => {
+                let param = unsafe { (*param).adv_start_cmpl };
+                self.on_advertising_started(param.status == esp_bt_status_t_ESP_BT_STATUS_SUCCESS);
             }
             esp_gap_ble_cb_event_t_ESP_GAP_BLE_ADV_STOP_COMPLETE_EVT => {
-                let param = unsafe { (*param).adv_data_cmpl };
+                let param = unsafe { (*param).adv_stop_cmpl };
                 if param.status == esp_bt_status_t_ESP_BT_STATUS_SUCCESS {
                     debug!("BLE GAP advertisement stopped.");
                 } else {
@@ -57,10 +58,50 @@ impl GattServer {
                 }
             }
             esp_gap_ble_cb_event_t_ESP_GAP_BLE_UPDATE_CONN_PARAMS_EVT => {
-                let param = unsafe { (*param).update_conn_params };
-                info!("Connection parameters updated: {:?}", param);
+                self.on_connection_parameters(unsafe { (*param).update_conn_params });
+            }
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_SET_PKT_LENGTH_COMPLETE_EVT => {
+                self.on_data_length(unsafe { (*param).pkt_data_length_cmpl });
+            }
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_READ_RSSI_COMPLETE_EVT => {
+                Self::on_rssi_read(unsafe { (*param).read_rssi_cmpl });
+            }
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_SEC_REQ_EVT => {
+                self.on_security_request(unsafe { (*param).ble_security.ble_req });
+            }
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_PASSKEY_REQ_EVT => {
+                self.on_passkey_request(unsafe { (*param).ble_security.ble_req });
+            }
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_PASSKEY_NOTIF_EVT => {
+                self.on_passkey_display(unsafe { (*param).ble_security.key_notif });
+            }
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_NC_REQ_EVT => {
+                self.on_numeric_comparison(unsafe { (*param).ble_security.key_notif });
+            }
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_KEY_EVT => {
+                debug!("BLE GAP key exchanged.");
+            }
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_AUTH_CMPL_EVT => {
+                self.on_authentication_complete(unsafe { (*param).ble_security.auth_cmpl });
+            }
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_REMOVE_BOND_DEV_COMPLETE_EVT => {
+                let param = unsafe { (*param).remove_bond_dev_cmpl };
+                if param.status == esp_bt_status_t_ESP_BT_STATUS_SUCCESS {
+                    info!("BLE GAP bond with {:02X?} removed.", param.bd_addr);
+                    subscriptions().unbond(param.bd_addr);
+                    database::unbond(param.bd_addr);
+                } else {
+                    warn!("BLE GAP bond removal failed.");
+                }
             }
             _ => {
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                if self.on_extended_advertising_event(event, param)
+                    || self.on_phy_event(event, param)
+                {
+                    return;
+                }
+
                 warn!("Unhandled GAP event: {:?}", event);
             }
         }
@@ -1,7 +1,9 @@
+use std::sync::Mutex;
+
 use crate::gatt_server::{GattServer, Profile};
 
 #[allow(clippy::wildcard_imports)]
-use esp_idf_sys::*;
+use crate::sys::*;
 use log::{debug, warn};
 
 mod profile;
@@ -11,51 +13,98 @@ impl GattServer {
     /// The main GATT server event loop.
     ///
     /// Dispatches the received events across the appropriate profile-related handlers.
+    ///
+    /// The server is only locked while handling server-related events,
+    /// so that user callbacks, deferred or invoked by the profile handlers, can access it.
     pub(crate) fn gatts_event_handler(
-        &mut self,
+        server: &Mutex<Self>,
         event: esp_gatts_cb_event_t,
         gatts_if: esp_gatt_if_t,
         param: *mut esp_ble_gatts_cb_param_t,
     ) {
+        let mut server = server.lock().expect("Cannot lock GATT server.");
+
+        if !server.started {
+            debug!("Ignoring event {} received by a stopped server.", event);
+            return;
+        }
+
         #[allow(non_upper_case_globals)]
         match event {
             esp_gatts_cb_event_t_ESP_GATTS_CONNECT_EVT => {
                 let param = unsafe { (*param).connect };
-                self.on_connect(param);
+                server.on_client_connect(gatts_if, param);
+                Self::release(server);
 
                 // Do not pass this event to the profile handlers.
                 return;
             }
             esp_gatts_cb_event_t_ESP_GATTS_DISCONNECT_EVT => {
                 let param = unsafe { (*param).disconnect };
-                self.on_disconnect(param);
+                server.on_client_disconnect(param);
+                Self::release(server);
 
                 // Do not pass this event to the profile handlers.
                 return;
             }
             esp_gatts_cb_event_t_ESP_GATTS_MTU_EVT => {
                 let param = unsafe { (*param).mtu };
-                self.on_mtu_change(param);
+                server.on_mtu_exchange(param);
+                Self::release(server);
 
                 // Do not pass this event to the profile handlers.
                 return;
             }
-            esp_gatts_cb_event_t_ESP_GATTS_REG_EVT => {
-                let param = unsafe { (*param).reg };
-                self.on_reg(gatts_if, param);
+            esp_gatts_cb_event_t_ESP_GATTS_REG_EVT
+            | esp_gatts_cb_event_t_ESP_GATTS_CREATE_EVT
+            | esp_gatts_cb_event_t_ESP_GATTS_CREAT_ATTR_TAB_EVT
+            | esp_gatts_cb_event_t_ESP_GATTS_ADD_INCL_SRVC_EVT
+            | esp_gatts_cb_event_t_ESP_GATTS_ADD_CHAR_EVT
+            | esp_gatts_cb_event_t_ESP_GATTS_ADD_CHAR_DESCR_EVT
+            | esp_gatts_cb_event_t_ESP_GATTS_START_EVT
+            | esp_gatts_cb_event_t_ESP_GATTS_STOP_EVT
+            | esp_gatts_cb_event_t_ESP_GATTS_DELETE_EVT => {
+                if event == esp_gatts_cb_event_t_ESP_GATTS_REG_EVT {
+                    let param = unsafe { (*param).reg };
+                    server.on_reg(param);
+                }
+
+                // The registration locks the attributes: release the server first.
+                let registration = server.registration.clone();
+                Self::release(server);
+                registration.on_event(event, gatts_if, param);
 
-                // Pass this event to the profile handlers.
+                // Do not pass this event to the profile handlers.
+                return;
             }
             esp_gatts_cb_event_t_ESP_GATTS_RESPONSE_EVT => {
                 let param = unsafe { (*param).rsp };
-                self.on_response(param);
+                server.on_response(param);
+                Self::release(server);
 
                 // Do not pass this event to the profile handlers.
                 return;
             }
             esp_gatts_cb_event_t_ESP_GATTS_SET_ATTR_VAL_EVT => {
                 let param = unsafe { (*param).set_attr_val };
-                self.on_set_attr_val(gatts_if, param);
+                server.on_set_attr_val(gatts_if, param);
+                Self::release(server);
+
+                // Do not pass this event to the profile handlers.
+                return;
+            }
+            esp_gatts_cb_event_t_ESP_GATTS_CONF_EVT => {
+                let param = unsafe { (*param).conf };
+                server.on_conf(param);
+                Self::release(server);
+
+                // Do not pass this event to the profile handlers.
+                return;
+            }
+            esp_gatts_cb_event_t_ESP_GATTS_SEND_SERVICE_CHANGE_EVT => {
+                let param = unsafe { (*param).service_change };
+                server.on_service_change(param);
+                Self::release(server);
 
                 // Do not pass this event to the profile handlers.
                 return;
@@ -63,7 +112,11 @@ impl GattServer {
             _ => {}
         }
 
-        self.profiles.iter().for_each(|profile| {
+        // Profile handlers invoke user callbacks: release the server first.
+        let profiles = server.profiles.clone();
+        Self::release(server);
+
+        for profile in &profiles {
             if profile.read().unwrap().interface == Some(gatts_if) {
                 debug!(
                     "Handling event {} on profile {}.",
@@ -75,7 +128,7 @@ impl GattServer {
                     .unwrap()
                     .gatts_event_handler(event, gatts_if, param);
             }
-        });
+        }
     }
 }
 
@@ -89,31 +142,6 @@ impl Profile {
     ) {
         #[allow(non_upper_case_globals)]
         match event {
-            esp_gatts_cb_event_t_ESP_GATTS_REG_EVT => {
-                let param = unsafe { (*param).reg };
-
-                self.on_reg(param);
-            }
-            esp_gatts_cb_event_t_ESP_GATTS_CREATE_EVT => {
-                let param = unsafe { (*param).create };
-
-                self.on_create(param);
-            }
-            esp_gatts_cb_event_t_ESP_GATTS_START_EVT => {
-                let param = unsafe { (*param).start };
-
-                self.on_start(param);
-            }
-            esp_gatts_cb_event_t_ESP_GATTS_ADD_CHAR_EVT => {
-                let param = unsafe { (*param).add_char };
-
-                self.on_char_add(param);
-            }
-            esp_gatts_cb_event_t_ESP_GATTS_ADD_CHAR_DESCR_EVT => {
-                let param = unsafe { (*param).add_char_descr };
-
-                self.on_char_add_descr(param);
-            }
             esp_gatts_cb_event_t_ESP_GATTS_WRITE_EVT => {
                 let param = unsafe { (*param).write };
 
@@ -124,11 +152,10 @@ impl Profile {
 
                 self.on_read(gatts_if, param);
             }
-            esp_gatts_cb_event_t_ESP_GATTS_CONF_EVT => {
-                let _param = unsafe { (*param).conf };
+            esp_gatts_cb_event_t_ESP_GATTS_EXEC_WRITE_EVT => {
+                let param = unsafe { (*param).exec_write };
 
-                // TODO: on_conf.
-                debug!("Received confirmation event.");
+                self.on_exec_write(gatts_if, param);
             }
             _ => {
                 warn!("Unhandled GATT server event: {:?}", event);
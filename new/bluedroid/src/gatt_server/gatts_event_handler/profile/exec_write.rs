@@ -0,0 +1,61 @@
+use super::send_response;
+use crate::gatt_server::{connection_state::connection_states, profile::PreparedWrite, Profile};
+use crate::sys::*;
+use log::debug;
+
+impl Profile {
+    #[allow(clippy::cast_possible_truncation)]
+    pub(crate) fn on_exec_write(
+        &mut self,
+        gatts_if: esp_gatt_if_t,
+        param: esp_ble_gatts_cb_param_t_gatts_exec_write_evt_param,
+    ) {
+        let queue = connection_states()
+            .get_mut(&param.conn_id)
+            .map(|state| state.take_prepared_writes(gatts_if))
+            .unwrap_or_default();
+
+        if i32::from(param.exec_write_flag) != ESP_GATT_PREP_WRITE_EXEC {
+            debug!("Cancelling {} prepared writes.", queue.len());
+            send_response(gatts_if, param.conn_id, param.trans_id, 0, 0, Ok(&[]));
+            return;
+        }
+
+        debug!("Executing {} prepared writes.", queue.len());
+
+        // Each attribute receives its whole value at once, stopping at the first rejected one.
+        let mut handle = 0;
+        let result = PreparedWrite::assemble(queue).into_iter().try_for_each(
+            |(attribute_handle, mut value)| {
+                handle = attribute_handle;
+
+                let Some(attribute) = self.get_attribute(attribute_handle) else {
+                    return Ok(());
+                };
+
+                let write_param = esp_ble_gatts_cb_param_t_gatts_write_evt_param {
+                    conn_id: param.conn_id,
+                    trans_id: param.trans_id,
+                    bda: param.bda,
+                    handle: attribute_handle,
+                    offset: 0,
+                    need_rsp: true,
+                    is_prep: false,
+                    len: value.len() as u16,
+                    value: value.as_mut_ptr(),
+                };
+
+                attribute.write(&value, write_param)
+            },
+        );
+
+        send_response(
+            gatts_if,
+            param.conn_id,
+            param.trans_id,
+            handle,
+            0,
+            result.map(|()| [].as_slice()),
+        );
+    }
+}
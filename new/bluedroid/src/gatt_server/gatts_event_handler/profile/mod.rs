@@ -1,8 +1,46 @@
-mod add_char;
-mod add_char_descr;
-mod conf;
-mod create;
+use crate::backend::backend;
+use crate::sys::*;
+use crate::utilities::AttError;
+use log::{debug, warn};
+
+mod exec_write;
 mod read;
-mod reg;
-mod start;
 mod write;
+
+/// Answers a request of a client with a value, or with an error.
+#[allow(clippy::cast_possible_truncation)]
+fn send_response(
+    gatts_if: esp_gatt_if_t,
+    conn_id: u16,
+    trans_id: u32,
+    handle: u16,
+    offset: u16,
+    result: Result<&[u8], AttError>,
+) {
+    let (status, value) = match result {
+        Ok(value) => (esp_gatt_status_t_ESP_GATT_OK, value),
+        Err(error) => {
+            debug!("Rejecting request on handle 0x{:04x}: {}.", handle, error);
+            (error.into(), [].as_slice())
+        }
+    };
+
+    // Extend the response to the maximum length.
+    let mut response = [0u8; ESP_GATT_MAX_ATTR_LEN as usize];
+    let length = value.len().min(response.len());
+    response[..length].copy_from_slice(&value[..length]);
+
+    let mut esp_rsp = esp_gatt_rsp_t {
+        attr_value: esp_gatt_value_t {
+            auth_req: 0,
+            handle,
+            len: length as u16,
+            offset,
+            value: response,
+        },
+    };
+
+    if let Err(error) = backend().send_response(gatts_if, conn_id, trans_id, status, &mut esp_rsp) {
+        warn!("Cannot send the response: {}.", error);
+    }
+}
@@ -1,6 +1,7 @@
-use crate::gatt_server::Profile;
-use crate::utilities::AttributeControl;
-use esp_idf_sys::*;
+use super::send_response;
+use crate::gatt_server::{connection_state::connection_states, Profile};
+use crate::sys::*;
+use crate::utilities::{AttError, AttributeControl, DEFAULT_MTU};
 use log::debug;
 
 impl Profile {
@@ -10,102 +11,79 @@ impl Profile {
         param: esp_ble_gatts_cb_param_t_gatts_read_evt_param,
     ) {
         for service in &self.services {
-            service
-                .read()
-                .unwrap()
-                .characteristics
-                .iter()
-                .for_each(|characteristic| {
-                    if characteristic.read().unwrap().attribute_handle == Some(param.handle) {
-                        debug!(
-                            "Received read event for characteristic {}.",
-                            characteristic.read().unwrap()
-                        );
+            let characteristics = service.read().unwrap().characteristics.clone();
 
-                        // If the characteristic has a read handler, call it.
-                        if let AttributeControl::ResponseByApp(callback) =
-                            &characteristic.read().unwrap().control
-                        {
-                            let value = callback(param);
+            for characteristic in characteristics {
+                // The locks are released before calling the callbacks, so that they can access the attribute.
+                let characteristic = characteristic.read().unwrap();
 
-                            // Extend the response to the maximum length.
-                            let mut response = [0u8; 600];
-                            response[..value.len()].copy_from_slice(&value);
+                if characteristic.attribute_handle == Some(param.handle) {
+                    debug!("Received read event for characteristic {}.", characteristic);
 
-                            let mut esp_rsp = esp_gatt_rsp_t {
-                                attr_value: esp_gatt_value_t {
-                                    auth_req: 0,
-                                    handle: param.handle,
-                                    len: value.len() as u16,
-                                    offset: 0,
-                                    value: response,
-                                },
-                            };
+                    let control = characteristic.control.clone();
+                    let value = characteristic.internal_value.clone();
+                    drop(characteristic);
 
-                            unsafe {
-                                esp_nofail!(esp_ble_gatts_send_response(
-                                    gatts_if,
-                                    param.conn_id,
-                                    param.trans_id,
-                                    // TODO: Allow different statuses.
-                                    esp_gatt_status_t_ESP_GATT_OK,
-                                    &mut esp_rsp
-                                ));
-                            }
-                        }
-                    } else {
-                        characteristic
-                            .read()
-                            .unwrap()
-                            .descriptors
-                            .iter()
-                            .for_each(|descriptor| {
-                                debug!(
-                                    "MCC: Checking descriptor {} ({:?}).",
-                                    descriptor.read().unwrap(),
-                                    descriptor.read().unwrap().attribute_handle
-                                );
+                    Self::respond_to_read(gatts_if, param, &control, value);
+                    return;
+                }
 
-                                if descriptor.read().unwrap().attribute_handle == Some(param.handle)
-                                {
-                                    debug!(
-                                        "Received read event for descriptor {}.",
-                                        descriptor.read().unwrap()
-                                    );
+                let descriptors = characteristic.descriptors.clone();
+                drop(characteristic);
 
-                                    if let AttributeControl::ResponseByApp(callback) =
-                                        &descriptor.read().unwrap().control
-                                    {
-                                        let value = callback(param);
+                for descriptor in descriptors {
+                    let descriptor = descriptor.read().unwrap();
 
-                                        // Extend the response to the maximum length.
-                                        let mut response = [0u8; 600];
-                                        response[..value.len()].copy_from_slice(&value);
+                    if descriptor.attribute_handle == Some(param.handle) {
+                        debug!("Received read event for descriptor {}.", descriptor);
 
-                                        let mut esp_rsp = esp_gatt_rsp_t {
-                                            attr_value: esp_gatt_value_t {
-                                                auth_req: 0,
-                                                handle: param.handle,
-                                                len: value.len() as u16,
-                                                offset: 0,
-                                                value: response,
-                                            },
-                                        };
+                        let control = descriptor.control.clone();
+                        let value = descriptor.value.clone();
+                        drop(descriptor);
 
-                                        unsafe {
-                                            esp_nofail!(esp_ble_gatts_send_response(
-                                                gatts_if,
-                                                param.conn_id,
-                                                param.trans_id,
-                                                esp_gatt_status_t_ESP_GATT_OK,
-                                                &mut esp_rsp
-                                            ));
-                                        }
-                                    }
-                                }
-                            });
+                        Self::respond_to_read(gatts_if, param, &control, value);
+                        return;
                     }
-                });
+                }
+            }
+        }
+    }
+
+    /// Answers a read request, if the attribute is not handled by the stack.
+    ///
+    /// The callback is called again for each part of a long read.
+    /// Each part is as long as the MTU of the connection allows.
+    fn respond_to_read(
+        gatts_if: esp_gatt_if_t,
+        param: esp_ble_gatts_cb_param_t_gatts_read_evt_param,
+        control: &AttributeControl,
+        stored_value: Vec<u8>,
+    ) {
+        if let AttributeControl::ResponseByApp(callback) = control {
+            let result = callback
+                .as_ref()
+                .map_or(Ok(stored_value), |callback| callback(param));
+
+            let mtu = connection_states()
+                .get(&param.conn_id)
+                .map_or(DEFAULT_MTU, |state| state.connection.mtu());
+
+            // Long values are read in parts, starting at increasing offsets.
+            let response = result.as_deref().map_err(|error| *error).and_then(|value| {
+                value
+                    .get(usize::from(param.offset)..)
+                    .map(|part| &part[..part.len().min(usize::from(mtu - 1))])
+                    .ok_or(AttError::InvalidOffset)
+            });
+
+            send_response(
+                gatts_if,
+                param.conn_id,
+                param.trans_id,
+                param.handle,
+                param.offset,
+                response,
+            );
         }
     }
 }
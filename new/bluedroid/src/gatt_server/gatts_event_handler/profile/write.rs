@@ -1,161 +1,206 @@
-use crate::gatt_server::Profile;
-use crate::utilities::AttributeControl;
-use esp_idf_sys::*;
+use super::send_response;
+use crate::gatt_server::{
+    connection_state::connection_states, profile::PreparedWrite, Characteristic, Descriptor,
+    Profile,
+};
+use crate::sys::*;
+use crate::utilities::{AttError, AttributeControl};
 use log::debug;
+use std::sync::{Arc, RwLock};
+
+/// The maximum number of prepared writes queued for each connection.
+const MAX_PREPARED_WRITES: usize = 64;
+
+/// A characteristic or a descriptor, identified by its handle.
+pub(super) enum Attribute {
+    Characteristic(Arc<RwLock<Characteristic>>),
+    Descriptor(Arc<RwLock<Descriptor>>),
+}
+
+impl Attribute {
+    fn control(&self) -> AttributeControl {
+        match self {
+            Self::Characteristic(characteristic) => characteristic.read().unwrap().control.clone(),
+            Self::Descriptor(descriptor) => descriptor.read().unwrap().control.clone(),
+        }
+    }
+
+    /// Returns the maximum length of a value assembled from prepared writes.
+    fn max_length(&self) -> usize {
+        let max_length = match self {
+            Self::Characteristic(characteristic) => {
+                let characteristic = characteristic.read().unwrap();
+
+                // The stack does not limit the values that the application answers for.
+                match characteristic.control {
+                    AttributeControl::ResponseByApp(None) => characteristic.max_length(),
+                    _ => None,
+                }
+            }
+            Self::Descriptor(_) => None,
+        };
+
+        max_length.unwrap_or(ESP_GATT_MAX_ATTR_LEN as usize)
+    }
+
+    /// Calls the write callback, and stores the value if the application answers reads with it.
+    ///
+    /// The locks are released before calling the callbacks, so that they can access the attribute.
+    pub(super) fn write(
+        &self,
+        value: &[u8],
+        param: esp_ble_gatts_cb_param_t_gatts_write_evt_param,
+    ) -> Result<(), AttError> {
+        match self {
+            Self::Characteristic(characteristic) => {
+                let (write_callback, control, max_length) = {
+                    let characteristic = characteristic.read().unwrap();
+                    (
+                        characteristic.write_callback.clone(),
+                        characteristic.control.clone(),
+                        characteristic.max_length(),
+                    )
+                };
+
+                // The stack does not store the values that the application answers for.
+                let stored = matches!(control, AttributeControl::ResponseByApp(None));
+
+                if stored && max_length.map_or(false, |max_length| value.len() > max_length) {
+                    return Err(AttError::InvalidAttributeValueLength);
+                }
+
+                write_callback.map_or(Ok(()), |callback| callback(value.to_vec(), param))?;
+
+                if stored {
+                    value.clone_into(&mut characteristic.write().unwrap().internal_value);
+                }
+            }
+            Self::Descriptor(descriptor) => {
+                let (write_callback, control) = {
+                    let descriptor = descriptor.read().unwrap();
+                    (descriptor.write_callback, descriptor.control.clone())
+                };
+
+                write_callback.map_or(Ok(()), |callback| callback(value.to_vec(), param))?;
+
+                if matches!(control, AttributeControl::ResponseByApp(None)) {
+                    value.clone_into(&mut descriptor.write().unwrap().value);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Attribute {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Characteristic(characteristic) => {
+                write!(f, "characteristic {}", characteristic.read().unwrap())
+            }
+            Self::Descriptor(descriptor) => {
+                write!(f, "descriptor {}", descriptor.read().unwrap())
+            }
+        }
+    }
+}
 
 impl Profile {
-    #[allow(clippy::too_many_lines)]
     pub(crate) fn on_write(
         &mut self,
         gatts_if: esp_gatt_if_t,
         param: esp_ble_gatts_cb_param_t_gatts_write_evt_param,
     ) {
+        let value = unsafe { std::slice::from_raw_parts(param.value, param.len as usize) }.to_vec();
+
+        let Some(attribute) = self.get_attribute(param.handle) else {
+            return;
+        };
+
+        debug!("Received write event for {}.", attribute);
+
+        // The stack stores and answers the writes to the other attributes, including long ones.
+        if !matches!(attribute.control(), AttributeControl::ResponseByApp(_)) {
+            return;
+        }
+
+        let result = if param.is_prep {
+            Self::prepare_write(gatts_if, &attribute, param, &value)
+        } else {
+            attribute.write(&value, param)
+        };
+
+        // Responses to prepared writes echo the received part.
+        if param.need_rsp {
+            send_response(
+                gatts_if,
+                param.conn_id,
+                param.trans_id,
+                param.handle,
+                param.offset,
+                result.map(|()| value.as_slice()),
+            );
+        }
+    }
+
+    /// Queues a part of a long write, until the client executes or cancels it.
+    fn prepare_write(
+        gatts_if: esp_gatt_if_t,
+        attribute: &Attribute,
+        param: esp_ble_gatts_cb_param_t_gatts_write_evt_param,
+        value: &[u8],
+    ) -> Result<(), AttError> {
+        let max_length = attribute.max_length();
+
+        let mut states = connection_states();
+        let Some(state) = states.get_mut(&param.conn_id) else {
+            return Err(AttError::UnlikelyError);
+        };
+
+        if state.prepared_writes.len() >= MAX_PREPARED_WRITES {
+            return Err(AttError::PrepareQueueFull);
+        }
+
+        // Each part must continue or overwrite the value assembled so far.
+        let length = PreparedWrite::assembled_length(&state.prepared_writes, param.handle);
+        if usize::from(param.offset) > length {
+            return Err(AttError::InvalidOffset);
+        }
+
+        if usize::from(param.offset) + value.len() > max_length {
+            return Err(AttError::InvalidAttributeValueLength);
+        }
+
+        state.prepared_writes.push(PreparedWrite {
+            interface: gatts_if,
+            handle: param.handle,
+            offset: param.offset,
+            value: value.to_vec(),
+        });
+
+        Ok(())
+    }
+
+    /// Finds the characteristic or the descriptor with the given handle.
+    pub(super) fn get_attribute(&self, handle: u16) -> Option<Attribute> {
         for service in &self.services {
-            service
-                .read()
-                .unwrap()
-                .characteristics
-                .iter()
-                .for_each(|characteristic| {
-                    if characteristic.read().unwrap().attribute_handle == Some(param.handle) {
-                        debug!(
-                            "Received write event for characteristic {}.",
-                            characteristic.read().unwrap()
-                        );
-
-                        // If the characteristic has a write handler, call it.
-                        if let Some(write_callback) = &characteristic.read().unwrap().write_callback
-                        {
-                            let value = unsafe {
-                                std::slice::from_raw_parts(param.value, param.len as usize)
-                            }
-                            .to_vec();
-
-                            write_callback(value, param);
-
-                            // Send response if needed.
-                            if param.need_rsp {
-                                if let AttributeControl::ResponseByApp(read_callback) =
-                                    &characteristic.read().unwrap().control
-                                {
-                                    // Simulate a read operation.
-                                    let param_as_read_operation =
-                                        esp_ble_gatts_cb_param_t_gatts_read_evt_param {
-                                            bda: param.bda,
-                                            conn_id: param.conn_id,
-                                            handle: param.handle,
-                                            need_rsp: param.need_rsp,
-                                            offset: param.offset,
-                                            trans_id: param.trans_id,
-                                            ..Default::default()
-                                        };
-
-                                    // Get value.
-                                    let value = read_callback(param_as_read_operation);
-
-                                    // Extend the response to the maximum length.
-                                    let mut response = [0u8; 600];
-                                    response[..value.len()].copy_from_slice(&value);
-
-                                    let mut esp_rsp = esp_gatt_rsp_t {
-                                        attr_value: esp_gatt_value_t {
-                                            auth_req: 0,
-                                            handle: param.handle,
-                                            len: value.len() as u16,
-                                            offset: 0,
-                                            value: response,
-                                        },
-                                    };
-
-                                    unsafe {
-                                        esp_nofail!(esp_ble_gatts_send_response(
-                                            gatts_if,
-                                            param.conn_id,
-                                            param.trans_id,
-                                            esp_gatt_status_t_ESP_GATT_OK,
-                                            &mut esp_rsp
-                                        ));
-                                    }
-                                }
-                            }
-                        }
-                    } else {
-                        characteristic
-                            .read()
-                            .unwrap()
-                            .descriptors
-                            .iter()
-                            .for_each(|descriptor| {
-                                if descriptor.read().unwrap().attribute_handle == Some(param.handle)
-                                {
-                                    debug!(
-                                        "Received write event for descriptor {}.",
-                                        descriptor.read().unwrap()
-                                    );
-
-                                    if let Some(write_callback) =
-                                        descriptor.read().unwrap().write_callback
-                                    {
-                                        let value = unsafe {
-                                            std::slice::from_raw_parts(
-                                                param.value,
-                                                param.len as usize,
-                                            )
-                                        }
-                                        .to_vec();
-
-                                        write_callback(value, param);
-
-                                        // Send response if needed.
-                                        if param.need_rsp {
-                                            if let AttributeControl::ResponseByApp(read_callback) =
-                                                &descriptor.read().unwrap().control
-                                            {
-                                                // Simulate a read operation.
-                                                let param_as_read_operation =
-                                                    esp_ble_gatts_cb_param_t_gatts_read_evt_param {
-                                                        bda: param.bda,
-                                                        conn_id: param.conn_id,
-                                                        handle: param.handle,
-                                                        need_rsp: param.need_rsp,
-                                                        offset: param.offset,
-                                                        trans_id: param.trans_id,
-                                                        ..Default::default()
-                                                    };
-
-                                                // Get value.
-                                                let value = read_callback(param_as_read_operation);
-
-                                                // Extend the response to the maximum length.
-                                                let mut response = [0u8; 600];
-                                                response[..value.len()].copy_from_slice(&value);
-
-                                                let mut esp_rsp = esp_gatt_rsp_t {
-                                                    attr_value: esp_gatt_value_t {
-                                                        auth_req: 0,
-                                                        handle: param.handle,
-                                                        len: value.len() as u16,
-                                                        offset: 0,
-                                                        value: response,
-                                                    },
-                                                };
-
-                                                unsafe {
-                                                    esp_nofail!(esp_ble_gatts_send_response(
-                                                        gatts_if,
-                                                        param.conn_id,
-                                                        param.trans_id,
-                                                        esp_gatt_status_t_ESP_GATT_OK,
-                                                        &mut esp_rsp
-                                                    ));
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                            });
+            let characteristics = service.read().unwrap().characteristics.clone();
+
+            for characteristic in characteristics {
+                if characteristic.read().unwrap().attribute_handle == Some(handle) {
+                    return Some(Attribute::Characteristic(characteristic));
+                }
+
+                let descriptors = characteristic.read().unwrap().descriptors.clone();
+                for descriptor in descriptors {
+                    if descriptor.read().unwrap().attribute_handle == Some(handle) {
+                        return Some(Attribute::Descriptor(descriptor));
                     }
-                });
+                }
+            }
         }
+
+        None
     }
 }
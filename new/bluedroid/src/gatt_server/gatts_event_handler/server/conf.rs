@@ -0,0 +1,33 @@
+use crate::gatt_server::{notification::Outcome, GattServer};
+use crate::sys::*;
+use log::{debug, warn};
+
+impl GattServer {
+    pub(crate) fn on_conf(&mut self, param: esp_ble_gatts_cb_param_t_gatts_conf_evt_param) {
+        // Indications are confirmed in the order they were sent.
+        let Some(index) = self
+            .pending_indications
+            .iter()
+            .position(|pending| pending.conn_id == param.conn_id && pending.handle == param.handle)
+        else {
+            debug!(
+                "Received confirmation event for handle 0x{:04x} without a pending indication.",
+                param.handle
+            );
+            return;
+        };
+
+        let pending = self.pending_indications.remove(index);
+
+        if param.status == esp_gatt_status_t_ESP_GATT_OK {
+            debug!("Indication on handle 0x{:04x} confirmed.", param.handle);
+            pending.confirmation.complete(Outcome::Confirmed);
+        } else {
+            warn!(
+                "Indication on handle 0x{:04x} failed, error code: {:04x}.",
+                param.handle, param.status
+            );
+            pending.confirmation.complete(Outcome::Failed(param.status));
+        }
+    }
+}
@@ -1,13 +1,30 @@
-use crate::gatt_server::GattServer;
+use crate::gatt_server::{database, GattServer};
 use crate::utilities::Connection;
 use log::info;
 
 impl GattServer {
-    pub(crate) fn on_connect(
+    pub(crate) fn on_client_connect(
         &mut self,
-        param: esp_idf_sys::esp_ble_gatts_cb_param_t_gatts_connect_evt_param,
+        gatts_if: crate::sys::esp_gatt_if_t,
+        param: crate::sys::esp_ble_gatts_cb_param_t_gatts_connect_evt_param,
     ) {
-        info!("GATT client {} connected.", Connection::from(param));
-        self.active_connections.insert(param.into());
+        let mut connection = Connection::from(param);
+
+        // Bonded clients are recognised by their identity, even with a new private address.
+        if let Some(identity) = Self::bonded_identity(param.remote_bda) {
+            connection.identity = identity;
+            connection.bonded = true;
+        }
+
+        info!(
+            "GATT client {} connected, with identity {:02X?}.",
+            connection, connection.identity
+        );
+
+        self.configure_preferred_parameters();
+        self.on_connection_open(connection);
+        database::on_connect(gatts_if, &connection);
+        self.request_preferred_parameters(&connection);
+        self.on_advertising_connect();
     }
 }
@@ -2,19 +2,18 @@ use crate::gatt_server::GattServer;
 use log::info;
 
 impl GattServer {
-    pub(crate) fn on_disconnect(
+    pub(crate) fn on_client_disconnect(
         &mut self,
-        param: esp_idf_sys::esp_ble_gatts_cb_param_t_gatts_disconnect_evt_param,
+        param: crate::sys::esp_ble_gatts_cb_param_t_gatts_disconnect_evt_param,
     ) {
         info!(
-            "GATT client {:02X?} disconnected.",
-            param.remote_bda.to_vec()
+            "GATT client {:02X?} disconnected with reason 0x{:02x}.",
+            param.remote_bda.to_vec(),
+            param.reason
         );
 
-        self.active_connections.remove(&param.into());
-
-        unsafe {
-            esp_idf_sys::esp_ble_gap_start_advertising(&mut self.advertisement_parameters);
-        }
+        self.on_connection_close(param.conn_id, param.reason);
+        self.fail_pending_indications(Some(param.conn_id));
+        self.on_advertising_disconnect();
     }
 }
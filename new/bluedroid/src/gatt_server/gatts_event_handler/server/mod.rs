@@ -1,6 +1,8 @@
+mod conf;
 mod connect;
 mod disconnect;
 mod mtu;
 mod reg;
 mod response;
+mod service_change;
 mod set_attr_val;
@@ -2,11 +2,14 @@ use crate::gatt_server::GattServer;
 use log::debug;
 
 impl GattServer {
-    #[allow(clippy::unused_self)]
-    pub(crate) fn on_mtu_change(
-        &self,
-        param: esp_idf_sys::esp_ble_gatts_cb_param_t_gatts_mtu_evt_param,
+    pub(crate) fn on_mtu_exchange(
+        &mut self,
+        param: crate::sys::esp_ble_gatts_cb_param_t_gatts_mtu_evt_param,
     ) {
-        debug!("MTU changed to {}.", param.mtu);
+        debug!(
+            "MTU of connection {} changed to {}.",
+            param.conn_id, param.mtu
+        );
+        self.on_connection_mtu(param.conn_id, param.mtu);
     }
 }
@@ -0,0 +1,20 @@
+use crate::gatt_server::GattServer;
+use crate::sys::*;
+use log::{debug, warn};
+
+impl GattServer {
+    #[allow(clippy::unused_self)]
+    pub(crate) fn on_service_change(
+        &self,
+        param: esp_ble_gatts_cb_param_t_gatts_send_service_change_evt_param,
+    ) {
+        if param.status == esp_gatt_status_t_ESP_GATT_OK {
+            debug!("Service Changed characteristic indicated.");
+        } else {
+            warn!(
+                "Cannot indicate the Service Changed characteristic, error code: {:04x}.",
+                param.status
+            );
+        }
+    }
+}
@@ -1,12 +1,11 @@
+use crate::backend::backend;
 use crate::gatt_server::GattServer;
-use crate::utilities::BleUuid;
-use esp_idf_sys::*;
+use crate::sys::*;
 use log::{debug, warn};
 
 impl GattServer {
-    #[allow(clippy::too_many_lines)]
     pub(crate) fn on_set_attr_val(
-        &self,
+        &mut self,
         gatts_if: esp_gatt_if_t,
         param: esp_ble_gatts_cb_param_t_gatts_set_attr_val_evt_param,
     ) {
@@ -27,7 +26,11 @@ impl GattServer {
             return;
         };
 
-        let Some(characteristic) = service.read().unwrap().get_characteristic_by_handle(param.attr_handle) else {
+        let Some(characteristic) = service
+            .read()
+            .unwrap()
+            .get_characteristic_by_handle(param.attr_handle)
+        else {
             warn!("Cannot find characteristic described by service handle {} and attribute handle {} received in set attribute value event.", param.srvc_handle, param.attr_handle);
             return;
         };
@@ -37,98 +40,26 @@ impl GattServer {
             characteristic.read().unwrap()
         );
 
-        for connection in self.active_connections.clone() {
-            // Get the current status of the CCCD via a fake read operation.
-            let simulated_read_param = esp_ble_gatts_cb_param_t_gatts_read_evt_param {
-                bda: connection.remote_bda,
-                conn_id: connection.id,
-                handle: characteristic
-                    .read()
-                    .unwrap()
-                    .descriptors
-                    .iter()
-                    .find(|desc| desc.read().unwrap().uuid == BleUuid::Uuid16(0x2902))
-                    .unwrap()
-                    .read()
-                    .unwrap()
-                    .attribute_handle
-                    .unwrap(),
-                ..Default::default()
-            };
-
-            let status = characteristic
-                .read()
-                .unwrap()
-                .get_cccd_status(simulated_read_param);
-
-            // Check that the status is not None, otherwise bail.
-            let Some((notification, indication)) = status else { return; };
-            let properties = characteristic.read().unwrap().properties;
-
-            let mut internal_value = characteristic.write().unwrap().internal_value.clone();
-
-            if properties.indicate && indication {
-                debug!(
-                    "Indicating {} value change to {:02X?}.",
-                    characteristic.read().unwrap(),
-                    connection.id
-                );
-                let result = unsafe {
-                    esp!(esp_ble_gatts_send_indicate(
-                        gatts_if,
-                        connection.id,
-                        param.attr_handle,
-                        internal_value.len() as u16,
-                        internal_value.as_mut_slice().as_mut_ptr(),
-                        true
-                    ))
-                };
-
-                if result.is_err() {
-                    warn!(
-                        "Failed to indicate value change: {}.",
-                        result.err().unwrap()
-                    );
-                }
-            } else if properties.notify && notification {
-                debug!(
-                    "Notifying {} value change to {}.",
-                    characteristic.read().unwrap(),
-                    connection
-                );
-                let result = unsafe {
-                    esp!(esp_ble_gatts_send_indicate(
-                        gatts_if,
-                        connection.id,
-                        param.attr_handle,
-                        internal_value.len() as u16,
-                        internal_value.as_mut_slice().as_mut_ptr(),
-                        false
-                    ))
-                };
-
-                if result.is_err() {
-                    warn!("Failed to notify value change: {}.", result.err().unwrap());
-                }
+        // Clients subscribed to both are sent an indication.
+        let characteristic = characteristic.read().unwrap();
+        match characteristic.notifier() {
+            Ok(notifier) => {
+                self.notify_subscribers(&notifier, &characteristic.internal_value, true);
             }
+            Err(error) => warn!("Cannot notify {}: {}.", characteristic, error),
         }
 
-        let value: *mut *const u8 = &mut [0u8].as_ptr();
-        let mut len = 512;
-        let vector = unsafe {
-            esp_nofail!(esp_ble_gatts_get_attr_value(
-                param.attr_handle,
-                &mut len,
-                value,
-            ));
-
-            std::slice::from_raw_parts(*value, len as usize)
+        let vector = match backend().get_attr_value(param.attr_handle) {
+            Ok(vector) => vector,
+            Err(error) => {
+                warn!("Cannot get the attribute value: {}.", error);
+                return;
+            }
         };
 
         debug!(
             "Characteristic {} value changed to {:02X?}.",
-            characteristic.read().unwrap(),
-            vector
+            characteristic, vector
         );
     }
 }
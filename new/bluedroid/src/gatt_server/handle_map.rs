@@ -0,0 +1,106 @@
+use std::fmt::Formatter;
+
+use crate::{gatt_server::GattServer, utilities::BleUuid, Error};
+
+/// The attribute handles assigned by the Bluetooth stack, as returned by [`GattServer::handle_map`].
+///
+/// Each attribute is identified by its path: the UUID of its service, followed by the UUIDs of its characteristic
+/// and of its descriptor, if any, separated by slashes. Characteristics are listed with the handles of their values.
+/// Include declarations are identified by their type, `0x2802`, followed by the UUID of the included service.
+///
+/// It is displayed with an attribute per line, in the order of the handles, so that the maps of two firmware versions
+/// can be stored and compared: clients that cached the attributes, and the CCCD values of bonded clients,
+/// only stay valid if the handles are the same.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HandleMap {
+    entries: Vec<(u16, String)>,
+}
+
+impl HandleMap {
+    /// Returns the handle of the attribute with the given path, if it is registered.
+    ///
+    /// If several attributes have the same path, the handle of the first one is returned.
+    #[must_use]
+    pub fn get(&self, path: &str) -> Option<u16> {
+        self.entries
+            .iter()
+            .find(|(_, candidate)| candidate == path)
+            .map(|(handle, _)| *handle)
+    }
+
+    /// Returns the handles and the paths of the attributes, in the order of the handles.
+    pub fn iter(&self) -> impl Iterator<Item = (u16, &str)> {
+        self.entries
+            .iter()
+            .map(|(handle, path)| (*handle, path.as_str()))
+    }
+}
+
+impl std::fmt::Display for HandleMap {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (handle, path) in &self.entries {
+            writeln!(f, "0x{handle:04x} {path}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl GattServer {
+    /// Returns the handles assigned to the registered services, include declarations, characteristics and descriptors.
+    ///
+    /// The attributes that are not registered yet are left out:
+    /// call it once [`GattServer::wait_until_ready`] returns.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a lock is poisoned.
+    pub fn handle_map(&self) -> Result<HandleMap, Error> {
+        let mut entries = Vec::new();
+
+        for profile in &self.profiles {
+            for service in &profile.read()?.services {
+                let service = service.read()?;
+                let Some(handle) = service.handle else {
+                    continue;
+                };
+                entries.push((handle, service.uuid.to_string()));
+
+                for include in &service.includes {
+                    if let Some(handle) = include.handle {
+                        let included = include.service.read()?.uuid;
+                        entries.push((
+                            handle,
+                            format!(
+                                "{}/{}/{}",
+                                service.uuid,
+                                BleUuid::from_uuid16(0x2802),
+                                included
+                            ),
+                        ));
+                    }
+                }
+
+                for characteristic in &service.characteristics {
+                    let characteristic = characteristic.read()?;
+                    let path = format!("{}/{}", service.uuid, characteristic.uuid);
+
+                    for descriptor in &characteristic.descriptors {
+                        let descriptor = descriptor.read()?;
+                        if let Some(handle) = descriptor.attribute_handle {
+                            entries.push((handle, format!("{}/{}", path, descriptor.uuid)));
+                        }
+                    }
+
+                    if let Some(handle) = characteristic.attribute_handle {
+                        entries.push((handle, path));
+                    }
+                }
+            }
+        }
+
+        entries.sort_by_key(|(handle, _)| *handle);
+
+        Ok(HandleMap { entries })
+    }
+}
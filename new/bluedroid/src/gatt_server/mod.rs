@@ -2,192 +2,428 @@
 
 #![allow(clippy::cast_possible_truncation)]
 
-use std::{
-    collections::HashSet,
-    sync::{Arc, Mutex, RwLock},
-};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, MutexGuard, RwLock, Weak};
 
-use esp_idf_sys::*;
+use crate::sys::*;
 use lazy_static::lazy_static;
-use log::{info, warn};
+use log::{debug, warn};
 
 use crate::{
-    leaky_box_raw,
-    utilities::{Appearance, Connection},
+    backend::backend,
+    utilities::{Appearance, PreferredConnectionParameters, DEFAULT_MTU},
+    Error,
 };
 
+use advertising::AdvertisingState;
+use connections::ConnectionCallbacks;
+#[cfg(esp_idf_bt_ble_50_features_supported)]
+use extended_advertising::AdvertisingSetState;
+use notification::PendingIndication;
+use registration::Registration;
+use security::PairingRequest;
+use subscriptions::subscriptions;
+
+pub use advertisement::Advertisement;
+pub use advertising::{AdvertisingConfig, AdvertisingMode, ResumePolicy};
+pub use attribute_table::{AttributeTable, TableEntry};
 pub use characteristic::Characteristic;
+pub use config::GattServerConfig;
 pub use descriptor::Descriptor;
+#[cfg(esp_idf_bt_ble_50_features_supported)]
+pub use extended_advertising::{AdvertisingSet, PeriodicAdvertising};
+pub use handle_map::HandleMap;
+pub use notification::{Confirmation, LongNotificationPolicy};
 pub use profile::Profile;
+pub use security::{IoCapabilities, KeyDistribution, PairingOutcome, SecurityConfig};
 pub use service::Service;
+pub use typed_characteristic::TypedCharacteristic;
 
 // Structs.
+mod advertisement;
+mod advertising;
+mod attribute_table;
 mod characteristic;
+mod config;
+pub(crate) mod connection_state;
+mod connections;
+mod database;
 mod descriptor;
+#[cfg(esp_idf_bt_ble_50_features_supported)]
+mod extended_advertising;
+mod handle_map;
+mod notification;
 mod profile;
+mod registration;
+mod security;
 mod service;
+mod subscriptions;
+mod typed_characteristic;
 
 // Custom stuff.
 mod custom_attributes;
 
+// Declarative macro support.
+#[doc(hidden)]
+pub mod declaration;
+
 // Event handler.
 mod gap_event_handler;
 mod gatts_event_handler;
 
 lazy_static! {
     /// The GATT server singleton.
-    pub static ref GLOBAL_GATT_SERVER: Mutex<GattServer> = Mutex::new(GattServer {
-        profiles: Vec::new(),
-        started: false,
-        advertisement_parameters: esp_ble_adv_params_t {
-            adv_int_min: 0x20,
-            adv_int_max: 0x40,
-            adv_type: esp_ble_adv_type_t_ADV_TYPE_IND,
-            own_addr_type: esp_ble_addr_type_t_BLE_ADDR_TYPE_RPA_PUBLIC,
-            channel_map: esp_ble_adv_channel_t_ADV_CHNL_ALL,
-            adv_filter_policy: esp_ble_adv_filter_t_ADV_FILTER_ALLOW_SCAN_ANY_CON_ANY,
-            ..Default::default()
-        },
-        advertisement_data: esp_ble_adv_data_t {
-            set_scan_rsp: false,
-            include_name: true,
-            include_txpower: true,
-            min_interval: 0x0006,
-            max_interval: 0x0010,
-            appearance: Appearance::GenericUnknown.into(),
-            manufacturer_len: 0,
-            p_manufacturer_data: std::ptr::null_mut(),
-            service_data_len: 0,
-            p_service_data: std::ptr::null_mut(),
-            service_uuid_len: 0,
-            p_service_uuid: std::ptr::null_mut(),
-            flag: (ESP_BLE_ADV_FLAG_GEN_DISC | ESP_BLE_ADV_FLAG_BREDR_NOT_SPT) as u8,
-        },
-        scan_response_data: esp_ble_adv_data_t {
-            set_scan_rsp: true,
-            include_name: false,
-            include_txpower: false,
-            min_interval: 0x0006,
-            max_interval: 0x0010,
-            appearance: Appearance::GenericUnknown.into(),
-            manufacturer_len: 0,
-            p_manufacturer_data: std::ptr::null_mut(),
-            service_data_len: 0,
-            p_service_data: std::ptr::null_mut(),
-            service_uuid_len: 0,
-            p_service_uuid: std::ptr::null_mut(),
-            flag: (ESP_BLE_ADV_FLAG_GEN_DISC | ESP_BLE_ADV_FLAG_BREDR_NOT_SPT) as u8,
-        },
-        advertisement_configured: false,
-        device_name: "ESP32".to_string(),
-        active_connections: HashSet::new(),
-    });
+    ///
+    /// # Notes
+    ///
+    /// This is kept for compatibility with existing code.
+    /// New code should own its server, created with [`GattServer::new`].
+    pub static ref GLOBAL_GATT_SERVER: Arc<Mutex<GattServer>> =
+        GattServer::new(&GattServerConfig::default());
 }
 
+/// The largest ATT MTU supported by the Bluetooth stack.
+const MAX_MTU: u16 = 517;
+
+/// The largest number of attribute handles that the Bluetooth stack can hold.
+const MAX_ATTRIBUTES: usize = CONFIG_BT_GATT_MAX_SR_ATTRIBUTES as usize;
+
+/// A user callback, called once the server is released.
+type DeferredCallback = Box<dyn FnOnce() + Send>;
+
+/// The server that receives the events of the Bluetooth stack.
+static ACTIVE_SERVER: Mutex<Option<Weak<Mutex<GattServer>>>> = Mutex::new(None);
+
 /// Represents a GATT server.
 ///
-/// This is a singleton, and can be accessed via the [`GLOBAL_GATT_SERVER`] static.
+/// Create a server with [`GattServer::new`]. Only one server at a time can be started,
+/// because the Bluetooth stack is a singleton.
 pub struct GattServer {
+    this: Weak<Mutex<GattServer>>,
     profiles: Vec<Arc<RwLock<Profile>>>,
+    registration: Registration,
     started: bool,
-    advertisement_parameters: esp_ble_adv_params_t,
-    advertisement_data: esp_ble_adv_data_t,
-    scan_response_data: esp_ble_adv_data_t,
+    advertising: AdvertisingConfig,
+    advertisement: Advertisement,
     device_name: String,
     advertisement_configured: bool,
-    active_connections: HashSet<Connection>,
+    advertising_state: AdvertisingState,
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    advertising_sets: BTreeMap<u8, AdvertisingSetState>,
+    connection_callbacks: ConnectionCallbacks,
+    preferred_parameters: Option<PreferredConnectionParameters>,
+    published_parameters: Option<PreferredConnectionParameters>,
+    local_mtu: Option<u16>,
+    long_notifications: LongNotificationPolicy,
+    pending_indications: Vec<PendingIndication>,
+    persist_subscriptions: bool,
+    security: SecurityConfig,
+    pairing_requests: BTreeMap<esp_bd_addr_t, PairingRequest>,
+    deferred: Vec<DeferredCallback>,
 }
 
 unsafe impl Send for GattServer {}
 
 impl GattServer {
+    /// Creates a new [`GattServer`] with the given configuration.
+    ///
+    /// The returned server is stopped and freed when the last reference to it is dropped.
+    #[must_use]
+    pub fn new(config: &GattServerConfig) -> Arc<Mutex<Self>> {
+        Arc::new_cyclic(|this| {
+            let mut server = Self {
+                this: this.clone(),
+                profiles: Vec::new(),
+                registration: Registration::default(),
+                started: false,
+                advertising: AdvertisingConfig::default(),
+                advertisement: Advertisement::new().connection_interval(0x0006, 0x0010),
+                advertisement_configured: false,
+                advertising_state: AdvertisingState::default(),
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                advertising_sets: BTreeMap::new(),
+                device_name: String::new(),
+                connection_callbacks: ConnectionCallbacks::default(),
+                preferred_parameters: None,
+                published_parameters: None,
+                local_mtu: config.local_mtu,
+                long_notifications: config.long_notifications,
+                pending_indications: Vec::new(),
+                persist_subscriptions: config.persist_subscriptions,
+                security: SecurityConfig::default(),
+                pairing_requests: BTreeMap::new(),
+                deferred: Vec::new(),
+            };
+
+            server
+                .device_name(config.device_name.clone())
+                .appearance(config.appearance);
+
+            if let Some(parameters) = config.preferred_connection_parameters {
+                server.preferred_connection_parameters(parameters);
+            }
+
+            Mutex::new(server)
+        })
+    }
+
     /// Starts a [`GattServer`].
     ///
-    /// # Panics
+    /// The attributes are registered in the background, one at a time.
+    /// Use [`GattServer::wait_until_ready`] or [`GattServer::on_ready`] to know when they are all registered.
     ///
-    /// Panics if a profile's lock is poisoned.
-    pub fn start(&mut self) {
+    /// # Errors
+    ///
+    /// Returns an error if this or another server is already started, if a profile is invalid,
+    /// if a service includes a service added after it or a secondary service is not included by any service,
+    /// if the services use more attributes than the Bluetooth stack can hold,
+    /// or if the Bluetooth stack cannot be initialised.
+    pub fn start(&mut self) -> Result<(), Error> {
         if self.started {
-            warn!("GATT server already started.");
-            return;
+            return Err(Error::AlreadyStarted);
         }
 
+        for profile in &self.profiles {
+            profile.read()?.validate()?;
+        }
+
+        self.check_includes()?;
+        self.check_attribute_count(0)?;
+        self.security.validate()?;
+        self.advertising.validate()?;
+        self.advertisement.encode()?;
+        if let Some(parameters) = self.preferred_parameters {
+            parameters.validate()?;
+        }
+        if let Some(mtu) = self
+            .local_mtu
+            .filter(|mtu| !(DEFAULT_MTU..=MAX_MTU).contains(mtu))
+        {
+            return Err(Error::InvalidMtu(mtu));
+        }
+
+        {
+            let mut active_server = ACTIVE_SERVER.lock()?;
+            if active_server.as_ref().map_or(false, |server| {
+                server.strong_count() > 0 && !server.ptr_eq(&self.this)
+            }) {
+                return Err(Error::AlreadyStarted);
+            }
+
+            *active_server = Some(self.this.clone());
+        }
+
+        // Drop whatever a previous server left, if it was not stopped cleanly.
+        connection_state::reset();
+        subscriptions().clear();
+
+        if let Err(error) = self.initialise_ble_stack() {
+            self.deactivate();
+            return Err(error);
+        }
+
+        database::check_config();
+        subscriptions::migrate_storage();
         self.started = true;
-        Self::initialise_ble_stack();
+        self.advertising_state.enabled = self.advertising.autostart;
+        subscriptions().set_persistent(self.persist_subscriptions);
 
         // Registration of profiles, services, characteristics and descriptors.
-        self.profiles.iter().for_each(|profile| {
-            profile.write().unwrap().register_self();
-        });
+        self.registration.start(&self.profiles)
     }
 
-    /// Sets the name to be advertised in GAP packets.
+    /// Stops a [`GattServer`] and deinitialises the Bluetooth stack.
     ///
-    /// The name must be set before starting the GATT server.
-    pub fn device_name<S: Into<String>>(&mut self, name: S) -> &mut Self {
-        if self.advertisement_configured {
-            warn!(
-                "Device name already set. Please set the device name before starting the server."
-            );
-            return self;
+    /// The server can be started again afterwards.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server is not started or if the Bluetooth stack cannot be deinitialised.
+    pub fn stop(&mut self) -> Result<(), Error> {
+        if !self.started {
+            return Err(Error::NotStarted);
         }
 
-        self.device_name = name.into();
-        self.device_name.push('\0');
+        self.deactivate();
+        self.started = false;
+        self.advertisement_configured = false;
+        self.published_parameters = None;
+        self.advertising_state.enabled = false;
+        self.advertising_state.active = false;
+        self.advertising_state.generation = self.advertising_state.generation.wrapping_add(1);
+        #[cfg(esp_idf_bt_ble_50_features_supported)]
+        for state in self.advertising_sets.values_mut() {
+            state.enabled = false;
+            state.active = false;
+        }
+        self.registration.stop();
+        connection_state::reset();
+        self.fail_pending_indications(None);
+        self.pairing_requests.clear();
+        subscriptions().clear();
+
+        for profile in &self.profiles {
+            profile.write()?.unregister();
+        }
 
-        self
+        backend().deinitialise()?;
+
+        Ok(())
     }
 
-    /// Sets the device appearance value to be advertised in GAP packets.
-    pub fn appearance(&mut self, appearance: Appearance) -> &mut Self {
-        if self.advertisement_configured {
-            warn!("Appearance already set. Please set the appearance before starting the server.");
-            return self;
+    /// Stops routing the events of the Bluetooth stack to this server.
+    fn deactivate(&self) {
+        let mut active_server = ACTIVE_SERVER
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+
+        if active_server
+            .as_ref()
+            .map_or(false, |server| server.ptr_eq(&self.this))
+        {
+            *active_server = None;
         }
+    }
 
-        self.advertisement_data.appearance = appearance.into();
-        self.scan_response_data.appearance = appearance.into();
+    /// Sets the name to be advertised in GAP packets.
+    ///
+    /// It replaces the local name of the [`Advertisement`].
+    /// If the server is started, the GAP name and the advertised data are updated.
+    pub fn device_name<S: Into<String>>(&mut self, name: S) -> &mut Self {
+        self.device_name = name.into();
+        self.advertisement.set_name(self.device_name.clone());
+        self.device_name.push('\0');
+
+        if self.advertisement_configured {
+            if let Err(error) = backend().set_device_name(self.device_name.trim_end_matches('\0')) {
+                warn!("Cannot set the device name: {}.", error);
+            }
+
+            self.reconfigure_advertisement();
+        }
 
         self
     }
 
-    /// Sets the raw GAP advertisement parameters.
-    pub fn set_adv_params(&mut self, params: esp_ble_adv_params_t) -> &mut Self {
-        self.advertisement_parameters = params;
+    /// Sets the device appearance value to be advertised in GAP packets.
+    ///
+    /// If the server is started, the advertised data is updated.
+    pub fn appearance(&mut self, appearance: Appearance) -> &mut Self {
+        self.advertisement.set_appearance(appearance);
+        self.reconfigure_advertisement();
+
         self
     }
 
-    /// Sets the raw GAP advertisement data.
-    pub fn set_adv_data(&mut self, data: esp_ble_adv_data_t) -> &mut Self {
-        self.advertisement_data = data;
+    /// Sets the content of the advertising data and of the scan response.
+    ///
+    /// The advertisement replaces the one built from the device name, the appearance
+    /// and the advertised services. It is validated when the server starts.
+    /// Use [`GattServer::update_advertisement`] to replace it while the server is running.
+    pub fn advertisement(&mut self, advertisement: Advertisement) -> &mut Self {
+        self.advertisement = advertisement;
+        self.reconfigure_advertisement();
 
         self
     }
 
-    /// Advertises the specified [`Service`] in GAP packets.
+    /// Advertises the UUID of the specified [`Service`] in GAP packets.
+    ///
+    /// Several services can be advertised.
     ///
     /// # Panics
     ///
     /// Panics if the service lock is poisoned.
     pub fn advertise_service(&mut self, service: &Arc<RwLock<Service>>) -> &mut Self {
-        let uuid = service.read().unwrap().uuid.as_uuid128_array();
-        self.scan_response_data.p_service_uuid = leaky_box_raw!(uuid).cast::<u8>();
-        self.scan_response_data.service_uuid_len = uuid.len() as u16;
+        self.advertisement
+            .add_service_uuid(service.read().unwrap().uuid);
+        self.reconfigure_advertisement();
 
         self
     }
 
+    /// Updates the advertised data after a change, if the server is started.
+    fn reconfigure_advertisement(&mut self) {
+        if !self.advertisement_configured {
+            return;
+        }
+
+        if let Err(error) = self.configure_advertisement() {
+            warn!("Cannot update the advertisement: {}.", error);
+        }
+    }
+
     /// Add a [`Profile`] to the GATT server.
+    ///
+    /// If the server is started, the profile is registered after the pending attributes,
+    /// and the clients are told that the attributes changed once it is.
     pub fn profile(&mut self, profile: Arc<RwLock<Profile>>) -> &mut Self {
-        if self.started {
-            warn!("Cannot add profile after server has started.");
+        if !self.started {
+            self.profiles.push(profile);
             return self;
         }
 
-        self.profiles.push(profile);
+        if let Err(error) = self.add_profile(&profile) {
+            warn!("Cannot add profile to the started server: {}.", error);
+        }
+
         self
     }
 
+    /// Checks and registers a profile added to the started server.
+    fn add_profile(&mut self, profile: &Arc<RwLock<Profile>>) -> Result<(), Error> {
+        {
+            let profile = profile.read()?;
+            profile.validate()?;
+            self.check_attribute_count(profile.handle_count()?)?;
+        }
+
+        self.profiles.push(profile.clone());
+        if let Err(error) = self.check_includes() {
+            self.profiles.pop();
+            return Err(error);
+        }
+
+        self.registration.add_profile(profile)
+    }
+
+    /// Returns the number of attribute handles used by the services of all the profiles.
+    ///
+    /// The Bluetooth stack holds at most `CONFIG_BT_GATT_MAX_SR_ATTRIBUTES` handles,
+    /// and starting the server fails if the services need more.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a lock is poisoned.
+    pub fn attribute_count(&self) -> Result<usize, Error> {
+        let mut count = 0;
+
+        for profile in &self.profiles {
+            count += profile.read()?.handle_count()?;
+        }
+
+        Ok(count)
+    }
+
+    /// Checks that the Bluetooth stack can hold the attributes of the services,
+    /// with the given number of handles added.
+    fn check_attribute_count(&self, added: usize) -> Result<(), Error> {
+        let attribute_count = self.attribute_count()? + added;
+        debug!(
+            "The services use {} of {} attribute handles.",
+            attribute_count, MAX_ATTRIBUTES
+        );
+
+        if attribute_count > MAX_ATTRIBUTES {
+            return Err(Error::TooManyAttributes {
+                count: attribute_count,
+                max_count: MAX_ATTRIBUTES,
+            });
+        }
+
+        Ok(())
+    }
+
     pub(crate) fn get_profile(&self, interface: u8) -> Option<Arc<RwLock<Profile>>> {
         self.profiles
             .iter()
@@ -195,197 +431,91 @@ impl GattServer {
             .cloned()
     }
 
-    #[allow(clippy::too_many_lines)]
-    fn initialise_ble_stack() {
-        info!("Initialising BLE stack.");
-
-        // NVS initialisation.
-        unsafe {
-            let result = nvs_flash_init();
-            if result == ESP_ERR_NVS_NO_FREE_PAGES || result == ESP_ERR_NVS_NEW_VERSION_FOUND {
-                warn!("NVS initialisation failed. Erasing NVS.");
-                esp_nofail!(nvs_flash_erase());
-                esp_nofail!(nvs_flash_init());
-            }
-        }
+    fn initialise_ble_stack(&self) -> Result<(), Error> {
+        backend().initialise(
+            Some(Self::default_gatts_callback),
+            Some(Self::default_gap_callback),
+        )?;
 
-        #[cfg(esp32)]
-        let default_controller_configuration = esp_bt_controller_config_t {
-            controller_task_stack_size: ESP_TASK_BT_CONTROLLER_STACK as _,
-            controller_task_prio: ESP_TASK_BT_CONTROLLER_PRIO as _,
-            hci_uart_no: BT_HCI_UART_NO_DEFAULT as _,
-            hci_uart_baudrate: BT_HCI_UART_BAUDRATE_DEFAULT,
-            scan_duplicate_mode: SCAN_DUPLICATE_MODE as _,
-            scan_duplicate_type: SCAN_DUPLICATE_TYPE_VALUE as _,
-            normal_adv_size: NORMAL_SCAN_DUPLICATE_CACHE_SIZE as _,
-            mesh_adv_size: MESH_DUPLICATE_SCAN_CACHE_SIZE as _,
-            send_adv_reserved_size: SCAN_SEND_ADV_RESERVED_SIZE as _,
-            controller_debug_flag: CONTROLLER_ADV_LOST_DEBUG_BIT,
-            mode: esp_bt_mode_t_ESP_BT_MODE_BLE as _,
-            ble_max_conn: CONFIG_BTDM_CTRL_BLE_MAX_CONN_EFF as _,
-            bt_max_acl_conn: CONFIG_BTDM_CTRL_BR_EDR_MAX_ACL_CONN_EFF as _,
-            bt_sco_datapath: CONFIG_BTDM_CTRL_BR_EDR_SCO_DATA_PATH_EFF as _,
-            auto_latency: BTDM_CTRL_AUTO_LATENCY_EFF != 0,
-            bt_legacy_auth_vs_evt: BTDM_CTRL_LEGACY_AUTH_VENDOR_EVT_EFF != 0,
-            bt_max_sync_conn: CONFIG_BTDM_CTRL_BR_EDR_MAX_SYNC_CONN_EFF as _,
-            ble_sca: CONFIG_BTDM_BLE_SLEEP_CLOCK_ACCURACY_INDEX_EFF as _,
-            pcm_role: CONFIG_BTDM_CTRL_PCM_ROLE_EFF as _,
-            pcm_polar: CONFIG_BTDM_CTRL_PCM_POLAR_EFF as _,
-            hli: BTDM_CTRL_HLI != 0,
-            magic: ESP_BT_CONTROLLER_CONFIG_MAGIC_VAL,
-            #[cfg(any(esp_idf_version = "5.0", esp_idf_version = "5.1"))]
-            dup_list_refresh_period: SCAN_DUPL_CACHE_REFRESH_PERIOD as u16,
-        };
+        let configured = self.security.apply().and_then(|()| {
+            self.local_mtu
+                .map_or(Ok(()), |mtu| Ok(backend().set_local_mtu(mtu)?))
+        });
 
-        #[cfg(esp32c3)]
-        let default_controller_configuration = esp_bt_controller_config_t {
-            magic: ESP_BT_CTRL_CONFIG_MAGIC_VAL,
-            version: ESP_BT_CTRL_CONFIG_VERSION,
-            controller_task_stack_size: ESP_TASK_BT_CONTROLLER_STACK as u16,
-            controller_task_prio: ESP_TASK_BT_CONTROLLER_PRIO as u8,
-            controller_task_run_cpu: CONFIG_BT_CTRL_PINNED_TO_CORE as u8,
-            bluetooth_mode: CONFIG_BT_CTRL_MODE_EFF as u8,
-            ble_max_act: CONFIG_BT_CTRL_BLE_MAX_ACT_EFF as u8,
-            sleep_mode: CONFIG_BT_CTRL_SLEEP_MODE_EFF as u8,
-            sleep_clock: CONFIG_BT_CTRL_SLEEP_CLOCK_EFF as u8,
-            ble_st_acl_tx_buf_nb: CONFIG_BT_CTRL_BLE_STATIC_ACL_TX_BUF_NB as u8,
-            ble_hw_cca_check: CONFIG_BT_CTRL_HW_CCA_EFF as u8,
-            ble_adv_dup_filt_max: CONFIG_BT_CTRL_ADV_DUP_FILT_MAX as u16,
-            coex_param_en: false,
-            ce_len_type: CONFIG_BT_CTRL_CE_LENGTH_TYPE_EFF as u8,
-            coex_use_hooks: false,
-            hci_tl_type: CONFIG_BT_CTRL_HCI_TL_EFF as u8,
-            hci_tl_funcs: std::ptr::null_mut(),
-            txant_dft: CONFIG_BT_CTRL_TX_ANTENNA_INDEX_EFF as u8,
-            rxant_dft: CONFIG_BT_CTRL_RX_ANTENNA_INDEX_EFF as u8,
-            txpwr_dft: CONFIG_BT_CTRL_DFT_TX_POWER_LEVEL_EFF as u8,
-            #[cfg(any(esp_idf_version = "5.1"))]
-            cfg_mask: CFG_MASK,
-            #[cfg(any(
-                esp_idf_version_full = "4.4.3",
-                esp_idf_version_full = "4.4.4",
-                esp_idf_version = "5.0"
-            ))]
-            cfg_mask: CFG_NASK,
-            scan_duplicate_mode: SCAN_DUPLICATE_MODE as u8,
-            scan_duplicate_type: SCAN_DUPLICATE_TYPE_VALUE as u8,
-            normal_adv_size: NORMAL_SCAN_DUPLICATE_CACHE_SIZE as u16,
-            mesh_adv_size: MESH_DUPLICATE_SCAN_CACHE_SIZE as u16,
-            coex_phy_coded_tx_rx_time_limit: CONFIG_BT_CTRL_COEX_PHY_CODED_TX_RX_TLIM_EFF as u8,
-            #[cfg(any(
-                esp_idf_version_full = "4.4.3",
-                esp_idf_version_full = "4.4.4",
-                esp_idf_version = "5.0"
-            ))]
-            hw_target_code: BLE_HW_TARGET_CODE_ESP32C3_CHIP_ECO0,
-            #[cfg(any(esp_idf_version = "5.1"))]
-            hw_target_code: BLE_HW_TARGET_CODE_CHIP_ECO0,
-            slave_ce_len_min: SLAVE_CE_LEN_MIN_DEFAULT as u8,
-            hw_recorrect_en: AGC_RECORRECT_EN as u8,
-            cca_thresh: CONFIG_BT_CTRL_HW_CCA_VAL as u8,
-            #[cfg(any(
-                esp_idf_version_full = "4.4.4",
-                esp_idf_version = "5.0",
-                esp_idf_version = "5.1"
-            ))]
-            scan_backoff_upperlimitmax: BT_CTRL_SCAN_BACKOFF_UPPERLIMITMAX as u16,
-            #[cfg(any(esp_idf_version = "5.0", esp_idf_version = "5.1"))]
-            dup_list_refresh_period: DUPL_SCAN_CACHE_REFRESH_PERIOD as u16,
-            #[cfg(any(esp_idf_version = "5.1"))]
-            ble_50_feat_supp: BT_CTRL_50_FEATURE_SUPPORT != 0,
-        };
+        if let Err(error) = configured {
+            if let Err(error) = backend().deinitialise() {
+                warn!("Cannot deinitialise the Bluetooth stack: {}.", error);
+            }
 
-        #[cfg(esp32s3)]
-        let default_controller_configuration = esp_bt_controller_config_t {
-            magic: ESP_BT_CTRL_CONFIG_MAGIC_VAL,
-            version: ESP_BT_CTRL_CONFIG_VERSION,
-            controller_task_stack_size: ESP_TASK_BT_CONTROLLER_STACK as u16,
-            controller_task_prio: ESP_TASK_BT_CONTROLLER_PRIO as u8,
-            controller_task_run_cpu: CONFIG_BT_CTRL_PINNED_TO_CORE as u8,
-            bluetooth_mode: CONFIG_BT_CTRL_MODE_EFF as u8,
-            ble_max_act: CONFIG_BT_CTRL_BLE_MAX_ACT_EFF as u8,
-            sleep_mode: CONFIG_BT_CTRL_SLEEP_MODE_EFF as u8,
-            sleep_clock: CONFIG_BT_CTRL_SLEEP_CLOCK_EFF as u8,
-            ble_st_acl_tx_buf_nb: CONFIG_BT_CTRL_BLE_STATIC_ACL_TX_BUF_NB as u8,
-            ble_hw_cca_check: CONFIG_BT_CTRL_HW_CCA_EFF as u8,
-            ble_adv_dup_filt_max: CONFIG_BT_CTRL_ADV_DUP_FILT_MAX as u16,
-            coex_param_en: false,
-            ce_len_type: CONFIG_BT_CTRL_CE_LENGTH_TYPE_EFF as u8,
-            coex_use_hooks: false,
-            hci_tl_type: CONFIG_BT_CTRL_HCI_TL_EFF as u8,
-            hci_tl_funcs: std::ptr::null_mut(),
-            txant_dft: CONFIG_BT_CTRL_TX_ANTENNA_INDEX_EFF as u8,
-            rxant_dft: CONFIG_BT_CTRL_RX_ANTENNA_INDEX_EFF as u8,
-            txpwr_dft: CONFIG_BT_CTRL_DFT_TX_POWER_LEVEL_EFF as u8,
-            cfg_mask: CFG_MASK,
-            scan_duplicate_mode: SCAN_DUPLICATE_MODE as u8,
-            scan_duplicate_type: SCAN_DUPLICATE_TYPE_VALUE as u8,
-            normal_adv_size: NORMAL_SCAN_DUPLICATE_CACHE_SIZE as u16,
-            mesh_adv_size: MESH_DUPLICATE_SCAN_CACHE_SIZE as u16,
-            coex_phy_coded_tx_rx_time_limit: CONFIG_BT_CTRL_COEX_PHY_CODED_TX_RX_TLIM_EFF as u8,
-
-            #[cfg(any(esp_idf_version = "4.4", esp_idf_version = "5.0"))]
-            hw_target_code: BLE_HW_TARGET_CODE_ESP32S3_CHIP_ECO0,
-            #[cfg(esp_idf_version = "5.1")]
-            hw_target_code: BLE_HW_TARGET_CODE_CHIP_ECO0,
-            slave_ce_len_min: SLAVE_CE_LEN_MIN_DEFAULT as u8,
-            hw_recorrect_en: AGC_RECORRECT_EN as u8,
-            cca_thresh: CONFIG_BT_CTRL_HW_CCA_VAL as u8,
-            #[cfg(any(
-                esp_idf_version_full = "4.4.4",
-                esp_idf_version = "5.0",
-                esp_idf_version = "5.1"
-            ))]
-            scan_backoff_upperlimitmax: BT_CTRL_SCAN_BACKOFF_UPPERLIMITMAX as u16,
-            #[cfg(any(esp_idf_version = "5.0", esp_idf_version = "5.1"))]
-            dup_list_refresh_period: DUPL_SCAN_CACHE_REFRESH_PERIOD as u16,
-            #[cfg(any(esp_idf_version = "5.1"))]
-            ble_50_feat_supp: EXT_CSD_SEC_FEATURE_SUPPORT != 0,
-        };
-        // BLE controller initialisation.
-        unsafe {
-            esp_nofail!(esp_bt_controller_mem_release(
-                esp_bt_mode_t_ESP_BT_MODE_CLASSIC_BT
-            ));
-            esp_nofail!(esp_bt_controller_init(leaky_box_raw!(
-                default_controller_configuration
-            )));
-            esp_nofail!(esp_bt_controller_enable(esp_bt_mode_t_ESP_BT_MODE_BLE));
-            esp_nofail!(esp_bluedroid_init());
-            esp_nofail!(esp_bluedroid_enable());
-            esp_nofail!(esp_ble_gatts_register_callback(Some(
-                Self::default_gatts_callback
-            )));
-            esp_nofail!(esp_ble_gap_register_callback(Some(
-                Self::default_gap_callback
-            )));
+            return Err(error);
         }
+
+        Ok(())
     }
 
-    /// Calls the global server's GATT event callback.
-    ///
-    /// This is a bad workaround, and only works because we have a singleton server.
+    /// Returns the server that receives the events of the Bluetooth stack.
+    fn active_server() -> Option<Arc<Mutex<Self>>> {
+        ACTIVE_SERVER
+            .lock()
+            .expect("Cannot lock the active GATT server.")
+            .as_ref()
+            .and_then(Weak::upgrade)
+    }
+
+    /// Forwards a GATT event to the active server.
     extern "C" fn default_gatts_callback(
         event: esp_gatts_cb_event_t,
         gatts_if: esp_gatt_if_t,
         param: *mut esp_ble_gatts_cb_param_t,
     ) {
-        GLOBAL_GATT_SERVER
-            .lock()
-            .expect("Cannot lock global GATT server.")
-            .gatts_event_handler(event, gatts_if, param);
+        let Some(server) = Self::active_server() else {
+            warn!("Received GATT event {} without a running server.", event);
+            return;
+        };
+
+        Self::gatts_event_handler(&server, event, gatts_if, param);
     }
 
-    /// Calls the global server's GAP event callback.
-    ///
-    /// This is a bad workaround, and only works because we have a singleton server.
+    /// Forwards a GAP event to the active server.
     extern "C" fn default_gap_callback(
         event: esp_gap_ble_cb_event_t,
         param: *mut esp_ble_gap_cb_param_t,
     ) {
-        GLOBAL_GATT_SERVER
-            .lock()
-            .expect("Cannot lock global GATT server.")
-            .gap_event_handler(event, param);
+        let Some(server) = Self::active_server() else {
+            warn!("Received GAP event {} without a running server.", event);
+            return;
+        };
+
+        // The state is updated with the server locked, the user callbacks are called once it is released.
+        let mut guard = server.lock().expect("Cannot lock GATT server.");
+        guard.gap_event_handler(event, param);
+        Self::release(guard);
+    }
+
+    /// Queues a user callback, that is called once the event handler releases the server.
+    ///
+    /// User callbacks can access the server, so they are never called with the server locked.
+    pub(crate) fn defer(&mut self, callback: impl FnOnce() + Send + 'static) {
+        self.deferred.push(Box::new(callback));
+    }
+
+    /// Releases the server, then calls the user callbacks queued while handling an event.
+    pub(crate) fn release(mut server: MutexGuard<'_, Self>) {
+        let deferred = std::mem::take(&mut server.deferred);
+        drop(server);
+
+        for callback in deferred {
+            callback();
+        }
+    }
+}
+
+impl Drop for GattServer {
+    fn drop(&mut self) {
+        if self.started {
+            if let Err(error) = self.stop() {
+                warn!("Cannot stop the GATT server: {}.", error);
+            }
+        }
     }
 }
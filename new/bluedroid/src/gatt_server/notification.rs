@@ -0,0 +1,410 @@
+use std::{
+    sync::{Arc, Condvar, Mutex, RwLock},
+    time::Duration,
+};
+
+use crate::{
+    backend::backend,
+    gatt_server::{connection_state::connection_states, Characteristic, Descriptor, GattServer},
+    sys::{esp_ble_gatts_cb_param_t_gatts_read_evt_param, esp_gatt_if_t, esp_gatt_status_t},
+    utilities::{AttributeControl, BleUuid, CharacteristicProperties, Connection},
+    Error,
+};
+use log::{debug, warn};
+
+/// The state of an indication.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Outcome {
+    Pending,
+    Confirmed,
+    Failed(esp_gatt_status_t),
+    Disconnected,
+}
+
+/// The outcome of an indication, and the number of its parts that are not confirmed yet.
+#[derive(Debug)]
+struct Progress {
+    outcome: Outcome,
+    unconfirmed: usize,
+}
+
+/// The confirmation of an indication, sent with [`Characteristic::indicate_to`].
+///
+/// An indication fragmented with [`LongNotificationPolicy::Fragment`] is confirmed
+/// once the client confirms all its parts, and fails as soon as one of them fails.
+/// Clones refer to the same indication.
+#[derive(Debug, Clone)]
+pub struct Confirmation {
+    progress: Arc<(Mutex<Progress>, Condvar)>,
+}
+
+impl Confirmation {
+    fn new(parts: usize) -> Self {
+        Self {
+            progress: Arc::new((
+                Mutex::new(Progress {
+                    outcome: Outcome::Pending,
+                    unconfirmed: parts,
+                }),
+                Condvar::new(),
+            )),
+        }
+    }
+
+    /// Completes a part of the indication.
+    pub(crate) fn complete(&self, outcome: Outcome) {
+        let (lock, signal) = &*self.progress;
+        let mut progress = lock.lock().unwrap();
+
+        if progress.outcome != Outcome::Pending {
+            return;
+        }
+
+        if outcome == Outcome::Confirmed {
+            progress.unconfirmed = progress.unconfirmed.saturating_sub(1);
+            if progress.unconfirmed > 0 {
+                return;
+            }
+        }
+
+        progress.outcome = outcome;
+        signal.notify_all();
+    }
+
+    /// Returns whether the client has neither confirmed the indication yet, nor disconnected.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the confirmation lock is poisoned.
+    #[must_use]
+    pub fn is_pending(&self) -> bool {
+        self.progress.0.lock().unwrap().outcome == Outcome::Pending
+    }
+
+    /// Blocks until the client confirms the indication, or until the timeout expires.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::IndicationTimeout`] if the client does not confirm the indication in time,
+    /// [`Error::NotConnected`] if the client disconnects before confirming it,
+    /// or [`Error::IndicationFailed`] if the stack cannot deliver it.
+    ///
+    /// # Notes
+    ///
+    /// Confirmations are received from the Bluetooth stack's context:
+    /// waiting for them from a callback always times out.
+    pub fn wait(&self, timeout: Duration) -> Result<(), Error> {
+        let (lock, signal) = &*self.progress;
+        let (progress, _) = signal.wait_timeout_while(lock.lock()?, timeout, |progress| {
+            progress.outcome == Outcome::Pending
+        })?;
+
+        match progress.outcome {
+            Outcome::Pending => Err(Error::IndicationTimeout),
+            Outcome::Confirmed => Ok(()),
+            Outcome::Failed(status) => Err(Error::IndicationFailed(status)),
+            Outcome::Disconnected => Err(Error::NotConnected),
+        }
+    }
+}
+
+/// What to do with values that do not fit in a notification or an indication.
+///
+/// A notification carries at most the MTU of the connection minus 3 bytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LongNotificationPolicy {
+    /// Reject the value with [`Error::ValueTooLong`].
+    #[default]
+    Reject,
+    /// Send the value in several notifications or indications, each as long as the MTU allows.
+    ///
+    /// The client must reassemble the parts. The [`Confirmation`] of an indication covers all of them.
+    Fragment,
+}
+
+/// An indication waiting for the confirmation of a client.
+#[derive(Debug)]
+pub(crate) struct PendingIndication {
+    pub(crate) conn_id: u16,
+    pub(crate) handle: u16,
+    pub(crate) confirmation: Confirmation,
+}
+
+/// What a notification or an indication needs from a [`Characteristic`].
+///
+/// It is copied out of the characteristic, so that the characteristic is not locked while the server is:
+/// the server locks characteristics while handling events.
+pub(crate) struct Notifier {
+    interface: esp_gatt_if_t,
+    handle: u16,
+    properties: CharacteristicProperties,
+    cccd: Option<Arc<RwLock<Descriptor>>>,
+    description: String,
+}
+
+impl Notifier {
+    /// Sends a notification to all the clients subscribed to notifications.
+    pub(crate) fn notify(&self, value: &[u8]) -> Result<(), Error> {
+        GattServer::running()?
+            .lock()?
+            .notify_subscribers(self, value, false);
+
+        Ok(())
+    }
+
+    /// Sends a notification or an indication to a subscribed client.
+    pub(crate) fn send_to(
+        &self,
+        conn_id: u16,
+        value: &[u8],
+        indicate: bool,
+    ) -> Result<Confirmation, Error> {
+        let server = GattServer::running()?;
+        let mut server = server.lock()?;
+        let connection = server.connection(conn_id)?;
+        let (notification, indication) = self.subscription(&connection);
+
+        if !(if indicate { indication } else { notification }) {
+            return Err(Error::NotSubscribed);
+        }
+
+        server.send_indication(self, conn_id, value, indicate)
+    }
+
+    /// Returns whether a client is subscribed to notifications and to indications.
+    fn subscription(&self, connection: &Connection) -> (bool, bool) {
+        self.cccd_status(connection)
+            .map_or((false, false), |(notification, indication)| {
+                (
+                    self.properties.notify && notification,
+                    self.properties.indicate && indication,
+                )
+            })
+    }
+
+    /// Returns whether a client enabled notifications and indications, if the characteristic has a CCCD.
+    fn cccd_status(&self, connection: &Connection) -> Option<(bool, bool)> {
+        let cccd = self.cccd.as_ref()?.read().unwrap();
+
+        if let AttributeControl::ResponseByApp(Some(callback)) = &cccd.control {
+            // Get the current status of the CCCD via a fake read operation.
+            let value = callback(esp_ble_gatts_cb_param_t_gatts_read_evt_param {
+                bda: connection.remote_bda,
+                conn_id: connection.id,
+                handle: cccd.attribute_handle?,
+                ..Default::default()
+            })
+            .ok()?;
+
+            return Some((
+                value[0] & 0b0000_0001 == 0b0000_0001,
+                value[0] & 0b0000_0010 == 0b0000_0010,
+            ));
+        }
+
+        None
+    }
+}
+
+impl std::fmt::Display for Notifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.description)
+    }
+}
+
+impl Characteristic {
+    /// Sends a notification with the given value to all the clients subscribed to notifications.
+    ///
+    /// The value of the characteristic is not changed,
+    /// see [`Self::set_value`] to change it and notify the subscribed clients.
+    /// Failures to notify a single client are logged.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server is not started, or if the characteristic is not registered.
+    ///
+    /// # Notes
+    ///
+    /// The server is locked while sending, and it locks characteristics while handling events:
+    /// calling this method through a lock on the characteristic can deadlock
+    /// with a concurrent [`Self::set_value`]. [`TypedCharacteristic::notify`] releases the characteristic first.
+    ///
+    /// [`TypedCharacteristic::notify`]: crate::gatt_server::TypedCharacteristic::notify
+    pub fn notify<T: Into<Vec<u8>>>(&self, value: T) -> Result<(), Error> {
+        self.notifier()?.notify(&value.into())
+    }
+
+    /// Sends a notification with the given value to a client.
+    ///
+    /// The value of the characteristic is not changed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server is not started, if the characteristic is not registered,
+    /// if the client is not connected or not subscribed to notifications,
+    /// if the value does not fit in the MTU of the connection and is not fragmented,
+    /// or if the Bluetooth stack cannot send the notification.
+    ///
+    /// # Notes
+    ///
+    /// Like [`Self::notify`], this method must not be called through a lock on the characteristic
+    /// while other threads can change its value: see [`TypedCharacteristic::notify_to`].
+    ///
+    /// [`TypedCharacteristic::notify_to`]: crate::gatt_server::TypedCharacteristic::notify_to
+    pub fn notify_to<T: Into<Vec<u8>>>(&self, conn_id: u16, value: T) -> Result<(), Error> {
+        self.notifier()?.send_to(conn_id, &value.into(), false)?;
+        Ok(())
+    }
+
+    /// Sends an indication with the given value to a client.
+    ///
+    /// The value of the characteristic is not changed.
+    /// The returned [`Confirmation`] tells when the client confirms the indication.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server is not started, if the characteristic is not registered,
+    /// if the client is not connected or not subscribed to indications,
+    /// if the value does not fit in the MTU of the connection and is not fragmented,
+    /// or if the Bluetooth stack cannot send the indication.
+    ///
+    /// # Notes
+    ///
+    /// Like [`Self::notify`], this method must not be called through a lock on the characteristic
+    /// while other threads can change its value: see [`TypedCharacteristic::indicate_to`].
+    ///
+    /// [`TypedCharacteristic::indicate_to`]: crate::gatt_server::TypedCharacteristic::indicate_to
+    pub fn indicate_to<T: Into<Vec<u8>>>(
+        &self,
+        conn_id: u16,
+        value: T,
+    ) -> Result<Confirmation, Error> {
+        self.notifier()?.send_to(conn_id, &value.into(), true)
+    }
+
+    /// Copies what notifications and indications need out of the characteristic.
+    ///
+    /// Returns [`Error::NotRegistered`] if the characteristic is not registered.
+    pub(crate) fn notifier(&self) -> Result<Notifier, Error> {
+        let (Some(interface), Some(handle)) = (self.interface, self.attribute_handle) else {
+            return Err(Error::NotRegistered);
+        };
+
+        Ok(Notifier {
+            interface,
+            handle,
+            properties: self.properties,
+            cccd: self
+                .descriptors
+                .iter()
+                .find(|descriptor| descriptor.read().unwrap().uuid == BleUuid::Uuid16(0x2902))
+                .cloned(),
+            description: self.to_string(),
+        })
+    }
+}
+
+impl GattServer {
+    /// Returns the started server.
+    fn running() -> Result<Arc<Mutex<Self>>, Error> {
+        Self::active_server().ok_or(Error::NotStarted)
+    }
+
+    #[allow(clippy::unused_self)]
+    fn connection(&self, conn_id: u16) -> Result<Connection, Error> {
+        connection_states()
+            .get(&conn_id)
+            .map(|state| state.connection)
+            .ok_or(Error::NotConnected)
+    }
+
+    /// Sends a value to all the subscribed clients.
+    ///
+    /// Clients subscribed to both are sent an indication if `indications` is set, otherwise a notification.
+    pub(crate) fn notify_subscribers(
+        &mut self,
+        characteristic: &Notifier,
+        value: &[u8],
+        indications: bool,
+    ) {
+        for connection in self.connections() {
+            let (notification, indication) = characteristic.subscription(&connection);
+            let indicate = indications && indication;
+
+            if !indicate && !notification {
+                continue;
+            }
+
+            debug!(
+                "{} {} value change to {}.",
+                if indicate { "Indicating" } else { "Notifying" },
+                characteristic,
+                connection
+            );
+
+            if let Err(error) = self.send_indication(characteristic, connection.id, value, indicate)
+            {
+                warn!("Cannot send the value of {}: {}.", characteristic, error);
+            }
+        }
+    }
+
+    /// Sends a notification or an indication, and tracks the confirmation of indications.
+    ///
+    /// Values that do not fit in the MTU of the connection are rejected or fragmented,
+    /// depending on the [`LongNotificationPolicy`]. The returned confirmation covers all the parts.
+    fn send_indication(
+        &mut self,
+        characteristic: &Notifier,
+        conn_id: u16,
+        value: &[u8],
+        indicate: bool,
+    ) -> Result<Confirmation, Error> {
+        let (interface, handle) = (characteristic.interface, characteristic.handle);
+        let max_length = usize::from(self.connection(conn_id)?.mtu - 3);
+
+        if value.len() > max_length && self.long_notifications == LongNotificationPolicy::Reject {
+            return Err(Error::ValueTooLong {
+                length: value.len(),
+                max_length,
+            });
+        }
+
+        // Empty values are sent once.
+        let parts: Vec<&[u8]> = value
+            .chunks(max_length)
+            .chain(value.is_empty().then_some(value))
+            .collect();
+        let confirmation = Confirmation::new(parts.len());
+
+        for part in parts {
+            backend().send_indicate(interface, conn_id, handle, part, indicate)?;
+
+            // The server is locked: the confirmation cannot be received before it is tracked.
+            if indicate {
+                self.pending_indications.push(PendingIndication {
+                    conn_id,
+                    handle,
+                    confirmation: confirmation.clone(),
+                });
+            } else {
+                confirmation.complete(Outcome::Confirmed);
+            }
+        }
+
+        Ok(confirmation)
+    }
+
+    /// Completes the indications waiting for the confirmation of a client, or of all the clients.
+    pub(crate) fn fail_pending_indications(&mut self, conn_id: Option<u16>) {
+        self.pending_indications.retain(|pending| {
+            let failed = conn_id.map_or(true, |conn_id| pending.conn_id == conn_id);
+
+            if failed {
+                pending.confirmation.complete(Outcome::Disconnected);
+            }
+
+            !failed
+        });
+    }
+}
@@ -1,7 +1,9 @@
 use std::sync::{Arc, RwLock};
 
+use crate::backend::backend;
 use crate::gatt_server::service::Service;
-use esp_idf_sys::*;
+use crate::sys::*;
+use crate::Error;
 use log::debug;
 
 /// Represents a GATT profile.
@@ -20,6 +22,55 @@ pub struct Profile {
     pub(crate) interface: Option<u8>,
 }
 
+/// A part of a long write, queued until the client executes or cancels it.
+#[derive(Debug, Clone)]
+pub(crate) struct PreparedWrite {
+    /// The interface of the profile that owns the attribute.
+    pub(crate) interface: esp_gatt_if_t,
+    pub(crate) handle: u16,
+    pub(crate) offset: u16,
+    pub(crate) value: Vec<u8>,
+}
+
+impl PreparedWrite {
+    /// Returns the length of the value assembled so far for an attribute.
+    pub(crate) fn assembled_length(queue: &[Self], handle: u16) -> usize {
+        queue
+            .iter()
+            .filter(|write| write.handle == handle)
+            .map(|write| usize::from(write.offset) + write.value.len())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Assembles the queued parts into the value of each attribute, in the order they were first written.
+    pub(crate) fn assemble(queue: Vec<Self>) -> Vec<(u16, Vec<u8>)> {
+        let mut values: Vec<(u16, Vec<u8>)> = Vec::new();
+
+        for write in queue {
+            let index = values
+                .iter()
+                .position(|(handle, _)| *handle == write.handle)
+                .unwrap_or_else(|| {
+                    values.push((write.handle, Vec::new()));
+                    values.len() - 1
+                });
+
+            let value = &mut values[index].1;
+            let start = usize::from(write.offset);
+            let end = start + write.value.len();
+
+            if value.len() < end {
+                value.resize(end, 0);
+            }
+
+            value[start..end].copy_from_slice(&write.value);
+        }
+
+        values
+    }
+}
+
 impl Profile {
     /// Creates a new [`Profile`].
     #[must_use]
@@ -56,19 +107,39 @@ impl Profile {
         Arc::new(RwLock::new(self.clone()))
     }
 
-    pub(crate) fn get_service(&self, handle: u16) -> Option<Arc<RwLock<Service>>> {
+    /// Validates the [`Profile`] and returns a reference to it, like [`Self::build`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if one of the services is invalid.
+    pub fn try_build(&self) -> Result<Arc<RwLock<Self>>, Error> {
+        self.validate()?;
+        Ok(self.build())
+    }
+
+    /// Checks that all the services of the [`Profile`] can be registered.
+    pub(crate) fn validate(&self) -> Result<(), Error> {
         for service in &self.services {
-            if service.read().unwrap().handle == Some(handle) {
-                return Some(service.clone());
-            }
+            service.read()?.validate()?;
         }
 
-        None
+        Ok(())
+    }
+
+    /// Returns the number of attribute handles used by the services of the [`Profile`].
+    pub(crate) fn handle_count(&self) -> Result<usize, Error> {
+        let mut count = 0;
+
+        for service in &self.services {
+            count += service.read()?.handle_count()?;
+        }
+
+        Ok(count)
     }
 
-    pub(crate) fn get_service_by_id(&self, id: esp_gatt_id_t) -> Option<Arc<RwLock<Service>>> {
+    pub(crate) fn get_service(&self, handle: u16) -> Option<Arc<RwLock<Service>>> {
         for service in &self.services {
-            if service.read().unwrap().uuid == id.into() {
+            if service.read().unwrap().handle == Some(handle) {
                 return Some(service.clone());
             }
         }
@@ -76,18 +147,18 @@ impl Profile {
         None
     }
 
-    pub(crate) fn register_self(&self) {
+    pub(crate) fn register_self(&self) -> Result<(), Error> {
         debug!("Registering {}.", self);
-        unsafe { esp_nofail!(esp_ble_gatts_app_register(self.identifier)) };
+        backend().app_register(self.identifier)?;
+
+        Ok(())
     }
 
-    pub(crate) fn register_services(&mut self) {
-        debug!("Registering {}'s services.", &self);
-        self.services.iter_mut().for_each(|service| {
-            service
-                .write()
-                .unwrap()
-                .register_self(self.interface.unwrap());
+    /// Forgets the interface and handles assigned by the stack, so that the [`Profile`] can be registered again.
+    pub(crate) fn unregister(&mut self) {
+        self.interface = None;
+        self.services.iter().for_each(|service| {
+            service.write().unwrap().unregister();
         });
     }
 }
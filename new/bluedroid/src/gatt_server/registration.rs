@@ -0,0 +1,970 @@
+use std::{
+    collections::VecDeque,
+    fmt::Formatter,
+    sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError, RwLock},
+    time::Duration,
+};
+
+use crate::{
+    backend::backend,
+    gatt_server::{
+        connection_state::connection_states, database, subscriptions::subscriptions,
+        Characteristic, Descriptor, GattServer, Profile, Service,
+    },
+    sys::*,
+    utilities::BleUuid,
+    Error,
+};
+use log::{debug, info, warn};
+
+type ReadyCallback = dyn Fn(Result<(), &Error>) + Send + Sync;
+
+/// A step of the registration, completed by an event of the Bluetooth stack.
+enum Step {
+    /// Registers a profile, completed by `ESP_GATTS_REG_EVT`.
+    Profile(Arc<RwLock<Profile>>),
+    /// Creates a service on an interface, completed by `ESP_GATTS_CREATE_EVT`,
+    /// or by `ESP_GATTS_CREAT_ATTR_TAB_EVT` with all its attributes if it is registered as a table.
+    Service(esp_gatt_if_t, Arc<RwLock<Service>>),
+    /// Adds an include declaration to a service, pointing to a registered service,
+    /// completed by `ESP_GATTS_ADD_INCL_SRVC_EVT`.
+    Include(Arc<RwLock<Service>>, Arc<RwLock<Service>>),
+    /// Adds a characteristic to a service, completed by `ESP_GATTS_ADD_CHAR_EVT`.
+    Characteristic(Arc<RwLock<Service>>, Arc<RwLock<Characteristic>>),
+    /// Adds a descriptor to the last characteristic of a service, completed by `ESP_GATTS_ADD_CHAR_DESCR_EVT`.
+    Descriptor(
+        Arc<RwLock<Service>>,
+        Arc<RwLock<Characteristic>>,
+        Arc<RwLock<Descriptor>>,
+    ),
+    /// Starts a service, completed by `ESP_GATTS_START_EVT`.
+    Start(Arc<RwLock<Service>>),
+    /// Stops a service, completed by `ESP_GATTS_STOP_EVT`.
+    Stop(Arc<RwLock<Service>>),
+    /// Deletes a stopped service, completed by `ESP_GATTS_DELETE_EVT`.
+    Delete(Arc<RwLock<Service>>),
+}
+
+/// The state of the registration.
+#[derive(Debug, Default)]
+enum Outcome {
+    /// The server is not started.
+    #[default]
+    Stopped,
+    /// Some attributes are not registered yet.
+    Pending,
+    /// All the attributes are registered.
+    Ready,
+    /// The registration stopped because of this error.
+    Failed(Error),
+}
+
+#[derive(Default)]
+struct State {
+    steps: VecDeque<Step>,
+    outcome: Outcome,
+    /// The profiles of the server, to describe the database once the registration ends.
+    profiles: Vec<Arc<RwLock<Profile>>>,
+    callback: Option<Arc<ReadyCallback>>,
+}
+
+/// The registration of the profiles, services, characteristics and descriptors of a server.
+///
+/// The attributes are registered one at a time, in the order they were declared:
+/// each step starts when the event of the previous one is received.
+/// The events carry no reference to the attributes, so this is the only way to tell them apart.
+/// Services registered as an attribute table are created with all their attributes in a single step.
+///
+/// Once the server is started, services can be added and removed: their steps are queued
+/// after the pending ones, and the registration ends again when they are all completed.
+///
+/// Clones refer to the same registration.
+#[derive(Clone, Default)]
+pub(crate) struct Registration {
+    state: Arc<(Mutex<State>, Condvar)>,
+}
+
+impl Registration {
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Starts registering the attributes of the profiles.
+    ///
+    /// Returns the error of the first step, if the stack rejects it.
+    pub(crate) fn start(&self, profiles: &[Arc<RwLock<Profile>>]) -> Result<(), Error> {
+        let mut state = self.state();
+        state.steps = profiles.iter().cloned().map(Step::Profile).collect();
+        state.profiles = profiles.to_vec();
+        state.outcome = Outcome::Pending;
+
+        let ended = state.advance();
+        self.release(state, ended.as_ref());
+
+        ended.unwrap_or(Ok(()))
+    }
+
+    /// Registers a profile added to a started server.
+    pub(crate) fn add_profile(&self, profile: &Arc<RwLock<Profile>>) -> Result<(), Error> {
+        let mut state = self.state();
+        state.profiles.push(profile.clone());
+        self.queue(state, vec![Step::Profile(profile.clone())])
+    }
+
+    /// Registers a service added to a registered profile of a started server.
+    pub(crate) fn add_service(
+        &self,
+        interface: esp_gatt_if_t,
+        service: &Arc<RwLock<Service>>,
+    ) -> Result<(), Error> {
+        let state = self.state();
+        self.queue(state, vec![Step::Service(interface, service.clone())])
+    }
+
+    /// Stops and deletes a service removed from a started server.
+    pub(crate) fn remove_service(&self, service: &Arc<RwLock<Service>>) -> Result<(), Error> {
+        let state = self.state();
+        self.queue(
+            state,
+            vec![Step::Stop(service.clone()), Step::Delete(service.clone())],
+        )
+    }
+
+    /// Queues steps after the pending ones, restarting the registration if it ended.
+    ///
+    /// Returns the error of the first step, if it is sent and the stack rejects it.
+    fn queue(&self, mut state: MutexGuard<'_, State>, steps: Vec<Step>) -> Result<(), Error> {
+        state.steps.extend(steps);
+        if matches!(state.outcome, Outcome::Pending) {
+            return Ok(());
+        }
+
+        state.outcome = Outcome::Pending;
+        let ended = state.advance();
+        self.release(state, ended.as_ref());
+
+        ended.unwrap_or(Ok(()))
+    }
+
+    /// Abandons the registration, when the server stops.
+    pub(crate) fn stop(&self) {
+        let mut state = self.state();
+        state.steps.clear();
+        state.profiles.clear();
+        state.outcome = Outcome::Stopped;
+        drop(state);
+
+        self.state.1.notify_all();
+    }
+
+    /// Completes the current step with an event of the Bluetooth stack, and starts the next one.
+    pub(crate) fn on_event(
+        &self,
+        event: esp_gatts_cb_event_t,
+        gatts_if: esp_gatt_if_t,
+        param: *mut esp_ble_gatts_cb_param_t,
+    ) {
+        let mut state = self.state();
+
+        let ended = match state.complete(event, gatts_if, param) {
+            Ok(true) => state.advance(),
+            Ok(false) => None,
+            Err(error) => Some(state.fail(error)),
+        };
+
+        self.release(state, ended.as_ref());
+    }
+
+    /// Releases the state, then calls the callback and wakes the waiting threads if the registration ended.
+    ///
+    /// Clients are told about the changes to the database before the callback is called.
+    fn release(&self, state: MutexGuard<'_, State>, ended: Option<&Result<(), Error>>) {
+        let callback = state.callback.clone();
+        let profiles = matches!(ended, Some(Ok(()))).then(|| state.profiles.clone());
+        drop(state);
+
+        let Some(result) = ended else {
+            return;
+        };
+
+        if let Some(profiles) = profiles {
+            database::on_registered(&profiles);
+        }
+
+        if let Some(callback) = callback {
+            callback(result.as_ref().copied());
+        }
+
+        self.state.1.notify_all();
+    }
+
+    /// Blocks until the registration ends, or until the timeout expires.
+    fn wait(&self, timeout: Duration) -> Result<(), Error> {
+        let (lock, signal) = &*self.state;
+        let (state, _) = signal.wait_timeout_while(lock.lock()?, timeout, |state| {
+            matches!(state.outcome, Outcome::Pending)
+        })?;
+
+        match &state.outcome {
+            Outcome::Stopped => Err(Error::NotStarted),
+            Outcome::Pending => Err(Error::RegistrationTimeout),
+            Outcome::Ready => Ok(()),
+            Outcome::Failed(error) => Err(error.clone()),
+        }
+    }
+}
+
+impl State {
+    /// Sends the request of the current step to the stack, or ends the registration if there are no steps left.
+    ///
+    /// Returns the result of the registration, if it ended.
+    fn advance(&mut self) -> Option<Result<(), Error>> {
+        let Some(step) = self.steps.front() else {
+            info!("All the attributes are registered.");
+            self.outcome = Outcome::Ready;
+            return Some(Ok(()));
+        };
+
+        debug!("Registering {}.", step);
+        step.send().err().map(|error| self.fail(error))
+    }
+
+    /// Ends the registration with an error.
+    fn fail(&mut self, error: Error) -> Result<(), Error> {
+        if let Some(step) = self.steps.front() {
+            warn!("Cannot register {}: {}.", step, error);
+        }
+
+        self.steps.clear();
+        self.outcome = Outcome::Failed(error.clone());
+
+        Err(error)
+    }
+
+    /// Completes the current step with an event, and queues the steps that follow from it.
+    ///
+    /// Returns whether the event completed the current step.
+    #[allow(non_upper_case_globals)]
+    fn complete(
+        &mut self,
+        event: esp_gatts_cb_event_t,
+        gatts_if: esp_gatt_if_t,
+        param: *mut esp_ble_gatts_cb_param_t,
+    ) -> Result<bool, Error> {
+        let Some(step) = self.steps.front() else {
+            debug!(
+                "Ignoring registration event {} without a pending step.",
+                event
+            );
+            return Ok(false);
+        };
+
+        let next = match (step, event) {
+            (Step::Profile(profile), esp_gatts_cb_event_t_ESP_GATTS_REG_EVT) => {
+                on_reg(profile, gatts_if, unsafe { (*param).reg })?
+            }
+            (Step::Service(_, service), esp_gatts_cb_event_t_ESP_GATTS_CREATE_EVT) => {
+                on_create(service, unsafe { (*param).create })?
+            }
+            (Step::Service(_, service), esp_gatts_cb_event_t_ESP_GATTS_CREAT_ATTR_TAB_EVT) => {
+                on_create_attr_tab(service, gatts_if, unsafe { (*param).add_attr_tab })?
+            }
+            (
+                Step::Include(service, included),
+                esp_gatts_cb_event_t_ESP_GATTS_ADD_INCL_SRVC_EVT,
+            ) => on_add_incl_srvc(service, included, unsafe { (*param).add_incl_srvc })?,
+            (
+                Step::Characteristic(service, characteristic),
+                esp_gatts_cb_event_t_ESP_GATTS_ADD_CHAR_EVT,
+            ) => on_add_char(service, characteristic, gatts_if, unsafe {
+                (*param).add_char
+            })?,
+            (
+                Step::Descriptor(service, characteristic, descriptor),
+                esp_gatts_cb_event_t_ESP_GATTS_ADD_CHAR_DESCR_EVT,
+            ) => on_add_char_descr(service, characteristic, descriptor, unsafe {
+                (*param).add_char_descr
+            })?,
+            (Step::Start(service), esp_gatts_cb_event_t_ESP_GATTS_START_EVT) => {
+                on_start(service, unsafe { (*param).start })?
+            }
+            (Step::Stop(service), esp_gatts_cb_event_t_ESP_GATTS_STOP_EVT) => {
+                on_stop(service, unsafe { (*param).stop })?
+            }
+            (Step::Delete(service), esp_gatts_cb_event_t_ESP_GATTS_DELETE_EVT) => {
+                on_delete(service, unsafe { (*param).del })?
+            }
+            _ => None,
+        };
+
+        let Some(next) = next else {
+            warn!(
+                "Ignoring unexpected event {} while registering {}.",
+                event, step
+            );
+            return Ok(false);
+        };
+
+        self.steps.pop_front();
+        for step in next.into_iter().rev() {
+            self.steps.push_front(step);
+        }
+
+        Ok(true)
+    }
+}
+
+// Each event handler returns the steps that follow from the event,
+// or nothing if the event does not belong to the current step.
+
+fn on_reg(
+    profile: &Arc<RwLock<Profile>>,
+    gatts_if: esp_gatt_if_t,
+    param: esp_ble_gatts_cb_param_t_gatts_reg_evt_param,
+) -> Result<Option<Vec<Step>>, Error> {
+    let mut profile = profile.write()?;
+    if profile.identifier != param.app_id {
+        return Ok(None);
+    }
+
+    check_status(param.status)?;
+    profile.interface = Some(gatts_if);
+    info!("{} registered on interface {}.", profile, gatts_if);
+
+    Ok(Some(
+        profile
+            .services
+            .iter()
+            .map(|service| Step::Service(gatts_if, service.clone()))
+            .collect(),
+    ))
+}
+
+fn on_create(
+    service: &Arc<RwLock<Service>>,
+    param: esp_ble_gatts_cb_param_t_gatts_create_evt_param,
+) -> Result<Option<Vec<Step>>, Error> {
+    let mut service_guard = service.write()?;
+    if service_guard.uuid != BleUuid::from(param.service_id.id) {
+        return Ok(None);
+    }
+
+    check_status(param.status)?;
+    service_guard.handle = Some(param.service_handle);
+    info!(
+        "GATT service {} registered on handle 0x{:04x}.",
+        service_guard, param.service_handle
+    );
+
+    // The include declarations come before the characteristics,
+    // and the service is started once all its characteristics are added.
+    Ok(Some(
+        service_guard
+            .includes
+            .iter()
+            .map(|include| Step::Include(service.clone(), include.service.clone()))
+            .chain(service_guard.characteristics.iter().map(|characteristic| {
+                Step::Characteristic(service.clone(), characteristic.clone())
+            }))
+            .chain(std::iter::once(Step::Start(service.clone())))
+            .collect(),
+    ))
+}
+
+fn on_create_attr_tab(
+    service: &Arc<RwLock<Service>>,
+    gatts_if: esp_gatt_if_t,
+    param: esp_ble_gatts_cb_param_t_gatts_add_attr_tab_evt_param,
+) -> Result<Option<Vec<Step>>, Error> {
+    let mut service_guard = service.write()?;
+    if service_guard.uuid != BleUuid::from(param.svc_uuid) {
+        return Ok(None);
+    }
+
+    check_status(param.status)?;
+
+    let handles = if param.handles.is_null() {
+        &[]
+    } else {
+        unsafe { std::slice::from_raw_parts(param.handles, usize::from(param.num_handle)) }
+    };
+
+    let (Some(first), Some(last)) = (handles.first().copied(), handles.last().copied()) else {
+        return Err(Error::RegistrationFailed(esp_gatt_status_t_ESP_GATT_ERROR));
+    };
+
+    if handles.len() != service_guard.handle_count()? {
+        return Err(Error::RegistrationFailed(esp_gatt_status_t_ESP_GATT_ERROR));
+    }
+
+    // The handles are in the order of the table: the service, its include declarations,
+    // then the declaration, the value and the descriptors of each characteristic.
+    let mut handles = handles.iter().copied();
+    service_guard.handle = handles.next();
+
+    for include in &mut service_guard.includes {
+        include.handle = handles.next();
+    }
+
+    for characteristic in &service_guard.characteristics {
+        let mut characteristic = characteristic.write()?;
+        handles.next();
+        characteristic.attribute_handle = handles.next();
+        characteristic.service_handle = service_guard.handle;
+        characteristic.interface = Some(gatts_if);
+
+        for descriptor in &characteristic.descriptors {
+            let mut descriptor = descriptor.write()?;
+            descriptor.attribute_handle = handles.next();
+            register_cccd(service_guard.uuid, characteristic.uuid, &descriptor);
+        }
+    }
+
+    info!(
+        "GATT service {} registered as a table on handles 0x{:04x} to 0x{:04x}.",
+        service_guard, first, last
+    );
+
+    Ok(Some(vec![Step::Start(service.clone())]))
+}
+
+fn on_add_incl_srvc(
+    service: &Arc<RwLock<Service>>,
+    included: &Arc<RwLock<Service>>,
+    param: esp_ble_gatts_cb_param_t_gatts_add_incl_srvc_evt_param,
+) -> Result<Option<Vec<Step>>, Error> {
+    let mut service = service.write()?;
+    if service.handle != Some(param.service_handle) {
+        return Ok(None);
+    }
+
+    check_status(param.status)?;
+
+    // A service can include the same service more than once: the includes are registered in order.
+    if let Some(include) = service
+        .includes
+        .iter_mut()
+        .find(|include| include.handle.is_none() && Arc::ptr_eq(&include.service, included))
+    {
+        include.handle = Some(param.attr_handle);
+    }
+
+    info!(
+        "GATT service {} included in {} at attribute handle 0x{:04x}.",
+        included.read()?,
+        service,
+        param.attr_handle
+    );
+
+    Ok(Some(Vec::new()))
+}
+
+fn on_add_char(
+    service: &Arc<RwLock<Service>>,
+    characteristic: &Arc<RwLock<Characteristic>>,
+    gatts_if: esp_gatt_if_t,
+    param: esp_ble_gatts_cb_param_t_gatts_add_char_evt_param,
+) -> Result<Option<Vec<Step>>, Error> {
+    let mut characteristic_guard = characteristic.write()?;
+    if characteristic_guard.uuid != BleUuid::from(param.char_uuid) {
+        return Ok(None);
+    }
+
+    check_status(param.status)?;
+    characteristic_guard.attribute_handle = Some(param.attr_handle);
+    characteristic_guard.interface = Some(gatts_if);
+    info!(
+        "GATT characteristic {} registered at attribute handle 0x{:04x}.",
+        characteristic_guard, param.attr_handle
+    );
+
+    // The descriptors follow their characteristic, including the CCCD added when it was registered.
+    Ok(Some(
+        characteristic_guard
+            .descriptors
+            .iter()
+            .map(|descriptor| {
+                Step::Descriptor(service.clone(), characteristic.clone(), descriptor.clone())
+            })
+            .collect(),
+    ))
+}
+
+fn on_add_char_descr(
+    service: &Arc<RwLock<Service>>,
+    characteristic: &Arc<RwLock<Characteristic>>,
+    descriptor: &Arc<RwLock<Descriptor>>,
+    param: esp_ble_gatts_cb_param_t_gatts_add_char_descr_evt_param,
+) -> Result<Option<Vec<Step>>, Error> {
+    let mut descriptor = descriptor.write()?;
+    if descriptor.uuid != BleUuid::from(param.descr_uuid) {
+        return Ok(None);
+    }
+
+    check_status(param.status)?;
+    descriptor.attribute_handle = Some(param.attr_handle);
+    info!(
+        "GATT descriptor {} registered at attribute handle 0x{:04x}.",
+        descriptor, param.attr_handle
+    );
+
+    register_cccd(
+        service.read()?.uuid,
+        characteristic.read()?.uuid,
+        &descriptor,
+    );
+
+    Ok(Some(Vec::new()))
+}
+
+/// Tells the subscriptions about a registered descriptor, if it is a CCCD.
+///
+/// CCCD values are persisted by characteristic, because handles can change between firmware versions.
+fn register_cccd(service: BleUuid, characteristic: BleUuid, descriptor: &Descriptor) {
+    if descriptor.uuid != BleUuid::from_uuid16(0x2902) {
+        return;
+    }
+
+    if let Some(handle) = descriptor.attribute_handle {
+        subscriptions().register_cccd(handle, format!("{service}/{characteristic}"));
+    }
+}
+
+fn on_start(
+    service: &Arc<RwLock<Service>>,
+    param: esp_ble_gatts_cb_param_t_gatts_start_evt_param,
+) -> Result<Option<Vec<Step>>, Error> {
+    let service = service.read()?;
+    if service.handle != Some(param.service_handle) {
+        return Ok(None);
+    }
+
+    check_status(param.status)?;
+    debug!("GATT service {} started.", service);
+
+    Ok(Some(Vec::new()))
+}
+
+fn on_stop(
+    service: &Arc<RwLock<Service>>,
+    param: esp_ble_gatts_cb_param_t_gatts_stop_evt_param,
+) -> Result<Option<Vec<Step>>, Error> {
+    let service = service.read()?;
+    if service.handle != Some(param.service_handle) {
+        return Ok(None);
+    }
+
+    check_status(param.status)?;
+    debug!("GATT service {} stopped.", service);
+
+    Ok(Some(Vec::new()))
+}
+
+fn on_delete(
+    service: &Arc<RwLock<Service>>,
+    param: esp_ble_gatts_cb_param_t_gatts_delete_evt_param,
+) -> Result<Option<Vec<Step>>, Error> {
+    let mut service = service.write()?;
+    if service.handle != Some(param.service_handle) {
+        return Ok(None);
+    }
+
+    check_status(param.status)?;
+    info!(
+        "GATT service {} deleted from handle 0x{:04x}.",
+        service, param.service_handle
+    );
+
+    // The handles of the CCCDs can be assigned to other attributes: forget what clients wrote to them.
+    let mut cccds = Vec::new();
+    for characteristic in &service.characteristics {
+        for descriptor in &characteristic.read()?.descriptors {
+            let descriptor = descriptor.read()?;
+            if descriptor.uuid == BleUuid::from_uuid16(0x2902) {
+                cccds.extend(descriptor.attribute_handle);
+            }
+        }
+    }
+
+    subscriptions().unregister_cccds(&cccds);
+    for state in connection_states().values_mut() {
+        state.cccds.retain(|handle, _| !cccds.contains(handle));
+    }
+
+    service.unregister();
+
+    Ok(Some(Vec::new()))
+}
+
+/// Turns the status of a registration event into an error.
+fn check_status(status: esp_gatt_status_t) -> Result<(), Error> {
+    if status == esp_gatt_status_t_ESP_GATT_OK {
+        Ok(())
+    } else {
+        Err(Error::RegistrationFailed(status))
+    }
+}
+
+impl Step {
+    /// Sends the request of this step to the stack.
+    fn send(&self) -> Result<(), Error> {
+        match self {
+            Self::Profile(profile) => profile.read()?.register_self(),
+            Self::Service(interface, service) => service.write()?.register_self(*interface),
+            Self::Include(service, included) => {
+                let service_handle = service.read()?.handle.ok_or(Error::NotRegistered)?;
+                let included_handle = included.read()?.handle.ok_or(Error::NotRegistered)?;
+                Ok(backend().add_included_service(service_handle, included_handle)?)
+            }
+            Self::Characteristic(service, characteristic) => {
+                let service_handle = service.read()?.handle.ok_or(Error::NotRegistered)?;
+                characteristic.write()?.register_self(service_handle)
+            }
+            Self::Descriptor(service, _, descriptor) => {
+                let service_handle = service.read()?.handle.ok_or(Error::NotRegistered)?;
+                descriptor.write()?.register_self(service_handle)
+            }
+            Self::Start(service) => {
+                let service_handle = service.read()?.handle.ok_or(Error::NotRegistered)?;
+                Ok(backend().start_service(service_handle)?)
+            }
+            Self::Stop(service) => {
+                let service_handle = service.read()?.handle.ok_or(Error::NotRegistered)?;
+                Ok(backend().stop_service(service_handle)?)
+            }
+            Self::Delete(service) => {
+                let service_handle = service.read()?.handle.ok_or(Error::NotRegistered)?;
+                Ok(backend().delete_service(service_handle)?)
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Step {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        // Poisoned attributes are shown without their names.
+        match self {
+            Self::Profile(profile) => match profile.read() {
+                Ok(profile) => write!(f, "{profile}"),
+                Err(_) => write!(f, "a profile"),
+            },
+            Self::Service(_, service) => match service.read() {
+                Ok(service) => write!(f, "{service}"),
+                Err(_) => write!(f, "a service"),
+            },
+            Self::Include(service, included) => match (service.read(), included.read()) {
+                (Ok(service), Ok(included)) => {
+                    write!(f, "the inclusion of {included} in {service}")
+                }
+                _ => write!(f, "the inclusion of a service"),
+            },
+            Self::Characteristic(_, characteristic) => match characteristic.read() {
+                Ok(characteristic) => write!(f, "{characteristic}"),
+                Err(_) => write!(f, "a characteristic"),
+            },
+            Self::Descriptor(_, _, descriptor) => match descriptor.read() {
+                Ok(descriptor) => write!(f, "{descriptor}"),
+                Err(_) => write!(f, "a descriptor"),
+            },
+            Self::Start(service) => match service.read() {
+                Ok(service) => write!(f, "the start of {service}"),
+                Err(_) => write!(f, "the start of a service"),
+            },
+            Self::Stop(service) => match service.read() {
+                Ok(service) => write!(f, "the stop of {service}"),
+                Err(_) => write!(f, "the stop of a service"),
+            },
+            Self::Delete(service) => match service.read() {
+                Ok(service) => write!(f, "the deletion of {service}"),
+                Err(_) => write!(f, "the deletion of a service"),
+            },
+        }
+    }
+}
+
+impl GattServer {
+    /// Sets the callback that is called when the registration of the attributes ends,
+    /// with the error that stopped it, if any.
+    ///
+    /// It is called again each time the services added or removed at runtime are registered.
+    ///
+    /// # Notes
+    ///
+    /// The callback is called from the Bluetooth stack's context, without the server locked,
+    /// or from [`GattServer::start`] if the registration ends there. It must not block.
+    pub fn on_ready<C: Fn(Result<(), &Error>) + Send + Sync + 'static>(
+        &mut self,
+        callback: C,
+    ) -> &mut Self {
+        self.registration.state().callback = Some(Arc::new(callback));
+        self
+    }
+
+    /// Blocks until the Bluetooth stack registers all the attributes of a started server,
+    /// or until the timeout expires.
+    ///
+    /// The server is only locked while the waiting starts, so that the events of the stack can be handled.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::RegistrationTimeout`] if the attributes are not registered in time,
+    /// [`Error::NotStarted`] if the server is not started, or is stopped while waiting,
+    /// or the error that stopped the registration, such as [`Error::RegistrationFailed`].
+    ///
+    /// # Notes
+    ///
+    /// Registration events are received from the Bluetooth stack's context:
+    /// waiting for them from a callback always times out.
+    pub fn wait_until_ready(server: &Mutex<Self>, timeout: Duration) -> Result<(), Error> {
+        let registration = server.lock()?.registration.clone();
+        registration.wait(timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTERFACE: esp_gatt_if_t = 3;
+    const SERVICE_HANDLE: u16 = 0x0028;
+    const FAILURE: esp_gatt_status_t = esp_gatt_status_t_ESP_GATT_NO_RESOURCES;
+
+    fn reg(app_id: u16, status: esp_gatt_status_t) -> esp_ble_gatts_cb_param_t {
+        esp_ble_gatts_cb_param_t {
+            reg: esp_ble_gatts_cb_param_t_gatts_reg_evt_param { status, app_id },
+        }
+    }
+
+    fn create(uuid: BleUuid, status: esp_gatt_status_t) -> esp_ble_gatts_cb_param_t {
+        let mut service_id = esp_gatt_srvc_id_t::default();
+        service_id.id.uuid = uuid.into();
+
+        esp_ble_gatts_cb_param_t {
+            create: esp_ble_gatts_cb_param_t_gatts_create_evt_param {
+                status,
+                service_handle: SERVICE_HANDLE,
+                service_id,
+            },
+        }
+    }
+
+    fn add_char(uuid: BleUuid, status: esp_gatt_status_t) -> esp_ble_gatts_cb_param_t {
+        esp_ble_gatts_cb_param_t {
+            add_char: esp_ble_gatts_cb_param_t_gatts_add_char_evt_param {
+                status,
+                attr_handle: SERVICE_HANDLE + 2,
+                service_handle: SERVICE_HANDLE,
+                char_uuid: uuid.into(),
+            },
+        }
+    }
+
+    fn add_char_descr(uuid: BleUuid, status: esp_gatt_status_t) -> esp_ble_gatts_cb_param_t {
+        esp_ble_gatts_cb_param_t {
+            add_char_descr: esp_ble_gatts_cb_param_t_gatts_add_char_descr_evt_param {
+                status,
+                attr_handle: SERVICE_HANDLE + 3,
+                service_handle: SERVICE_HANDLE,
+                descr_uuid: uuid.into(),
+            },
+        }
+    }
+
+    fn start(service_handle: u16, status: esp_gatt_status_t) -> esp_ble_gatts_cb_param_t {
+        esp_ble_gatts_cb_param_t {
+            start: esp_ble_gatts_cb_param_t_gatts_start_evt_param {
+                status,
+                service_handle,
+            },
+        }
+    }
+
+    /// A service created on [`SERVICE_HANDLE`].
+    fn created_service() -> Arc<RwLock<Service>> {
+        let service = Service::new(BleUuid::Uuid16(0xA000)).build();
+        service.write().unwrap().handle = Some(SERVICE_HANDLE);
+        service
+    }
+
+    fn complete(
+        state: &mut State,
+        event: esp_gatts_cb_event_t,
+        mut param: esp_ble_gatts_cb_param_t,
+    ) -> Result<bool, Error> {
+        state.complete(event, INTERFACE, std::ptr::addr_of_mut!(param))
+    }
+
+    #[test]
+    fn out_of_order_events() {
+        let service = created_service();
+        let described = Characteristic::new(BleUuid::Uuid16(0xA001)).build();
+        let description = Descriptor::new(BleUuid::Uuid16(0x2901)).build();
+        described.write().unwrap().descriptor(&description);
+        let plain = Characteristic::new(BleUuid::Uuid16(0xA002)).build();
+        let mut state = State {
+            steps: VecDeque::from([
+                Step::Characteristic(service.clone(), described.clone()),
+                Step::Characteristic(service.clone(), plain.clone()),
+                Step::Start(service.clone()),
+            ]),
+            ..State::default()
+        };
+
+        // Events of other steps are ignored, and the current step keeps waiting.
+        let (described_uuid, plain_uuid) = (BleUuid::Uuid16(0xA001), BleUuid::Uuid16(0xA002));
+        let add_char_evt = esp_gatts_cb_event_t_ESP_GATTS_ADD_CHAR_EVT;
+        let add_char_descr_evt = esp_gatts_cb_event_t_ESP_GATTS_ADD_CHAR_DESCR_EVT;
+        let start_evt = esp_gatts_cb_event_t_ESP_GATTS_START_EVT;
+        let ok = esp_gatt_status_t_ESP_GATT_OK;
+        for (event, param) in [
+            (esp_gatts_cb_event_t_ESP_GATTS_REG_EVT, reg(1, ok)),
+            (add_char_evt, add_char(plain_uuid, ok)),
+            (
+                add_char_descr_evt,
+                add_char_descr(BleUuid::Uuid16(0x2901), ok),
+            ),
+            (start_evt, start(SERVICE_HANDLE, ok)),
+        ] {
+            assert!(!complete(&mut state, event, param).unwrap());
+        }
+        assert_eq!(state.steps.len(), 3);
+        assert_eq!(described.read().unwrap().attribute_handle, None);
+
+        // The descriptor of a characteristic is registered before the next characteristic.
+        assert!(complete(&mut state, add_char_evt, add_char(described_uuid, ok)).unwrap());
+        assert_eq!(described.read().unwrap().attribute_handle, Some(0x002A));
+        assert!(matches!(state.steps.front(), Some(Step::Descriptor(..))));
+        assert!(!complete(&mut state, add_char_evt, add_char(plain_uuid, ok)).unwrap());
+        assert!(complete(
+            &mut state,
+            add_char_descr_evt,
+            add_char_descr(BleUuid::Uuid16(0x2901), ok)
+        )
+        .unwrap());
+        assert_eq!(description.read().unwrap().attribute_handle, Some(0x002B));
+
+        // A start event for another service does not start this one.
+        assert!(complete(&mut state, add_char_evt, add_char(plain_uuid, ok)).unwrap());
+        assert!(!complete(&mut state, start_evt, start(SERVICE_HANDLE + 0x10, ok)).unwrap());
+        assert!(complete(&mut state, start_evt, start(SERVICE_HANDLE, ok)).unwrap());
+        assert!(state.steps.is_empty());
+
+        // Late events are ignored once the steps are completed.
+        assert!(!complete(&mut state, start_evt, start(SERVICE_HANDLE, ok)).unwrap());
+    }
+
+    #[test]
+    fn error_statuses() {
+        let profile = Profile::new(7).build();
+        let service = Service::new(BleUuid::Uuid16(0xA000)).build();
+        let created = created_service();
+        let characteristic = Characteristic::new(BleUuid::Uuid16(0xA001)).build();
+        let descriptor = Descriptor::new(BleUuid::Uuid16(0x2901)).build();
+        let other = BleUuid::Uuid16(0xB000);
+
+        // Each step fails with the status of its event, but not with those of other attributes.
+        let cases = [
+            (
+                Step::Profile(profile),
+                esp_gatts_cb_event_t_ESP_GATTS_REG_EVT,
+                reg(7, FAILURE),
+                reg(8, FAILURE),
+            ),
+            (
+                Step::Service(INTERFACE, service),
+                esp_gatts_cb_event_t_ESP_GATTS_CREATE_EVT,
+                create(BleUuid::Uuid16(0xA000), FAILURE),
+                create(other, FAILURE),
+            ),
+            (
+                Step::Characteristic(created.clone(), characteristic.clone()),
+                esp_gatts_cb_event_t_ESP_GATTS_ADD_CHAR_EVT,
+                add_char(BleUuid::Uuid16(0xA001), FAILURE),
+                add_char(other, FAILURE),
+            ),
+            (
+                Step::Descriptor(created.clone(), characteristic, descriptor),
+                esp_gatts_cb_event_t_ESP_GATTS_ADD_CHAR_DESCR_EVT,
+                add_char_descr(BleUuid::Uuid16(0x2901), FAILURE),
+                add_char_descr(other, FAILURE),
+            ),
+            (
+                Step::Start(created),
+                esp_gatts_cb_event_t_ESP_GATTS_START_EVT,
+                start(SERVICE_HANDLE, FAILURE),
+                start(SERVICE_HANDLE + 0x10, FAILURE),
+            ),
+        ];
+
+        for (step, event, failed, unrelated) in cases {
+            let description = step.to_string();
+            let mut state = State {
+                steps: VecDeque::from([step]),
+                ..State::default()
+            };
+
+            assert!(
+                !complete(&mut state, event, unrelated).unwrap(),
+                "{description}"
+            );
+            assert!(
+                matches!(
+                    complete(&mut state, event, failed),
+                    Err(Error::RegistrationFailed(FAILURE))
+                ),
+                "{description}"
+            );
+        }
+    }
+
+    #[test]
+    fn attribute_table_handles() {
+        let characteristic = Characteristic::new(BleUuid::Uuid16(0xA001)).build();
+        let service = Service::new(BleUuid::Uuid16(0xA000))
+            .characteristic(&characteristic)
+            .attribute_table()
+            .build();
+        let add_attr_tab = |handles: &mut [u16]| esp_ble_gatts_cb_param_t {
+            add_attr_tab: esp_ble_gatts_cb_param_t_gatts_add_attr_tab_evt_param {
+                status: esp_gatt_status_t_ESP_GATT_OK,
+                svc_uuid: BleUuid::Uuid16(0xA000).into(),
+                svc_inst_id: 0,
+                num_handle: handles.len() as u16,
+                handles: handles.as_mut_ptr(),
+            },
+        };
+        let table_evt = esp_gatts_cb_event_t_ESP_GATTS_CREAT_ATTR_TAB_EVT;
+
+        // The stack must assign a handle to each entry of the table.
+        for mut handles in [
+            vec![],
+            vec![0x0028, 0x0029],
+            vec![0x0028, 0x0029, 0x002A, 0x002B],
+        ] {
+            let mut state = State {
+                steps: VecDeque::from([Step::Service(INTERFACE, service.clone())]),
+                ..State::default()
+            };
+            assert!(
+                matches!(
+                    complete(&mut state, table_evt, add_attr_tab(&mut handles)),
+                    Err(Error::RegistrationFailed(status)) if status == esp_gatt_status_t_ESP_GATT_ERROR
+                ),
+                "{handles:?}"
+            );
+        }
+
+        let mut state = State {
+            steps: VecDeque::from([Step::Service(INTERFACE, service.clone())]),
+            ..State::default()
+        };
+        let mut handles = [0x0028, 0x0029, 0x002A];
+        assert!(complete(&mut state, table_evt, add_attr_tab(&mut handles)).unwrap());
+        assert_eq!(service.read().unwrap().handle, Some(0x0028));
+        assert_eq!(
+            characteristic.read().unwrap().attribute_handle,
+            Some(0x002A)
+        );
+        assert!(matches!(state.steps.front(), Some(Step::Start(_))));
+    }
+}
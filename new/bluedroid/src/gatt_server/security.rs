@@ -0,0 +1,573 @@
+use std::sync::Arc;
+
+use crate::{
+    backend::backend,
+    gatt_server::{subscriptions::subscriptions, GattServer},
+    sys::*,
+    Error,
+};
+use log::{debug, info, warn};
+
+type AddressCallback<T> = dyn Fn(esp_bd_addr_t) -> T + Send + Sync;
+type PasskeyCallback<T> = dyn Fn(esp_bd_addr_t, u32) -> T + Send + Sync;
+type PairingCallback = dyn Fn(esp_bd_addr_t, PairingOutcome) + Send + Sync;
+
+/// The largest passkey, which has six digits.
+const MAX_PASSKEY: u32 = 999_999;
+
+/// The input and output capabilities of the device, used to choose the pairing method.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IoCapabilities {
+    /// The device can display a passkey.
+    DisplayOnly,
+    /// The device can display a passkey, and the user can confirm it.
+    DisplayYesNo,
+    /// The user can enter a passkey.
+    KeyboardOnly,
+    /// The device has no way to interact with the user: pairing is never protected against MITM attacks.
+    #[default]
+    NoInputNoOutput,
+    /// The device can display a passkey, and the user can enter one.
+    KeyboardDisplay,
+}
+
+impl From<IoCapabilities> for esp_ble_io_cap_t {
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn from(capabilities: IoCapabilities) -> Self {
+        let result = match capabilities {
+            IoCapabilities::DisplayOnly => ESP_IO_CAP_OUT,
+            IoCapabilities::DisplayYesNo => ESP_IO_CAP_IO,
+            IoCapabilities::KeyboardOnly => ESP_IO_CAP_IN,
+            IoCapabilities::NoInputNoOutput => ESP_IO_CAP_NONE,
+            IoCapabilities::KeyboardDisplay => ESP_IO_CAP_KBDISP,
+        };
+
+        result as Self
+    }
+}
+
+/// The keys exchanged at the end of the pairing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct KeyDistribution {
+    mask: u8,
+}
+
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+impl KeyDistribution {
+    /// Creates a new [`KeyDistribution`], with no keys.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Distributes the long term key, used to encrypt later connections.
+    #[must_use]
+    pub const fn encryption(mut self) -> Self {
+        self.mask |= ESP_BLE_ENC_KEY_MASK as u8;
+        self
+    }
+
+    /// Distributes the identity resolving key and the identity address,
+    /// used to recognise devices that use private addresses.
+    #[must_use]
+    pub const fn identity(mut self) -> Self {
+        self.mask |= ESP_BLE_ID_KEY_MASK as u8;
+        self
+    }
+
+    /// Distributes the connection signature resolving key, used for signed writes.
+    #[must_use]
+    pub const fn signing(mut self) -> Self {
+        self.mask |= ESP_BLE_CSR_KEY_MASK as u8;
+        self
+    }
+
+    /// Derives the BR/EDR link key from the LE one.
+    #[must_use]
+    pub const fn link(mut self) -> Self {
+        self.mask |= ESP_BLE_LINK_KEY_MASK as u8;
+        self
+    }
+}
+
+impl From<KeyDistribution> for u8 {
+    fn from(keys: KeyDistribution) -> Self {
+        keys.mask
+    }
+}
+
+/// The result of a pairing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PairingOutcome {
+    /// The devices paired, and the keys were stored for later connections.
+    Bonded,
+    /// The devices paired for the current connection only.
+    Paired,
+    /// The pairing failed with the given SMP reason.
+    Failed(u8),
+}
+
+/// A pairing step that waits for the user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PairingRequest {
+    /// The user must enter the passkey displayed by the client.
+    Passkey,
+    /// The user must confirm that the client displays this number.
+    NumericComparison(u32),
+}
+
+/// The security configuration of a [`GattServer`].
+///
+/// It is applied when the server starts.
+/// LE Secure Connections are always preferred to legacy pairing.
+///
+/// # Notes
+///
+/// The callbacks are called from the Bluetooth stack's context, once the server is released:
+/// they must not block, but they can access the server.
+#[derive(Clone)]
+pub struct SecurityConfig {
+    pub(crate) io_capabilities: IoCapabilities,
+    pub(crate) bonding: bool,
+    pub(crate) mitm: bool,
+    pub(crate) secure_connections_only: bool,
+    pub(crate) initiator_keys: KeyDistribution,
+    pub(crate) responder_keys: KeyDistribution,
+    pub(crate) static_passkey: Option<u32>,
+    security_request_callback: Option<Arc<AddressCallback<bool>>>,
+    passkey_request_callback: Option<Arc<AddressCallback<()>>>,
+    passkey_display_callback: Option<Arc<PasskeyCallback<()>>>,
+    numeric_comparison_callback: Option<Arc<PasskeyCallback<()>>>,
+    pairing_complete_callback: Option<Arc<PairingCallback>>,
+}
+
+impl Default for SecurityConfig {
+    fn default() -> Self {
+        Self {
+            io_capabilities: IoCapabilities::NoInputNoOutput,
+            bonding: true,
+            mitm: false,
+            secure_connections_only: false,
+            initiator_keys: KeyDistribution::new().encryption().identity(),
+            responder_keys: KeyDistribution::new().encryption().identity(),
+            static_passkey: None,
+            security_request_callback: None,
+            passkey_request_callback: None,
+            passkey_display_callback: None,
+            numeric_comparison_callback: None,
+            pairing_complete_callback: None,
+        }
+    }
+}
+
+impl std::fmt::Debug for SecurityConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SecurityConfig")
+            .field("io_capabilities", &self.io_capabilities)
+            .field("bonding", &self.bonding)
+            .field("mitm", &self.mitm)
+            .field("secure_connections_only", &self.secure_connections_only)
+            .field("initiator_keys", &self.initiator_keys)
+            .field("responder_keys", &self.responder_keys)
+            .field("static_passkey", &self.static_passkey.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+impl SecurityConfig {
+    /// Creates a new [`SecurityConfig`] with the default values:
+    /// bonding without MITM protection, for a device with no input and no output.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the input and output capabilities of the device.
+    pub fn io_capabilities(&mut self, capabilities: IoCapabilities) -> &mut Self {
+        self.io_capabilities = capabilities;
+        self
+    }
+
+    /// Sets whether the keys are stored, so that later connections are encrypted without pairing again.
+    pub fn bonding(&mut self, bonding: bool) -> &mut Self {
+        self.bonding = bonding;
+        self
+    }
+
+    /// Sets whether protection against MITM attacks is required.
+    ///
+    /// MITM protection needs a passkey or a numeric comparison,
+    /// so the devices must have the input and output capabilities for it.
+    pub fn mitm(&mut self, mitm: bool) -> &mut Self {
+        self.mitm = mitm;
+        self
+    }
+
+    /// Sets whether legacy pairing and pairings that do not meet the MITM requirement are refused.
+    pub fn secure_connections_only(&mut self, secure_connections_only: bool) -> &mut Self {
+        self.secure_connections_only = secure_connections_only;
+        self
+    }
+
+    /// Sets the keys that the client distributes.
+    pub fn initiator_keys(&mut self, keys: KeyDistribution) -> &mut Self {
+        self.initiator_keys = keys;
+        self
+    }
+
+    /// Sets the keys that the server distributes.
+    pub fn responder_keys(&mut self, keys: KeyDistribution) -> &mut Self {
+        self.responder_keys = keys;
+        self
+    }
+
+    /// Sets a fixed passkey to display instead of a random one.
+    ///
+    /// The passkey must have at most six digits.
+    pub fn static_passkey(&mut self, passkey: u32) -> &mut Self {
+        self.static_passkey = Some(passkey);
+        self
+    }
+
+    /// Sets the callback that accepts or rejects the pairing requests of clients.
+    ///
+    /// Without a callback, all the requests are accepted.
+    pub fn on_security_request<C: Fn(esp_bd_addr_t) -> bool + Send + Sync + 'static>(
+        &mut self,
+        callback: C,
+    ) -> &mut Self {
+        self.security_request_callback = Some(Arc::new(callback));
+        self
+    }
+
+    /// Sets the callback that asks the user to enter the passkey displayed by the client.
+    ///
+    /// The pairing waits until the passkey is given to [`GattServer::passkey_reply`].
+    /// Without a callback, pairings that need a passkey entry are rejected.
+    pub fn on_passkey_request<C: Fn(esp_bd_addr_t) + Send + Sync + 'static>(
+        &mut self,
+        callback: C,
+    ) -> &mut Self {
+        self.passkey_request_callback = Some(Arc::new(callback));
+        self
+    }
+
+    /// Sets the callback that shows the passkey that the user must enter on the client.
+    ///
+    /// Without a callback, the passkey is logged.
+    pub fn on_passkey_display<C: Fn(esp_bd_addr_t, u32) + Send + Sync + 'static>(
+        &mut self,
+        callback: C,
+    ) -> &mut Self {
+        self.passkey_display_callback = Some(Arc::new(callback));
+        self
+    }
+
+    /// Sets the callback that shows the number displayed by the client too,
+    /// and asks the user to confirm that they match.
+    ///
+    /// The pairing waits until the answer is given to [`GattServer::confirm_reply`].
+    /// Without a callback, pairings that need a numeric comparison are rejected.
+    pub fn on_numeric_comparison<C: Fn(esp_bd_addr_t, u32) + Send + Sync + 'static>(
+        &mut self,
+        callback: C,
+    ) -> &mut Self {
+        self.numeric_comparison_callback = Some(Arc::new(callback));
+        self
+    }
+
+    /// Sets the callback that is called when a pairing completes or fails.
+    pub fn on_pairing_complete<C: Fn(esp_bd_addr_t, PairingOutcome) + Send + Sync + 'static>(
+        &mut self,
+        callback: C,
+    ) -> &mut Self {
+        self.pairing_complete_callback = Some(Arc::new(callback));
+        self
+    }
+
+    /// Checks that the configuration can be applied.
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        match self.static_passkey {
+            Some(passkey) if passkey > MAX_PASSKEY => Err(Error::InvalidPasskey(passkey)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the authentication requirements requested during pairing.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub(crate) fn authentication_requirements(&self) -> esp_ble_auth_req_t {
+        let mut result = ESP_LE_AUTH_REQ_SC_ONLY;
+
+        if self.bonding {
+            result |= ESP_LE_AUTH_BOND;
+        }
+
+        if self.mitm {
+            result |= ESP_LE_AUTH_REQ_MITM;
+        }
+
+        result as esp_ble_auth_req_t
+    }
+
+    /// Configures the security manager of the stack.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub(crate) fn apply(&self) -> Result<(), Error> {
+        let only_accept_specified = if self.secure_connections_only {
+            ESP_BLE_ONLY_ACCEPT_SPECIFIED_AUTH_ENABLE
+        } else {
+            ESP_BLE_ONLY_ACCEPT_SPECIFIED_AUTH_DISABLE
+        };
+
+        let parameters = [
+            (
+                esp_ble_sm_param_t_ESP_BLE_SM_AUTHEN_REQ_MODE,
+                self.authentication_requirements(),
+            ),
+            (
+                esp_ble_sm_param_t_ESP_BLE_SM_IOCAP_MODE,
+                self.io_capabilities.into(),
+            ),
+            (
+                esp_ble_sm_param_t_ESP_BLE_SM_SET_INIT_KEY,
+                self.initiator_keys.into(),
+            ),
+            (
+                esp_ble_sm_param_t_ESP_BLE_SM_SET_RSP_KEY,
+                self.responder_keys.into(),
+            ),
+            (
+                esp_ble_sm_param_t_ESP_BLE_SM_ONLY_ACCEPT_SPECIFIED_SEC_AUTH,
+                only_accept_specified as u8,
+            ),
+        ];
+
+        for (parameter, value) in parameters {
+            backend().set_security_param(parameter, &[value])?;
+        }
+
+        if let Some(passkey) = self.static_passkey {
+            backend().set_security_param(
+                esp_ble_sm_param_t_ESP_BLE_SM_SET_STATIC_PASSKEY,
+                &passkey.to_ne_bytes(),
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl GattServer {
+    /// Sets the security configuration of the server.
+    ///
+    /// The configuration must be set before starting the server.
+    pub fn security(&mut self, config: SecurityConfig) -> &mut Self {
+        if self.started {
+            warn!("Cannot change the security configuration after the server has started.");
+            return self;
+        }
+
+        self.security = config;
+        self
+    }
+
+    /// Returns the identity addresses of the bonded devices.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server is not started, or if the Bluetooth stack cannot list the bonds.
+    pub fn bonded_devices(&self) -> Result<Vec<esp_bd_addr_t>, Error> {
+        if !self.started {
+            return Err(Error::NotStarted);
+        }
+
+        Ok(backend().bonded_devices()?)
+    }
+
+    /// Removes the bond with a device.
+    ///
+    /// The subscriptions stored for the device are removed when the stack completes the removal.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server is not started, or if the device is not bonded.
+    pub fn remove_bond(&self, address: esp_bd_addr_t) -> Result<(), Error> {
+        if !self.started {
+            return Err(Error::NotStarted);
+        }
+
+        Ok(backend().remove_bond_device(address)?)
+    }
+
+    /// Answers a passkey request, with the passkey entered by the user or `None` to reject the pairing.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server is not started, if no passkey was requested for the device,
+    /// if the passkey has more than six digits, or if the Bluetooth stack rejects the reply.
+    pub fn passkey_reply(
+        &mut self,
+        address: esp_bd_addr_t,
+        passkey: Option<u32>,
+    ) -> Result<(), Error> {
+        if !self.started {
+            return Err(Error::NotStarted);
+        }
+
+        if let Some(passkey) = passkey.filter(|passkey| *passkey > MAX_PASSKEY) {
+            return Err(Error::InvalidPasskey(passkey));
+        }
+
+        self.take_pairing_request(address, |request| request == PairingRequest::Passkey)?;
+
+        if passkey.is_none() {
+            debug!("Rejecting the passkey entry with {:02X?}.", address);
+        }
+
+        Ok(backend().passkey_reply(address, passkey.is_some(), passkey.unwrap_or(0))?)
+    }
+
+    /// Answers a numeric comparison, with whether the user confirmed that the numbers match.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server is not started, if no numeric comparison is waiting for the device,
+    /// or if the Bluetooth stack rejects the reply.
+    pub fn confirm_reply(&mut self, address: esp_bd_addr_t, accept: bool) -> Result<(), Error> {
+        if !self.started {
+            return Err(Error::NotStarted);
+        }
+
+        self.take_pairing_request(address, |request| {
+            matches!(request, PairingRequest::NumericComparison(_))
+        })?;
+
+        if !accept {
+            debug!("Numeric comparison with {:02X?} not confirmed.", address);
+        }
+
+        Ok(backend().confirm_reply(address, accept)?)
+    }
+
+    /// Removes the pairing step of a device that waits for the user, if it is of the expected kind.
+    fn take_pairing_request(
+        &mut self,
+        address: esp_bd_addr_t,
+        expected: impl Fn(PairingRequest) -> bool,
+    ) -> Result<(), Error> {
+        if !self
+            .pairing_requests
+            .get(&address)
+            .map_or(false, |request| expected(*request))
+        {
+            return Err(Error::NoPairingRequest);
+        }
+
+        self.pairing_requests.remove(&address);
+        Ok(())
+    }
+
+    /// Removes the bonds with all the devices.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server is not started, or if a bond cannot be removed.
+    pub fn clear_bonds(&self) -> Result<(), Error> {
+        for address in self.bonded_devices()? {
+            self.remove_bond(address)?;
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn on_security_request(&mut self, param: esp_ble_sec_req_t) {
+        let callback = self.security.security_request_callback.clone();
+
+        self.defer(move || {
+            let accept = callback.map_or(true, |callback| callback(param.bd_addr));
+
+            debug!(
+                "{} pairing request from {:02X?}.",
+                if accept { "Accepting" } else { "Rejecting" },
+                param.bd_addr
+            );
+
+            if let Err(error) = backend().security_response(param.bd_addr, accept) {
+                warn!("Cannot respond to the pairing request: {}.", error);
+            }
+        });
+    }
+
+    pub(crate) fn on_passkey_request(&mut self, param: esp_ble_sec_req_t) {
+        let Some(callback) = self.security.passkey_request_callback.clone() else {
+            warn!("No passkey can be entered for {:02X?}.", param.bd_addr);
+
+            if let Err(error) = backend().passkey_reply(param.bd_addr, false, 0) {
+                warn!("Cannot reply to the passkey request: {}.", error);
+            }
+
+            return;
+        };
+
+        self.pairing_requests
+            .insert(param.bd_addr, PairingRequest::Passkey);
+        self.defer(move || callback(param.bd_addr));
+    }
+
+    pub(crate) fn on_passkey_display(&mut self, param: esp_ble_sec_key_notif_t) {
+        if let Some(callback) = self.security.passkey_display_callback.clone() {
+            self.defer(move || callback(param.bd_addr, param.passkey));
+        } else {
+            info!("Passkey for {:02X?}: {:06}.", param.bd_addr, param.passkey);
+        }
+    }
+
+    pub(crate) fn on_numeric_comparison(&mut self, param: esp_ble_sec_key_notif_t) {
+        let Some(callback) = self.security.numeric_comparison_callback.clone() else {
+            warn!(
+                "No numeric comparison can be confirmed with {:02X?}.",
+                param.bd_addr
+            );
+
+            if let Err(error) = backend().confirm_reply(param.bd_addr, false) {
+                warn!("Cannot reply to the numeric comparison: {}.", error);
+            }
+
+            return;
+        };
+
+        self.pairing_requests.insert(
+            param.bd_addr,
+            PairingRequest::NumericComparison(param.passkey),
+        );
+        self.defer(move || callback(param.bd_addr, param.passkey));
+    }
+
+    pub(crate) fn on_authentication_complete(&mut self, param: esp_ble_auth_cmpl_t) {
+        // A reply that comes too late has nothing to answer.
+        self.pairing_requests.remove(&param.bd_addr);
+
+        let outcome = if param.success {
+            let identity = Self::bonded_identity(param.bd_addr);
+            Self::on_connection_security(param, identity);
+
+            if let Some(identity) = identity {
+                info!("Bonded with {:02X?}.", identity);
+                subscriptions().bond(identity);
+                PairingOutcome::Bonded
+            } else {
+                info!("Paired with {:02X?}.", param.bd_addr);
+                PairingOutcome::Paired
+            }
+        } else {
+            warn!(
+                "Pairing with {:02X?} failed with reason 0x{:02x}.",
+                param.bd_addr, param.fail_reason
+            );
+            PairingOutcome::Failed(param.fail_reason)
+        };
+
+        if let Some(callback) = self.security.pairing_complete_callback.clone() {
+            self.defer(move || callback(param.bd_addr, outcome));
+        }
+    }
+}
@@ -1,8 +1,10 @@
+use crate::sys::*;
+use crate::Error;
 use crate::{
-    gatt_server::characteristic::Characteristic, gatt_server::descriptor::Descriptor,
-    leaky_box_raw, utilities::BleUuid,
+    backend::backend,
+    gatt_server::{characteristic::Characteristic, AttributeTable},
+    utilities::BleUuid,
 };
-use esp_idf_sys::*;
 use log::debug;
 use std::{
     fmt::Formatter,
@@ -15,7 +17,10 @@ pub struct Service {
     name: Option<String>,
     pub(crate) uuid: BleUuid,
     pub(crate) characteristics: Vec<Arc<RwLock<Characteristic>>>,
-    primary: bool,
+    pub(crate) primary: bool,
+    pub(crate) includes: Vec<Include>,
+    reserved_handles: Option<u16>,
+    attribute_table: bool,
     pub(crate) handle: Option<u16>,
 }
 
@@ -28,6 +33,9 @@ impl Service {
             uuid,
             characteristics: Vec::new(),
             primary: false,
+            includes: Vec::new(),
+            reserved_handles: None,
+            attribute_table: false,
             handle: None,
         }
     }
@@ -43,17 +51,54 @@ impl Service {
     /// Sets the [`Service`] as primary.
     ///
     /// If you want your service to show up after an interrogation, you need to set it as primary.
+    /// Otherwise, the service is secondary, and clients can only find it through the services that include it.
     pub fn primary(&mut self) -> &mut Self {
         self.primary = true;
         self
     }
 
+    /// Includes another [`Service`] in the [`Service`].
+    ///
+    /// An include declaration, pointing to the other service, is registered right after the service declaration,
+    /// once both services exist: the included service must be added to the server before this one.
+    /// This is the only way for clients to find a secondary service, which can be included by several services.
+    pub fn include(&mut self, service: &Arc<RwLock<Service>>) -> &mut Self {
+        self.includes.push(Include {
+            service: service.clone(),
+            handle: None,
+        });
+        self
+    }
+
     /// Adds a [`Characteristic`] to the [`Service`].
+    ///
+    /// The characteristics of a service must have different UUIDs.
     pub fn characteristic(&mut self, characteristic: &Arc<RwLock<Characteristic>>) -> &mut Self {
         self.characteristics.push(characteristic.clone());
         self
     }
 
+    /// Reserves a fixed number of attribute handles for the [`Service`], including its declaration.
+    ///
+    /// The services are registered in the order they are declared, each right after the previous one,
+    /// so adding a characteristic to a service moves the handles of the services that follow it.
+    /// Reserving more handles than needed leaves room for the characteristics of later firmware versions,
+    /// so that the handles of the other services stay the same.
+    pub fn reserve_handles(&mut self, count: u16) -> &mut Self {
+        self.reserved_handles = Some(count);
+        self
+    }
+
+    /// Registers the [`Service`] with a single attribute table, instead of one attribute at a time.
+    ///
+    /// The Bluetooth stack creates the service and all its attributes with a single call,
+    /// and assigns all the handles at once, which is faster. See [`AttributeTable`] for the attributes.
+    /// A service registered this way cannot reserve handles.
+    pub fn attribute_table(&mut self) -> &mut Self {
+        self.attribute_table = true;
+        self
+    }
+
     /// Returns a reference to the built [`Service`] behind an `Arc` and an `RwLock`.
     ///
     /// The returned value can be passed to any function of this crate that expects a [`Service`].
@@ -63,6 +108,74 @@ impl Service {
         Arc::new(RwLock::new(self.clone()))
     }
 
+    /// Validates the [`Service`] and returns a reference to it, like [`Self::build`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if one of the characteristics is invalid, or if two of them have the same UUID.
+    pub fn try_build(&self) -> Result<Arc<RwLock<Self>>, Error> {
+        self.validate()?;
+        Ok(self.build())
+    }
+
+    /// Checks that all the characteristics of the [`Service`] can be registered,
+    /// in the handles reserved for it, if any.
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        let mut uuids = Vec::new();
+        for characteristic in &self.characteristics {
+            let characteristic = characteristic.read()?;
+            characteristic.validate()?;
+
+            // The subscriptions of bonded clients are persisted by characteristic UUID.
+            if uuids.contains(&characteristic.uuid) {
+                return Err(Error::InvalidService(
+                    "two characteristics of a service cannot have the same UUID",
+                ));
+            }
+            uuids.push(characteristic.uuid);
+        }
+
+        if self.attribute_table && self.reserved_handles.is_some() {
+            return Err(Error::InvalidService(
+                "an attribute table cannot reserve handles",
+            ));
+        }
+
+        if let Some(reserved) = self.reserved_handles {
+            let count = self.needed_handle_count()?;
+            if count > usize::from(reserved) {
+                return Err(Error::TooManyAttributes {
+                    count,
+                    max_count: usize::from(reserved),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the number of attribute handles used by the [`Service`], including the reserved ones.
+    pub(crate) fn handle_count(&self) -> Result<usize, Error> {
+        let count = self.needed_handle_count()?;
+        Ok(self
+            .reserved_handles
+            .map_or(count, |reserved| count.max(usize::from(reserved))))
+    }
+
+    /// Returns the number of attribute handles needed by the attributes of the [`Service`].
+    ///
+    /// The service declaration uses a handle, followed by a handle per include declaration
+    /// and the handles of its characteristics.
+    fn needed_handle_count(&self) -> Result<usize, Error> {
+        let mut count = 1 + self.includes.len();
+
+        for characteristic in &self.characteristics {
+            count += characteristic.read()?.handle_count();
+        }
+
+        Ok(count)
+    }
+
     pub(crate) fn get_characteristic_by_handle(
         &self,
         handle: u16,
@@ -73,73 +186,54 @@ impl Service {
             .cloned()
     }
 
-    pub(crate) fn get_characteristic_by_id(
-        &self,
-        id: esp_bt_uuid_t,
-    ) -> Option<Arc<RwLock<Characteristic>>> {
-        self.characteristics
-            .iter()
-            .find(|characteristic| characteristic.read().unwrap().uuid == id.into())
-            .cloned()
-    }
-
-    pub(crate) fn get_descriptors_by_id(&self, id: esp_bt_uuid_t) -> Vec<Arc<RwLock<Descriptor>>> {
-        self.characteristics
-            .iter()
-            .filter_map(|characteristic| {
-                characteristic
-                    .read()
-                    .unwrap()
-                    .clone()
-                    .descriptors
-                    .into_iter()
-                    .find(|descriptor| descriptor.read().unwrap().uuid == id.into())
-            })
-            .collect()
-    }
-
-    pub(crate) fn register_self(&mut self, interface: u8) {
+    pub(crate) fn register_self(&mut self, interface: u8) -> Result<(), Error> {
         debug!("Registering {} on interface {}.", &self, interface);
 
+        if self.attribute_table {
+            for characteristic in &self.characteristics {
+                characteristic.write()?.prepare()?;
+            }
+
+            return AttributeTable::try_from(&*self)?.register(interface);
+        }
+
         let id: esp_gatt_srvc_id_t = esp_gatt_srvc_id_t {
             id: self.uuid.into(),
             is_primary: self.primary,
         };
 
-        unsafe {
-            esp_nofail!(esp_ble_gatts_create_service(
-                interface,
-                leaky_box_raw!(id),
-                256, // TODO: count the number of characteristics and descriptors.
-            ));
-        }
-    }
-
-    pub(crate) fn register_characteristics(&mut self) {
-        debug!("Registering {}'s characteristics.", &self);
-
-        // Attention: The characteristics should be registered one after another.
-        // We need to wait for the previous characteristic to be registered before we can register the next one.
+        let handle_count = self.handle_count()?;
+        backend().create_service(
+            interface,
+            id,
+            u16::try_from(handle_count).map_err(|_| Error::TooManyAttributes {
+                count: handle_count,
+                max_count: usize::from(u16::MAX),
+            })?,
+        )?;
 
-        if self.characteristics.is_empty() {
-            return;
-        }
-
-        // Loghi docet.
+        Ok(())
+    }
 
-        let service_handle = self.handle.unwrap();
-        let characteristics = self.characteristics.clone();
-        std::thread::spawn(move || {
-            for c in characteristics {
-                c.write().unwrap().register_self(service_handle);
-                while c.read().unwrap().attribute_handle.is_none() {
-                    std::thread::yield_now();
-                }
-            }
+    /// Forgets the handles assigned by the stack, so that the [`Service`] can be registered again.
+    pub(crate) fn unregister(&mut self) {
+        self.handle = None;
+        self.includes
+            .iter_mut()
+            .for_each(|include| include.handle = None);
+        self.characteristics.iter().for_each(|characteristic| {
+            characteristic.write().unwrap().unregister();
         });
     }
 }
 
+/// An include declaration of a [`Service`], pointing to another service.
+#[derive(Debug, Clone)]
+pub(crate) struct Include {
+    pub(crate) service: Arc<RwLock<Service>>,
+    pub(crate) handle: Option<u16>,
+}
+
 impl std::fmt::Display for Service {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -152,3 +246,109 @@ impl std::fmt::Display for Service {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{gatt_server::Descriptor, utilities::CharacteristicProperties};
+
+    fn handle_count(service: &Arc<RwLock<Service>>) -> usize {
+        service.read().unwrap().handle_count().unwrap()
+    }
+
+    #[test]
+    fn declarations() {
+        assert_eq!(
+            handle_count(&Service::new(BleUuid::Uuid16(0x1000)).build()),
+            1
+        );
+
+        // A characteristic uses a declaration and a value handle.
+        let characteristic = Characteristic::new(BleUuid::Uuid16(0x1001)).build();
+        let service = Service::new(BleUuid::Uuid16(0x1000))
+            .characteristic(&characteristic)
+            .build();
+        assert_eq!(handle_count(&service), 3);
+    }
+
+    #[test]
+    fn implicit_cccd() {
+        let notified = Characteristic::new(BleUuid::Uuid16(0x1001))
+            .properties(CharacteristicProperties::new().notify())
+            .build();
+        let service = Service::new(BleUuid::Uuid16(0x1000))
+            .characteristic(&notified)
+            .build();
+        assert_eq!(handle_count(&service), 4);
+
+        // The count does not change once the CCCD is added, nor with an explicit one.
+        notified.write().unwrap().prepare().unwrap();
+        assert_eq!(handle_count(&service), 4);
+        let indicated = Characteristic::new(BleUuid::Uuid16(0x1002))
+            .properties(CharacteristicProperties::new().indicate())
+            .descriptor(&Descriptor::cccd().build())
+            .build();
+        service.write().unwrap().characteristic(&indicated);
+        assert_eq!(handle_count(&service), 7);
+    }
+
+    #[test]
+    fn user_descriptions() {
+        let named = Characteristic::new(BleUuid::from_uuid128_string(
+            "F9DFBD73-0181-433A-8091-372E0CA8A598",
+        ))
+        .name("Named")
+        .show_name()
+        .build();
+        let unnamed = Characteristic::new(BleUuid::Uuid16(0x1002))
+            .show_name()
+            .build();
+        let service = Service::new(BleUuid::Uuid16(0x1000))
+            .characteristic(&named)
+            .characteristic(&unnamed)
+            .build();
+
+        // Only a named characteristic gets a user description.
+        assert_eq!(handle_count(&service), 6);
+    }
+
+    #[test]
+    fn includes() {
+        let included = Service::new(BleUuid::Uuid16(0x1100)).build();
+        let service = Service::new(BleUuid::Uuid16(0x1000))
+            .include(&included)
+            .include(&included)
+            .build();
+        assert_eq!(handle_count(&service), 3);
+    }
+
+    #[test]
+    fn duplicate_uuids() {
+        let first = Characteristic::new(BleUuid::Uuid16(0x1001)).build();
+        let second = Characteristic::new(BleUuid::Uuid16(0x1001)).build();
+        let service = Service::new(BleUuid::Uuid16(0x1000))
+            .characteristic(&first)
+            .characteristic(&second)
+            .clone();
+        assert!(matches!(service.try_build(), Err(Error::InvalidService(_))));
+
+        second.write().unwrap().uuid = BleUuid::Uuid16(0x1002);
+        assert!(service.try_build().is_ok());
+    }
+
+    #[test]
+    fn reserved_handles() {
+        let characteristic = Characteristic::new(BleUuid::Uuid16(0x1001)).build();
+        let service = Service::new(BleUuid::Uuid16(0x1000))
+            .characteristic(&characteristic)
+            .reserve_handles(8)
+            .build();
+        assert_eq!(handle_count(&service), 8);
+
+        // Reserving fewer handles than needed uses the needed ones.
+        service.write().unwrap().reserve_handles(2);
+        assert_eq!(handle_count(&service), 3);
+        service.write().unwrap().reserve_handles(3);
+        assert_eq!(handle_count(&service), 3);
+    }
+}
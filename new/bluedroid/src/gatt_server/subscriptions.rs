@@ -0,0 +1,279 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    sync::{Mutex, MutexGuard},
+};
+
+use crate::{
+    backend::backend, gatt_server::connection_state::connection_states, sys::esp_bd_addr_t,
+    utilities::Connection, Error,
+};
+use log::{debug, info, warn};
+
+/// The subscriptions of the connected clients.
+///
+/// They are not kept in the CCCDs, because the same descriptor serves every connection.
+/// Like the connection states, they are reset when a server starts and stops.
+static SUBSCRIPTIONS: Mutex<Subscriptions> = Mutex::new(Subscriptions::new());
+
+/// The storage key of the version of the persisted values.
+const VERSION_KEY: &str = "version";
+
+/// The version of the persisted values.
+///
+/// Earlier versions persisted the CCCD values of every client by address and handle,
+/// under keys like `0A0B0C0D-002A` that are never read anymore.
+const VERSION: u8 = 1;
+
+/// Returns the subscriptions of the connected clients.
+pub(crate) fn subscriptions() -> MutexGuard<'static, Subscriptions> {
+    SUBSCRIPTIONS
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+/// Removes the values persisted by earlier versions, once.
+pub(crate) fn migrate_storage() {
+    match backend().storage_get(VERSION_KEY) {
+        Ok(Some(_)) => return,
+        Ok(None) => {}
+        Err(error) => {
+            warn!("Cannot read the storage version: {}.", Error::Nvs(error));
+            return;
+        }
+    }
+
+    info!("Removing the values persisted by an earlier version.");
+    let result = backend()
+        .storage_clear()
+        .and_then(|()| backend().storage_set(VERSION_KEY, &[VERSION]));
+
+    if let Err(error) = result {
+        warn!(
+            "Cannot remove the values persisted by an earlier version: {}.",
+            Error::Nvs(error)
+        );
+    }
+}
+
+/// The CCCD values written by the connected clients.
+///
+/// Values are kept in the state of each connection, for the duration of the connection.
+/// The values of bonded clients are also persisted, keyed by their identity address
+/// and by the UUIDs of the service and of the characteristic, so that they survive
+/// reconnections, address changes, reboots and changes to the attribute handles.
+#[derive(Debug)]
+pub(crate) struct Subscriptions {
+    /// The UUID path of the characteristic of each CCCD, by CCCD handle.
+    paths: BTreeMap<u16, String>,
+    /// Whether the values of bonded clients are persisted.
+    persistent: bool,
+}
+
+impl Subscriptions {
+    const fn new() -> Self {
+        Self {
+            paths: BTreeMap::new(),
+            persistent: true,
+        }
+    }
+
+    /// Sets whether the values of bonded clients are persisted.
+    pub(crate) fn set_persistent(&mut self, persistent: bool) {
+        self.persistent = persistent;
+    }
+
+    /// Registers the UUID path of the characteristic of a CCCD.
+    pub(crate) fn register_cccd(&mut self, handle: u16, path: String) {
+        debug!("Registering CCCD at handle 0x{:04x} for {}.", handle, path);
+        self.paths.insert(handle, path);
+    }
+
+    /// Forgets the CCCDs of a deleted service.
+    ///
+    /// The values persisted for bonded clients are kept, because they are keyed by characteristic.
+    pub(crate) fn unregister_cccds(&mut self, handles: &[u16]) {
+        for handle in handles {
+            debug!("Unregistering CCCD at handle 0x{:04x}.", handle);
+            self.paths.remove(handle);
+        }
+    }
+
+    /// Forgets the registered CCCDs.
+    pub(crate) fn clear(&mut self) {
+        self.paths.clear();
+    }
+
+    /// Returns the value of a CCCD for a connection.
+    ///
+    /// Bonded clients get the value they wrote in a previous connection.
+    pub(crate) fn read(&mut self, conn_id: u16, handle: u16) -> u16 {
+        let mut states = connection_states();
+        let Some(state) = states.get_mut(&conn_id) else {
+            return 0;
+        };
+
+        if let Some(value) = state.cccds.get(&handle) {
+            return *value;
+        }
+
+        let value = self
+            .stored_path(&state.connection, handle)
+            .and_then(|(identity, path)| {
+                Self::load(identity)
+                    .map_err(|error| warn!("Cannot read CCCD values: {}.", error))
+                    .ok()?
+                    .remove(&path)
+            })
+            .unwrap_or(0);
+
+        state.cccds.insert(handle, value);
+        value
+    }
+
+    /// Sets the value of a CCCD for a connection, and persists it if the client is bonded.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the value cannot be persisted. It is kept in memory anyway.
+    pub(crate) fn write(&mut self, conn_id: u16, handle: u16, value: u16) -> Result<(), Error> {
+        let stored_path = {
+            let mut states = connection_states();
+            let Some(state) = states.get_mut(&conn_id) else {
+                return Ok(());
+            };
+
+            state.cccds.insert(handle, value);
+            self.stored_path(&state.connection, handle)
+        };
+
+        let Some((identity, path)) = stored_path else {
+            return Ok(());
+        };
+
+        Self::persist(identity, &[(path, value)])
+    }
+
+    /// Persists the values of the connections of a client that just bonded.
+    pub(crate) fn bond(&mut self, identity: esp_bd_addr_t) {
+        let values: Vec<(String, u16)> = connection_states()
+            .values()
+            .filter(|state| state.connection.identity == identity)
+            .flat_map(|state| {
+                state.cccds.iter().filter_map(|(handle, value)| {
+                    self.stored_path(&state.connection, *handle)
+                        .map(|(_, path)| (path, *value))
+                })
+            })
+            .collect();
+
+        if let Err(error) = Self::persist(identity, &values) {
+            warn!("Cannot persist CCCD values: {}.", error);
+        }
+    }
+
+    /// Forgets the persisted values of a client that is no longer bonded.
+    pub(crate) fn unbond(&mut self, identity: esp_bd_addr_t) {
+        for state in connection_states().values_mut() {
+            if state.connection.identity == identity {
+                state.connection.bonded = false;
+            }
+        }
+
+        if !self.persistent {
+            return;
+        }
+
+        debug!("Removing CCCD values of {:02X?}.", identity);
+        if let Err(error) = backend().storage_remove(&Self::key(identity)) {
+            warn!("Cannot remove CCCD values: {}.", Error::Nvs(error));
+        }
+    }
+
+    /// Returns the identity address and the UUID path under which a value is persisted,
+    /// if it must be persisted.
+    fn stored_path(&self, connection: &Connection, handle: u16) -> Option<(esp_bd_addr_t, String)> {
+        if !self.persistent || !connection.bonded {
+            return None;
+        }
+
+        Some((connection.identity, self.paths.get(&handle)?.clone()))
+    }
+
+    /// Updates the persisted values of a client. Values set to zero are removed.
+    fn persist(identity: esp_bd_addr_t, updates: &[(String, u16)]) -> Result<(), Error> {
+        if updates.is_empty() {
+            return Ok(());
+        }
+
+        let mut values = Self::load(identity)?;
+        for (path, value) in updates {
+            if *value == 0 {
+                values.remove(path);
+            } else {
+                values.insert(path.clone(), *value);
+            }
+        }
+
+        Self::store(identity, &values)
+    }
+
+    /// The storage key of a client: its address, that fits in the 15 characters allowed by NVS.
+    fn key(address: esp_bd_addr_t) -> String {
+        address.iter().fold(String::new(), |mut key, byte| {
+            let _ = write!(key, "{byte:02X}");
+            key
+        })
+    }
+
+    /// Reads the persisted values of a client.
+    ///
+    /// Values are stored as a sequence of UUID paths, prefixed by their length, each followed by the value.
+    fn load(address: esp_bd_addr_t) -> Result<HashMap<String, u16>, Error> {
+        let mut values = HashMap::new();
+        let Some(bytes) = backend()
+            .storage_get(&Self::key(address))
+            .map_err(Error::Nvs)?
+        else {
+            return Ok(values);
+        };
+
+        let mut bytes = bytes.as_slice();
+        while let Some((&length, rest)) = bytes.split_first() {
+            let length = usize::from(length);
+            let (Some(path), Some(value)) = (rest.get(..length), rest.get(length..length + 2))
+            else {
+                warn!("Ignoring malformed CCCD values of {:02X?}.", address);
+                break;
+            };
+
+            values.insert(
+                String::from_utf8_lossy(path).into_owned(),
+                u16::from_le_bytes([value[0], value[1]]),
+            );
+            bytes = &rest[length + 2..];
+        }
+
+        Ok(values)
+    }
+
+    /// Persists the values of a client.
+    #[allow(clippy::cast_possible_truncation)]
+    fn store(address: esp_bd_addr_t, values: &HashMap<String, u16>) -> Result<(), Error> {
+        let key = Self::key(address);
+
+        if values.is_empty() {
+            return backend().storage_remove(&key).map_err(Error::Nvs);
+        }
+
+        let mut bytes = Vec::new();
+        for (path, value) in values {
+            // UUID paths are at most 73 characters long.
+            bytes.push(path.len() as u8);
+            bytes.extend_from_slice(path.as_bytes());
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+
+        backend().storage_set(&key, &bytes).map_err(Error::Nvs)
+    }
+}
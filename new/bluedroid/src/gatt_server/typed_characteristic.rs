@@ -0,0 +1,217 @@
+use crate::{
+    gatt_server::{Characteristic, Confirmation},
+    utilities::{AttError, DecodeError, GattValue},
+    Error,
+};
+
+use crate::sys::{
+    esp_ble_gatts_cb_param_t_gatts_read_evt_param, esp_ble_gatts_cb_param_t_gatts_write_evt_param,
+};
+use log::{debug, warn};
+use std::{
+    marker::PhantomData,
+    ops::Deref,
+    sync::{Arc, RwLock},
+};
+
+/// A built [`Characteristic`] whose value is a [`GattValue`].
+///
+/// It can be passed to any function of this crate that expects a built [`Characteristic`].
+/// Create one with [`Characteristic::typed`].
+pub struct TypedCharacteristic<T> {
+    characteristic: Arc<RwLock<Characteristic>>,
+    value_type: PhantomData<fn(T) -> T>,
+}
+
+impl Characteristic {
+    /// Returns a reference to the built [`Characteristic`], like [`Self::build`],
+    /// with callbacks and setters that encode and decode values of type `T`.
+    ///
+    /// If the maximum length of the value was not set, it is set to the length of `T`, if fixed.
+    #[must_use]
+    pub fn typed<T: GattValue + 'static>(&self) -> TypedCharacteristic<T> {
+        let mut characteristic = self.clone();
+
+        if let (None, Some(length)) = (characteristic.max_length(), T::LENGTH) {
+            #[allow(clippy::cast_possible_truncation)]
+            characteristic.max_value_length(length as u16);
+        }
+
+        TypedCharacteristic {
+            characteristic: Arc::new(RwLock::new(characteristic)),
+            value_type: PhantomData,
+        }
+    }
+}
+
+impl<T: GattValue + 'static> TypedCharacteristic<T> {
+    /// Sets the read callback for this characteristic.
+    ///
+    /// The returned value is encoded and put into the response to the read request,
+    /// while an [`AttError`] rejects the request.
+    /// See [`Characteristic::on_read`] for details.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the characteristic lock is poisoned.
+    #[must_use]
+    pub fn on_read<
+        C: Fn(esp_ble_gatts_cb_param_t_gatts_read_evt_param) -> Result<T, AttError>
+            + Send
+            + Sync
+            + 'static,
+    >(
+        self,
+        callback: C,
+    ) -> Self {
+        self.characteristic
+            .write()
+            .unwrap()
+            .on_read(move |param| callback(param).map(|value| value.encode()));
+
+        self
+    }
+
+    /// Sets the write callback for this characteristic.
+    ///
+    /// The callback receives the decoded value, and returns an [`AttError`] to reject it.
+    /// Malformed write requests are answered with an error and do not reach the callback,
+    /// while malformed write commands, that have no response, are passed to it as an error.
+    /// See [`Characteristic::on_write`] for details.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the characteristic lock is poisoned.
+    #[must_use]
+    pub fn on_write<
+        C: Fn(
+                Result<T, DecodeError>,
+                esp_ble_gatts_cb_param_t_gatts_write_evt_param,
+            ) -> Result<(), AttError>
+            + Send
+            + Sync
+            + 'static,
+    >(
+        self,
+        callback: C,
+    ) -> Self {
+        self.characteristic.write().unwrap().on_write(
+            move |value: Vec<u8>, param| match T::decode(&value) {
+                Ok(value) => callback(Ok(value), param),
+                Err(error) => {
+                    if param.need_rsp {
+                        debug!("Rejecting malformed value {:02X?}: {}.", value, error);
+                    } else {
+                        callback(Err(error), param)?;
+                    }
+
+                    Err(error.into())
+                }
+            },
+        );
+
+        self
+    }
+
+    /// Sets the value of this characteristic.
+    ///
+    /// Errors are logged, see [`Self::try_set_value`] for a fallible version.
+    /// See [`Characteristic::set_value`] for details.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the characteristic lock is poisoned.
+    pub fn set_value(&self, value: T) -> &Self {
+        if let Err(error) = self.try_set_value(value) {
+            warn!(
+                "Cannot set the value of {}: {}.",
+                self.characteristic.read().unwrap(),
+                error
+            );
+        }
+
+        self
+    }
+
+    /// Sets the value of this characteristic.
+    ///
+    /// See [`Characteristic::try_set_value`] for details.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the encoded value is longer than the maximum length,
+    /// or if the Bluetooth stack rejects the value.
+    #[allow(clippy::needless_pass_by_value)]
+    pub fn try_set_value(&self, value: T) -> Result<&Self, Error> {
+        self.characteristic.write()?.try_set_value(value.encode())?;
+        Ok(self)
+    }
+
+    /// Sends a notification with the given value to all the clients subscribed to notifications.
+    ///
+    /// See [`Characteristic::notify`] for details.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server is not started, or if the characteristic is not registered.
+    #[allow(clippy::needless_pass_by_value)]
+    pub fn notify(&self, value: T) -> Result<(), Error> {
+        // The characteristic is released before the server is locked.
+        let notifier = self.characteristic.read()?.notifier()?;
+        notifier.notify(&value.encode())
+    }
+
+    /// Sends a notification with the given value to a client.
+    ///
+    /// See [`Characteristic::notify_to`] for details.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the client is not connected or not subscribed to notifications,
+    /// or if the notification cannot be sent.
+    #[allow(clippy::needless_pass_by_value)]
+    pub fn notify_to(&self, conn_id: u16, value: T) -> Result<(), Error> {
+        let notifier = self.characteristic.read()?.notifier()?;
+        notifier.send_to(conn_id, &value.encode(), false)?;
+        Ok(())
+    }
+
+    /// Sends an indication with the given value to a client.
+    ///
+    /// See [`Characteristic::indicate_to`] for details.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the client is not connected or not subscribed to indications,
+    /// or if the indication cannot be sent.
+    #[allow(clippy::needless_pass_by_value)]
+    pub fn indicate_to(&self, conn_id: u16, value: T) -> Result<Confirmation, Error> {
+        let notifier = self.characteristic.read()?.notifier()?;
+        notifier.send_to(conn_id, &value.encode(), true)
+    }
+}
+
+impl<T> Deref for TypedCharacteristic<T> {
+    type Target = Arc<RwLock<Characteristic>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.characteristic
+    }
+}
+
+impl<T> Clone for TypedCharacteristic<T> {
+    fn clone(&self) -> Self {
+        Self {
+            characteristic: self.characteristic.clone(),
+            value_type: PhantomData,
+        }
+    }
+}
+
+impl<T> std::fmt::Debug for TypedCharacteristic<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("TypedCharacteristic")
+            .field(&self.characteristic)
+            .finish()
+    }
+}
@@ -18,5 +18,30 @@
 #[cfg(not(esp32s2))]
 pub mod gatt_server;
 
+#[cfg(not(esp32s2))]
+mod error;
+#[cfg(not(esp32s2))]
+pub use error::Error;
+
+// Platform abstraction: the real stack on ESP-IDF, a simulated one everywhere else.
+#[cfg(not(esp32s2))]
+mod backend;
+
+#[cfg(not(target_os = "espidf"))]
+pub use backend::simulated;
+
+/// Raw Bluedroid bindings.
+///
+/// On ESP-IDF targets, this is `esp-idf-sys`. On other targets, it is a host-side mirror
+/// of the bindings used by this crate.
+#[cfg(target_os = "espidf")]
+pub use esp_idf_sys as sys;
+
+#[cfg(not(target_os = "espidf"))]
+pub use backend::sys;
+
 #[cfg(not(esp32s2))]
 pub mod utilities;
+
+#[cfg(not(esp32s2))]
+pub mod services;
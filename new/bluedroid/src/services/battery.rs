@@ -0,0 +1,77 @@
+use std::{
+    ops::Deref,
+    sync::{Arc, RwLock},
+};
+
+use crate::{
+    gatt_server::{Characteristic, Service, TypedCharacteristic},
+    utilities::{AttributePermissions, BleUuid, CharacteristicProperties},
+    Error,
+};
+
+/// The Battery service (`0x180F`), with a Battery Level characteristic (`0x2A19`).
+///
+/// The level is a percentage, that clients can read and be notified of.
+#[derive(Debug, Clone)]
+pub struct BatteryService {
+    service: Arc<RwLock<Service>>,
+    level: TypedCharacteristic<u8>,
+}
+
+impl BatteryService {
+    /// The UUID of the Battery service.
+    pub const UUID: BleUuid = BleUuid::from_uuid16(0x180F);
+
+    /// The UUID of the Battery Level characteristic.
+    pub const LEVEL_UUID: BleUuid = BleUuid::from_uuid16(0x2A19);
+
+    /// Creates a new [`BatteryService`] with the given battery level, in percent.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidValue`] if the level is above 100%.
+    pub fn new(level: u8) -> Result<Self, Error> {
+        let battery_level = Characteristic::new(Self::LEVEL_UUID)
+            .name("Battery Level")
+            .permissions(AttributePermissions::new().read())
+            .properties(CharacteristicProperties::new().read().notify())
+            .typed::<u8>();
+
+        let service = Self {
+            service: Service::new(Self::UUID)
+                .name("Battery")
+                .primary()
+                .characteristic(&battery_level)
+                .build(),
+            level: battery_level,
+        };
+
+        service.set_level(level)?;
+        Ok(service)
+    }
+
+    /// Sets the battery level, in percent, and notifies the subscribed clients.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidValue`] if the level is above 100%,
+    /// or an error if the Bluetooth stack rejects the value.
+    pub fn set_level(&self, level: u8) -> Result<&Self, Error> {
+        if level > 100 {
+            return Err(Error::InvalidValue(
+                "the battery level must be at most 100%",
+            ));
+        }
+
+        self.level.try_set_value(level)?;
+        Ok(self)
+    }
+}
+
+impl Deref for BatteryService {
+    type Target = Arc<RwLock<Service>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.service
+    }
+}
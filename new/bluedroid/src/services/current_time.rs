@@ -0,0 +1,203 @@
+use std::{
+    ops::Deref,
+    sync::{Arc, RwLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    gatt_server::{Characteristic, Service, TypedCharacteristic},
+    utilities::{AttributePermissions, BleUuid, CharacteristicProperties, DecodeError, GattValue},
+    Error,
+};
+
+/// A date and time, as encoded in the Current Time characteristic (`0x2A2B`).
+///
+/// Unknown fields are zero.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CurrentTime {
+    /// The year, from 1582 to 9999.
+    pub year: u16,
+    /// The month, from 1 to 12.
+    pub month: u8,
+    /// The day of the month, from 1 to 31.
+    pub day: u8,
+    /// The hours, from 0 to 23.
+    pub hours: u8,
+    /// The minutes, from 0 to 59.
+    pub minutes: u8,
+    /// The seconds, from 0 to 59.
+    pub seconds: u8,
+    /// The day of the week, from 1 (Monday) to 7 (Sunday).
+    pub day_of_week: u8,
+    /// The fractions of the second, in units of 1/256 s.
+    pub fractions256: u8,
+    /// Why the time was last adjusted: manually (bit 0), from an external reference (bit 1),
+    /// for a change of time zone (bit 2) or of daylight saving time (bit 3).
+    pub adjust_reason: u8,
+}
+
+impl CurrentTime {
+    /// Returns whether the fields are in their ranges, or unknown.
+    const fn is_valid(&self) -> bool {
+        (self.year == 0 || (self.year >= 1582 && self.year <= 9999))
+            && self.month <= 12
+            && self.day <= 31
+            && self.hours <= 23
+            && self.minutes <= 59
+            && self.seconds <= 59
+            && self.day_of_week <= 7
+            && self.adjust_reason <= 0x0F
+    }
+}
+
+impl From<SystemTime> for CurrentTime {
+    /// Converts a system time to a UTC date and time, without an adjust reason.
+    ///
+    /// Times before 1970 are converted to the Unix epoch.
+    #[allow(clippy::cast_possible_truncation)]
+    fn from(time: SystemTime) -> Self {
+        let elapsed = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let days = elapsed.as_secs() / 86_400;
+        let seconds = elapsed.as_secs() % 86_400;
+
+        // Days to civil date, from Howard Hinnant's `civil_from_days`, with eras starting on March 1st.
+        let shifted = days + 719_468;
+        let era = shifted / 146_097;
+        let day_of_era = shifted % 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+        let month = if shifted_month < 10 {
+            shifted_month + 3
+        } else {
+            shifted_month - 9
+        };
+        let year = year_of_era + era * 400 + u64::from(month <= 2);
+
+        Self {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hours: (seconds / 3600) as u8,
+            minutes: (seconds / 60 % 60) as u8,
+            seconds: (seconds % 60) as u8,
+            // The Unix epoch was a Thursday.
+            day_of_week: ((days + 3) % 7 + 1) as u8,
+            fractions256: (u64::from(elapsed.subsec_nanos()) * 256 / 1_000_000_000) as u8,
+            adjust_reason: 0,
+        }
+    }
+}
+
+impl GattValue for CurrentTime {
+    const LENGTH: Option<usize> = Some(10);
+
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = self.year.encode();
+        bytes.extend([
+            self.month,
+            self.day,
+            self.hours,
+            self.minutes,
+            self.seconds,
+            self.day_of_week,
+            self.fractions256,
+            self.adjust_reason,
+        ]);
+
+        bytes
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let bytes = <[u8; 10]>::decode(bytes)?;
+        let time = Self {
+            year: u16::from_le_bytes([bytes[0], bytes[1]]),
+            month: bytes[2],
+            day: bytes[3],
+            hours: bytes[4],
+            minutes: bytes[5],
+            seconds: bytes[6],
+            day_of_week: bytes[7],
+            fractions256: bytes[8],
+            adjust_reason: bytes[9],
+        };
+
+        if time.is_valid() {
+            Ok(time)
+        } else {
+            Err(DecodeError::InvalidValue)
+        }
+    }
+}
+
+/// The Current Time service (`0x1805`), with a Current Time characteristic (`0x2A2B`).
+///
+/// Clients can read the time and be notified when it is adjusted.
+#[derive(Debug, Clone)]
+pub struct CurrentTimeService {
+    service: Arc<RwLock<Service>>,
+    current_time: TypedCharacteristic<CurrentTime>,
+}
+
+impl CurrentTimeService {
+    /// The UUID of the Current Time service.
+    pub const UUID: BleUuid = BleUuid::from_uuid16(0x1805);
+
+    /// The UUID of the Current Time characteristic.
+    pub const CURRENT_TIME_UUID: BleUuid = BleUuid::from_uuid16(0x2A2B);
+
+    /// Creates a new [`CurrentTimeService`] with the given time.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidValue`] if a field of the time is out of its range.
+    pub fn new(time: CurrentTime) -> Result<Self, Error> {
+        let current_time = Characteristic::new(Self::CURRENT_TIME_UUID)
+            .name("Current Time")
+            .permissions(AttributePermissions::new().read())
+            .properties(CharacteristicProperties::new().read().notify())
+            .typed::<CurrentTime>();
+
+        let service = Self {
+            service: Service::new(Self::UUID)
+                .name("Current Time")
+                .primary()
+                .characteristic(&current_time)
+                .build(),
+            current_time,
+        };
+
+        service.set_time(time)?;
+        Ok(service)
+    }
+
+    /// Sets the time, and notifies the subscribed clients.
+    ///
+    /// The specification expects notifications only when the time is adjusted,
+    /// with the reason of the adjustment, and not while it flows normally.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidValue`] if a field of the time is out of its range,
+    /// or an error if the Bluetooth stack rejects the value.
+    pub fn set_time(&self, time: CurrentTime) -> Result<&Self, Error> {
+        if !time.is_valid() {
+            return Err(Error::InvalidValue(
+                "a field of the current time is out of its range",
+            ));
+        }
+
+        self.current_time.try_set_value(time)?;
+        Ok(self)
+    }
+}
+
+impl Deref for CurrentTimeService {
+    type Target = Arc<RwLock<Service>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.service
+    }
+}
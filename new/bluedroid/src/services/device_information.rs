@@ -0,0 +1,248 @@
+use std::{
+    ops::Deref,
+    sync::{Arc, RwLock},
+};
+
+use crate::{
+    gatt_server::{Characteristic, Service},
+    utilities::{AttributePermissions, BleUuid, CharacteristicProperties, DecodeError, GattValue},
+    Error,
+};
+
+/// The maximum length of the strings of the Device Information service, in bytes.
+const MAX_STRING_LENGTH: u16 = 64;
+
+/// The organisation that assigned a vendor ID.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum VendorIdSource {
+    /// The Bluetooth SIG.
+    Bluetooth = 1,
+    /// The USB Implementers Forum.
+    Usb = 2,
+}
+
+/// The vendor and product IDs of a device, as encoded in the Plug and Play ID characteristic (`0x2A50`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PnpId {
+    /// The organisation that assigned the vendor ID.
+    pub vendor_id_source: VendorIdSource,
+    /// The vendor ID.
+    pub vendor_id: u16,
+    /// The product ID, assigned by the vendor.
+    pub product_id: u16,
+    /// The product version, assigned by the vendor.
+    pub product_version: u16,
+}
+
+impl GattValue for PnpId {
+    const LENGTH: Option<usize> = Some(7);
+
+    fn encode(&self) -> Vec<u8> {
+        (
+            self.vendor_id_source as u8,
+            self.vendor_id,
+            self.product_id,
+            self.product_version,
+        )
+            .encode()
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let (source, vendor_id, product_id, product_version) =
+            <(u8, u16, u16, u16)>::decode(bytes)?;
+
+        let vendor_id_source = match source {
+            1 => VendorIdSource::Bluetooth,
+            2 => VendorIdSource::Usb,
+            _ => return Err(DecodeError::InvalidValue),
+        };
+
+        Ok(Self {
+            vendor_id_source,
+            vendor_id,
+            product_id,
+            product_version,
+        })
+    }
+}
+
+/// The Device Information service (`0x180A`).
+///
+/// Each setter adds its read-only characteristic the first time it is called, and updates it afterwards.
+/// Characteristics can only be added before the server is started.
+#[derive(Debug, Clone)]
+pub struct DeviceInformationService {
+    service: Arc<RwLock<Service>>,
+}
+
+impl DeviceInformationService {
+    /// The UUID of the Device Information service.
+    pub const UUID: BleUuid = BleUuid::from_uuid16(0x180A);
+
+    /// The UUID of the Manufacturer Name String characteristic.
+    pub const MANUFACTURER_NAME_UUID: BleUuid = BleUuid::from_uuid16(0x2A29);
+    /// The UUID of the Model Number String characteristic.
+    pub const MODEL_NUMBER_UUID: BleUuid = BleUuid::from_uuid16(0x2A24);
+    /// The UUID of the Serial Number String characteristic.
+    pub const SERIAL_NUMBER_UUID: BleUuid = BleUuid::from_uuid16(0x2A25);
+    /// The UUID of the Hardware Revision String characteristic.
+    pub const HARDWARE_REVISION_UUID: BleUuid = BleUuid::from_uuid16(0x2A27);
+    /// The UUID of the Firmware Revision String characteristic.
+    pub const FIRMWARE_REVISION_UUID: BleUuid = BleUuid::from_uuid16(0x2A26);
+    /// The UUID of the Software Revision String characteristic.
+    pub const SOFTWARE_REVISION_UUID: BleUuid = BleUuid::from_uuid16(0x2A28);
+    /// The UUID of the Plug and Play ID characteristic.
+    pub const PNP_ID_UUID: BleUuid = BleUuid::from_uuid16(0x2A50);
+
+    /// Creates a new [`DeviceInformationService`], without characteristics.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            service: Service::new(Self::UUID)
+                .name("Device Information")
+                .primary()
+                .build(),
+        }
+    }
+
+    /// Sets the manufacturer name.
+    ///
+    /// # Errors
+    ///
+    /// See [`Self::pnp_id`].
+    pub fn manufacturer_name<S: Into<String>>(&self, name: S) -> Result<&Self, Error> {
+        self.set(
+            Self::MANUFACTURER_NAME_UUID,
+            "Manufacturer Name String",
+            name.into(),
+        )
+    }
+
+    /// Sets the model number.
+    ///
+    /// # Errors
+    ///
+    /// See [`Self::pnp_id`].
+    pub fn model_number<S: Into<String>>(&self, model: S) -> Result<&Self, Error> {
+        self.set(Self::MODEL_NUMBER_UUID, "Model Number String", model.into())
+    }
+
+    /// Sets the serial number.
+    ///
+    /// # Errors
+    ///
+    /// See [`Self::pnp_id`].
+    pub fn serial_number<S: Into<String>>(&self, serial: S) -> Result<&Self, Error> {
+        self.set(
+            Self::SERIAL_NUMBER_UUID,
+            "Serial Number String",
+            serial.into(),
+        )
+    }
+
+    /// Sets the hardware revision.
+    ///
+    /// # Errors
+    ///
+    /// See [`Self::pnp_id`].
+    pub fn hardware_revision<S: Into<String>>(&self, revision: S) -> Result<&Self, Error> {
+        self.set(
+            Self::HARDWARE_REVISION_UUID,
+            "Hardware Revision String",
+            revision.into(),
+        )
+    }
+
+    /// Sets the firmware revision.
+    ///
+    /// # Errors
+    ///
+    /// See [`Self::pnp_id`].
+    pub fn firmware_revision<S: Into<String>>(&self, revision: S) -> Result<&Self, Error> {
+        self.set(
+            Self::FIRMWARE_REVISION_UUID,
+            "Firmware Revision String",
+            revision.into(),
+        )
+    }
+
+    /// Sets the software revision.
+    ///
+    /// # Errors
+    ///
+    /// See [`Self::pnp_id`].
+    pub fn software_revision<S: Into<String>>(&self, revision: S) -> Result<&Self, Error> {
+        self.set(
+            Self::SOFTWARE_REVISION_UUID,
+            "Software Revision String",
+            revision.into(),
+        )
+    }
+
+    /// Sets the vendor and product IDs.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::AlreadyStarted`] if the characteristic is new and the service is already registered,
+    /// [`Error::ValueTooLong`] if a string is longer than 64 bytes,
+    /// or an error if the Bluetooth stack rejects the value.
+    pub fn pnp_id(&self, id: PnpId) -> Result<&Self, Error> {
+        self.set(Self::PNP_ID_UUID, "Plug and Play ID", id)
+    }
+
+    /// Sets the value of the characteristic with the given UUID, adding it if needed.
+    fn set<T: GattValue + 'static>(
+        &self,
+        uuid: BleUuid,
+        name: &'static str,
+        value: T,
+    ) -> Result<&Self, Error> {
+        let mut service = self.service.write()?;
+
+        let existing = service
+            .characteristics
+            .iter()
+            .find(|characteristic| {
+                matches!(characteristic.read(), Ok(characteristic) if characteristic.uuid == uuid)
+            })
+            .cloned();
+
+        if let Some(characteristic) = existing {
+            characteristic.write()?.try_set_value(value.encode())?;
+        } else if service.handle.is_some() {
+            return Err(Error::AlreadyStarted);
+        } else {
+            let mut characteristic = Characteristic::new(uuid);
+            characteristic
+                .name(name)
+                .permissions(AttributePermissions::new().read())
+                .properties(CharacteristicProperties::new().read());
+
+            // Strings have no fixed length, the other values set their own.
+            if T::LENGTH.is_none() {
+                characteristic.max_value_length(MAX_STRING_LENGTH);
+            }
+
+            let characteristic = characteristic.typed::<T>();
+            characteristic.try_set_value(value)?;
+            service.characteristic(&characteristic);
+        }
+
+        Ok(self)
+    }
+}
+
+impl Default for DeviceInformationService {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Deref for DeviceInformationService {
+    type Target = Arc<RwLock<Service>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.service
+    }
+}
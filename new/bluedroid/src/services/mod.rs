@@ -0,0 +1,30 @@
+//! Standard services defined by the Bluetooth SIG, ready to be added to a [`Profile`].
+//!
+//! Each service dereferences to its built [`Service`], so it can be passed to any function
+//! of this crate that expects one, and has setters that encode and update its values.
+//!
+//! # Notes
+//!
+//! The Generic Access (`0x1800`) and Generic Attribute (`0x1801`) services are provided by the Bluetooth stack,
+//! and must not be declared again. The device name, the appearance and the preferred connection parameters
+//! of the Generic Access service are set on the [`GattServer`].
+//!
+//! [`Profile`]: crate::gatt_server::Profile
+//! [`Service`]: crate::gatt_server::Service
+//! [`GattServer`]: crate::gatt_server::GattServer
+
+// Battery service: public.
+mod battery;
+pub use battery::BatteryService;
+
+// Current time service: public.
+mod current_time;
+pub use current_time::{CurrentTime, CurrentTimeService};
+
+// Device information service: public.
+mod device_information;
+pub use device_information::{DeviceInformationService, PnpId, VendorIdSource};
+
+// Tx power service: public.
+mod tx_power;
+pub use tx_power::TxPowerService;
@@ -0,0 +1,78 @@
+use std::{
+    ops::Deref,
+    sync::{Arc, RwLock},
+};
+
+use crate::{
+    gatt_server::{Characteristic, Service, TypedCharacteristic},
+    utilities::{AttributePermissions, BleUuid, CharacteristicProperties},
+    Error,
+};
+
+/// The Tx Power service (`0x1804`), with a Tx Power Level characteristic (`0x2A07`).
+///
+/// The level is the transmit power of the device, in dBm, from -100 to 20 dBm.
+/// Clients use it to estimate the path loss from the received signal strength.
+#[derive(Debug, Clone)]
+pub struct TxPowerService {
+    service: Arc<RwLock<Service>>,
+    level: TypedCharacteristic<i8>,
+}
+
+impl TxPowerService {
+    /// The UUID of the Tx Power service.
+    pub const UUID: BleUuid = BleUuid::from_uuid16(0x1804);
+
+    /// The UUID of the Tx Power Level characteristic.
+    pub const LEVEL_UUID: BleUuid = BleUuid::from_uuid16(0x2A07);
+
+    /// Creates a new [`TxPowerService`] with the given transmit power, in dBm.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidValue`] if the level is not between -100 and 20 dBm.
+    pub fn new(level: i8) -> Result<Self, Error> {
+        let tx_power_level = Characteristic::new(Self::LEVEL_UUID)
+            .name("Tx Power Level")
+            .permissions(AttributePermissions::new().read())
+            .properties(CharacteristicProperties::new().read())
+            .typed::<i8>();
+
+        let service = Self {
+            service: Service::new(Self::UUID)
+                .name("Tx Power")
+                .primary()
+                .characteristic(&tx_power_level)
+                .build(),
+            level: tx_power_level,
+        };
+
+        service.set_level(level)?;
+        Ok(service)
+    }
+
+    /// Sets the transmit power, in dBm.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidValue`] if the level is not between -100 and 20 dBm,
+    /// or an error if the Bluetooth stack rejects the value.
+    pub fn set_level(&self, level: i8) -> Result<&Self, Error> {
+        if !(-100..=20).contains(&level) {
+            return Err(Error::InvalidValue(
+                "the transmit power must be between -100 and 20 dBm",
+            ));
+        }
+
+        self.level.try_set_value(level)?;
+        Ok(self)
+    }
+}
+
+impl Deref for TxPowerService {
+    type Target = Arc<RwLock<Service>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.service
+    }
+}
@@ -0,0 +1,42 @@
+use crate::sys::{
+    esp_ble_addr_type_t, esp_ble_addr_type_t_BLE_ADDR_TYPE_PUBLIC,
+    esp_ble_addr_type_t_BLE_ADDR_TYPE_RANDOM, esp_ble_addr_type_t_BLE_ADDR_TYPE_RPA_PUBLIC,
+    esp_ble_addr_type_t_BLE_ADDR_TYPE_RPA_RANDOM,
+};
+
+/// The type of a Bluetooth device address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AddressType {
+    /// A public device address.
+    #[default]
+    Public,
+    /// A random device address, static or private.
+    Random,
+    /// A resolvable private address, or the public address if the peer's IRK is unknown.
+    RpaPublic,
+    /// A resolvable private address, or the random static address if the peer's IRK is unknown.
+    RpaRandom,
+}
+
+impl From<AddressType> for esp_ble_addr_type_t {
+    fn from(address_type: AddressType) -> Self {
+        match address_type {
+            AddressType::Public => esp_ble_addr_type_t_BLE_ADDR_TYPE_PUBLIC,
+            AddressType::Random => esp_ble_addr_type_t_BLE_ADDR_TYPE_RANDOM,
+            AddressType::RpaPublic => esp_ble_addr_type_t_BLE_ADDR_TYPE_RPA_PUBLIC,
+            AddressType::RpaRandom => esp_ble_addr_type_t_BLE_ADDR_TYPE_RPA_RANDOM,
+        }
+    }
+}
+
+impl From<esp_ble_addr_type_t> for AddressType {
+    #[allow(non_upper_case_globals)]
+    fn from(address_type: esp_ble_addr_type_t) -> Self {
+        match address_type {
+            esp_ble_addr_type_t_BLE_ADDR_TYPE_RANDOM => Self::Random,
+            esp_ble_addr_type_t_BLE_ADDR_TYPE_RPA_PUBLIC => Self::RpaPublic,
+            esp_ble_addr_type_t_BLE_ADDR_TYPE_RPA_RANDOM => Self::RpaRandom,
+            _ => Self::Public,
+        }
+    }
+}
@@ -0,0 +1,128 @@
+//! AES-128 encryption, used to resolve private addresses and to hash the simulated database.
+//!
+//! This is a plain implementation, without any protection against timing attacks:
+//! it only handles IRKs and public attributes.
+
+/// The AES substitution box.
+const S_BOX: [u8; 256] = [
+    0x63, 0x7C, 0x77, 0x7B, 0xF2, 0x6B, 0x6F, 0xC5, 0x30, 0x01, 0x67, 0x2B, 0xFE, 0xD7, 0xAB, 0x76,
+    0xCA, 0x82, 0xC9, 0x7D, 0xFA, 0x59, 0x47, 0xF0, 0xAD, 0xD4, 0xA2, 0xAF, 0x9C, 0xA4, 0x72, 0xC0,
+    0xB7, 0xFD, 0x93, 0x26, 0x36, 0x3F, 0xF7, 0xCC, 0x34, 0xA5, 0xE5, 0xF1, 0x71, 0xD8, 0x31, 0x15,
+    0x04, 0xC7, 0x23, 0xC3, 0x18, 0x96, 0x05, 0x9A, 0x07, 0x12, 0x80, 0xE2, 0xEB, 0x27, 0xB2, 0x75,
+    0x09, 0x83, 0x2C, 0x1A, 0x1B, 0x6E, 0x5A, 0xA0, 0x52, 0x3B, 0xD6, 0xB3, 0x29, 0xE3, 0x2F, 0x84,
+    0x53, 0xD1, 0x00, 0xED, 0x20, 0xFC, 0xB1, 0x5B, 0x6A, 0xCB, 0xBE, 0x39, 0x4A, 0x4C, 0x58, 0xCF,
+    0xD0, 0xEF, 0xAA, 0xFB, 0x43, 0x4D, 0x33, 0x85, 0x45, 0xF9, 0x02, 0x7F, 0x50, 0x3C, 0x9F, 0xA8,
+    0x51, 0xA3, 0x40, 0x8F, 0x92, 0x9D, 0x38, 0xF5, 0xBC, 0xB6, 0xDA, 0x21, 0x10, 0xFF, 0xF3, 0xD2,
+    0xCD, 0x0C, 0x13, 0xEC, 0x5F, 0x97, 0x44, 0x17, 0xC4, 0xA7, 0x7E, 0x3D, 0x64, 0x5D, 0x19, 0x73,
+    0x60, 0x81, 0x4F, 0xDC, 0x22, 0x2A, 0x90, 0x88, 0x46, 0xEE, 0xB8, 0x14, 0xDE, 0x5E, 0x0B, 0xDB,
+    0xE0, 0x32, 0x3A, 0x0A, 0x49, 0x06, 0x24, 0x5C, 0xC2, 0xD3, 0xAC, 0x62, 0x91, 0x95, 0xE4, 0x79,
+    0xE7, 0xC8, 0x37, 0x6D, 0x8D, 0xD5, 0x4E, 0xA9, 0x6C, 0x56, 0xF4, 0xEA, 0x65, 0x7A, 0xAE, 0x08,
+    0xBA, 0x78, 0x25, 0x2E, 0x1C, 0xA6, 0xB4, 0xC6, 0xE8, 0xDD, 0x74, 0x1F, 0x4B, 0xBD, 0x8B, 0x8A,
+    0x70, 0x3E, 0xB5, 0x66, 0x48, 0x03, 0xF6, 0x0E, 0x61, 0x35, 0x57, 0xB9, 0x86, 0xC1, 0x1D, 0x9E,
+    0xE1, 0xF8, 0x98, 0x11, 0x69, 0xD9, 0x8E, 0x94, 0x9B, 0x1E, 0x87, 0xE9, 0xCE, 0x55, 0x28, 0xDF,
+    0x8C, 0xA1, 0x89, 0x0D, 0xBF, 0xE6, 0x42, 0x68, 0x41, 0x99, 0x2D, 0x0F, 0xB0, 0x54, 0xBB, 0x16,
+];
+
+/// The round constants of the AES key expansion.
+const ROUND_CONSTANTS: [u8; 10] = [0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80, 0x1B, 0x36];
+
+/// Encrypts a block with an AES-128 key, both in the byte order of the standard.
+pub(crate) fn aes128_encrypt(key: &[u8; 16], block: [u8; 16]) -> [u8; 16] {
+    encrypt(&expand_key(key), block)
+}
+
+/// Expands an AES-128 key into the keys of the eleven rounds.
+fn expand_key(key: &[u8; 16]) -> [[u8; 16]; 11] {
+    let mut words = [[0u8; 4]; 44];
+    for (word, bytes) in words.iter_mut().zip(key.chunks_exact(4)) {
+        word.copy_from_slice(bytes);
+    }
+
+    for index in 4..44 {
+        let mut word = words[index - 1];
+        if index % 4 == 0 {
+            word.rotate_left(1);
+            word = word.map(|byte| S_BOX[usize::from(byte)]);
+            word[0] ^= ROUND_CONSTANTS[index / 4 - 1];
+        }
+
+        for (byte, previous) in word.iter_mut().zip(words[index - 4]) {
+            *byte ^= previous;
+        }
+        words[index] = word;
+    }
+
+    let mut round_keys = [[0; 16]; 11];
+    for (round_key, words) in round_keys.iter_mut().zip(words.chunks_exact(4)) {
+        round_key.copy_from_slice(&words.concat());
+    }
+
+    round_keys
+}
+
+/// Encrypts a block with the round keys. The state is stored column by column, like the block.
+fn encrypt(round_keys: &[[u8; 16]; 11], block: [u8; 16]) -> [u8; 16] {
+    let mut state = block;
+    xor(&mut state, &round_keys[0]);
+
+    for (round, round_key) in round_keys.iter().enumerate().skip(1) {
+        state = state.map(|byte| S_BOX[usize::from(byte)]);
+
+        // Shift the rows: row `r` rotates left by `r` columns.
+        let shifted = state;
+        for (index, byte) in state.iter_mut().enumerate() {
+            let (column, row) = (index / 4, index % 4);
+            *byte = shifted[(column + row) % 4 * 4 + row];
+        }
+
+        // The last round does not mix the columns.
+        if round < 10 {
+            for column in state.chunks_exact_mut(4) {
+                let [a, b, c, d] = [column[0], column[1], column[2], column[3]];
+                let all = a ^ b ^ c ^ d;
+                column[0] ^= all ^ times_two(a ^ b);
+                column[1] ^= all ^ times_two(b ^ c);
+                column[2] ^= all ^ times_two(c ^ d);
+                column[3] ^= all ^ times_two(d ^ a);
+            }
+        }
+
+        xor(&mut state, round_key);
+    }
+
+    state
+}
+
+/// Multiplies a byte by two in the AES field.
+const fn times_two(byte: u8) -> u8 {
+    byte << 1 ^ if byte & 0x80 == 0 { 0 } else { 0x1B }
+}
+
+fn xor(block: &mut [u8; 16], other: &[u8; 16]) {
+    for (byte, other) in block.iter_mut().zip(other) {
+        *byte ^= other;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fips_197() {
+        let key = [
+            0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B, 0x0C, 0x0D,
+            0x0E, 0x0F,
+        ];
+        let plaintext = [
+            0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xAA, 0xBB, 0xCC, 0xDD,
+            0xEE, 0xFF,
+        ];
+        let ciphertext = [
+            0x69, 0xC4, 0xE0, 0xD8, 0x6A, 0x7B, 0x04, 0x30, 0xD8, 0xCD, 0xB7, 0x80, 0x70, 0xB4,
+            0xC5, 0x5A,
+        ];
+
+        assert_eq!(aes128_encrypt(&key, plaintext), ciphertext);
+    }
+}
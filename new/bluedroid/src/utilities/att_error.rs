@@ -0,0 +1,126 @@
+use crate::sys::esp_gatt_status_t;
+use crate::utilities::DecodeError;
+
+/// An ATT error, sent to the client in response to a rejected request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum AttError {
+    /// The attribute cannot be read.
+    ReadNotPermitted,
+    /// The attribute cannot be written.
+    WriteNotPermitted,
+    /// The link must be authenticated before accessing the attribute.
+    InsufficientAuthentication,
+    /// The request is not supported by the server.
+    RequestNotSupported,
+    /// The offset is past the end of the attribute value.
+    InvalidOffset,
+    /// The client must be authorised before accessing the attribute.
+    InsufficientAuthorization,
+    /// Too many prepared writes have been queued.
+    PrepareQueueFull,
+    /// The attribute cannot be read or written with a long request.
+    AttributeNotLong,
+    /// The encryption key is too short to access the attribute.
+    InsufficientEncryptionKeySize,
+    /// The value does not have a valid length.
+    InvalidAttributeValueLength,
+    /// The request could not be completed for an unspecified reason.
+    UnlikelyError,
+    /// The link must be encrypted before accessing the attribute.
+    InsufficientEncryption,
+    /// The server does not have enough resources to complete the request.
+    InsufficientResources,
+    /// The value is not allowed.
+    ValueNotAllowed,
+    /// An application error.
+    ///
+    /// Its meaning is defined by the application. The code must be in the `0x80..=0x9F` range,
+    /// otherwise [`Self::UnlikelyError`] is sent instead.
+    Application(u8),
+    /// The write request was rejected.
+    WriteRequestRejected,
+    /// The client characteristic configuration descriptor is improperly configured.
+    CccdImproperlyConfigured,
+    /// A request is already in progress.
+    ProcedureAlreadyInProgress,
+    /// The value is out of range.
+    OutOfRange,
+}
+
+impl AttError {
+    /// Returns the code of the error, as sent to the client.
+    #[must_use]
+    pub const fn code(self) -> u8 {
+        match self {
+            Self::ReadNotPermitted => 0x02,
+            Self::WriteNotPermitted => 0x03,
+            Self::InsufficientAuthentication => 0x05,
+            Self::RequestNotSupported => 0x06,
+            Self::InvalidOffset => 0x07,
+            Self::InsufficientAuthorization => 0x08,
+            Self::PrepareQueueFull => 0x09,
+            Self::AttributeNotLong => 0x0B,
+            Self::InsufficientEncryptionKeySize => 0x0C,
+            Self::InvalidAttributeValueLength => 0x0D,
+            Self::UnlikelyError => 0x0E,
+            Self::InsufficientEncryption => 0x0F,
+            Self::InsufficientResources => 0x11,
+            Self::ValueNotAllowed => 0x13,
+            Self::Application(code @ 0x80..=0x9F) => code,
+            Self::Application(_) => Self::UnlikelyError.code(),
+            Self::WriteRequestRejected => 0xFC,
+            Self::CccdImproperlyConfigured => 0xFD,
+            Self::ProcedureAlreadyInProgress => 0xFE,
+            Self::OutOfRange => 0xFF,
+        }
+    }
+}
+
+impl From<AttError> for esp_gatt_status_t {
+    fn from(error: AttError) -> Self {
+        Self::from(error.code())
+    }
+}
+
+impl From<DecodeError> for AttError {
+    fn from(error: DecodeError) -> Self {
+        match error {
+            DecodeError::InvalidLength { .. } => Self::InvalidAttributeValueLength,
+            DecodeError::InvalidValue => Self::ValueNotAllowed,
+        }
+    }
+}
+
+impl std::fmt::Display for AttError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ReadNotPermitted => write!(f, "read not permitted"),
+            Self::WriteNotPermitted => write!(f, "write not permitted"),
+            Self::InsufficientAuthentication => write!(f, "insufficient authentication"),
+            Self::RequestNotSupported => write!(f, "request not supported"),
+            Self::InvalidOffset => write!(f, "invalid offset"),
+            Self::InsufficientAuthorization => write!(f, "insufficient authorization"),
+            Self::PrepareQueueFull => write!(f, "prepare queue full"),
+            Self::AttributeNotLong => write!(f, "attribute not long"),
+            Self::InsufficientEncryptionKeySize => write!(f, "insufficient encryption key size"),
+            Self::InvalidAttributeValueLength => write!(f, "invalid attribute value length"),
+            Self::UnlikelyError => write!(f, "unlikely error"),
+            Self::InsufficientEncryption => write!(f, "insufficient encryption"),
+            Self::InsufficientResources => write!(f, "insufficient resources"),
+            Self::ValueNotAllowed => write!(f, "value not allowed"),
+            Self::Application(code) => write!(f, "application error 0x{code:02x}"),
+            Self::WriteRequestRejected => write!(f, "write request rejected"),
+            Self::CccdImproperlyConfigured => {
+                write!(
+                    f,
+                    "client characteristic configuration descriptor improperly configured"
+                )
+            }
+            Self::ProcedureAlreadyInProgress => write!(f, "procedure already in progress"),
+            Self::OutOfRange => write!(f, "out of range"),
+        }
+    }
+}
+
+impl std::error::Error for AttError {}
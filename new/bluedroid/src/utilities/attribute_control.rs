@@ -1,19 +1,26 @@
-use esp_idf_sys::*;
+use crate::sys::*;
+use crate::utilities::AttError;
 use std::sync::Arc;
 
+pub(crate) type ReadCallback = dyn Fn(esp_ble_gatts_cb_param_t_gatts_read_evt_param) -> Result<Vec<u8>, AttError>
+    + Send
+    + Sync;
+
 #[derive(Clone)]
 pub(crate) enum AttributeControl {
-    ResponseByApp(
-        Arc<dyn Fn(esp_ble_gatts_cb_param_t_gatts_read_evt_param) -> Vec<u8> + Send + Sync>,
-    ),
-    AutomaticResponse(Vec<u8>),
+    /// Requests are answered by the application.
+    ///
+    /// Without a read callback, reads are answered with the value stored by the application.
+    ResponseByApp(Option<Arc<ReadCallback>>),
+    /// Requests are answered by the Bluetooth stack with the value in its database.
+    AutomaticResponse,
 }
 
 impl From<AttributeControl> for esp_attr_control_t {
     fn from(control: AttributeControl) -> Self {
         #[allow(clippy::cast_possible_truncation)]
         let result: u8 = match control {
-            AttributeControl::AutomaticResponse(_) => ESP_GATT_AUTO_RSP as u8,
+            AttributeControl::AutomaticResponse => ESP_GATT_AUTO_RSP as u8,
             AttributeControl::ResponseByApp(_) => ESP_GATT_RSP_BY_APP as u8,
         };
 
@@ -24,7 +31,7 @@ impl From<AttributeControl> for esp_attr_control_t {
 impl std::fmt::Debug for AttributeControl {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            AttributeControl::AutomaticResponse(_) => write!(f, "automatic response"),
+            AttributeControl::AutomaticResponse => write!(f, "automatic response"),
             AttributeControl::ResponseByApp(_) => write!(f, "response by app"),
         }
     }
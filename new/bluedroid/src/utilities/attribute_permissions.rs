@@ -1,14 +1,41 @@
-use esp_idf_sys::*;
+use crate::{sys::*, Error};
+
+/// Represents the security that a client needs to access an attribute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SecurityLevel {
+    /// No security is needed.
+    #[default]
+    Open,
+    /// The link must be encrypted.
+    Encrypted,
+    /// The link must be encrypted, with keys exchanged in a pairing protected against MITM attacks.
+    EncryptedMitm,
+    /// Writes must be signed, or the link must be encrypted.
+    ///
+    /// Only valid for writes.
+    Signed,
+    /// Writes must be signed, or the link must be encrypted,
+    /// with keys exchanged in a pairing protected against MITM attacks.
+    ///
+    /// Only valid for writes.
+    SignedMitm,
+    /// The client must be authorized.
+    Authorization,
+}
 
 /// Represents an attribute's access permissions.
 ///
 /// This struct is used to set the permissions of a [`Characteristic`] or a [`Descriptor`].
-/// It can represent read and write permissions, and encryption requirements.
+/// It represents read and write access, each with its own [`SecurityLevel`].
+///
+/// [`Characteristic`]: crate::gatt_server::Characteristic
+/// [`Descriptor`]: crate::gatt_server::Descriptor
 #[derive(Debug, Clone, Copy, Default)]
 pub struct AttributePermissions {
     pub(crate) read_access: bool,
     pub(crate) write_access: bool,
-    pub(crate) encryption_required: bool,
+    pub(crate) read_level: SecurityLevel,
+    pub(crate) write_level: SecurityLevel,
 }
 
 impl AttributePermissions {
@@ -32,32 +59,71 @@ impl AttributePermissions {
         self
     }
 
-    /// Sets the encryption requirement of the [`AttributePermissions`].
+    /// Sets the read access of the [`AttributePermissions`], with the given security level.
+    #[must_use]
+    pub const fn read_with(mut self, level: SecurityLevel) -> Self {
+        self.read_access = true;
+        self.read_level = level;
+        self
+    }
+
+    /// Sets the write access of the [`AttributePermissions`], with the given security level.
+    #[must_use]
+    pub const fn write_with(mut self, level: SecurityLevel) -> Self {
+        self.write_access = true;
+        self.write_level = level;
+        self
+    }
+
+    /// Sets the encryption requirement of the [`AttributePermissions`], for both reads and writes.
     #[must_use]
     pub const fn encrypted(mut self) -> Self {
-        self.encryption_required = true;
+        self.read_level = SecurityLevel::Encrypted;
+        self.write_level = SecurityLevel::Encrypted;
         self
     }
+
+    /// Checks that the security levels can be applied.
+    pub(crate) fn validate(self) -> Result<(), Error> {
+        if self.read_access
+            && matches!(
+                self.read_level,
+                SecurityLevel::Signed | SecurityLevel::SignedMitm
+            )
+        {
+            return Err(Error::InconsistentPermissions("reads cannot be signed"));
+        }
+
+        Ok(())
+    }
 }
 
 impl From<AttributePermissions> for esp_gatt_perm_t {
-    #[allow(clippy::cast_possible_truncation)]
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
     fn from(permissions: AttributePermissions) -> Self {
-        let result = match (
-            permissions.read_access,
-            permissions.write_access,
-            permissions.encryption_required,
-        ) {
-            // TODO: Implement all the supported modes.
-            (false, false, _) => 0,
-            (true, false, false) => ESP_GATT_PERM_READ,
-            (false, true, false) => ESP_GATT_PERM_WRITE,
-            (true, true, false) => ESP_GATT_PERM_READ | ESP_GATT_PERM_WRITE,
-            (true, false, true) => ESP_GATT_PERM_READ_ENCRYPTED,
-            (false, true, true) => ESP_GATT_PERM_WRITE_ENCRYPTED,
-            (true, true, true) => ESP_GATT_PERM_READ_ENCRYPTED | ESP_GATT_PERM_WRITE_ENCRYPTED,
+        let read = match (permissions.read_access, permissions.read_level) {
+            (false, _) => 0,
+            (true, SecurityLevel::Open) => ESP_GATT_PERM_READ,
+            // Reads cannot be signed: validation rejects these levels.
+            (true, SecurityLevel::Encrypted | SecurityLevel::Signed) => {
+                ESP_GATT_PERM_READ_ENCRYPTED
+            }
+            (true, SecurityLevel::EncryptedMitm | SecurityLevel::SignedMitm) => {
+                ESP_GATT_PERM_READ_ENC_MITM
+            }
+            (true, SecurityLevel::Authorization) => ESP_GATT_PERM_READ_AUTHORIZATION,
+        };
+
+        let write = match (permissions.write_access, permissions.write_level) {
+            (false, _) => 0,
+            (true, SecurityLevel::Open) => ESP_GATT_PERM_WRITE,
+            (true, SecurityLevel::Encrypted) => ESP_GATT_PERM_WRITE_ENCRYPTED,
+            (true, SecurityLevel::EncryptedMitm) => ESP_GATT_PERM_WRITE_ENC_MITM,
+            (true, SecurityLevel::Signed) => ESP_GATT_PERM_WRITE_SIGNED,
+            (true, SecurityLevel::SignedMitm) => ESP_GATT_PERM_WRITE_SIGNED_MITM,
+            (true, SecurityLevel::Authorization) => ESP_GATT_PERM_WRITE_AUTHORIZATION,
         };
 
-        result as Self
+        (read | write) as Self
     }
 }
@@ -1,4 +1,4 @@
-use esp_idf_sys::{
+use crate::sys::{
     esp_bt_uuid_t, esp_gatt_id_t, ESP_UUID_LEN_128, ESP_UUID_LEN_16, ESP_UUID_LEN_32,
 };
 
@@ -139,6 +139,16 @@ impl BleUuid {
             Self::Uuid128(uuid) => *uuid,
         }
     }
+
+    /// Returns the UUID in its shortest over-the-air form, least significant byte first.
+    #[must_use]
+    pub(crate) fn to_le_bytes(self) -> Vec<u8> {
+        match self {
+            Self::Uuid16(uuid) => uuid.to_le_bytes().to_vec(),
+            Self::Uuid32(uuid) => uuid.to_le_bytes().to_vec(),
+            Self::Uuid128(uuid) => uuid.to_vec(),
+        }
+    }
 }
 
 impl PartialEq for BleUuid {
@@ -1,4 +1,4 @@
-use esp_idf_sys::*;
+use crate::sys::*;
 use log::warn;
 
 /// Represents the properties of a [`Characteristic`].
@@ -7,10 +7,14 @@ use log::warn;
 ///
 /// # Notes
 ///
-/// Keep in mind that you *must* set the [`read`] and [`write`] properties in the same way as
-/// the ones in [`AttributePermissions`]. Otherwise, the client might issue a read or write command
-/// to a [`Characteristic`] that doesn't allow it.
+/// The [`read`] and [`write`] properties need the matching access in [`AttributePermissions`],
+/// and the [`authenticated_signed_writes`] property needs a signed write [`SecurityLevel`].
+/// Otherwise, validating the [`Characteristic`] fails.
 ///
+/// [`read`]: Self::read
+/// [`write`]: Self::write
+/// [`authenticated_signed_writes`]: Self::authenticated_signed_writes
+/// [`SecurityLevel`]: crate::utilities::SecurityLevel
 /// [`AttributePermissions`]: crate::utilities::AttributePermissions
 /// [`Characteristic`]: crate::gatt_server::characteristic::Characteristic
 #[allow(clippy::struct_excessive_bools)]
@@ -22,7 +26,7 @@ pub struct CharacteristicProperties {
     pub(crate) write: bool,
     pub(crate) notify: bool,
     pub(crate) indicate: bool,
-    authenticated_signed_writes: bool,
+    pub(crate) authenticated_signed_writes: bool,
     extended_properties: bool,
 }
 
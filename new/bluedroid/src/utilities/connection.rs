@@ -1,62 +1,425 @@
-use esp_idf_sys::{
-    esp_ble_gatts_cb_param_t_gatts_connect_evt_param,
-    esp_ble_gatts_cb_param_t_gatts_disconnect_evt_param,
+use crate::backend::backend;
+use crate::gatt_server::connection_state::{connection_states, data_length_requests};
+use crate::sys::{
+    esp_bd_addr_t, esp_ble_conn_update_params_t, esp_ble_gatts_cb_param_t_gatts_connect_evt_param,
+    esp_gatt_conn_reason_t, esp_gatt_conn_reason_t_ESP_GATT_CONN_FAIL_ESTABLISH,
+    esp_gatt_conn_reason_t_ESP_GATT_CONN_TERMINATE_LOCAL_HOST,
+    esp_gatt_conn_reason_t_ESP_GATT_CONN_TERMINATE_PEER_USER,
+    esp_gatt_conn_reason_t_ESP_GATT_CONN_TIMEOUT,
 };
+use crate::utilities::{private_address, AddressType, Phy, SecurityLevel};
+use crate::Error;
 
-#[derive(Debug, Copy, Clone)]
-pub(crate) struct Connection {
-    pub(crate) id: u16,
-    #[cfg(esp_idf_version_major = "4")]
-    pub(crate) is_slave: bool,
-    pub(crate) remote_bda: [u8; 6],
+/// The ATT MTU of a new connection, before the client requests a larger one.
+pub(crate) const DEFAULT_MTU: u16 = 23;
+
+/// The data length of the packets of a new connection, in bytes.
+const DEFAULT_DATA_LENGTH: u16 = 27;
+
+/// The largest data length of the packets of a connection, in bytes.
+const MAX_DATA_LENGTH: u16 = 251;
+
+/// The role of the server in a connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    /// The server initiated the connection.
+    Central,
+    /// The client initiated the connection, after the server advertised.
+    Peripheral,
 }
 
-impl From<esp_ble_gatts_cb_param_t_gatts_connect_evt_param> for Connection {
-    fn from(param: esp_ble_gatts_cb_param_t_gatts_connect_evt_param) -> Self {
+/// The parameters of a connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ConnectionParameters {
+    /// The connection interval, in units of 1.25 ms.
+    pub interval: u16,
+    /// The number of connection events that the client can skip.
+    pub latency: u16,
+    /// The supervision timeout, in units of 10 ms.
+    pub timeout: u16,
+}
+
+/// The maximum data length of the packets of a connection, in bytes.
+///
+/// Longer packets, with the LE Data Length Extension, carry notifications larger than 27 bytes
+/// in a single packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DataLength {
+    /// The maximum data length of the packets sent by the server.
+    pub tx_octets: u16,
+    /// The maximum data length of the packets received by the server.
+    pub rx_octets: u16,
+}
+
+impl Default for DataLength {
+    fn default() -> Self {
         Self {
-            id: param.conn_id,
-            #[cfg(esp_idf_version_major = "4")]
-            is_slave: param.link_role == 1,
-            remote_bda: param.remote_bda,
+            tx_octets: DEFAULT_DATA_LENGTH,
+            rx_octets: DEFAULT_DATA_LENGTH,
         }
     }
 }
 
-impl From<esp_ble_gatts_cb_param_t_gatts_disconnect_evt_param> for Connection {
-    fn from(param: esp_ble_gatts_cb_param_t_gatts_disconnect_evt_param) -> Self {
+/// The connection parameters preferred by the server.
+///
+/// The interval range is in units of 1.25 ms, from 7.5 ms (`0x0006`) to 4 s (`0x0C80`),
+/// and the supervision timeout in units of 10 ms, from 100 ms (`0x000A`) to 32 s (`0x0C80`).
+/// The client chooses the interval in the range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PreferredConnectionParameters {
+    pub(crate) min_interval: u16,
+    pub(crate) max_interval: u16,
+    pub(crate) latency: u16,
+    pub(crate) timeout: u16,
+}
+
+impl PreferredConnectionParameters {
+    /// Creates new [`PreferredConnectionParameters`] with an interval range,
+    /// no latency, and a supervision timeout of 4 s.
+    #[must_use]
+    pub const fn new(min_interval: u16, max_interval: u16) -> Self {
         Self {
-            id: param.conn_id,
-            #[cfg(esp_idf_version_major = "4")]
-            is_slave: param.link_role == 1,
-            remote_bda: param.remote_bda,
+            min_interval,
+            max_interval,
+            latency: 0,
+            timeout: 400,
+        }
+    }
+
+    /// Sets the number of connection events that the client can skip.
+    #[must_use]
+    pub const fn latency(mut self, latency: u16) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Sets the supervision timeout, in units of 10 ms.
+    #[must_use]
+    pub const fn timeout(mut self, timeout: u16) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Returns whether the parameters of a connection satisfy these preferences.
+    pub(crate) const fn accepts(self, parameters: ConnectionParameters) -> bool {
+        parameters.interval >= self.min_interval
+            && parameters.interval <= self.max_interval
+            && parameters.latency <= self.latency
+    }
+
+    /// Checks the ranges of the parameters, and that the supervision timeout
+    /// is longer than two effective connection intervals.
+    pub(crate) fn validate(self) -> Result<(), Error> {
+        if self.min_interval < 0x0006
+            || self.max_interval > 0x0C80
+            || self.min_interval > self.max_interval
+        {
+            return Err(Error::InvalidConnectionParameters(
+                "the interval range must be between 0x0006 and 0x0C80",
+            ));
+        }
+
+        if self.latency > 0x01F3 {
+            return Err(Error::InvalidConnectionParameters(
+                "the latency must be at most 0x01F3",
+            ));
+        }
+
+        if self.timeout < 0x000A || self.timeout > 0x0C80 {
+            return Err(Error::InvalidConnectionParameters(
+                "the supervision timeout must be between 0x000A and 0x0C80",
+            ));
+        }
+
+        // 10 ms * timeout > 2 * 1.25 ms * interval * (1 + latency)
+        if u32::from(self.timeout) * 4
+            <= u32::from(self.max_interval) * (1 + u32::from(self.latency))
+        {
+            return Err(Error::InvalidConnectionParameters(
+                "the supervision timeout must be longer than two effective intervals",
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the parameters of an update request for a connection.
+    pub(crate) const fn update_params(
+        self,
+        address: esp_bd_addr_t,
+    ) -> esp_ble_conn_update_params_t {
+        esp_ble_conn_update_params_t {
+            bda: address,
+            min_int: self.min_interval,
+            max_int: self.max_interval,
+            latency: self.latency,
+            timeout: self.timeout,
         }
     }
 }
 
-#[cfg(esp_idf_version_major = "4")]
-impl std::fmt::Display for Connection {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{:02X}:{:02X}:{:02X}:{:02X}:{:02X}:{:02X} ({}, slave: {})",
-            self.remote_bda[0],
-            self.remote_bda[1],
-            self.remote_bda[2],
-            self.remote_bda[3],
-            self.remote_bda[4],
-            self.remote_bda[5],
-            self.id,
-            self.is_slave,
-        )
+/// The reason why a connection was terminated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisconnectReason {
+    /// The client stopped responding, and the supervision timeout expired.
+    Timeout,
+    /// The client closed the connection.
+    RemoteUser,
+    /// The server closed the connection.
+    LocalHost,
+    /// The connection could not be established.
+    FailedToEstablish,
+    /// Another reason, with the code reported by the stack.
+    Other(esp_gatt_conn_reason_t),
+}
+
+#[allow(non_upper_case_globals)]
+impl From<esp_gatt_conn_reason_t> for DisconnectReason {
+    fn from(reason: esp_gatt_conn_reason_t) -> Self {
+        match reason {
+            esp_gatt_conn_reason_t_ESP_GATT_CONN_TIMEOUT => Self::Timeout,
+            esp_gatt_conn_reason_t_ESP_GATT_CONN_TERMINATE_PEER_USER => Self::RemoteUser,
+            esp_gatt_conn_reason_t_ESP_GATT_CONN_TERMINATE_LOCAL_HOST => Self::LocalHost,
+            esp_gatt_conn_reason_t_ESP_GATT_CONN_FAIL_ESTABLISH => Self::FailedToEstablish,
+            reason => Self::Other(reason),
+        }
+    }
+}
+
+/// A connection with a client.
+///
+/// Connections are listed by [`GattServer::connections`], and passed to the connection callbacks.
+/// They are snapshots: the MTU, the parameters, the security and the RSSI of a connection
+/// are those known when the snapshot was taken.
+///
+/// A connection is identified by its identifier, which the stack does not reuse while it is open.
+/// The same client can have several connections, for example when it reconnects
+/// before the stack reports the end of the previous one: use [`Connection::identity`]
+/// to recognise it.
+///
+/// [`GattServer::connections`]: crate::gatt_server::GattServer::connections
+#[derive(Debug, Copy, Clone)]
+pub struct Connection {
+    pub(crate) id: u16,
+    pub(crate) remote_bda: esp_bd_addr_t,
+    pub(crate) identity: esp_bd_addr_t,
+    pub(crate) address_type: AddressType,
+    pub(crate) role: Role,
+    pub(crate) mtu: u16,
+    pub(crate) parameters: ConnectionParameters,
+    pub(crate) data_length: DataLength,
+    pub(crate) tx_phy: Phy,
+    pub(crate) rx_phy: Phy,
+    pub(crate) security: SecurityLevel,
+    pub(crate) bonded: bool,
+    pub(crate) rssi: Option<i8>,
+}
+
+impl Connection {
+    /// Returns the connection identifier, used to notify or indicate a single client.
+    #[must_use]
+    pub const fn id(&self) -> u16 {
+        self.id
+    }
+
+    /// Returns the address of the client.
+    #[must_use]
+    pub const fn address(&self) -> esp_bd_addr_t {
+        self.remote_bda
+    }
+
+    /// Returns the identity address of the client.
+    ///
+    /// Bonded clients that use resolvable private addresses are recognised with the IRK
+    /// they distributed, and their identity address is returned.
+    /// For other clients, this is the address of the connection.
+    #[must_use]
+    pub const fn identity(&self) -> esp_bd_addr_t {
+        self.identity
+    }
+
+    /// Returns the type of the address of the client.
+    ///
+    /// The type is reported by the stack when the client pairs. Before that, resolvable private
+    /// addresses are recognised as [`AddressType::Random`], and other addresses are
+    /// assumed to be public.
+    #[must_use]
+    pub const fn address_type(&self) -> AddressType {
+        self.address_type
+    }
+
+    /// Returns the role of the server in the connection.
+    #[must_use]
+    pub const fn role(&self) -> Role {
+        self.role
+    }
+
+    /// Returns the ATT MTU negotiated with the client.
+    #[must_use]
+    pub const fn mtu(&self) -> u16 {
+        self.mtu
+    }
+
+    /// Returns the current parameters of the connection.
+    #[must_use]
+    pub const fn parameters(&self) -> ConnectionParameters {
+        self.parameters
+    }
+
+    /// Returns the maximum data length of the packets of the connection.
+    #[must_use]
+    pub const fn data_length(&self) -> DataLength {
+        self.data_length
+    }
+
+    /// Returns the PHY used to send packets to the client.
+    #[must_use]
+    pub const fn tx_phy(&self) -> Phy {
+        self.tx_phy
+    }
+
+    /// Returns the PHY used to receive packets from the client.
+    #[must_use]
+    pub const fn rx_phy(&self) -> Phy {
+        self.rx_phy
+    }
+
+    /// Returns the security of the link: [`SecurityLevel::Open`], [`SecurityLevel::Encrypted`]
+    /// or [`SecurityLevel::EncryptedMitm`].
+    #[must_use]
+    pub const fn security(&self) -> SecurityLevel {
+        self.security
+    }
+
+    /// Returns whether the client is bonded.
+    #[must_use]
+    pub const fn is_bonded(&self) -> bool {
+        self.bonded
+    }
+
+    /// Returns the last signal strength read with [`GattServer::read_rssi`], in dBm.
+    ///
+    /// [`GattServer::read_rssi`]: crate::gatt_server::GattServer::read_rssi
+    #[must_use]
+    pub const fn rssi(&self) -> Option<i8> {
+        self.rssi
+    }
+
+    /// Requests new parameters for the connection.
+    ///
+    /// The client chooses the interval in the range, or rejects the request.
+    /// The parameters actually applied are reported to the [`GattServer::on_parameters_change`] callback.
+    ///
+    /// [`GattServer::on_parameters_change`]: crate::gatt_server::GattServer::on_parameters_change
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the parameters are invalid, if the client is not connected anymore,
+    /// or if the Bluetooth stack rejects the request.
+    pub fn request_parameters(
+        &self,
+        parameters: PreferredConnectionParameters,
+    ) -> Result<(), Error> {
+        parameters.validate()?;
+        self.check_open()?;
+
+        Ok(backend().update_conn_params(&parameters.update_params(self.remote_bda))?)
+    }
+
+    /// Requests a maximum data length for the packets sent to the client, between 27 and 251 bytes.
+    ///
+    /// The lengths negotiated with the client are reported to the
+    /// [`GattServer::on_data_length_change`] callback. The stack handles one request at a time:
+    /// the request waits for those of other clients, and replaces an earlier one still waiting.
+    ///
+    /// [`GattServer::on_data_length_change`]: crate::gatt_server::GattServer::on_data_length_change
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the length is invalid, if the client is not connected anymore,
+    /// or if the Bluetooth stack rejects the request. The rejection of a waiting request is logged.
+    pub fn request_data_length(&self, tx_octets: u16) -> Result<(), Error> {
+        if !(DEFAULT_DATA_LENGTH..=MAX_DATA_LENGTH).contains(&tx_octets) {
+            return Err(Error::InvalidDataLength(tx_octets));
+        }
+
+        self.check_open()?;
+
+        // The request is sent once those of the other clients complete.
+        data_length_requests().request(self.remote_bda, self.id, tx_octets)
+    }
+
+    /// Requests the PHYs used to send packets to the client and to receive packets from it.
+    ///
+    /// The PHYs chosen by the controllers are reported to the [`GattServer::on_phy_change`] callback.
+    ///
+    /// [`GattServer::on_phy_change`]: crate::gatt_server::GattServer::on_phy_change
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the client is not connected anymore,
+    /// or if the Bluetooth stack rejects the request.
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    pub fn request_phy(&self, tx_phy: Phy, rx_phy: Phy) -> Result<(), Error> {
+        self.check_open()?;
+
+        Ok(backend().set_preferred_phy(self.remote_bda, tx_phy.mask(), rx_phy.mask())?)
+    }
+
+    /// Checks that the connection is still open.
+    fn check_open(&self) -> Result<(), Error> {
+        if connection_states()
+            .get(&self.id)
+            .map_or(true, |state| state.connection.remote_bda != self.remote_bda)
+        {
+            return Err(Error::NotConnected);
+        }
+
+        Ok(())
+    }
+}
+
+impl From<esp_ble_gatts_cb_param_t_gatts_connect_evt_param> for Connection {
+    fn from(param: esp_ble_gatts_cb_param_t_gatts_connect_evt_param) -> Self {
+        Self {
+            id: param.conn_id,
+            remote_bda: param.remote_bda,
+            identity: param.remote_bda,
+            address_type: if private_address::is_resolvable(param.remote_bda) {
+                AddressType::Random
+            } else {
+                AddressType::Public
+            },
+            #[cfg(esp_idf_version_major = "4")]
+            role: if param.link_role == 0 {
+                Role::Central
+            } else {
+                Role::Peripheral
+            },
+            // The server does not initiate connections.
+            #[cfg(esp_idf_version_major = "5")]
+            role: Role::Peripheral,
+            mtu: DEFAULT_MTU,
+            parameters: ConnectionParameters {
+                interval: param.conn_params.interval,
+                latency: param.conn_params.latency,
+                timeout: param.conn_params.timeout,
+            },
+            data_length: DataLength::default(),
+            tx_phy: Phy::Le1M,
+            rx_phy: Phy::Le1M,
+            security: SecurityLevel::Open,
+            bonded: false,
+            rssi: None,
+        }
     }
 }
 
-#[cfg(esp_idf_version_major = "5")]
 impl std::fmt::Display for Connection {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "{:02X}:{:02X}:{:02X}:{:02X}:{:02X}:{:02X} ({})",
+            "{:02X}:{:02X}:{:02X}:{:02X}:{:02X}:{:02X} ({}, {:?})",
             self.remote_bda[0],
             self.remote_bda[1],
             self.remote_bda[2],
@@ -64,20 +427,7 @@ impl std::fmt::Display for Connection {
             self.remote_bda[4],
             self.remote_bda[5],
             self.id,
+            self.role,
         )
     }
 }
-
-impl std::hash::Hash for Connection {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        self.remote_bda.hash(state);
-    }
-}
-
-impl PartialEq for Connection {
-    fn eq(&self, other: &Self) -> bool {
-        self.remote_bda == other.remote_bda
-    }
-}
-
-impl Eq for Connection {}
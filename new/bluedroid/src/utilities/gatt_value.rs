@@ -0,0 +1,201 @@
+/// A type that can be stored in a GATT attribute.
+///
+/// Values are encoded in little-endian order, as mandated by the Bluetooth specification.
+pub trait GattValue: Sized {
+    /// The length of the encoded value, if it is always the same.
+    const LENGTH: Option<usize> = None;
+
+    /// Encodes the value in its over-the-air representation.
+    fn encode(&self) -> Vec<u8>;
+
+    /// Decodes a value from its over-the-air representation.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the bytes are not a valid representation of the value.
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError>;
+}
+
+/// The error returned when a written value cannot be decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DecodeError {
+    /// The value does not have the expected length.
+    InvalidLength {
+        /// The expected length, in bytes.
+        expected: usize,
+        /// The received length, in bytes.
+        actual: usize,
+    },
+    /// The bytes are not a valid representation of the value.
+    InvalidValue,
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidLength { expected, actual } => {
+                write!(f, "expected {expected} bytes, received {actual}")
+            }
+            Self::InvalidValue => write!(f, "invalid value"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Checks that `bytes` has the length of a fixed-size value.
+fn expect_length(bytes: &[u8], expected: usize) -> Result<(), DecodeError> {
+    if bytes.len() == expected {
+        Ok(())
+    } else {
+        Err(DecodeError::InvalidLength {
+            expected,
+            actual: bytes.len(),
+        })
+    }
+}
+
+/// Decodes the fixed-size value at the beginning of `bytes`, and returns it with the remaining bytes.
+fn split<T: GattValue>(bytes: &[u8]) -> Result<(T, &[u8]), DecodeError> {
+    // Only the last element of a tuple can have a variable length.
+    let length = T::LENGTH.ok_or(DecodeError::InvalidValue)?;
+
+    if bytes.len() < length {
+        return Err(DecodeError::InvalidLength {
+            expected: length,
+            actual: bytes.len(),
+        });
+    }
+
+    let (head, tail) = bytes.split_at(length);
+    Ok((T::decode(head)?, tail))
+}
+
+macro_rules! impl_gatt_value_for_number {
+    ($($number:ty),*) => {
+        $(
+            impl GattValue for $number {
+                const LENGTH: Option<usize> = Some(std::mem::size_of::<$number>());
+
+                fn encode(&self) -> Vec<u8> {
+                    self.to_le_bytes().to_vec()
+                }
+
+                fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+                    let bytes = bytes.try_into().map_err(|_| DecodeError::InvalidLength {
+                        expected: std::mem::size_of::<$number>(),
+                        actual: bytes.len(),
+                    })?;
+
+                    Ok(Self::from_le_bytes(bytes))
+                }
+            }
+        )*
+    };
+}
+
+impl_gatt_value_for_number!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128, f32, f64);
+
+impl GattValue for bool {
+    const LENGTH: Option<usize> = Some(1);
+
+    fn encode(&self) -> Vec<u8> {
+        vec![u8::from(*self)]
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        expect_length(bytes, 1)?;
+
+        match bytes[0] {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(DecodeError::InvalidValue),
+        }
+    }
+}
+
+impl GattValue for String {
+    fn encode(&self) -> Vec<u8> {
+        self.as_bytes().to_vec()
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        Self::from_utf8(bytes.to_vec()).map_err(|_| DecodeError::InvalidValue)
+    }
+}
+
+impl GattValue for Vec<u8> {
+    fn encode(&self) -> Vec<u8> {
+        self.clone()
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        Ok(bytes.to_vec())
+    }
+}
+
+impl<const N: usize> GattValue for [u8; N] {
+    const LENGTH: Option<usize> = Some(N);
+
+    fn encode(&self) -> Vec<u8> {
+        self.to_vec()
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        bytes.try_into().map_err(|_| DecodeError::InvalidLength {
+            expected: N,
+            actual: bytes.len(),
+        })
+    }
+}
+
+/// Tuples are encoded by concatenating their elements.
+///
+/// Only the last element can have a variable length:
+/// decoding a tuple with a variable-length element in any other position always fails.
+macro_rules! impl_gatt_value_for_tuple {
+    ($($element:ident),* ; $last:ident) => {
+        impl<$($element: GattValue,)* $last: GattValue> GattValue for ($($element,)* $last,) {
+            const LENGTH: Option<usize> = {
+                let mut length = match $last::LENGTH {
+                    Some(length) => length,
+                    None => 0,
+                };
+                let mut fixed = $last::LENGTH.is_some();
+
+                $(
+                    match $element::LENGTH {
+                        Some(element_length) => length += element_length,
+                        None => fixed = false,
+                    }
+                )*
+
+                if fixed {
+                    Some(length)
+                } else {
+                    None
+                }
+            };
+
+            #[allow(non_snake_case)]
+            fn encode(&self) -> Vec<u8> {
+                let ($($element,)* $last,) = self;
+                let mut bytes = Vec::new();
+                $(bytes.extend($element.encode());)*
+                bytes.extend($last.encode());
+                bytes
+            }
+
+            #[allow(non_snake_case)]
+            fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+                $(let ($element, bytes) = split::<$element>(bytes)?;)*
+                Ok(($($element,)* $last::decode(bytes)?,))
+            }
+        }
+    };
+}
+
+impl_gatt_value_for_tuple!(A; B);
+impl_gatt_value_for_tuple!(A, B; C);
+impl_gatt_value_for_tuple!(A, B, C; D);
@@ -7,14 +7,32 @@ pub(crate) mod leaky_box;
 mod attribute_control;
 pub(crate) use attribute_control::AttributeControl;
 
-// Connection: private.
+// Connection: public.
 mod connection;
-pub(crate) use connection::Connection;
+pub(crate) use connection::DEFAULT_MTU;
+pub use connection::{
+    Connection, ConnectionParameters, DataLength, DisconnectReason, PreferredConnectionParameters,
+    Role,
+};
 
 // BLE identifiers: public.
 mod ble_uuid;
 pub use ble_uuid::BleUuid;
 
+// AES-128 encryption: private.
+pub(crate) mod aes;
+
+// Resolvable private addresses: private.
+pub(crate) mod private_address;
+
+// Bluetooth device address type: public.
+mod address_type;
+pub use address_type::AddressType;
+
+// Bluetooth LE physical layer: public.
+mod phy;
+pub use phy::Phy;
+
 // Bluetooth device appearance: public.
 mod appearance;
 pub use appearance::Appearance;
@@ -25,4 +43,12 @@ pub use characteristic_properties::CharacteristicProperties;
 
 // Attribute permissions: public.
 mod attribute_permissions;
-pub use attribute_permissions::AttributePermissions;
+pub use attribute_permissions::{AttributePermissions, SecurityLevel};
+
+// Attribute value encoding: public.
+mod gatt_value;
+pub use gatt_value::{DecodeError, GattValue};
+
+// ATT errors: public.
+mod att_error;
+pub use att_error::AttError;
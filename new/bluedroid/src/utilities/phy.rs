@@ -0,0 +1,39 @@
+/// A physical layer of Bluetooth Low Energy.
+///
+/// The discriminants are the values of `ESP_BLE_GAP_PHY_*`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[repr(u8)]
+pub enum Phy {
+    /// The 1 Mbit/s PHY, supported by every device.
+    #[default]
+    Le1M = 1,
+    /// The 2 Mbit/s PHY.
+    Le2M = 2,
+    /// The coded PHY, for long range.
+    LeCoded = 3,
+}
+
+impl Phy {
+    /// Returns the PHY for a value of `ESP_BLE_GAP_PHY_*`.
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    pub(crate) const fn from_value(value: u8) -> Option<Self> {
+        match value {
+            1 => Some(Self::Le1M),
+            2 => Some(Self::Le2M),
+            3 => Some(Self::LeCoded),
+            _ => None,
+        }
+    }
+
+    /// Returns the mask of the PHY in preferences, one of `ESP_BLE_GAP_PHY_*_PREF_MASK`.
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    pub(crate) const fn mask(self) -> u8 {
+        1 << (self as u8 - 1)
+    }
+}
+
+impl From<Phy> for u8 {
+    fn from(phy: Phy) -> Self {
+        phy as u8
+    }
+}
@@ -0,0 +1,71 @@
+//! Resolvable private addresses.
+//!
+//! A resolvable private address is made of a random part and of its hash with the
+//! identity resolving key (IRK) of the device, computed with the `ah` function of the
+//! security manager. IRKs are stored least significant byte first, like the stack does.
+
+use crate::{sys::esp_bd_addr_t, utilities::aes::aes128_encrypt};
+
+/// Returns whether an address is a resolvable private address.
+pub(crate) const fn is_resolvable(address: esp_bd_addr_t) -> bool {
+    address[0] >> 6 == 0b01
+}
+
+/// Returns whether a resolvable private address was generated with an IRK.
+pub(crate) fn resolves(address: esp_bd_addr_t, irk: &[u8; 16]) -> bool {
+    is_resolvable(address)
+        && hash(irk, [address[0], address[1], address[2]]) == [address[3], address[4], address[5]]
+}
+
+/// Generates a resolvable private address from an IRK and a random value.
+#[cfg(not(target_os = "espidf"))]
+pub(crate) fn generate(irk: &[u8; 16], random: [u8; 3]) -> esp_bd_addr_t {
+    let prand = [random[0] & 0x3F | 0x40, random[1], random[2]];
+    let hash = hash(irk, prand);
+
+    [prand[0], prand[1], prand[2], hash[0], hash[1], hash[2]]
+}
+
+/// The `ah` function of the security manager, on a random part stored most significant byte first.
+fn hash(irk: &[u8; 16], prand: [u8; 3]) -> [u8; 3] {
+    let mut key = *irk;
+    key.reverse();
+
+    let mut block = [0; 16];
+    block[13..].copy_from_slice(&prand);
+    let block = aes128_encrypt(&key, block);
+
+    [block[13], block[14], block[15]]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The IRK of the `ah` sample data of the Core Specification, least significant byte first.
+    const IRK: [u8; 16] = [
+        0x9b, 0x7d, 0x39, 0x0a, 0xa6, 0x10, 0x10, 0x34, 0x05, 0xad, 0xc8, 0x57, 0xa3, 0x34, 0x02,
+        0xec,
+    ];
+
+    #[test]
+    fn ah() {
+        assert_eq!(hash(&IRK, [0x70, 0x81, 0x94]), [0x0d, 0xfb, 0xaa]);
+    }
+
+    #[test]
+    fn resolution() {
+        let address = [0x70, 0x81, 0x94, 0x0d, 0xfb, 0xaa];
+        assert!(resolves(address, &IRK));
+        assert!(!resolves([0x70, 0x81, 0x94, 0x0d, 0xfb, 0xab], &IRK));
+        assert!(!resolves(address, &[0; 16]));
+
+        // Static random and public addresses are never resolved.
+        assert!(!resolves([0xF0, 0x81, 0x94, 0x0d, 0xfb, 0xaa], &IRK));
+        assert!(!is_resolvable([0x30, 0x81, 0x94, 0x0d, 0xfb, 0xaa]));
+
+        let generated = generate(&IRK, [0xFF, 0x81, 0x94]);
+        assert!(is_resolvable(generated));
+        assert!(resolves(generated, &IRK));
+    }
+}
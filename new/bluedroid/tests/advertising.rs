@@ -0,0 +1,47 @@
+//! Advertises through the simulated stack.
+
+mod common;
+
+use std::time::Duration;
+
+use bluedroid::gatt_server::{AdvertisingConfig, GattServerConfig, Service};
+use bluedroid::utilities::BleUuid;
+
+#[test]
+fn duration() {
+    let (_guard, stack) = common::stack();
+    let service = Service::new(BleUuid::Uuid16(0x1000)).primary().build();
+    let server = common::start_with(&GattServerConfig::new(), &[&service], |server| {
+        server.advertising(
+            AdvertisingConfig::new()
+                .duration(Duration::from_millis(500))
+                .clone(),
+        );
+    });
+    assert!(stack.is_advertising());
+
+    // Restarting advertising gives it its whole duration again.
+    std::thread::sleep(Duration::from_millis(250));
+    server.lock().unwrap().stop_advertising().unwrap();
+    server.lock().unwrap().start_advertising().unwrap();
+    stack.wait_idle();
+    std::thread::sleep(Duration::from_millis(350));
+    stack.wait_idle();
+    assert!(stack.is_advertising());
+    assert!(server.lock().unwrap().is_advertising());
+
+    std::thread::sleep(Duration::from_millis(400));
+    stack.wait_idle();
+    assert!(!stack.is_advertising());
+    assert!(!server.lock().unwrap().is_advertising());
+
+    // It times out again when started again.
+    server.lock().unwrap().start_advertising().unwrap();
+    stack.wait_idle();
+    assert!(stack.is_advertising());
+    std::thread::sleep(Duration::from_millis(750));
+    stack.wait_idle();
+    assert!(!stack.is_advertising());
+
+    common::stop(&server);
+}
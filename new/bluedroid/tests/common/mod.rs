@@ -0,0 +1,76 @@
+//! Helpers shared by the tests, which all drive the process-wide simulated stack.
+
+#![allow(dead_code)]
+
+use std::{
+    sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock},
+    time::Duration,
+};
+
+use bluedroid::{
+    gatt_server::{Characteristic, GattServer, GattServerConfig, Profile, Service},
+    simulated::SimulatedStack,
+};
+
+/// How long a test waits for the registration of the services.
+pub const READY_TIMEOUT: Duration = Duration::from_secs(2);
+
+static STACK: Mutex<()> = Mutex::new(());
+
+/// Gives a test exclusive access to the simulated stack.
+///
+/// The tests of a file run in parallel, but only one GATT server can be started at a time.
+/// A test that fails does not poison the lock for the others.
+pub fn stack() -> (MutexGuard<'static, ()>, &'static SimulatedStack) {
+    let guard = STACK.lock().unwrap_or_else(PoisonError::into_inner);
+    let stack = SimulatedStack::global();
+    stack.wait_idle();
+    (guard, stack)
+}
+
+/// Starts a server with a single profile holding the given services, and waits until they are registered.
+pub fn start(
+    config: &GattServerConfig,
+    services: &[&Arc<RwLock<Service>>],
+) -> Arc<Mutex<GattServer>> {
+    start_with(config, services, |_| {})
+}
+
+/// Like [`start`], configuring the server before starting it.
+pub fn start_with(
+    config: &GattServerConfig,
+    services: &[&Arc<RwLock<Service>>],
+    setup: impl FnOnce(&mut GattServer),
+) -> Arc<Mutex<GattServer>> {
+    let mut profile = Profile::new(1);
+    for service in services {
+        let _ = profile.service(service);
+    }
+
+    let server = GattServer::new(config);
+    let mut locked = server.lock().unwrap();
+    setup(locked.profile(profile.build()));
+    locked.start().unwrap();
+    drop(locked);
+
+    GattServer::wait_until_ready(&server, READY_TIMEOUT).unwrap();
+    SimulatedStack::global().wait_idle();
+    server
+}
+
+/// Stops a server, and waits until the stack is deinitialised.
+pub fn stop(server: &Arc<Mutex<GattServer>>) {
+    server.lock().unwrap().stop().unwrap();
+    SimulatedStack::global().wait_idle();
+}
+
+/// A readable characteristic with a static value.
+pub fn readable(uuid: u16, value: &[u8]) -> Arc<RwLock<Characteristic>> {
+    use bluedroid::utilities::{AttributePermissions, BleUuid, CharacteristicProperties};
+
+    Characteristic::new(BleUuid::Uuid16(uuid))
+        .permissions(AttributePermissions::new().read())
+        .properties(CharacteristicProperties::new().read())
+        .set_value(value.to_vec())
+        .build()
+}
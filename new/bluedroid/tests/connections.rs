@@ -0,0 +1,187 @@
+//! Tracks the connections of simulated clients that reconnect, and that rotate their private address.
+
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use bluedroid::{
+    gatt_server::{Characteristic, GattServer, GattServerConfig, Service},
+    simulated::Notification,
+    utilities::{
+        AttributePermissions, BleUuid, CharacteristicProperties, PreferredConnectionParameters,
+    },
+    Error,
+};
+
+#[test]
+fn reconnect_race() {
+    let (_guard, stack) = common::stack();
+    let service = Service::new(BleUuid::Uuid16(0x7000)).primary().build();
+    let server = common::start(&GattServerConfig::new(), &[&service]);
+    let address = [1, 0, 0, 0, 0, 8];
+
+    // The client reconnects before the stack reports that its first link is closed.
+    let first = stack.connect(address);
+    stack.wait_idle();
+    let stale = server.lock().unwrap().connections()[0];
+    let second = stack.connect(address);
+    stack.wait_idle();
+    let ids = |server: &Mutex<GattServer>| {
+        server
+            .lock()
+            .unwrap()
+            .connections()
+            .iter()
+            .map(|connection| (connection.id(), connection.address()))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(ids(&server), [(first, address), (second, address)]);
+
+    // Closing the first link leaves the second one open.
+    stack.disconnect(first);
+    stack.wait_idle();
+    assert_eq!(ids(&server), [(second, address)]);
+    assert!(matches!(
+        server.lock().unwrap().disconnect(&stale),
+        Err(Error::NotConnected)
+    ));
+
+    // A late disconnection of an unknown link is ignored.
+    stack.disconnect(first);
+    stack.wait_idle();
+    assert_eq!(ids(&server), [(second, address)]);
+
+    common::stop(&server);
+    assert!(server.lock().unwrap().connections().is_empty());
+}
+
+#[test]
+fn address_rotation() {
+    let (_guard, stack) = common::stack();
+    let characteristic = Characteristic::new(BleUuid::Uuid16(0x7101))
+        .permissions(AttributePermissions::new().read())
+        .properties(CharacteristicProperties::new().read().notify())
+        .set_value(vec![0])
+        .build();
+    let service = Service::new(BleUuid::Uuid16(0x7100))
+        .primary()
+        .characteristic(&characteristic)
+        .build();
+    let server = common::start(&GattServerConfig::new(), &[&service]);
+    let identity = [0xC0, 0, 0, 0, 0, 9];
+    let handle = stack.find_characteristic(BleUuid::Uuid16(0x7101)).unwrap();
+
+    // The client bonds while connected with a private address, and subscribes.
+    let address = stack.private_address(identity);
+    let connection = stack.connect(address);
+    stack.bond(connection);
+    stack.subscribe(connection, handle, true, false).unwrap();
+    stack.disconnect(connection);
+    stack.wait_idle();
+
+    // It is recognised with its next address, and keeps its subscription.
+    let rotated = stack.private_address(identity);
+    assert_ne!(rotated, address);
+    let connection = stack.connect(rotated);
+    stack.wait_idle();
+    let connections = server.lock().unwrap().connections();
+    assert_eq!(connections.len(), 1);
+    assert_eq!(connections[0].address(), rotated);
+    assert_eq!(connections[0].identity(), identity);
+    assert!(connections[0].is_bonded());
+
+    characteristic.write().unwrap().set_value(vec![1]);
+    stack.wait_idle();
+    assert_eq!(
+        stack.notifications(connection),
+        [Notification {
+            handle,
+            value: vec![1],
+            indication: false,
+        }]
+    );
+
+    // A stranger with a private address is not mistaken for the bonded client.
+    let stranger = stack.connect([0x40, 1, 2, 3, 4, 5]);
+    stack.wait_idle();
+    let connections = server.lock().unwrap().connections();
+    assert_eq!(connections[1].identity(), [0x40, 1, 2, 3, 4, 5]);
+    assert!(!connections[1].is_bonded());
+    assert!(stack.notifications(stranger).is_empty());
+
+    server.lock().unwrap().clear_bonds().unwrap();
+    common::stop(&server);
+}
+
+#[test]
+fn data_length_requests() {
+    let (_guard, stack) = common::stack();
+    let service = Service::new(BleUuid::Uuid16(0x7200)).primary().build();
+    let changes = Arc::new(Mutex::new(Vec::new()));
+    let log = changes.clone();
+    let server = common::start_with(&GattServerConfig::new(), &[&service], |server| {
+        server.on_data_length_change(move |connection| {
+            let length = connection.data_length().tx_octets;
+            log.lock().unwrap().push((connection.address()[0], length));
+        });
+    });
+    for client in 1..=3 {
+        stack.connect([client, 0, 0, 0, 0, 10]);
+    }
+    stack.wait_idle();
+
+    // The server is locked, so the stack cannot complete a request before the others are made.
+    let locked = server.lock().unwrap();
+    let connections = locked.connections();
+    connections[0].request_data_length(100).unwrap();
+    connections[1].request_data_length(200).unwrap();
+    connections[2].request_data_length(150).unwrap();
+    connections[1].request_data_length(251).unwrap();
+    // The third client leaves before its request is sent.
+    stack.disconnect(connections[2].id());
+    drop(locked);
+    stack.wait_idle();
+
+    // Each completed request is reported to the connection that made it.
+    assert_eq!(*changes.lock().unwrap(), [(1, 100), (2, 251)]);
+    let lengths: Vec<_> = server
+        .lock()
+        .unwrap()
+        .connections()
+        .iter()
+        .map(|connection| connection.data_length().tx_octets)
+        .collect();
+    assert_eq!(lengths, [100, 251]);
+
+    common::stop(&server);
+}
+
+#[test]
+fn preferred_parameters() {
+    let (_guard, stack) = common::stack();
+    let service = Service::new(BleUuid::Uuid16(0x7300)).primary().build();
+    let mut config = GattServerConfig::new();
+    config
+        .preferred_connection_parameters(PreferredConnectionParameters::new(400, 480).timeout(600));
+    let server = common::start(&config, &[&service]);
+    let published = || {
+        stack
+            .preferred_connection_parameters()
+            .map(|params| (params.min_int, params.max_int, params.timeout))
+    };
+
+    // The parameters are published when the profile is registered.
+    assert_eq!(published(), Some((400, 480, 600)));
+
+    // Changes are published from the stack's events, when the next client connects.
+    server
+        .lock()
+        .unwrap()
+        .preferred_connection_parameters(PreferredConnectionParameters::new(12, 24).timeout(400));
+    assert_eq!(published(), Some((400, 480, 600)));
+    stack.connect([1, 0, 0, 0, 0, 11]);
+    stack.wait_idle();
+    assert_eq!(published(), Some((12, 24, 400)));
+
+    common::stop(&server);
+}
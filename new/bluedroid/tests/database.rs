@@ -0,0 +1,129 @@
+//! Changes the attributes of the server, and checks how the clients are told about it.
+
+mod common;
+
+use std::sync::{Arc, Mutex, RwLock};
+
+use bluedroid::{
+    gatt_server::{GattServer, GattServerConfig, Profile, Service},
+    simulated::{Notification, SimulatedStack},
+    utilities::BleUuid,
+};
+
+/// The handle of the Service Changed characteristic, provided by the stack.
+const SERVICE_CHANGED: u16 = 0x0003;
+/// The handle of the Database Hash characteristic, provided by the stack.
+const DATABASE_HASH: u16 = 0x0008;
+/// The storage key of the bonded clients that were not told about the last change.
+const PENDING_KEY: &str = "gatt_changed";
+
+/// The indication that tells a client that the whole attribute table changed.
+fn service_changed() -> Notification {
+    Notification {
+        handle: SERVICE_CHANGED,
+        value: vec![0x01, 0x00, 0xFF, 0xFF],
+        indication: true,
+    }
+}
+
+fn service(uuid: u16) -> Arc<RwLock<Service>> {
+    Service::new(BleUuid::Uuid16(uuid))
+        .primary()
+        .characteristic(&common::readable(uuid + 1, b"v"))
+        .build()
+}
+
+/// Starts a server with a profile holding the given services, and returns the profile too.
+fn start(services: &[&Arc<RwLock<Service>>]) -> (Arc<Mutex<GattServer>>, Arc<RwLock<Profile>>) {
+    let mut profile = Profile::new(1);
+    for service in services {
+        let _ = profile.service(service);
+    }
+    let profile = profile.build();
+
+    let server = GattServer::new(&GattServerConfig::new());
+    server
+        .lock()
+        .unwrap()
+        .profile(profile.clone())
+        .start()
+        .unwrap();
+    GattServer::wait_until_ready(&server, common::READY_TIMEOUT).unwrap();
+    SimulatedStack::global().wait_idle();
+    (server, profile)
+}
+
+#[test]
+fn runtime_changes() {
+    let (_guard, stack) = common::stack();
+    let fixed = service(0x5000);
+    let added = service(0x5100);
+    let (server, profile) = start(&[&fixed]);
+    let connection = stack.connect([1, 0, 0, 0, 0, 20]);
+    stack.wait_idle();
+    let hash = stack.read(connection, DATABASE_HASH).unwrap();
+
+    // Adding a service registers it, and tells the connected clients.
+    server
+        .lock()
+        .unwrap()
+        .add_service(&profile, &added)
+        .unwrap();
+    GattServer::wait_until_ready(&server, common::READY_TIMEOUT).unwrap();
+    stack.wait_idle();
+    assert!(stack.find_characteristic(BleUuid::Uuid16(0x5101)).is_some());
+    assert_eq!(stack.notifications(connection), [service_changed()]);
+    assert_ne!(stack.read(connection, DATABASE_HASH).unwrap(), hash);
+
+    // Removing it deletes its attributes, and tells the clients again.
+    server.lock().unwrap().remove_service(&added).unwrap();
+    GattServer::wait_until_ready(&server, common::READY_TIMEOUT).unwrap();
+    stack.wait_idle();
+    assert!(stack.find_characteristic(BleUuid::Uuid16(0x5101)).is_none());
+    assert!(stack.find_characteristic(BleUuid::Uuid16(0x5001)).is_some());
+    assert_eq!(stack.notifications(connection), [service_changed()]);
+    assert_eq!(stack.read(connection, DATABASE_HASH).unwrap(), hash);
+
+    common::stop(&server);
+}
+
+#[test]
+fn bonded_clients() {
+    let (_guard, stack) = common::stack();
+    let fixed = service(0x5200);
+    let (server, _) = start(&[&fixed]);
+    let identity = [1, 0, 0, 0, 0, 21];
+    let connection = stack.connect(identity);
+    stack.bond(connection);
+    stack.disconnect(connection);
+    stack.wait_idle();
+    common::stop(&server);
+
+    // The same attributes after a reboot are not indicated.
+    let (server, _) = start(&[&fixed]);
+    assert!(!stack.storage_keys().iter().any(|key| key == PENDING_KEY));
+    let connection = stack.connect(identity);
+    stack.wait_idle();
+    assert!(stack.notifications(connection).is_empty());
+    stack.disconnect(connection);
+    stack.wait_idle();
+    common::stop(&server);
+
+    // Changed attributes are indicated to a bonded client when it reconnects, and only once.
+    let (server, _) = start(&[&fixed, &service(0x5300)]);
+    assert!(stack.storage_keys().iter().any(|key| key == PENDING_KEY));
+    let stranger = stack.connect([1, 0, 0, 0, 0, 22]);
+    let connection = stack.connect(identity);
+    stack.wait_idle();
+    assert!(stack.notifications(stranger).is_empty());
+    assert_eq!(stack.notifications(connection), [service_changed()]);
+    assert!(!stack.storage_keys().iter().any(|key| key == PENDING_KEY));
+    stack.disconnect(connection);
+    stack.wait_idle();
+    let connection = stack.connect(identity);
+    stack.wait_idle();
+    assert!(stack.notifications(connection).is_empty());
+
+    server.lock().unwrap().clear_bonds().unwrap();
+    common::stop(&server);
+}
@@ -0,0 +1,104 @@
+//! Registers the layout of the lamp firmware, and checks the handles assigned to its attributes.
+
+mod common;
+
+use std::sync::{Arc, RwLock};
+
+use bluedroid::{
+    gatt_server::{Characteristic, GattServerConfig, Service},
+    utilities::{AttributePermissions, BleUuid, CharacteristicProperties},
+};
+
+const LAMP_SERVICE: &str = "4E0F5E1E-FC5B-4D67-8E30-2A83B336476B";
+const BRIGHTNESS: &str = "F9DFBD73-0181-433A-8091-372E0CA8A598";
+const TEMPERATURE: &str = "CA344E9B-7445-43AA-AD20-43A33C8101E9";
+const COLOR: &str = "3A6E1A8C-9E5B-4A1F-8D25-7B0C6F2E4D91";
+
+/// A named characteristic like those of the lamp, with a user description and a CCCD.
+fn lamp_characteristic(uuid: &str, name: &str) -> Arc<RwLock<Characteristic>> {
+    Characteristic::new(BleUuid::from_uuid128_string(uuid))
+        .name(name)
+        .show_name()
+        .permissions(AttributePermissions::new().read().write())
+        .properties(CharacteristicProperties::new().read().write().notify())
+        .set_value(vec![0])
+        .build()
+}
+
+/// The lamp service, with room for later characteristics, followed by a battery service registered as a table.
+fn lamp_layout(extra: &[Arc<RwLock<Characteristic>>]) -> [Arc<RwLock<Service>>; 2] {
+    let mut lamp = Service::new(BleUuid::from_uuid128_string(LAMP_SERVICE));
+    lamp.primary()
+        .reserve_handles(16)
+        .characteristic(&lamp_characteristic(BRIGHTNESS, "Brightness"))
+        .characteristic(&lamp_characteristic(TEMPERATURE, "Temperature"));
+    for characteristic in extra {
+        lamp.characteristic(characteristic);
+    }
+
+    let battery = Service::new(BleUuid::Uuid16(0x180F))
+        .primary()
+        .attribute_table()
+        .characteristic(
+            &Characteristic::new(BleUuid::Uuid16(0x2A19))
+                .permissions(AttributePermissions::new().read())
+                .properties(CharacteristicProperties::new().read().notify())
+                .set_value(vec![100])
+                .build(),
+        )
+        .build();
+
+    [lamp.build(), battery]
+}
+
+/// The handle map of the lamp layout, as stored to compare firmware versions.
+const LAMP_MAP: &str = "\
+0x0028 4e0f5e1e-fc5b-4d67-8e30-2a83b336476b
+0x002a 4e0f5e1e-fc5b-4d67-8e30-2a83b336476b/f9dfbd73-0181-433a-8091-372e0ca8a598
+0x002b 4e0f5e1e-fc5b-4d67-8e30-2a83b336476b/f9dfbd73-0181-433a-8091-372e0ca8a598/0x2901
+0x002c 4e0f5e1e-fc5b-4d67-8e30-2a83b336476b/f9dfbd73-0181-433a-8091-372e0ca8a598/0x2902
+0x002e 4e0f5e1e-fc5b-4d67-8e30-2a83b336476b/ca344e9b-7445-43aa-ad20-43a33c8101e9
+0x002f 4e0f5e1e-fc5b-4d67-8e30-2a83b336476b/ca344e9b-7445-43aa-ad20-43a33c8101e9/0x2901
+0x0030 4e0f5e1e-fc5b-4d67-8e30-2a83b336476b/ca344e9b-7445-43aa-ad20-43a33c8101e9/0x2902
+0x0038 0x180f
+0x003a 0x180f/0x2a19
+0x003b 0x180f/0x2a19/0x2902
+";
+
+#[test]
+fn lamp() {
+    let (_guard, _) = common::stack();
+    let [lamp, battery] = lamp_layout(&[]);
+    let server = common::start(&GattServerConfig::new(), &[&lamp, &battery]);
+
+    // The lamp service reserves 16 handles, so the battery service starts 16 handles after it.
+    let map = server.lock().unwrap().handle_map().unwrap();
+    assert_eq!(map.to_string(), LAMP_MAP);
+    let brightness = format!("{LAMP_SERVICE}/{BRIGHTNESS}").to_lowercase();
+    assert_eq!(map.get(&brightness), Some(0x002A));
+    assert_eq!(map.get(&format!("{brightness}/0x2902")), Some(0x002C));
+    assert_eq!(map.get("0x180f/0x2a19"), Some(0x003A));
+    assert_eq!(map.get("0x180f/0x2a1a"), None);
+    common::stop(&server);
+
+    // A characteristic added in the reserved handles leaves the handles of the other attributes unchanged.
+    let color = lamp_characteristic(COLOR, "Color");
+    let [lamp, battery] = lamp_layout(&[color]);
+    let server = common::start(&GattServerConfig::new(), &[&lamp, &battery]);
+    let updated = server.lock().unwrap().handle_map().unwrap();
+    let added: Vec<(u16, &str)> = updated
+        .iter()
+        .filter(|entry| !map.iter().any(|existing| existing == *entry))
+        .collect();
+    let color = format!("{LAMP_SERVICE}/{COLOR}").to_lowercase();
+    assert_eq!(
+        added,
+        [
+            (0x0032, color.as_str()),
+            (0x0033, format!("{color}/0x2901").as_str()),
+            (0x0034, format!("{color}/0x2902").as_str()),
+        ]
+    );
+    assert_eq!(updated.iter().count(), map.iter().count() + 3);
+    common::stop(&server);
+}
@@ -0,0 +1,111 @@
+//! Registers services with the simulated stack, scripting the events that fail.
+
+mod common;
+
+use std::sync::{Arc, Mutex, RwLock};
+
+use bluedroid::{
+    gatt_server::{Characteristic, GattServer, GattServerConfig, Profile, Service},
+    sys::*,
+    utilities::{AttributePermissions, BleUuid, CharacteristicProperties},
+    Error,
+};
+
+const FAILURE: esp_gatt_status_t = esp_gatt_status_t_ESP_GATT_NO_RESOURCES;
+
+type Outcomes = Arc<Mutex<Vec<Result<(), String>>>>;
+
+/// Starts a server with a service holding a notified characteristic, and logs the registration outcomes.
+fn start(outcomes: &Outcomes) -> (Arc<Mutex<GattServer>>, Arc<RwLock<Profile>>) {
+    let characteristic = Characteristic::new(BleUuid::Uuid16(0x8001))
+        .permissions(AttributePermissions::new().read())
+        .properties(CharacteristicProperties::new().read().notify())
+        .set_value(vec![0])
+        .build();
+    let service = Service::new(BleUuid::Uuid16(0x8000))
+        .primary()
+        .characteristic(&characteristic)
+        .build();
+    let profile = Profile::new(1).service(&service).build();
+
+    let log = outcomes.clone();
+    let server = GattServer::new(&GattServerConfig::new());
+    server
+        .lock()
+        .unwrap()
+        .profile(profile.clone())
+        .on_ready(move |result| {
+            let outcome = result.map_err(|error| error.to_string());
+            log.lock().unwrap().push(outcome);
+        })
+        .start()
+        .unwrap();
+
+    (server, profile)
+}
+
+#[test]
+fn failed_events() {
+    let (_guard, stack) = common::stack();
+
+    for event in [
+        esp_gatts_cb_event_t_ESP_GATTS_REG_EVT,
+        esp_gatts_cb_event_t_ESP_GATTS_CREATE_EVT,
+        esp_gatts_cb_event_t_ESP_GATTS_ADD_CHAR_EVT,
+        esp_gatts_cb_event_t_ESP_GATTS_ADD_CHAR_DESCR_EVT,
+        esp_gatts_cb_event_t_ESP_GATTS_START_EVT,
+    ] {
+        let outcomes = Outcomes::default();
+        stack.fail_next(event, FAILURE);
+        let (server, _) = start(&outcomes);
+
+        // The registration stops at the failed step, and reports its status once.
+        assert!(
+            matches!(
+                GattServer::wait_until_ready(&server, common::READY_TIMEOUT),
+                Err(Error::RegistrationFailed(FAILURE))
+            ),
+            "event {event}"
+        );
+        stack.wait_idle();
+        let expected = Error::RegistrationFailed(FAILURE).to_string();
+        assert_eq!(*outcomes.lock().unwrap(), [Err(expected)], "event {event}");
+        common::stop(&server);
+        assert!(matches!(
+            GattServer::wait_until_ready(&server, common::READY_TIMEOUT),
+            Err(Error::NotStarted)
+        ));
+
+        // The next start registers everything.
+        server.lock().unwrap().start().unwrap();
+        GattServer::wait_until_ready(&server, common::READY_TIMEOUT).unwrap();
+        stack.wait_idle();
+        assert!(stack.find_characteristic(BleUuid::Uuid16(0x8001)).is_some());
+        assert_eq!(outcomes.lock().unwrap().last(), Some(&Ok(())));
+        common::stop(&server);
+    }
+}
+
+#[test]
+fn ready() {
+    let (_guard, stack) = common::stack();
+    let outcomes = Outcomes::default();
+    let (server, profile) = start(&outcomes);
+
+    GattServer::wait_until_ready(&server, common::READY_TIMEOUT).unwrap();
+    stack.wait_idle();
+    assert_eq!(*outcomes.lock().unwrap(), [Ok(())]);
+
+    // Services added at runtime end the registration again.
+    let added = Service::new(BleUuid::Uuid16(0x8100)).primary().build();
+    server
+        .lock()
+        .unwrap()
+        .add_service(&profile, &added)
+        .unwrap();
+    GattServer::wait_until_ready(&server, common::READY_TIMEOUT).unwrap();
+    stack.wait_idle();
+    assert_eq!(*outcomes.lock().unwrap(), [Ok(()), Ok(())]);
+
+    common::stop(&server);
+}
@@ -0,0 +1,311 @@
+//! Pairs clients of the simulated stack with a GATT server, and checks the access to secured attributes.
+
+mod common;
+
+use std::sync::{Arc, Mutex, Weak};
+
+use bluedroid::{
+    gatt_server::{
+        Characteristic, GattServer, GattServerConfig, IoCapabilities, PairingOutcome,
+        SecurityConfig, Service,
+    },
+    sys::*,
+    utilities::{AttributePermissions, BleUuid, CharacteristicProperties, SecurityLevel},
+    Error,
+};
+
+/// The statuses of the rejected accesses.
+const READ_NOT_PERMITTED: u32 = 0x02;
+const WRITE_NOT_PERMITTED: u32 = 0x03;
+const INSUFFICIENT_AUTHENTICATION: u32 = 0x05;
+const INSUFFICIENT_AUTHORIZATION: u32 = 0x08;
+
+/// The SMP reasons of the failed pairings.
+const PASSKEY_ENTRY_FAILED: u8 = 0x01;
+const NUMERIC_COMPARISON_FAILED: u8 = 0x0C;
+
+const PASSKEY: u32 = 123_456;
+
+type Log<T> = Arc<Mutex<Vec<T>>>;
+
+fn characteristic(
+    uuid: u16,
+    permissions: AttributePermissions,
+) -> Arc<std::sync::RwLock<Characteristic>> {
+    Characteristic::new(BleUuid::Uuid16(uuid))
+        .permissions(permissions)
+        .properties(CharacteristicProperties::new().read().write())
+        .set_value(vec![0])
+        .build()
+}
+
+/// Connects a client, and pairs it with the given capabilities if any.
+fn connect(address: u8, pairing: Option<esp_ble_io_cap_t>) -> u16 {
+    let stack = bluedroid::simulated::SimulatedStack::global();
+    let connection = stack.connect([address, 0, 0, 0, 0, 1]);
+
+    if let Some(io_capabilities) = pairing {
+        stack.pair(connection, io_capabilities, PASSKEY);
+    }
+
+    stack.wait_idle();
+    connection
+}
+
+#[test]
+fn permission_matrix() {
+    let (_guard, stack) = common::stack();
+    let read = |level| AttributePermissions::new().read_with(level).write();
+    let write = |level| AttributePermissions::new().read().write_with(level);
+    let attributes = [
+        (0x5001, AttributePermissions::new().read().write()),
+        (0x5002, read(SecurityLevel::Encrypted)),
+        (0x5003, read(SecurityLevel::EncryptedMitm)),
+        (0x5004, read(SecurityLevel::Authorization)),
+        (0x5005, write(SecurityLevel::Encrypted)),
+        (0x5006, write(SecurityLevel::EncryptedMitm)),
+    ];
+    let mut service = Service::new(BleUuid::Uuid16(0x5000));
+    service.primary();
+    for (uuid, permissions) in attributes {
+        service.characteristic(&characteristic(uuid, permissions));
+    }
+    let service = service.build();
+    let read_only = Characteristic::new(BleUuid::Uuid16(0x5007))
+        .permissions(AttributePermissions::new().read())
+        .properties(CharacteristicProperties::new().read())
+        .set_value(vec![0])
+        .build();
+    let write_only = Characteristic::new(BleUuid::Uuid16(0x5008))
+        .permissions(AttributePermissions::new().write())
+        .properties(CharacteristicProperties::new().write())
+        .build();
+    service
+        .write()
+        .unwrap()
+        .characteristic(&read_only)
+        .characteristic(&write_only);
+
+    // The server displays a passkey: clients with a keyboard authenticate, the others just encrypt.
+    let server = common::start_with(&GattServerConfig::new(), &[&service], |server| {
+        server.security(
+            SecurityConfig::new()
+                .io_capabilities(IoCapabilities::DisplayOnly)
+                .static_passkey(PASSKEY)
+                .clone(),
+        );
+    });
+
+    let unpaired = connect(0xA1, None);
+    let encrypted = connect(0xA2, Some(ESP_IO_CAP_NONE as _));
+    let authenticated = connect(0xA3, Some(ESP_IO_CAP_IN as _));
+    let insufficient = Err(INSUFFICIENT_AUTHENTICATION);
+
+    // The expected read and write results, for the unpaired, encrypted and authenticated links.
+    let matrix = [
+        (0x5001, [Ok(()); 3], [Ok(()); 3]),
+        (0x5002, [insufficient, Ok(()), Ok(())], [Ok(()); 3]),
+        (0x5003, [insufficient, insufficient, Ok(())], [Ok(()); 3]),
+        (0x5004, [Err(INSUFFICIENT_AUTHORIZATION); 3], [Ok(()); 3]),
+        (0x5005, [Ok(()); 3], [insufficient, Ok(()), Ok(())]),
+        (0x5006, [Ok(()); 3], [insufficient, insufficient, Ok(())]),
+        (0x5007, [Ok(()); 3], [Err(WRITE_NOT_PERMITTED); 3]),
+        (0x5008, [Err(READ_NOT_PERMITTED); 3], [Ok(()); 3]),
+    ];
+
+    for (uuid, reads, writes) in matrix {
+        let handle = stack.find_characteristic(BleUuid::Uuid16(uuid)).unwrap();
+        for (index, connection) in [unpaired, encrypted, authenticated].into_iter().enumerate() {
+            let read = stack.read(connection, handle).map(|_| ());
+            let write = stack.write(connection, handle, &[1]);
+            assert_eq!(read, reads[index], "read of 0x{uuid:04x} on link {index}");
+            assert_eq!(
+                write, writes[index],
+                "write of 0x{uuid:04x} on link {index}"
+            );
+        }
+    }
+
+    let connections = server.lock().unwrap().connections();
+    let levels: Vec<_> = connections
+        .iter()
+        .map(|connection| connection.security())
+        .collect();
+    assert_eq!(
+        levels,
+        [
+            SecurityLevel::Open,
+            SecurityLevel::Encrypted,
+            SecurityLevel::EncryptedMitm
+        ]
+    );
+
+    server.lock().unwrap().clear_bonds().unwrap();
+    common::stop(&server);
+}
+
+/// Starts a server with the given security configuration, and logs the pairing outcomes by client.
+fn start(config: &mut SecurityConfig) -> (Arc<Mutex<GattServer>>, Log<(u8, PairingOutcome)>) {
+    let outcomes = Log::default();
+    let log = outcomes.clone();
+    config.on_pairing_complete(move |address, outcome| {
+        log.lock().unwrap().push((address[0], outcome))
+    });
+
+    let service = Service::new(BleUuid::Uuid16(0x6000)).primary().build();
+    let server = common::start_with(&GattServerConfig::new(), &[&service], |server| {
+        server.security(config.clone());
+    });
+
+    (server, outcomes)
+}
+
+#[test]
+fn passkey_entry() {
+    let (_guard, stack) = common::stack();
+    let requests = Log::default();
+    let log = requests.clone();
+    let (server, outcomes) = start(
+        SecurityConfig::new()
+            .io_capabilities(IoCapabilities::KeyboardOnly)
+            .bonding(false)
+            .on_passkey_request(move |address| log.lock().unwrap().push(address)),
+    );
+    let address = |client: u8| [client, 0, 0, 0, 0, 1];
+    let display = ESP_IO_CAP_OUT as esp_ble_io_cap_t;
+
+    // The pairing waits for the reply.
+    connect(0xB1, Some(display));
+    assert_eq!(*requests.lock().unwrap(), [address(0xB1)]);
+    assert!(outcomes.lock().unwrap().is_empty());
+    let mut locked = server.lock().unwrap();
+    assert!(matches!(
+        locked.confirm_reply(address(0xB1), true),
+        Err(Error::NoPairingRequest)
+    ));
+    assert!(matches!(
+        locked.passkey_reply(address(0xB1), Some(1_000_000)),
+        Err(Error::InvalidPasskey(1_000_000))
+    ));
+    locked.passkey_reply(address(0xB1), Some(PASSKEY)).unwrap();
+    assert!(matches!(
+        locked.passkey_reply(address(0xB1), Some(PASSKEY)),
+        Err(Error::NoPairingRequest)
+    ));
+    drop(locked);
+    stack.wait_idle();
+
+    // A wrong passkey or no passkey fails the pairing.
+    connect(0xB2, Some(display));
+    connect(0xB3, Some(display));
+    server
+        .lock()
+        .unwrap()
+        .passkey_reply(address(0xB2), Some(111_111))
+        .unwrap();
+    server
+        .lock()
+        .unwrap()
+        .passkey_reply(address(0xB3), None)
+        .unwrap();
+    stack.wait_idle();
+
+    // The requests of disconnected clients are dropped.
+    let connection = connect(0xB4, Some(display));
+    stack.disconnect(connection);
+    stack.wait_idle();
+    assert!(matches!(
+        server
+            .lock()
+            .unwrap()
+            .passkey_reply(address(0xB4), Some(PASSKEY)),
+        Err(Error::NoPairingRequest)
+    ));
+
+    assert_eq!(
+        *outcomes.lock().unwrap(),
+        [
+            (0xB1, PairingOutcome::Paired),
+            (0xB2, PairingOutcome::Failed(PASSKEY_ENTRY_FAILED)),
+            (0xB3, PairingOutcome::Failed(PASSKEY_ENTRY_FAILED)),
+        ]
+    );
+    common::stop(&server);
+
+    // Without a callback, the passkey entry is rejected.
+    let (server, outcomes) = start(
+        SecurityConfig::new()
+            .io_capabilities(IoCapabilities::KeyboardOnly)
+            .bonding(false),
+    );
+    connect(0xB5, Some(display));
+    assert_eq!(
+        *outcomes.lock().unwrap(),
+        [(0xB5, PairingOutcome::Failed(PASSKEY_ENTRY_FAILED))]
+    );
+    common::stop(&server);
+    assert!(matches!(
+        server
+            .lock()
+            .unwrap()
+            .passkey_reply(address(0xB5), Some(PASSKEY)),
+        Err(Error::NotStarted)
+    ));
+}
+
+#[test]
+fn numeric_comparison() {
+    let (_guard, stack) = common::stack();
+    let this: Arc<Mutex<Weak<Mutex<GattServer>>>> = Arc::default();
+    let server_ref = this.clone();
+    let compared = Log::default();
+    let log = compared.clone();
+    let (server, outcomes) = start(
+        SecurityConfig::new()
+            .io_capabilities(IoCapabilities::DisplayYesNo)
+            .mitm(true)
+            .on_numeric_comparison(move |address, number| {
+                log.lock().unwrap().push(number);
+
+                // The callback is called with the server released, so it can answer right away.
+                if address[0] == 0xC1 {
+                    let server = server_ref.lock().unwrap().upgrade().unwrap();
+                    server.lock().unwrap().confirm_reply(address, true).unwrap();
+                }
+            }),
+    );
+    *this.lock().unwrap() = Arc::downgrade(&server);
+    let io = ESP_IO_CAP_IO as esp_ble_io_cap_t;
+
+    connect(0xC1, Some(io));
+    connect(0xC2, Some(io));
+    assert!(matches!(
+        server
+            .lock()
+            .unwrap()
+            .passkey_reply([0xC2, 0, 0, 0, 0, 1], Some(PASSKEY)),
+        Err(Error::NoPairingRequest)
+    ));
+    server
+        .lock()
+        .unwrap()
+        .confirm_reply([0xC2, 0, 0, 0, 0, 1], false)
+        .unwrap();
+    stack.wait_idle();
+
+    assert_eq!(*compared.lock().unwrap(), [PASSKEY, PASSKEY]);
+    assert_eq!(
+        *outcomes.lock().unwrap(),
+        [
+            (0xC1, PairingOutcome::Bonded),
+            (0xC2, PairingOutcome::Failed(NUMERIC_COMPARISON_FAILED)),
+        ]
+    );
+    assert_eq!(
+        server.lock().unwrap().bonded_devices().unwrap(),
+        [[0xC1, 0, 0, 0, 0, 1]]
+    );
+
+    server.lock().unwrap().clear_bonds().unwrap();
+    common::stop(&server);
+}
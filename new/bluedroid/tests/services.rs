@@ -0,0 +1,293 @@
+//! Reads the standard services through the simulated stack, and checks their encoding
+//! and how services include each other.
+
+mod common;
+
+use std::{
+    sync::{Arc, RwLock},
+    time::{Duration, UNIX_EPOCH},
+};
+
+use bluedroid::{
+    gatt_server::{GattServer, GattServerConfig, Profile, Service},
+    services::{
+        BatteryService, CurrentTime, CurrentTimeService, DeviceInformationService, PnpId,
+        TxPowerService, VendorIdSource,
+    },
+    simulated::{AttributeKind, Notification},
+    utilities::{BleUuid, DecodeError, GattValue},
+    Error,
+};
+
+#[test]
+fn device_information() {
+    let (_guard, stack) = common::stack();
+    let pnp_id = PnpId {
+        vendor_id_source: VendorIdSource::Bluetooth,
+        vendor_id: 0x02E5,
+        product_id: 0x1234,
+        product_version: 0x0100,
+    };
+    let encoded = [0x01, 0xE5, 0x02, 0x34, 0x12, 0x00, 0x01];
+    assert_eq!(pnp_id.encode(), encoded);
+    assert_eq!(PnpId::decode(&encoded), Ok(pnp_id));
+    assert_eq!(
+        PnpId::decode(&[0x03, 0xE5, 0x02, 0x34, 0x12, 0x00, 0x01]),
+        Err(DecodeError::InvalidValue)
+    );
+
+    let service = DeviceInformationService::new();
+    service
+        .manufacturer_name("Espressif")
+        .unwrap()
+        .firmware_revision("1.0.0")
+        .unwrap()
+        .pnp_id(pnp_id)
+        .unwrap();
+    assert!(matches!(
+        service.serial_number("0".repeat(65)),
+        Err(Error::ValueTooLong { .. })
+    ));
+    let server = common::start(&GattServerConfig::new(), &[&*service]);
+
+    let connection = stack.connect([1, 0, 0, 0, 0, 11]);
+    let read = |uuid| {
+        let handle = stack.find_characteristic(uuid).unwrap();
+        stack.read(connection, handle).unwrap()
+    };
+    assert_eq!(
+        read(DeviceInformationService::MANUFACTURER_NAME_UUID),
+        b"Espressif"
+    );
+    assert_eq!(
+        read(DeviceInformationService::FIRMWARE_REVISION_UUID),
+        b"1.0.0"
+    );
+    assert_eq!(read(DeviceInformationService::PNP_ID_UUID), encoded);
+    assert!(stack
+        .find_characteristic(DeviceInformationService::MODEL_NUMBER_UUID)
+        .is_none());
+
+    // Existing characteristics are updated, new ones cannot be added anymore.
+    service.firmware_revision("1.0.1").unwrap();
+    stack.wait_idle();
+    assert_eq!(
+        read(DeviceInformationService::FIRMWARE_REVISION_UUID),
+        b"1.0.1"
+    );
+    assert!(matches!(
+        service.model_number("Lamp"),
+        Err(Error::AlreadyStarted)
+    ));
+
+    common::stop(&server);
+}
+
+#[test]
+fn battery_level() {
+    let (_guard, stack) = common::stack();
+    assert!(matches!(
+        BatteryService::new(101),
+        Err(Error::InvalidValue(_))
+    ));
+    let service = BatteryService::new(80).unwrap();
+    let server = common::start(&GattServerConfig::new(), &[&*service]);
+
+    let connection = stack.connect([1, 0, 0, 0, 0, 12]);
+    let handle = stack
+        .find_characteristic(BatteryService::LEVEL_UUID)
+        .unwrap();
+    assert_eq!(stack.read(connection, handle).unwrap(), [80]);
+
+    // Subscribed clients are notified of the new levels, invalid ones are rejected.
+    stack.subscribe(connection, handle, true, false).unwrap();
+    stack.wait_idle();
+    service.set_level(100).unwrap();
+    assert!(matches!(
+        service.set_level(101),
+        Err(Error::InvalidValue(_))
+    ));
+    stack.wait_idle();
+    assert_eq!(
+        stack.notifications(connection),
+        [Notification {
+            handle,
+            value: vec![100],
+            indication: false,
+        }]
+    );
+    assert_eq!(stack.read(connection, handle).unwrap(), [100]);
+
+    common::stop(&server);
+}
+
+#[test]
+fn current_time() {
+    let (_guard, stack) = common::stack();
+
+    // 2024-02-29 13:45:30.5 UTC, a Thursday.
+    let time = CurrentTime::from(UNIX_EPOCH + Duration::from_millis(1_709_214_330_500));
+    assert_eq!(
+        time,
+        CurrentTime {
+            year: 2024,
+            month: 2,
+            day: 29,
+            hours: 13,
+            minutes: 45,
+            seconds: 30,
+            day_of_week: 4,
+            fractions256: 128,
+            adjust_reason: 0,
+        }
+    );
+    let adjusted = CurrentTime {
+        adjust_reason: 0x01,
+        ..time
+    };
+    let encoded = [0xE8, 0x07, 2, 29, 13, 45, 30, 4, 128, 0x01];
+    assert_eq!(adjusted.encode(), encoded);
+    assert_eq!(CurrentTime::decode(&encoded), Ok(adjusted));
+    assert_eq!(
+        CurrentTime::decode(&encoded[..9]),
+        Err(DecodeError::InvalidLength {
+            expected: 10,
+            actual: 9
+        })
+    );
+    assert_eq!(
+        CurrentTime::decode(&[0xE8, 0x07, 13, 29, 13, 45, 30, 4, 128, 0x01]),
+        Err(DecodeError::InvalidValue)
+    );
+
+    // Unknown fields are zero, and the epoch is a Thursday too.
+    assert_eq!(CurrentTime::default().encode(), [0; 10]);
+    assert_eq!(CurrentTime::from(UNIX_EPOCH).day_of_week, 4);
+
+    let invalid = CurrentTime { hours: 24, ..time };
+    assert!(matches!(
+        CurrentTimeService::new(invalid),
+        Err(Error::InvalidValue(_))
+    ));
+    let service = CurrentTimeService::new(time).unwrap();
+    let server = common::start(&GattServerConfig::new(), &[&*service]);
+
+    let connection = stack.connect([1, 0, 0, 0, 0, 13]);
+    let handle = stack
+        .find_characteristic(CurrentTimeService::CURRENT_TIME_UUID)
+        .unwrap();
+    assert_eq!(stack.read(connection, handle).unwrap(), time.encode());
+    service.set_time(adjusted).unwrap();
+    stack.wait_idle();
+    assert_eq!(stack.read(connection, handle).unwrap(), encoded);
+
+    common::stop(&server);
+}
+
+#[test]
+fn tx_power() {
+    let (_guard, stack) = common::stack();
+    for level in [-101, 21] {
+        assert!(matches!(
+            TxPowerService::new(level),
+            Err(Error::InvalidValue(_))
+        ));
+    }
+    let service = TxPowerService::new(-100).unwrap();
+    let server = common::start(&GattServerConfig::new(), &[&*service]);
+
+    // The level is a signed byte.
+    let connection = stack.connect([1, 0, 0, 0, 0, 14]);
+    let handle = stack
+        .find_characteristic(TxPowerService::LEVEL_UUID)
+        .unwrap();
+    assert_eq!(stack.read(connection, handle).unwrap(), [0x9C]);
+    for (level, encoded) in [(-1, 0xFF), (0, 0x00), (20, 0x14)] {
+        service.set_level(level).unwrap();
+        stack.wait_idle();
+        assert_eq!(stack.read(connection, handle).unwrap(), [encoded]);
+    }
+
+    common::stop(&server);
+}
+
+#[test]
+fn includes() {
+    let (_guard, stack) = common::stack();
+    let secondary = Service::new(BleUuid::Uuid16(0x6100))
+        .characteristic(&common::readable(0x6101, b"s"))
+        .build();
+    let primary = Service::new(BleUuid::Uuid16(0x6000))
+        .primary()
+        .include(&secondary)
+        .build();
+    let start = |services: &[&Arc<RwLock<Service>>]| {
+        let mut profile = Profile::new(1);
+        for service in services {
+            let _ = profile.service(service);
+        }
+        let server = GattServer::new(&GattServerConfig::new());
+        let result = server.lock().unwrap().profile(profile.build()).start();
+        result
+    };
+
+    // A secondary service must be included, by a service added after it.
+    assert!(matches!(
+        start(&[&secondary]),
+        Err(Error::InvalidService(reason)) if reason.contains("secondary")
+    ));
+    assert!(matches!(
+        start(&[&primary, &secondary]),
+        Err(Error::InvalidService(reason)) if reason.contains("added to the server before")
+    ));
+
+    let server = common::start(&GattServerConfig::new(), &[&secondary, &primary]);
+    // The services follow the built-in ones.
+    let kinds: Vec<AttributeKind> = stack
+        .attributes()
+        .into_iter()
+        .map(|attribute| attribute.kind)
+        .skip_while(|kind| *kind != AttributeKind::SecondaryService)
+        .collect();
+    assert_eq!(
+        kinds,
+        [
+            AttributeKind::SecondaryService,
+            AttributeKind::CharacteristicDeclaration,
+            AttributeKind::CharacteristicValue,
+            AttributeKind::PrimaryService,
+            AttributeKind::Include,
+        ]
+    );
+    let find = |kind| {
+        stack
+            .attributes()
+            .into_iter()
+            .find(|attribute| attribute.kind == kind)
+            .unwrap()
+    };
+    let secondary_handle = find(AttributeKind::SecondaryService).handle;
+    let include = find(AttributeKind::Include);
+    let [start, end] = [secondary_handle, secondary_handle + 2].map(u16::to_le_bytes);
+    assert_eq!(
+        include.value,
+        [&start[..], &end[..], &[0x00, 0x61]].concat()
+    );
+
+    // An included service outlives the services that include it.
+    let mut locked = server.lock().unwrap();
+    assert!(matches!(
+        locked.remove_service(&secondary),
+        Err(Error::InvalidService(reason)) if reason.contains("included")
+    ));
+    locked.remove_service(&primary).unwrap();
+    locked.remove_service(&secondary).unwrap();
+    drop(locked);
+    stack.wait_idle();
+    assert!(stack.attributes().iter().all(|attribute| !matches!(
+        attribute.kind,
+        AttributeKind::SecondaryService | AttributeKind::Include
+    )));
+
+    common::stop(&server);
+}
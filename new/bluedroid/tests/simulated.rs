@@ -0,0 +1,475 @@
+//! Drives a GATT server through the simulated stack, acting as a client.
+
+mod common;
+
+use std::sync::{Arc, Mutex, Weak};
+
+use bluedroid::{
+    gatt_server::{Characteristic, GattServer, GattServerConfig, LongNotificationPolicy, Service},
+    simulated::Notification,
+    sys::*,
+    utilities::{
+        AttError, AttributePermissions, BleUuid, CharacteristicProperties, DisconnectReason,
+    },
+    Error,
+};
+
+/// The status of an ATT error, as reported to the client.
+const INVALID_OFFSET: u32 = 0x07;
+const INVALID_ATTRIBUTE_VALUE_LENGTH: u32 = 0x0D;
+
+#[test]
+fn read() {
+    let (_guard, stack) = common::stack();
+    let calls = Arc::new(Mutex::new(0));
+    let counter = calls.clone();
+    let fixed = common::readable(0xA001, b"hello");
+    let dynamic = Characteristic::new(BleUuid::Uuid16(0xA002))
+        .permissions(AttributePermissions::new().read())
+        .properties(CharacteristicProperties::new().read())
+        .on_read(move |_| {
+            let mut calls = counter.lock().unwrap();
+            *calls += 1;
+            Ok(vec![*calls])
+        })
+        .build();
+    let service = Service::new(BleUuid::Uuid16(0xA000))
+        .primary()
+        .characteristic(&fixed)
+        .characteristic(&dynamic)
+        .build();
+    let server = common::start(&GattServerConfig::new(), &[&service]);
+
+    let connection = stack.connect([1, 0, 0, 0, 0, 1]);
+    let fixed_handle = stack.find_characteristic(BleUuid::Uuid16(0xA001)).unwrap();
+    let dynamic_handle = stack.find_characteristic(BleUuid::Uuid16(0xA002)).unwrap();
+    assert_eq!(stack.read(connection, fixed_handle).unwrap(), b"hello");
+    assert_eq!(stack.read(connection, dynamic_handle).unwrap(), [1]);
+    assert_eq!(stack.read(connection, dynamic_handle).unwrap(), [2]);
+
+    // A value set at runtime is read by the stack.
+    fixed.write().unwrap().set_value(b"world".to_vec());
+    stack.wait_idle();
+    assert_eq!(stack.read(connection, fixed_handle).unwrap(), b"world");
+
+    common::stop(&server);
+}
+
+#[test]
+fn long_read() {
+    let (_guard, stack) = common::stack();
+    let value: Vec<u8> = (0..40).collect();
+    let automatic = common::readable(0xB001, &value);
+    let by_app = Characteristic::new(BleUuid::Uuid16(0xB002))
+        .permissions(AttributePermissions::new().read().write())
+        .properties(CharacteristicProperties::new().read().write())
+        .max_value_length(64)
+        .on_write(|_, _| Ok(()))
+        .set_value(value.clone())
+        .build();
+    let service = Service::new(BleUuid::Uuid16(0xB000))
+        .primary()
+        .characteristic(&automatic)
+        .characteristic(&by_app)
+        .build();
+    let server = common::start(&GattServerConfig::new(), &[&service]);
+
+    let connection = stack.connect([1, 0, 0, 0, 0, 2]);
+    for uuid in [0xB001, 0xB002] {
+        let handle = stack.find_characteristic(BleUuid::Uuid16(uuid)).unwrap();
+        // A read returns the first MTU - 1 bytes, the rest is read by blobs.
+        assert_eq!(stack.read(connection, handle).unwrap(), value[..22]);
+        assert_eq!(stack.read_long(connection, handle).unwrap(), value);
+        assert_eq!(stack.read_blob(connection, handle, 40).unwrap(), []);
+        assert_eq!(stack.read_blob(connection, handle, 41), Err(INVALID_OFFSET));
+    }
+
+    common::stop(&server);
+}
+
+#[test]
+fn write() {
+    let (_guard, stack) = common::stack();
+    let written = Arc::new(Mutex::new(Vec::new()));
+    let log = written.clone();
+    let characteristic = Characteristic::new(BleUuid::Uuid16(0xC001))
+        .permissions(AttributePermissions::new().read().write())
+        .properties(
+            CharacteristicProperties::new()
+                .read()
+                .write()
+                .write_without_response(),
+        )
+        .max_value_length(4)
+        .on_write(move |value, _| {
+            if value == [0xFF] {
+                return Err(AttError::Application(0x80));
+            }
+            log.lock().unwrap().push(value);
+            Ok(())
+        })
+        .set_value(vec![0])
+        .build();
+    let service = Service::new(BleUuid::Uuid16(0xC000))
+        .primary()
+        .characteristic(&characteristic)
+        .build();
+    let server = common::start(&GattServerConfig::new(), &[&service]);
+
+    let connection = stack.connect([1, 0, 0, 0, 0, 3]);
+    let handle = stack.find_characteristic(BleUuid::Uuid16(0xC001)).unwrap();
+    stack.write(connection, handle, &[1, 2]).unwrap();
+    assert_eq!(stack.read(connection, handle).unwrap(), [1, 2]);
+
+    // Rejected values reach the client, and do not replace the stored one.
+    assert_eq!(stack.write(connection, handle, &[0xFF]), Err(0x80));
+    assert_eq!(
+        stack.write(connection, handle, &[0; 5]),
+        Err(INVALID_ATTRIBUTE_VALUE_LENGTH)
+    );
+    assert_eq!(stack.read(connection, handle).unwrap(), [1, 2]);
+
+    stack
+        .write_without_response(connection, handle, &[3])
+        .unwrap();
+    stack.wait_idle();
+    assert_eq!(*written.lock().unwrap(), [vec![1, 2], vec![3]]);
+
+    common::stop(&server);
+}
+
+#[test]
+fn prepared_write() {
+    let (_guard, stack) = common::stack();
+    let written = Arc::new(Mutex::new(Vec::new()));
+    let log = written.clone();
+    let by_app = Characteristic::new(BleUuid::Uuid16(0xD001))
+        .permissions(AttributePermissions::new().read().write())
+        .properties(CharacteristicProperties::new().read().write())
+        .max_value_length(100)
+        .on_write(move |value, _| {
+            log.lock().unwrap().push(value);
+            Ok(())
+        })
+        .build();
+    let automatic = Characteristic::new(BleUuid::Uuid16(0xD002))
+        .permissions(AttributePermissions::new().read().write())
+        .properties(CharacteristicProperties::new().read().write())
+        .max_value_length(50)
+        .set_value(vec![0])
+        .build();
+    let service = Service::new(BleUuid::Uuid16(0xD000))
+        .primary()
+        .characteristic(&by_app)
+        .characteristic(&automatic)
+        .build();
+    let server = common::start(&GattServerConfig::new(), &[&service]);
+
+    let connection = stack.connect([1, 0, 0, 0, 0, 4]);
+    let by_app_handle = stack.find_characteristic(BleUuid::Uuid16(0xD001)).unwrap();
+    let automatic_handle = stack.find_characteristic(BleUuid::Uuid16(0xD002)).unwrap();
+
+    // The callback receives the assembled value once.
+    let value: Vec<u8> = (0..60).collect();
+    stack.write_long(connection, by_app_handle, &value).unwrap();
+    assert_eq!(*written.lock().unwrap(), std::slice::from_ref(&value));
+    assert_eq!(stack.read_long(connection, by_app_handle).unwrap(), value);
+
+    // Cancelled writes never reach it.
+    stack
+        .prepare_write(connection, by_app_handle, 0, &[1, 2, 3])
+        .unwrap();
+    stack.execute_write(connection, false).unwrap();
+    assert_eq!(written.lock().unwrap().len(), 1);
+    assert_eq!(
+        stack.prepare_write(connection, by_app_handle, 5, &[1]),
+        Err(INVALID_OFFSET)
+    );
+    assert_eq!(
+        stack.write_long(connection, by_app_handle, &[0; 101]),
+        Err(INVALID_ATTRIBUTE_VALUE_LENGTH)
+    );
+
+    // The stack assembles the writes of characteristics without a write callback.
+    let value: Vec<u8> = (0..45).collect();
+    stack
+        .write_long(connection, automatic_handle, &value)
+        .unwrap();
+    assert_eq!(
+        stack.read_long(connection, automatic_handle).unwrap(),
+        value
+    );
+
+    // The writes prepared by a client are discarded when it disconnects.
+    let other = stack.connect([1, 0, 0, 0, 0, 5]);
+    stack.prepare_write(other, by_app_handle, 0, &[9]).unwrap();
+    stack.disconnect(other);
+    stack.wait_idle();
+    stack.execute_write(connection, true).unwrap();
+    assert_eq!(written.lock().unwrap().len(), 1);
+
+    common::stop(&server);
+}
+
+#[test]
+fn notify() {
+    let (_guard, stack) = common::stack();
+    let notified = Characteristic::new(BleUuid::Uuid16(0xE001))
+        .permissions(AttributePermissions::new().read())
+        .properties(CharacteristicProperties::new().read().notify())
+        .set_value(b"a".to_vec())
+        .build();
+    let indicated = Characteristic::new(BleUuid::Uuid16(0xE002))
+        .permissions(AttributePermissions::new().read())
+        .properties(CharacteristicProperties::new().read().indicate())
+        .set_value(b"b".to_vec())
+        .build();
+    let service = Service::new(BleUuid::Uuid16(0xE000))
+        .primary()
+        .characteristic(&notified)
+        .characteristic(&indicated)
+        .build();
+    let server = common::start(&GattServerConfig::new(), &[&service]);
+
+    let connection = stack.connect([1, 0, 0, 0, 0, 6]);
+    stack.wait_idle();
+    let notified_handle = stack.find_characteristic(BleUuid::Uuid16(0xE001)).unwrap();
+    let indicated_handle = stack.find_characteristic(BleUuid::Uuid16(0xE002)).unwrap();
+    assert!(matches!(
+        notified
+            .read()
+            .unwrap()
+            .notify_to(connection, b"x".to_vec()),
+        Err(Error::NotSubscribed)
+    ));
+    assert!(matches!(
+        notified.read().unwrap().notify_to(99, b"x".to_vec()),
+        Err(Error::NotConnected)
+    ));
+
+    stack
+        .subscribe(connection, notified_handle, true, false)
+        .unwrap();
+    stack
+        .subscribe(connection, indicated_handle, false, true)
+        .unwrap();
+    stack.wait_idle();
+    // Setting a value notifies the subscribed clients once the stack stores it.
+    notified.write().unwrap().set_value(b"c".to_vec());
+    stack.wait_idle();
+    notified
+        .read()
+        .unwrap()
+        .notify_to(connection, b"d".to_vec())
+        .unwrap();
+    let confirmation = indicated
+        .read()
+        .unwrap()
+        .indicate_to(connection, b"e".to_vec())
+        .unwrap();
+    confirmation.wait(common::READY_TIMEOUT).unwrap();
+    stack.wait_idle();
+    let notification = |handle, value: &[u8], indication| Notification {
+        handle,
+        value: value.to_vec(),
+        indication,
+    };
+    assert_eq!(
+        stack.notifications(connection),
+        [
+            notification(notified_handle, b"c", false),
+            notification(notified_handle, b"d", false),
+            notification(indicated_handle, b"e", true),
+        ]
+    );
+
+    // Unsubscribed clients are not notified anymore.
+    stack
+        .subscribe(connection, notified_handle, false, false)
+        .unwrap();
+    stack.wait_idle();
+    notified.write().unwrap().set_value(b"f".to_vec());
+    stack.wait_idle();
+    assert!(stack.notifications(connection).is_empty());
+
+    common::stop(&server);
+}
+
+#[test]
+fn fragmented_indication() {
+    let (_guard, stack) = common::stack();
+    let characteristic = Characteristic::new(BleUuid::Uuid16(0xE101))
+        .permissions(AttributePermissions::new().read())
+        .properties(CharacteristicProperties::new().read().indicate())
+        .build();
+    let service = Service::new(BleUuid::Uuid16(0xE100))
+        .primary()
+        .characteristic(&characteristic)
+        .build();
+    let server = common::start(
+        GattServerConfig::new().long_notifications(LongNotificationPolicy::Fragment),
+        &[&service],
+    );
+
+    let connection = stack.connect([1, 0, 0, 0, 0, 7]);
+    stack.wait_idle();
+    let handle = stack.find_characteristic(BleUuid::Uuid16(0xE101)).unwrap();
+    stack.subscribe(connection, handle, false, true).unwrap();
+    stack.wait_idle();
+
+    // The default MTU carries 20 bytes per indication: the value is sent in three parts,
+    // and its confirmation covers all of them.
+    let value: Vec<u8> = (0..50).collect();
+    let confirmation = characteristic
+        .read()
+        .unwrap()
+        .indicate_to(connection, value.clone())
+        .unwrap();
+    confirmation.wait(common::READY_TIMEOUT).unwrap();
+    let parts: Vec<Vec<u8>> = stack
+        .notifications(connection)
+        .into_iter()
+        .map(|notification| notification.value)
+        .collect();
+    assert_eq!(parts, [&value[..20], &value[20..40], &value[40..]]);
+
+    // A single part that is not confirmed fails the whole indication.
+    stack.fail_next(
+        esp_gatts_cb_event_t_ESP_GATTS_CONF_EVT,
+        esp_gatt_status_t_ESP_GATT_ERROR,
+    );
+    let confirmation = characteristic
+        .read()
+        .unwrap()
+        .indicate_to(connection, value)
+        .unwrap();
+    assert!(matches!(
+        confirmation.wait(common::READY_TIMEOUT),
+        Err(Error::IndicationFailed(status)) if status == esp_gatt_status_t_ESP_GATT_ERROR
+    ));
+    stack.wait_idle();
+    assert_eq!(stack.notifications(connection).len(), 3);
+
+    common::stop(&server);
+}
+
+#[test]
+fn restart() {
+    let (_guard, stack) = common::stack();
+    let characteristic = common::readable(0xF001, b"v");
+    let service = Service::new(BleUuid::Uuid16(0xF000))
+        .primary()
+        .characteristic(&characteristic)
+        .build();
+    let server = common::start(GattServerConfig::new().device_name("Restart"), &[&service]);
+    let handles = || {
+        stack
+            .attributes()
+            .into_iter()
+            .map(|attribute| (attribute.handle, attribute.uuid))
+            .collect::<Vec<_>>()
+    };
+    let attributes = handles();
+
+    for _ in 0..2 {
+        assert_eq!(stack.device_name(), "Restart");
+        assert!(stack.is_advertising());
+        let connection = stack.connect([1, 0, 0, 0, 0, 7]);
+        let handle = stack.find_characteristic(BleUuid::Uuid16(0xF001)).unwrap();
+        assert_eq!(stack.read(connection, handle).unwrap(), b"v");
+
+        // Stopping the server clears the database and drops the connections.
+        common::stop(&server);
+        assert!(stack.find_characteristic(BleUuid::Uuid16(0xF001)).is_none());
+        assert!(matches!(
+            server.lock().unwrap().stop(),
+            Err(Error::NotStarted)
+        ));
+
+        // Once started again, the services get the same handles.
+        server.lock().unwrap().start().unwrap();
+        bluedroid::gatt_server::GattServer::wait_until_ready(&server, common::READY_TIMEOUT)
+            .unwrap();
+        stack.wait_idle();
+        assert_eq!(handles(), attributes);
+    }
+
+    // Dropping the last reference stops the server too.
+    drop(server);
+    stack.wait_idle();
+    assert!(stack.find_characteristic(BleUuid::Uuid16(0xF001)).is_none());
+}
+
+#[test]
+fn connection_callbacks() {
+    let (_guard, stack) = common::stack();
+    let this: Arc<Mutex<Weak<Mutex<GattServer>>>> = Arc::default();
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let service = Service::new(BleUuid::Uuid16(0x1000)).primary().build();
+
+    // The callbacks are called once the server is released, so they can use it.
+    let server = common::start_with(&GattServerConfig::new(), &[&service], |server| {
+        let (server_ref, log) = (this.clone(), events.clone());
+        server.on_connect(move |connection| {
+            let server = server_ref.lock().unwrap().upgrade().unwrap();
+            let server = server.lock().unwrap();
+            let connected = server
+                .connections()
+                .iter()
+                .any(|open| open.id() == connection.id());
+            log.lock().unwrap().push(format!("connect {connected}"));
+
+            if connection.address()[0] == 2 {
+                server.disconnect(connection).unwrap();
+            }
+        });
+
+        let (server_ref, log) = (this.clone(), events.clone());
+        server.on_mtu_change(move |connection| {
+            let server = server_ref.lock().unwrap().upgrade().unwrap();
+            let connections = server.lock().unwrap().connections();
+            let mtu = connections[0].mtu();
+            log.lock()
+                .unwrap()
+                .push(format!("mtu {} {mtu}", connection.mtu()));
+        });
+
+        let (server_ref, log) = (this.clone(), events.clone());
+        server.on_disconnect(move |connection, reason| {
+            let server = server_ref.lock().unwrap().upgrade().unwrap();
+            let connected = server
+                .lock()
+                .unwrap()
+                .connections()
+                .iter()
+                .any(|open| open.id() == connection.id());
+            let local = reason == DisconnectReason::LocalHost;
+            log.lock()
+                .unwrap()
+                .push(format!("disconnect {connected} {local}"));
+        });
+    });
+    *this.lock().unwrap() = Arc::downgrade(&server);
+
+    let connection = stack.connect([1, 0, 0, 0, 0, 1]);
+    stack.exchange_mtu(connection, 100);
+    stack.wait_idle();
+    stack.disconnect(connection);
+    stack.wait_idle();
+
+    // A connection closed from a callback is reported like any other.
+    stack.connect([2, 0, 0, 0, 0, 1]);
+    stack.wait_idle();
+
+    assert_eq!(
+        *events.lock().unwrap(),
+        [
+            "connect true",
+            "mtu 100 100",
+            "disconnect false false",
+            "connect true",
+            "disconnect false true",
+        ]
+    );
+    common::stop(&server);
+}
@@ -0,0 +1,123 @@
+//! Subscribes clients through the simulated stack, and checks which subscriptions are kept.
+
+mod common;
+
+use std::sync::{Arc, RwLock};
+
+use bluedroid::{
+    gatt_server::{Characteristic, GattServerConfig, Service},
+    simulated::SimulatedStack,
+    utilities::{AttributePermissions, BleUuid, CharacteristicProperties},
+};
+
+fn notified() -> (Arc<RwLock<Characteristic>>, Arc<RwLock<Service>>) {
+    let characteristic = Characteristic::new(BleUuid::Uuid16(0x7301))
+        .permissions(AttributePermissions::new().read())
+        .properties(CharacteristicProperties::new().read().notify())
+        .set_value(vec![0])
+        .build();
+    let service = Service::new(BleUuid::Uuid16(0x7300))
+        .primary()
+        .characteristic(&characteristic)
+        .build();
+    (characteristic, service)
+}
+
+/// Sets a value, and waits until it is notified.
+fn set(stack: &SimulatedStack, characteristic: &Arc<RwLock<Characteristic>>, value: u8) {
+    characteristic.write().unwrap().set_value(vec![value]);
+    stack.wait_idle();
+}
+
+/// Returns the values notified to a client.
+fn notified_values(stack: &SimulatedStack, connection: u16) -> Vec<Vec<u8>> {
+    stack
+        .notifications(connection)
+        .into_iter()
+        .map(|notification| notification.value)
+        .collect()
+}
+
+#[test]
+fn bonded_clients() {
+    let (_guard, stack) = common::stack();
+    let (characteristic, service) = notified();
+    let server = common::start(&GattServerConfig::new(), &[&service]);
+    let handle = stack.find_characteristic(BleUuid::Uuid16(0x7301)).unwrap();
+    let identity = [0xC0, 0, 0, 0, 0, 30];
+    let stranger = [1, 0, 0, 0, 0, 31];
+
+    let connection = stack.connect(stack.private_address(identity));
+    stack.bond(connection);
+    stack.subscribe(connection, handle, true, false).unwrap();
+    let other = stack.connect(stranger);
+    stack.subscribe(other, handle, true, false).unwrap();
+    stack.wait_idle();
+    set(stack, &characteristic, 1);
+    assert_eq!(notified_values(stack, connection), [[1]]);
+    assert_eq!(notified_values(stack, other), [[1]]);
+    stack.disconnect(connection);
+    stack.disconnect(other);
+    stack.wait_idle();
+
+    // The bonded client keeps its subscription when it reconnects with another address,
+    // the other one does not.
+    let connection = stack.connect(stack.private_address(identity));
+    let other = stack.connect(stranger);
+    stack.wait_idle();
+    set(stack, &characteristic, 2);
+    assert_eq!(notified_values(stack, connection), [[2]]);
+    assert!(notified_values(stack, other).is_empty());
+    stack.disconnect(connection);
+    stack.disconnect(other);
+    stack.wait_idle();
+    common::stop(&server);
+
+    // It keeps it after a reboot too, and reads it back from the CCCD.
+    let server = common::start(&GattServerConfig::new(), &[&service]);
+    let connection = stack.connect(stack.private_address(identity));
+    stack.wait_idle();
+    set(stack, &characteristic, 3);
+    assert_eq!(notified_values(stack, connection), [[3]]);
+    assert_eq!(stack.read(connection, handle + 1).unwrap(), [1, 0]);
+
+    // Removing the bond forgets the subscription, from the next connection on.
+    let key = String::from("C0000000001E");
+    assert!(stack.storage_keys().contains(&key));
+    server.lock().unwrap().clear_bonds().unwrap();
+    stack.wait_idle();
+    assert!(!stack.storage_keys().contains(&key));
+    stack.disconnect(connection);
+    stack.wait_idle();
+    let connection = stack.connect(stack.private_address(identity));
+    stack.wait_idle();
+    set(stack, &characteristic, 4);
+    assert!(notified_values(stack, connection).is_empty());
+    assert_eq!(stack.read(connection, handle + 1).unwrap(), [0, 0]);
+
+    common::stop(&server);
+}
+
+#[test]
+fn earlier_storage() {
+    let (_guard, stack) = common::stack();
+    let (_, service) = notified();
+
+    // The values persisted by earlier versions, by address and handle, are removed once.
+    stack.set_storage("version", None);
+    stack.set_storage("02030405-002A", Some(&[1, 0]));
+    let server = common::start(&GattServerConfig::new(), &[&service]);
+    let keys = stack.storage_keys();
+    assert!(keys.iter().any(|key| key == "version"));
+    assert!(!keys.iter().any(|key| key == "02030405-002A"));
+    common::stop(&server);
+
+    stack.set_storage("02030405-002A", Some(&[1, 0]));
+    let server = common::start(&GattServerConfig::new(), &[&service]);
+    assert!(stack
+        .storage_keys()
+        .iter()
+        .any(|key| key == "02030405-002A"));
+    stack.set_storage("02030405-002A", None);
+    common::stop(&server);
+}
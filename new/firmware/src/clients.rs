@@ -0,0 +1,138 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use bluedroid::utilities::{Connection, PreferredConnectionParameters};
+use log::{info, warn};
+
+/// The interval range requested while a slider is dragged: 15 to 30 ms.
+const FAST_INTERVAL: (u16, u16) = (12, 24);
+
+/// The parameters requested while a slider is dragged.
+const FAST_PARAMETERS: PreferredConnectionParameters =
+    PreferredConnectionParameters::new(FAST_INTERVAL.0, FAST_INTERVAL.1);
+
+/// The parameters preferred when idle, to save power: a 500 to 600 ms interval.
+pub const SLOW_PARAMETERS: PreferredConnectionParameters =
+    PreferredConnectionParameters::new(400, 480).timeout(600);
+
+/// How long to wait for the client to apply requested parameters before asking again.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A connected client.
+struct Client {
+    /// The connection, with the parameters last applied.
+    connection: Connection,
+    /// The last time the client read or wrote a value.
+    last_activity: Instant,
+    /// The last time parameters were requested, until the client applies them.
+    pending_request: Option<Instant>,
+}
+
+impl Client {
+    /// Whether the client uses a fast interval.
+    fn is_fast(&self) -> bool {
+        self.connection.parameters().interval <= FAST_INTERVAL.1
+    }
+
+    /// Request new parameters, unless a request is already pending.
+    fn request(&mut self, parameters: PreferredConnectionParameters) {
+        if self
+            .pending_request
+            .map_or(false, |requested| requested.elapsed() < REQUEST_TIMEOUT)
+        {
+            return;
+        }
+
+        match self.connection.request_parameters(parameters) {
+            Ok(()) => self.pending_request = Some(Instant::now()),
+            Err(error) => warn!(
+                "Cannot request new parameters from {}: {}.",
+                self.connection, error
+            ),
+        }
+    }
+}
+
+/// The connected clients, by connection identifier.
+pub struct Clients {
+    clients: Mutex<HashMap<u16, Client>>,
+}
+
+impl Clients {
+    /// Create an empty list of clients.
+    pub fn new() -> Self {
+        Self {
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Add a new client.
+    pub fn connect(&self, connection: &Connection) {
+        self.clients.lock().unwrap().insert(
+            connection.id(),
+            Client {
+                connection: *connection,
+                last_activity: Instant::now(),
+                pending_request: None,
+            },
+        );
+    }
+
+    /// Remove a client, and get whether other clients are connected.
+    pub fn disconnect(&self, connection: &Connection) -> bool {
+        let mut clients = self.clients.lock().unwrap();
+        clients.remove(&connection.id());
+
+        !clients.is_empty()
+    }
+
+    /// Store the parameters applied by a client.
+    pub fn update(&self, connection: &Connection) {
+        if let Some(client) = self.clients.lock().unwrap().get_mut(&connection.id()) {
+            client.connection = *connection;
+            client.pending_request = None;
+        }
+    }
+
+    /// Record that a client read a value.
+    pub fn touch(&self, conn_id: u16) {
+        if let Some(client) = self.clients.lock().unwrap().get_mut(&conn_id) {
+            client.last_activity = Instant::now();
+        }
+    }
+
+    /// Record that a client wrote a value, and switch it to a fast interval
+    /// so that dragging a slider feels responsive.
+    pub fn interact(&self, conn_id: u16) {
+        if let Some(client) = self.clients.lock().unwrap().get_mut(&conn_id) {
+            client.last_activity = Instant::now();
+
+            if !client.is_fast() {
+                info!("Switching {} to a fast interval.", client.connection);
+                client.request(FAST_PARAMETERS);
+            }
+        }
+    }
+
+    /// Switch the clients that stopped interacting back to a slow interval.
+    pub fn slow_down(&self, after: Duration) {
+        for client in self.clients.lock().unwrap().values_mut() {
+            if client.is_fast() && client.last_activity.elapsed() > after {
+                info!("Switching {} to a slow interval.", client.connection);
+                client.request(SLOW_PARAMETERS);
+            }
+        }
+    }
+
+    /// Get whether a client did not read nor write for some time.
+    pub fn is_idle(&self, connection: &Connection, timeout: Duration) -> bool {
+        self.clients
+            .lock()
+            .unwrap()
+            .get(&connection.id())
+            .map_or(true, |client| client.last_activity.elapsed() > timeout)
+    }
+}
@@ -1,3 +1,6 @@
+mod status_led;
+pub use status_led::StatusLed;
+
 /// A lamp that can be controlled.
 pub struct Lamp {
     temperature: u8,
@@ -0,0 +1,32 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use log::info;
+
+/// The status LED of the lamp, lit while a client is connected.
+pub struct StatusLed {
+    connected: AtomicBool,
+}
+
+impl StatusLed {
+    /// Create a new status LED, initially off.
+    pub const fn new() -> Self {
+        Self {
+            connected: AtomicBool::new(false),
+        }
+    }
+
+    /// Show whether a client is connected.
+    pub fn set_connected(&self, connected: bool) {
+        if self.connected.swap(connected, Ordering::Relaxed) != connected {
+            info!(
+                "Status LED {}.",
+                if connected { "on (connected)" } else { "off" }
+            );
+        }
+    }
+
+    /// Get whether the LED shows a connected client.
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+}
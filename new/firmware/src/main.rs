@@ -1,14 +1,33 @@
-use std::sync::{Arc, RwLock};
+use std::{
+    sync::{Arc, RwLock},
+    time::Duration,
+};
 
 use bluedroid::{
-    gatt_server::{Characteristic, Profile, Service},
-    utilities::{Appearance, AttributePermissions, BleUuid, CharacteristicProperties},
+    gatt_server::{GattServer, GattServerConfig, Profile},
+    gatt_service,
+    services::DeviceInformationService,
+    utilities::Appearance,
+    uuid128,
 };
 use esp_idf_sys as _;
 use log::*;
 
+mod clients;
 mod lamp;
 
+/// How long a client can stay connected without reading or writing.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// How often idle clients are looked for.
+const IDLE_CHECK_PERIOD: Duration = Duration::from_secs(30);
+
+/// How long a client keeps a fast interval after its last read or write.
+const FAST_INTERVAL_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long the Bluetooth stack can take to register the lamp service.
+const REGISTRATION_TIMEOUT: Duration = Duration::from_secs(5);
+
 fn main() {
     // It is necessary to call this function once. Otherwise some patches to the runtime might not link properly.
     esp_idf_sys::link_patches();
@@ -17,83 +36,108 @@ fn main() {
     esp_idf_svc::log::EspLogger::initialize_default();
 
     let lamp = Arc::new(RwLock::new(lamp::Lamp::new()));
+    let status_led = Arc::new(lamp::StatusLed::new());
+
+    // The connected clients, with the last time they read or wrote a value.
+    let clients = Arc::new(clients::Clients::new());
 
     let lamp_write_brightness_ref = lamp.clone();
     let lamp_read_brightness_ref = lamp.clone();
+    let clients_write_brightness_ref = clients.clone();
+    let clients_read_brightness_ref = clients.clone();
 
     let lamp_write_temperature_ref = lamp.clone();
     let lamp_read_temperature_ref = lamp.clone();
+    let clients_write_temperature_ref = clients.clone();
+    let clients_read_temperature_ref = clients.clone();
 
-    let brightness_characteristic = Characteristic::new(BleUuid::from_uuid128_str(
-        "F9DFBD73-0181-433A-8091-372E0CA8A598",
-    ))
-    .name("Brightness")
-    .show_name()
-    .max_value_length(1)
-    .permissions(AttributePermissions::new().read().write())
-    .properties(
-        CharacteristicProperties::new()
-            .read()
-            .write()
-            .write_without_response()
-            .notify(),
-    )
-    .on_read(move |_| {
-        let brightness = lamp_read_brightness_ref.read().unwrap().get_brightness();
-        info!("Read brightness: {}", brightness);
-        vec![brightness]
-    })
-    .on_write(move |val, _| {
-        let brightness = val[0];
-        info!("Write brightness: {}", brightness);
-        lamp_write_brightness_ref
-            .write()
-            .unwrap()
-            .set_brightness(brightness, false);
-    })
-    .build();
-
-    let temperature_characteristic = Characteristic::new(BleUuid::from_uuid128_str(
-        "CA344E9B-7445-43AA-AD20-43A33C8101E9",
-    ))
-    .name("Brightness")
-    .show_name()
-    .max_value_length(1)
-    .permissions(AttributePermissions::new().read().write())
-    .properties(
-        CharacteristicProperties::new()
-            .read()
-            .write()
-            .write_without_response()
-            .notify(),
-    )
-    .on_read(move |_| {
-        let temperature = lamp_read_temperature_ref.read().unwrap().get_temperature();
-        info!("Read brightness: {}", temperature);
-        vec![temperature]
-    })
-    .on_write(move |val, _| {
-        let temperature = val[0];
-        info!("Write temperature: {}", temperature);
-        lamp_write_temperature_ref
-            .write()
-            .unwrap()
-            .set_temperature(temperature, false);
-    })
-    .build();
-
-    let lamp_service = Service::new(BleUuid::from_uuid128_str(
-        "4E0F5E1E-FC5B-4D67-8E30-2A83B336476B",
-    ))
-    .characteristic(&brightness_characteristic)
-    .characteristic(&temperature_characteristic)
-    .name("Color Lamp Service")
-    .primary()
-    .build();
+    gatt_service! {
+        service lamp_service(uuid128!("4E0F5E1E-FC5B-4D67-8E30-2A83B336476B")) {
+            name: "Color Lamp Service",
+            primary: true,
+            // Room for more characteristics, without moving the handles of the standard services.
+            reserve_handles: 16,
+
+            characteristic brightness_characteristic(uuid128!("F9DFBD73-0181-433A-8091-372E0CA8A598")): u8 {
+                name: "Brightness",
+                show_name: true,
+                properties: [read, write, write_without_response, notify],
+                on_read: move |param| {
+                    clients_read_brightness_ref.touch(param.conn_id);
+
+                    let brightness = lamp_read_brightness_ref.read().unwrap().get_brightness();
+                    info!("Read brightness: {}", brightness);
+                    Ok(brightness)
+                },
+                on_write: move |brightness, param| {
+                    clients_write_brightness_ref.interact(param.conn_id);
+
+                    // A value that is not a single byte is rejected with `InvalidAttributeValueLength`.
+                    let brightness = match brightness {
+                        Ok(brightness) => brightness,
+                        Err(error) => {
+                            warn!("Rejecting malformed brightness: {}", error);
+                            return Err(error.into());
+                        }
+                    };
+
+                    info!("Write brightness: {}", brightness);
+                    lamp_write_brightness_ref
+                        .write()
+                        .unwrap()
+                        .set_brightness(brightness, false);
+
+                    Ok(())
+                },
+            }
+
+            characteristic temperature_characteristic(uuid128!("CA344E9B-7445-43AA-AD20-43A33C8101E9")): u8 {
+                name: "Temperature",
+                show_name: true,
+                properties: [read, write, write_without_response, notify],
+                on_read: move |param| {
+                    clients_read_temperature_ref.touch(param.conn_id);
+
+                    let temperature = lamp_read_temperature_ref.read().unwrap().get_temperature();
+                    info!("Read temperature: {}", temperature);
+                    Ok(temperature)
+                },
+                on_write: move |temperature, param| {
+                    clients_write_temperature_ref.interact(param.conn_id);
+
+                    let temperature = match temperature {
+                        Ok(temperature) => temperature,
+                        Err(error) => {
+                            warn!("Rejecting malformed temperature: {}", error);
+                            return Err(error.into());
+                        }
+                    };
+
+                    info!("Write temperature: {}", temperature);
+                    lamp_write_temperature_ref
+                        .write()
+                        .unwrap()
+                        .set_temperature(temperature, false);
+
+                    Ok(())
+                },
+            }
+        }
+    }
+
+    let device_information = DeviceInformationService::new();
+    if let Err(error) = device_information
+        .manufacturer_name("Riccardo Persello")
+        .and_then(|service| service.model_number("Color Lamp"))
+        .and_then(|service| service.firmware_revision(env!("CARGO_PKG_VERSION")))
+    {
+        warn!("Cannot set the device information: {}.", error);
+    }
 
     let main_profile = Profile::new(0)
         .name("Main Profile")
         .service(&lamp_service)
+        .service(&device_information)
         .build();
 
     let brightness_for_notify = brightness_characteristic.clone();
@@ -103,27 +147,97 @@ fn main() {
         .unwrap()
         .attach_change_callback(move |lamp, notify| {
             if notify {
-                brightness_for_notify
-                    .write()
-                    .unwrap()
-                    .set_value(vec![lamp.get_brightness()]);
-
-                temperature_for_notify
-                    .write()
-                    .unwrap()
-                    .set_value(vec![lamp.get_temperature()]);
+                brightness_for_notify.set_value(lamp.get_brightness());
+                temperature_for_notify.set_value(lamp.get_temperature());
             }
         });
 
-    bluedroid::gatt_server::GLOBAL_GATT_SERVER
+    let server = GattServer::new(
+        GattServerConfig::new()
+            .appearance(Appearance::LEDLamp)
+            .device_name("Color Lamp")
+            .preferred_connection_parameters(clients::SLOW_PARAMETERS),
+    );
+
+    let clients_connect_ref = clients.clone();
+    let clients_disconnect_ref = clients.clone();
+    let clients_parameters_ref = clients.clone();
+    let status_led_connect_ref = status_led.clone();
+    let status_led_disconnect_ref = status_led.clone();
+
+    // The server must be unlocked while the service is registered.
+    let started = server
         .lock()
         .unwrap()
         .advertise_service(&lamp_service)
         .profile(main_profile)
-        .appearance(Appearance::LEDLamp)
-        .device_name("Color Lamp")
+        .on_connect(move |connection| {
+            info!("Client {} connected.", connection);
+            clients_connect_ref.connect(connection);
+            status_led_connect_ref.set_connected(true);
+        })
+        .on_disconnect(move |connection, reason| {
+            info!("Client {} disconnected: {:?}.", connection, reason);
+            status_led_disconnect_ref.set_connected(clients_disconnect_ref.disconnect(connection));
+        })
+        .on_mtu_change(|connection| {
+            info!("Client {} uses an MTU of {}.", connection, connection.mtu());
+        })
+        .on_parameters_change(move |connection| {
+            let parameters = connection.parameters();
+            info!(
+                "Client {} uses an interval of {} ms.",
+                connection,
+                f32::from(parameters.interval) * 1.25
+            );
+            clients_parameters_ref.update(connection);
+        })
         .start();
 
+    if let Err(error) = started {
+        error!("Cannot start the GATT server: {}.", error);
+    } else if let Err(error) = GattServer::wait_until_ready(&server, REGISTRATION_TIMEOUT) {
+        error!("Cannot register the lamp service: {}.", error);
+    } else {
+        match server.lock().unwrap().handle_map() {
+            Ok(handle_map) => debug!("Attribute handles:\n{}", handle_map),
+            Err(error) => warn!("Cannot list the attribute handles: {}.", error),
+        }
+    }
+
+    // Switch the clients that stopped dragging a slider back to a slow interval.
+    let slow_clients = clients.clone();
+    std::thread::spawn(move || loop {
+        std::thread::sleep(FAST_INTERVAL_TIMEOUT / 2);
+        slow_clients.slow_down(FAST_INTERVAL_TIMEOUT);
+    });
+
+    // Disconnect the clients that stopped using the lamp.
+    let idle_server = server.clone();
+    let idle_clients = clients;
+    std::thread::spawn(move || loop {
+        std::thread::sleep(IDLE_CHECK_PERIOD);
+
+        if !status_led.is_connected() {
+            continue;
+        }
+
+        // Find the idle clients first, so that the server and the clients are never locked together.
+        let connections = idle_server.lock().unwrap().connections();
+        let idle: Vec<_> = connections
+            .into_iter()
+            .filter(|connection| idle_clients.is_idle(connection, IDLE_TIMEOUT))
+            .collect();
+
+        for connection in idle {
+            info!("Disconnecting idle client {}.", connection);
+
+            if let Err(error) = idle_server.lock().unwrap().disconnect(&connection) {
+                warn!("Cannot disconnect client {}: {}.", connection, error);
+            }
+        }
+    });
+
     std::thread::spawn(move || loop {
         std::thread::sleep(std::time::Duration::from_secs(10));
 
@@ -142,4 +256,7 @@ fn main() {
             .unwrap()
             .set_temperature(random_temperature as u8, true);
     });
+
+    // Keep the server running after returning from main.
+    std::mem::forget(server);
 }